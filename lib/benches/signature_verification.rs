@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use lib::crypto::PrivateKey;
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::sigcache::SignatureCache;
+use lib::types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+// Builds a block with `input_count` single-input transactions spending
+// synthetic UTXOs, alternating between ECDSA and Schnorr locked outputs
+fn synthetic_block(input_count: usize) -> (Block, HashMap<Hash, TransactionOutput>) {
+    let owner = PrivateKey::new_key();
+    let mut utxos = HashMap::new();
+    let mut transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(50 * 10u64.pow(8), owner.public_key().into())],
+    )];
+
+    for i in 0..input_count {
+        let pubkey = if i % 2 == 0 {
+            owner.public_key()
+        } else {
+            owner.schnorr_public_key()
+        };
+        let utxo = TransactionOutput::new(1_000, pubkey.clone().into());
+        let utxo_hash = utxo.hash();
+        utxos.insert(utxo_hash, utxo);
+
+        let mut transaction = Transaction::new(
+            vec![TransactionInput::unsigned(utxo_hash)],
+            vec![TransactionOutput::new(1_000, owner.public_key().into())],
+        );
+        transaction.sign_input(0, 0, &owner, &pubkey);
+        transactions.push(transaction);
+    }
+
+    let header = BlockHeader::new(
+        Utc::now(),
+        0,
+        Hash::zero(),
+        MerkleRoot::calculate(&transactions),
+        U256::MAX,
+    );
+
+    (Block::new(header, transactions), utxos)
+}
+
+fn bench_verify_transactions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify_transactions");
+    group.sample_size(10);
+
+    for input_count in [500, 2_000, 8_000] {
+        let (block, utxos) = synthetic_block(input_count);
+
+        group.bench_with_input(BenchmarkId::new("serial", input_count), &block, |b, block| {
+            b.iter(|| block.verify_transactions_serial(&ChainParams::regtest(), 1, &utxos).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", input_count), &block, |b, block| {
+            b.iter_batched(
+                SignatureCache::default,
+                |cache| block.verify_transactions(&ChainParams::regtest(), 1, &utxos, &cache).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+
+        // every signature already verified, as after mempool acceptance
+        let warm_cache = SignatureCache::default();
+        block.verify_transactions(&ChainParams::regtest(), 1, &utxos, &warm_cache).unwrap();
+        group.bench_with_input(BenchmarkId::new("cached", input_count), &block, |b, block| {
+            b.iter(|| block.verify_transactions(&ChainParams::regtest(), 1, &utxos, &warm_cache).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_verify_transactions);
+criterion_main!(benches);
@@ -0,0 +1,100 @@
+use std::fmt::{self, Display, Formatter};
+use crate::crypto::PublicKey;
+use crate::error::{BtcError, Result};
+use crate::params::ChainParams;
+use crate::types::LockingCondition;
+use crate::util::checksum;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const CHECKSUM_SIZE: usize = 4;
+
+///Base58Check encoded public key, its version byte ties it to one network.
+///Outputs lock to whole keys, so addresses carry the key instead of its hash
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    pub prefix: u8,
+    pub pubkey: PublicKey
+}
+
+impl Address {
+    pub fn new(pubkey: PublicKey, params: &ChainParams) -> Self {
+        Address {
+            prefix: params.address_prefix,
+            pubkey
+        }
+    }
+
+    ///Decodes an address, rejecting those of other networks
+    pub fn parse(address: &str, params: &ChainParams) -> Result<Self> {
+        let bytes = base58_decode(address).ok_or(BtcError::InvalidAddress)?;
+        if bytes.len() <= 1 + CHECKSUM_SIZE {
+            return Err(BtcError::InvalidAddress)
+        }
+
+        let (payload, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        if checksum != &self::checksum(payload)[..] || payload[0] != params.address_prefix {
+            return Err(BtcError::InvalidAddress)
+        }
+
+        Ok(Address {
+            prefix: payload[0],
+            pubkey: PublicKey::from_bytes(&payload[1..])?
+        })
+    }
+
+    pub fn lock(&self) -> LockingCondition {
+        LockingCondition::PublicKey(self.pubkey.clone())
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut bytes = vec![self.prefix];
+        bytes.extend(self.pubkey.to_bytes());
+        bytes.extend(checksum(&bytes));
+        write!(f, "{}", base58_encode(&bytes))
+    }
+}
+
+fn base58_encode(bytes: &[u8]) -> String {
+    //base 58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in bytes {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    //every leading zero byte is written as a '1'
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat_n('1', zeros)
+        .chain(digits.iter().rev().map(|digit| BASE58_ALPHABET[*digit as usize] as char))
+        .collect()
+}
+
+fn base58_decode(encoded: &str) -> Option<Vec<u8>> {
+    //base 256 bytes, least significant first
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in encoded.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|a| *a == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let zeros = encoded.bytes().take_while(|c| *c == b'1').count();
+    Some(std::iter::repeat_n(0, zeros).chain(bytes.into_iter().rev()).collect())
+}
@@ -0,0 +1,274 @@
+use rand::seq::IteratorRandom;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use crate::network::NetAddress;
+use crate::sha256::Hash;
+use crate::util::Saveable;
+
+pub const NEW_BUCKET_COUNT: u64 = 64;
+pub const TRIED_BUCKET_COUNT: u64 = 16;
+pub const BUCKET_SIZE: u64 = 16;
+///New buckets the addresses from one source group are spread over, so a single
+///peer gossiping many addresses can only fill a few of them
+pub const NEW_BUCKETS_PER_SOURCE_GROUP: u64 = 8;
+///Tried buckets the addresses of one group are spread over
+pub const TRIED_BUCKETS_PER_GROUP: u64 = 4;
+
+///Addresses not heard of for this long are dropped, in seconds
+pub const ADDRESS_HORIZON: i64 = 30 * 24 * 60 * 60;
+//failed attempts after which an address never reached is given up
+const MAX_RETRIES: u32 = 3;
+//failed attempts after which an address is given up, once its last success is a week old
+const MAX_FAILURES: u32 = 10;
+const MIN_FAIL_SECONDS: i64 = 7 * 24 * 60 * 60;
+
+///Wait after a failed attempt, doubling with each further failure up to MAX_RETRY_DELAY
+pub const RETRY_DELAY: i64 = 60;
+pub const MAX_RETRY_DELAY: i64 = 60 * 60;
+
+///The network an address belongs to as far as who controls it goes: the /16 of
+///IPv4 and the /32 of IPv6 addresses. Local addresses all share one group
+pub fn address_group(ip: IpAddr) -> Vec<u8> {
+    let ip = ip.to_canonical();
+    if ip.is_loopback() || ip.is_unspecified() {
+        return vec![0]
+    }
+
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            vec![4, octets[0], octets[1]]
+        }
+        IpAddr::V6(ip) => {
+            let octets = ip.octets();
+            vec![6, octets[0], octets[1], octets[2], octets[3]]
+        }
+    }
+}
+
+///What the address book knows about a node, times in unix seconds
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AddrInfo {
+    pub addr: SocketAddr,
+    ///Group of the peer that told us about the address
+    pub source_group: Vec<u8>,
+    pub last_seen: i64,
+    pub last_attempt: i64,
+    pub last_success: i64,
+    ///Failed attempts since the last success
+    pub attempts: u32,
+    ///Connected to successfully, so it is kept in the tried table
+    pub tried: bool,
+    //bucket and position in its table
+    slot: (u64, u64)
+}
+
+impl AddrInfo {
+    ///Not worth keeping: not heard of for too long, or failing too often
+    pub fn is_terrible(&self, now: i64) -> bool {
+        now - self.last_seen > ADDRESS_HORIZON
+            || (self.last_success == 0 && self.attempts >= MAX_RETRIES)
+            || (now - self.last_success > MIN_FAIL_SECONDS && self.attempts >= MAX_FAILURES)
+    }
+
+    ///Seconds to wait after the last attempt before trying again
+    pub fn retry_delay(&self) -> i64 {
+        match self.attempts {
+            0 => 0,
+            attempts => (RETRY_DELAY << (attempts - 1).min(16)).min(MAX_RETRY_DELAY)
+        }
+    }
+
+    fn is_due(&self, now: i64) -> bool {
+        now - self.last_attempt >= self.retry_delay()
+    }
+}
+
+///Address book of nodes to connect to. Addresses heard of go to the new table,
+///those connected to successfully to the tried table. Both are split into
+///buckets by address group, so that whoever controls a few networks can't
+///take over the book; a full slot keeps its entry unless that went stale
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AddrMan {
+    ///Secret mixed into the bucket positions, so peers can't aim at particular slots
+    key: [u8; 32],
+    entries: HashMap<SocketAddr, AddrInfo>
+}
+
+impl AddrMan {
+    pub fn new() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+
+        AddrMan {
+            key,
+            entries: HashMap::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn tried_count(&self) -> usize {
+        self.entries.values().filter(|info| info.tried).count()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&AddrInfo> {
+        self.entries.get(addr)
+    }
+
+    ///Adds an address heard of from source to the new table, known addresses
+    ///only have their last seen time refreshed. Returns whether it was added
+    pub fn add(&mut self, address: NetAddress, source: IpAddr, now: i64) -> bool {
+        if address.addr.port() == 0 || address.addr.ip().is_unspecified() {
+            return false
+        }
+
+        //a time in the future isn't believed
+        let last_seen = address.timestamp.min(now);
+        if let Some(info) = self.entries.get_mut(&address.addr) {
+            info.last_seen = info.last_seen.max(last_seen);
+            return false
+        }
+
+        let mut info = AddrInfo {
+            addr: address.addr,
+            source_group: address_group(source),
+            last_seen,
+            last_attempt: 0,
+            last_success: 0,
+            attempts: 0,
+            tried: false,
+            slot: (0, 0)
+        };
+        if info.is_terrible(now) {
+            return false
+        }
+        info.slot = self.new_slot(&info);
+
+        if let Some(occupant) = self.occupant(false, info.slot) {
+            if !self.entries[&occupant].is_terrible(now) {
+                return false
+            }
+            self.entries.remove(&occupant);
+        }
+
+        self.entries.insert(address.addr, info);
+        true
+    }
+
+    ///Records a connection attempt, it counts as failed until good is called
+    pub fn attempt(&mut self, addr: &SocketAddr, now: i64) {
+        if let Some(info) = self.entries.get_mut(addr) {
+            info.last_attempt = now;
+            info.attempts += 1;
+        }
+    }
+
+    ///Records a successful connection, moving the address to the tried table.
+    ///The entry it displaces there goes back to the new table
+    pub fn good(&mut self, addr: &SocketAddr, now: i64) {
+        let Some(mut info) = self.entries.remove(addr) else {
+            return
+        };
+        info.last_seen = now;
+        info.last_success = now;
+        info.attempts = 0;
+
+        if !info.tried {
+            info.tried = true;
+            info.slot = self.tried_slot(addr);
+
+            if let Some(occupant) = self.occupant(true, info.slot) {
+                let mut displaced = self.entries.remove(&occupant).unwrap();
+                displaced.tried = false;
+                displaced.slot = self.new_slot(&displaced);
+
+                if let Some(other) = self.occupant(false, displaced.slot) {
+                    self.entries.remove(&other);
+                }
+                self.entries.insert(occupant, displaced);
+            }
+        }
+
+        self.entries.insert(*addr, info);
+    }
+
+    ///Picks an address to connect to from either table, leaving out the
+    ///excluded groups and addresses, those given up on and those still
+    ///waiting to be retried
+    pub fn select(
+        &self,
+        excluded_groups: &HashSet<Vec<u8>>,
+        excluded: &HashSet<SocketAddr>,
+        now: i64
+    ) -> Option<SocketAddr> {
+        let mut rng = rand::thread_rng();
+        let eligible = |info: &&AddrInfo| {
+            info.is_due(now)
+                && !excluded.contains(&info.addr)
+                && !info.is_terrible(now)
+                && !excluded_groups.contains(&address_group(info.addr.ip()))
+        };
+
+        let tried = self.entries.values().filter(|info| info.tried).filter(eligible).choose(&mut rng);
+        let new = self.entries.values().filter(|info| !info.tried).filter(eligible).choose(&mut rng);
+
+        let info = match (tried, new) {
+            (Some(tried), Some(new)) => if rng.gen_bool(0.5) { tried } else { new },
+            (tried, new) => tried.or(new)?
+        };
+        Some(info.addr)
+    }
+
+    ///Up to max random addresses worth sharing, for answering getaddr
+    pub fn sample(&self, max: usize, now: i64) -> Vec<NetAddress> {
+        self.entries
+            .values()
+            .filter(|info| !info.is_terrible(now))
+            .choose_multiple(&mut rand::thread_rng(), max)
+            .into_iter()
+            .map(|info| NetAddress { addr: info.addr, timestamp: info.last_seen })
+            .collect()
+    }
+
+    fn new_slot(&self, info: &AddrInfo) -> (u64, u64) {
+        let group = address_group(info.addr.ip());
+        let spread = self.keyed_hash(&(&group, &info.source_group)) % NEW_BUCKETS_PER_SOURCE_GROUP;
+        let bucket = self.keyed_hash(&(&info.source_group, spread)) % NEW_BUCKET_COUNT;
+        (bucket, self.keyed_hash(&("new", bucket, info.addr)) % BUCKET_SIZE)
+    }
+
+    fn tried_slot(&self, addr: &SocketAddr) -> (u64, u64) {
+        let spread = self.keyed_hash(addr) % TRIED_BUCKETS_PER_GROUP;
+        let bucket = self.keyed_hash(&(address_group(addr.ip()), spread)) % TRIED_BUCKET_COUNT;
+        (bucket, self.keyed_hash(&("tried", bucket, addr)) % BUCKET_SIZE)
+    }
+
+    fn occupant(&self, tried: bool, slot: (u64, u64)) -> Option<SocketAddr> {
+        self.entries
+            .values()
+            .find(|info| info.tried == tried && info.slot == slot)
+            .map(|info| info.addr)
+    }
+
+    fn keyed_hash<T: Serialize>(&self, data: &T) -> u64 {
+        let hash = Hash::hash(&(self.key, data)).as_bytes();
+        u64::from_le_bytes(hash[..8].try_into().unwrap())
+    }
+}
+
+impl Default for AddrMan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Saveable for AddrMan {}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use crate::error::BtcError;
+use crate::util::Saveable;
+
+///Misbehavior score at which a peer is disconnected and banned
+pub const BAN_THRESHOLD: u32 = 100;
+///How long misbehaving peers are banned for, in seconds
+pub const DEFAULT_BAN_TIME: i64 = 24 * 60 * 60;
+
+///Points a peer earns for sending something rejected with the error. Only
+///what no honest node relays counts: a missing parent, a conflict with our
+///mempool or a failed policy check can happen to anyone
+pub fn misbehavior_score(error: &BtcError) -> u32 {
+    match error {
+        BtcError::InvalidBlock
+        | BtcError::InvalidBlockHeader
+        | BtcError::InvalidMerkleRoot
+        | BtcError::InvalidWitnessCommitment
+        | BtcError::BlockLimitsExceeded
+        | BtcError::GenesisMismatch
+        | BtcError::InvalidHash
+        | BtcError::InvalidTransactionInput
+        | BtcError::InvalidTransactionOutput
+        | BtcError::InvalidSignature
+        | BtcError::NonCanonicalSignature
+        | BtcError::InvalidScript(_) => BAN_THRESHOLD,
+        BtcError::InvalidTransaction
+        | BtcError::MissingPreviousBlock
+        | BtcError::MissingInputs
+        | BtcError::MempoolFull
+        | BtcError::NonFinalTransaction
+        | BtcError::UnsatisfiedSequenceLock
+        | BtcError::NonStandardTransaction(_)
+        | BtcError::InvalidPublicKey
+        | BtcError::InvalidPrivateKey
+        | BtcError::InvalidAddress => 0
+    }
+}
+
+///Points a peer earns for sending a block that failed validation with the
+///error. Unlike a loose transaction's, a block's missing inputs, lock times
+///or values out of balance break consensus rules
+pub fn block_misbehavior_score(error: &BtcError) -> u32 {
+    match error {
+        //the parent may just not have arrived yet
+        BtcError::MissingPreviousBlock => 0,
+        //mempool policy, blocks aren't checked against it
+        BtcError::MempoolFull | BtcError::NonStandardTransaction(_) => 0,
+        _ => BAN_THRESHOLD
+    }
+}
+
+///Addresses refused as peers until a point in time, kept across restarts
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BanList {
+    //unix time each address is banned until
+    banned: HashMap<IpAddr, i64>
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        BanList::default()
+    }
+
+    ///Bans the address until the given time, an existing longer ban is kept
+    pub fn ban(&mut self, ip: IpAddr, until: i64) {
+        let until_time = self.banned.entry(ip.to_canonical()).or_insert(until);
+        *until_time = (*until_time).max(until);
+    }
+
+    ///Lifts the ban on the address, returns whether there was one
+    pub fn unban(&mut self, ip: &IpAddr) -> bool {
+        self.banned.remove(&ip.to_canonical()).is_some()
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr, now: i64) -> bool {
+        self.banned.get(&ip.to_canonical()).is_some_and(|until| *until > now)
+    }
+
+    ///Forgets the bans that ran out, returns whether there were any
+    pub fn sweep(&mut self, now: i64) -> bool {
+        let before = self.banned.len();
+        self.banned.retain(|_, until| *until > now);
+        self.banned.len() != before
+    }
+
+    ///Current bans with their end times, soonest ending first
+    pub fn entries(&self, now: i64) -> Vec<(IpAddr, i64)> {
+        let mut entries: Vec<(IpAddr, i64)> = self
+            .banned
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(ip, until)| (*ip, *until))
+            .collect();
+        entries.sort_by_key(|(ip, until)| (*until, *ip));
+        entries
+    }
+
+    pub fn clear(&mut self) {
+        self.banned.clear();
+    }
+}
+
+impl Saveable for BanList {}
@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use crate::error::{BtcError, Result};
+use crate::sha256::Hash;
+use crate::types::{Block, BlockHeader, Transaction};
+use crate::util::{self, MerkleRoot};
+
+///Bytes kept of a short transaction ID, plenty to tell a mempool's transactions apart
+pub const SHORT_ID_SIZE: usize = 6;
+
+///Transaction sent in full in a compact block, with its index in the block
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrefilledTransaction {
+    pub index: u32,
+    pub transaction: Transaction
+}
+
+///Block announced by its header and short IDs of its transactions, which the
+///receiver mostly has in its mempool already. The coinbase never is, so it
+///is always sent in full
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    ///Salts the short IDs, so nobody can craft transactions colliding for every block
+    pub nonce: u64,
+    ///Short IDs of the transactions not prefilled, in block order
+    pub short_ids: Vec<u64>,
+    ///In ascending index order
+    pub prefilled: Vec<PrefilledTransaction>
+}
+
+impl CompactBlock {
+    pub fn new(block: &Block, nonce: u64) -> Self {
+        let mut compact = CompactBlock {
+            header: block.header().clone(),
+            nonce,
+            short_ids: Vec::new(),
+            prefilled: Vec::new()
+        };
+
+        for (index, transaction) in block.transactions().iter().enumerate() {
+            if index == 0 {
+                compact.prefilled.push(PrefilledTransaction { index: 0, transaction: transaction.clone() });
+            } else {
+                compact.short_ids.push(compact.short_id(&transaction.wtxid()));
+            }
+        }
+        compact
+    }
+
+    ///Short ID of a transaction in this block. Taken over the wtxid, so a
+    ///transaction only matches with the same witness data
+    pub fn short_id(&self, wtxid: &Hash) -> u64 {
+        let key = Hash::hash(&(self.header.hash(), self.nonce));
+        let hash = Hash::hash(&(key, wtxid)).as_bytes();
+        let mut id = [0u8; 8];
+        id[..SHORT_ID_SIZE].copy_from_slice(&hash[..SHORT_ID_SIZE]);
+        u64::from_le_bytes(id)
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+}
+
+///Asks for the transactions of a block a compact block couldn't be completed without
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockTransactionsRequest {
+    pub block_hash: Hash,
+    ///Indexes in the block, ascending
+    pub indexes: Vec<u32>
+}
+
+///Answers a BlockTransactionsRequest with the transactions in the order asked
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockTransactions {
+    pub block_hash: Hash,
+    pub transactions: Vec<Transaction>
+}
+
+impl BlockTransactions {
+    ///The requested transactions of the block, None if an index is out of range
+    pub fn new(block: &Block, request: &BlockTransactionsRequest) -> Option<Self> {
+        let transactions = request
+            .indexes
+            .iter()
+            .map(|index| block.transactions().get(*index as usize).cloned())
+            .collect::<Option<Vec<Transaction>>>()?;
+
+        Some(BlockTransactions { block_hash: request.block_hash, transactions })
+    }
+}
+
+///Block being rebuilt from a compact block: prefilled transactions and those
+///found in the mempool are in place, the rest is requested from the sender
+pub struct PartialBlock {
+    header: BlockHeader,
+    slots: Vec<Option<Transaction>>,
+    ///Transactions taken from the mempool
+    pub from_mempool: usize
+}
+
+impl PartialBlock {
+    ///Places the prefilled transactions and matches the short IDs against
+    ///the mempool. Slots whose ID matches several transactions, or appears
+    ///twice in the block, are left to be requested. Fails on prefilled indexes
+    ///out of order or past the end of the block
+    pub fn new<'a>(compact: &CompactBlock, mempool: impl IntoIterator<Item = &'a Transaction>) -> Result<Self> {
+        let count = compact.transaction_count();
+        let mut slots: Vec<Option<Transaction>> = vec![None; count];
+        let mut prefilled = vec![false; count];
+
+        let mut next = 0;
+        for PrefilledTransaction { index, transaction } in &compact.prefilled {
+            let index = *index as usize;
+            if index < next || index >= count {
+                return Err(BtcError::InvalidBlock)
+            }
+            slots[index] = Some(transaction.clone());
+            prefilled[index] = true;
+            next = index + 1;
+        }
+
+        //the short IDs fill the slots left over, in order
+        let positions = (0..count).filter(|index| !prefilled[*index]);
+        let mut ids: HashMap<u64, usize> = HashMap::new();
+        let mut ambiguous: HashSet<usize> = HashSet::new();
+        for (id, position) in compact.short_ids.iter().zip(positions) {
+            if let Some(other) = ids.insert(*id, position) {
+                ambiguous.insert(other);
+                ambiguous.insert(position);
+            }
+        }
+
+        let mut from_mempool = 0;
+        for transaction in mempool {
+            let Some(position) = ids.get(&compact.short_id(&transaction.wtxid())) else {
+                continue
+            };
+            if ambiguous.contains(position) {
+                continue
+            }
+
+            if slots[*position].is_some() {
+                //two mempool transactions share the ID, neither is trusted
+                slots[*position] = None;
+                ambiguous.insert(*position);
+                from_mempool -= 1;
+            } else {
+                slots[*position] = Some(transaction.clone());
+                from_mempool += 1;
+            }
+        }
+
+        Ok(PartialBlock {
+            header: compact.header.clone(),
+            slots,
+            from_mempool
+        })
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+
+    ///Indexes of the transactions still missing
+    pub fn missing(&self) -> Vec<u32> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_none())
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    ///Completes the block with the missing transactions, in the order of
+    ///missing(). A wrong number of them makes an InvalidBlock; a block not
+    ///matching its header's commitments, most likely from a short ID
+    ///collision with the mempool, an InvalidMerkleRoot or InvalidWitnessCommitment
+    pub fn complete(self, missing: Vec<Transaction>) -> Result<Block> {
+        let mut missing = missing.into_iter();
+        let transactions = self
+            .slots
+            .into_iter()
+            .map(|slot| slot.or_else(|| missing.next()))
+            .collect::<Option<Vec<Transaction>>>()
+            .ok_or(BtcError::InvalidBlock)?;
+        if missing.next().is_some() {
+            return Err(BtcError::InvalidBlock)
+        }
+
+        if MerkleRoot::calculate(&transactions) != self.header.merkle_root {
+            return Err(BtcError::InvalidMerkleRoot)
+        }
+        let block = Block::new(self.header, transactions);
+        block.verify_witness_commitment()?;
+        Ok(block)
+    }
+}
+
+///Traffic of the blocks received as compact blocks, against the full blocks
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactStats {
+    ///Blocks rebuilt from compact blocks
+    pub blocks: u64,
+    ///Their transactions found in the mempool
+    pub from_mempool: u64,
+    ///Their transactions that had to be requested
+    pub requested: u64,
+    ///Compact blocks given up on for the full block
+    pub failures: u64,
+    ///Bytes of the compact blocks and requested transactions
+    pub received_bytes: u64,
+    ///Bytes the full blocks would have taken
+    pub full_bytes: u64
+}
+
+impl CompactStats {
+    ///Records a block rebuilt from the compact block with the transactions requested for it
+    pub fn record(&mut self, compact: &CompactBlock, requested: &[Transaction], block: &Block, from_mempool: usize) {
+        self.blocks += 1;
+        self.from_mempool += from_mempool as u64;
+        self.requested += requested.len() as u64;
+        self.received_bytes += (util::serialized_size(compact) + util::serialized_size(&requested)) as u64;
+        self.full_bytes += block.size() as u64;
+    }
+
+    pub fn saved_bytes(&self) -> u64 {
+        self.full_bytes.saturating_sub(self.received_bytes)
+    }
+}
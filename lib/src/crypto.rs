@@ -7,32 +7,142 @@ use ecdsa::{
     VerifyingKey
 };
 use ecdsa::signature::Verifier;
-use k256::{Secp256k1};
+use k256::Secp256k1;
+use k256::schnorr::{
+    Signature as SchnorrSignature,
+    SigningKey as SchnorrSigningKey,
+    VerifyingKey as SchnorrVerifyingKey
+};
+use rand::RngCore;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use crate::error::{BtcError, Result};
 use crate::sha256::Hash;
 
+///Signature scheme an output key (and the signature spending it) belongs to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignatureScheme {
+    ///ECDSA over secp256k1
+    Ecdsa,
+    ///BIP340 Schnorr over secp256k1 with x-only public keys
+    Schnorr
+}
+
 #[derive(Serialize, Deserialize, Clone)]
-pub struct Signature(ECDSASignature<Secp256k1>);
+pub enum Signature {
+    Ecdsa(ECDSASignature<Secp256k1>),
+    Schnorr(
+        #[serde(with = "schnorr_signature_serde")]
+        SchnorrSignature
+    )
+}
 
 impl Signature {
+    ///Signs a sighash (see Transaction::sighash) with ECDSA and a deterministic RFC6979 nonce.
+    ///s is normalized to the lower half of the curve order (BIP62) so relayers can't change the wtxid
     pub fn sign_output(output_hash: &Hash, private_key: &PrivateKey) -> Self {
         let signing_key = &private_key.0;
 
-        let signature = signing_key.sign(&output_hash.as_bytes());
+        let signature: ECDSASignature<Secp256k1> = signing_key.sign(&output_hash.as_bytes());
+
+        Signature::Ecdsa(signature.normalize_s().unwrap_or(signature))
+    }
+
+    ///Signs a sighash with BIP340 Schnorr using fresh auxiliary randomness
+    pub fn sign_output_schnorr(output_hash: &Hash, private_key: &PrivateKey) -> Self {
+        let mut aux_rand = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut aux_rand);
+
+        Self::sign_output_schnorr_with_aux_rand(output_hash, private_key, &aux_rand)
+    }
+
+    ///Signs a sighash with BIP340 Schnorr, the 32 hash bytes are the message
+    pub fn sign_output_schnorr_with_aux_rand(
+        output_hash: &Hash,
+        private_key: &PrivateKey,
+        aux_rand: &[u8; 32]
+    ) -> Self {
+        let signing_key = private_key.schnorr_signing_key();
+
+        let signature = signing_key
+            .sign_prehash_with_aux_rand(&output_hash.as_bytes(), aux_rand)
+            .expect("BIP340 signing with a valid secret key should not fail");
 
-        Signature(signature)
+        Signature::Schnorr(signature)
+    }
+
+    ///Signs a sighash with the scheme of the key the output is locked to
+    pub fn sign_output_for(
+        output_hash: &Hash,
+        private_key: &PrivateKey,
+        public_key: &PublicKey
+    ) -> Self {
+        match public_key.scheme() {
+            SignatureScheme::Ecdsa => Self::sign_output(output_hash, private_key),
+            SignatureScheme::Schnorr => Self::sign_output_schnorr(output_hash, private_key)
+        }
     }
 
     pub fn verify(&self, output_hash: &Hash, public_key: &PublicKey) -> bool {
-        public_key.0.verify(&output_hash.as_bytes(), &self.0).is_ok()
+        match (self, public_key) {
+            (Signature::Ecdsa(signature), PublicKey::Ecdsa(key)) => {
+                key.verify(&output_hash.as_bytes(), signature).is_ok()
+            }
+            (Signature::Schnorr(signature), PublicKey::Schnorr(key)) => {
+                key.verify_raw(&output_hash.as_bytes(), signature).is_ok()
+            }
+            _ => false
+        }
+    }
+
+    ///Whether an ECDSA signature is in low-S form. BIP340 signatures have no
+    ///equivalent malleability and always count as canonical
+    pub fn is_low_s(&self) -> bool {
+        match self {
+            Signature::Ecdsa(signature) => signature.normalize_s().is_none(),
+            Signature::Schnorr(_) => true
+        }
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Signature::Ecdsa(_) => SignatureScheme::Ecdsa,
+            Signature::Schnorr(_) => SignatureScheme::Schnorr
+        }
+    }
+
+    ///64 bytes: r || s for ECDSA, BIP340 encoding for Schnorr
+    pub fn to_bytes(&self) -> [u8; 64] {
+        match self {
+            Signature::Ecdsa(signature) => signature.to_bytes().into(),
+            Signature::Schnorr(signature) => signature.to_bytes()
+        }
+    }
+
+    pub fn from_bytes(scheme: SignatureScheme, bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 64 {
+            return Err(BtcError::InvalidSignature)
+        }
+
+        match scheme {
+            SignatureScheme::Ecdsa => ECDSASignature::from_slice(bytes)
+                .map(Signature::Ecdsa)
+                .map_err(|_| BtcError::InvalidSignature),
+            SignatureScheme::Schnorr => SchnorrSignature::try_from(bytes)
+                .map(Signature::Schnorr)
+                .map_err(|_| BtcError::InvalidSignature)
+        }
     }
 }
 
 impl Debug for Signature {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "ecdsa::Signature<{:?}>(", Secp256k1::default())?;
+        match self {
+            Signature::Ecdsa(_) => write!(f, "ecdsa::Signature<{:?}>(", Secp256k1)?,
+            Signature::Schnorr(_) => write!(f, "schnorr::Signature<{:?}>(", Secp256k1)?
+        }
 
-        for byte in self.0.to_bytes() {
+        for byte in self.to_bytes() {
             write!(f, "{:02X}", byte)?;
         }
 
@@ -40,8 +150,80 @@ impl Debug for Signature {
     }
 }
 
+///A single (message, signature, key) triple collected for deferred verification
+#[derive(Debug, Serialize, Clone)]
+pub struct SignatureCheck {
+    pub message: Hash,
+    pub signature: Signature,
+    pub public_key: PublicKey
+}
+
+impl SignatureCheck {
+    pub fn new(message: Hash, signature: Signature, public_key: PublicKey) -> Self {
+        SignatureCheck {
+            message,
+            signature,
+            public_key
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        self.signature.verify(&self.message, &self.public_key)
+    }
+}
+
+///Verifies the checks one after another, stopping at the first invalid one
+pub fn verify_serial(checks: &[SignatureCheck]) -> bool {
+    checks.iter().all(SignatureCheck::verify)
+}
+
+///Verifies the checks in parallel on the rayon thread pool.
+///k256 has no BIP340 batch verification, so Schnorr checks are verified
+///individually like ECDSA ones, only spread across threads
+pub fn verify_batch(checks: &[SignatureCheck]) -> bool {
+    checks.par_iter().all(SignatureCheck::verify)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct PublicKey(VerifyingKey<Secp256k1>);
+pub enum PublicKey {
+    Ecdsa(VerifyingKey<Secp256k1>),
+    ///x-only key as defined in BIP340
+    Schnorr(SchnorrVerifyingKey)
+}
+
+impl PublicKey {
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            PublicKey::Ecdsa(_) => SignatureScheme::Ecdsa,
+            PublicKey::Schnorr(_) => SignatureScheme::Schnorr
+        }
+    }
+
+    ///Parses a 32-byte BIP340 x-only public key
+    pub fn schnorr_from_bytes(bytes: &[u8]) -> Result<Self> {
+        SchnorrVerifyingKey::from_bytes(bytes)
+            .map(PublicKey::Schnorr)
+            .map_err(|_| BtcError::InvalidPublicKey)
+    }
+
+    ///33-byte compressed SEC1 encoding for ECDSA keys, 32-byte x-only for Schnorr
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            PublicKey::Ecdsa(key) => key.to_encoded_point(true).as_bytes().to_vec(),
+            PublicKey::Schnorr(key) => key.to_bytes().to_vec()
+        }
+    }
+
+    ///Parses either encoding produced by to_bytes, telling them apart by length
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.len() {
+            32 => Self::schnorr_from_bytes(bytes),
+            _ => VerifyingKey::from_sec1_bytes(bytes)
+                .map(PublicKey::Ecdsa)
+                .map_err(|_| BtcError::InvalidPublicKey)
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct PrivateKey(
@@ -50,13 +232,35 @@ pub struct PrivateKey(
 
 ///Used for generating PubKey
 impl PrivateKey {
-    ///
     pub fn new_key() -> Self {
         PrivateKey(SigningKey::random(&mut rand::thread_rng()))
     }
 
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        SigningKey::from_slice(bytes)
+            .map(PrivateKey)
+            .map_err(|_| BtcError::InvalidPrivateKey)
+    }
+
+    ///ECDSA public key
     pub fn public_key(&self) -> PublicKey {
-        PublicKey(VerifyingKey::from(self.0.clone()))
+        PublicKey::Ecdsa(VerifyingKey::from(self.0.clone()))
+    }
+
+    ///x-only public key for locking outputs to Schnorr signatures
+    pub fn schnorr_public_key(&self) -> PublicKey {
+        PublicKey::Schnorr(*self.schnorr_signing_key().verifying_key())
+    }
+
+    pub fn public_key_for(&self, scheme: SignatureScheme) -> PublicKey {
+        match scheme {
+            SignatureScheme::Ecdsa => self.public_key(),
+            SignatureScheme::Schnorr => self.schnorr_public_key()
+        }
+    }
+
+    fn schnorr_signing_key(&self) -> SchnorrSigningKey {
+        SchnorrSigningKey::from(*self.0.as_nonzero_scalar())
     }
 }
 
@@ -83,3 +287,29 @@ mod signkey_serde {
     }
 }
 
+mod schnorr_signature_serde {
+    use serde::Deserialize;
+    pub fn serialize<S>(
+        signature: &super::SchnorrSignature,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(&signature.to_bytes())
+    }
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<super::SchnorrSignature, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let bytes: Vec<u8> =
+            Vec::<u8>::deserialize(deserializer)?;
+        if bytes.len() != super::SchnorrSignature::BYTE_SIZE {
+            return Err(serde::de::Error::custom("invalid schnorr signature length"));
+        }
+        super::SchnorrSignature::try_from(bytes.as_slice())
+            .map_err(serde::de::Error::custom)
+    }
+}
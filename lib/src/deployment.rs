@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+
+//BIP9 style version bits: the top three bits mark a signalling version,
+//the remaining 29 bits are one per deployment
+pub const VERSIONBITS_TOP_BITS: u32 = 0x2000_0000;
+pub const VERSIONBITS_TOP_MASK: u32 = 0xe000_0000;
+pub const VERSIONBITS_NUM_BITS: u8 = 29;
+
+///Deployments tracked by default, not gating any rule yet
+pub const DEPLOYMENTS: &[Deployment] = &[Deployment {
+    name: "testdummy",
+    bit: 28,
+    start_height: 0,
+    timeout_height: u64::MAX
+}];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeploymentState {
+    ///Waiting for start_height
+    Defined,
+    ///Blocks signal for it
+    Started,
+    ///Enough blocks signalled, activates at the next period
+    LockedIn,
+    ///Its rules are enforced
+    Active,
+    ///Timed out without locking in
+    Failed
+}
+
+///Soft fork whose activation is signalled by miners in block versions. The length of
+///the signalling periods and the threshold are the chain's, see ChainParams
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deployment {
+    pub name: &'static str,
+    ///Version bit miners set to signal readiness
+    pub bit: u8,
+    ///Signalling starts with the first period at or after this height
+    pub start_height: u64,
+    ///Signalling fails with the first period at or after this height
+    pub timeout_height: u64
+}
+
+impl Deployment {
+    pub fn mask(&self) -> u32 {
+        1 << self.bit
+    }
+
+    ///Whether a block version signals for the deployment
+    pub fn signals(&self, version: u32) -> bool {
+        version & VERSIONBITS_TOP_MASK == VERSIONBITS_TOP_BITS && version & self.mask() != 0
+    }
+
+    ///State of the period starting at period_start, given the previous period's
+    ///state and how many of its blocks signalled, threshold of them locking it in
+    pub fn next_state(
+        &self,
+        previous: DeploymentState,
+        period_start: u64,
+        signalling: u64,
+        threshold: u64
+    ) -> DeploymentState {
+        match previous {
+            DeploymentState::Defined => {
+                if period_start >= self.timeout_height {
+                    DeploymentState::Failed
+                } else if period_start >= self.start_height {
+                    DeploymentState::Started
+                } else {
+                    DeploymentState::Defined
+                }
+            }
+            //lock in wins over a timeout in the same period
+            DeploymentState::Started => {
+                if signalling >= threshold {
+                    DeploymentState::LockedIn
+                } else if period_start >= self.timeout_height {
+                    DeploymentState::Failed
+                } else {
+                    DeploymentState::Started
+                }
+            }
+            DeploymentState::LockedIn => DeploymentState::Active,
+            DeploymentState::Active => DeploymentState::Active,
+            DeploymentState::Failed => DeploymentState::Failed
+        }
+    }
+}
+
+pub fn default_deployments() -> Vec<Deployment> {
+    DEPLOYMENTS.to_vec()
+}
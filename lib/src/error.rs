@@ -1,4 +1,5 @@
 use thiserror::Error;
+use crate::script::ScriptError;
 
 #[derive(Debug, Error)]
 pub enum BtcError {
@@ -18,10 +19,34 @@ pub enum BtcError {
     InvalidHash,
     #[error("Invalid signature")]
     InvalidSignature,
+    #[error("Non-canonical (high-S) signature")]
+    NonCanonicalSignature,
     #[error("Invalid public key")]
     InvalidPublicKey,
     #[error("Invalid private key")]
     InvalidPrivateKey,
+    #[error("Previous block is unknown")]
+    MissingPreviousBlock,
+    #[error("Transaction spends outputs that are unknown or already spent")]
+    MissingInputs,
+    #[error("Mempool is full and the transaction pays too low a fee rate")]
+    MempoolFull,
+    #[error("Chain does not start with the network's genesis block")]
+    GenesisMismatch,
+    #[error("Invalid address")]
+    InvalidAddress,
+    #[error("Block exceeds the size, weight or signature operation limit")]
+    BlockLimitsExceeded,
+    #[error("Invalid witness commitment")]
+    InvalidWitnessCommitment,
+    #[error("Transaction is not final")]
+    NonFinalTransaction,
+    #[error("Relative lock time of an input not satisfied")]
+    UnsatisfiedSequenceLock,
+    #[error("Non-standard transaction: {0}")]
+    NonStandardTransaction(&'static str),
+    #[error("Script verification failed: {0}")]
+    InvalidScript(#[from] ScriptError),
 }
 
 pub type Result<T> = std::result::Result<T, BtcError>;
\ No newline at end of file
@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+use crate::U256;
+use crate::error::{BtcError, Result};
+use crate::params::ChainParams;
+use crate::sha256::Hash;
+use crate::types::{BlockHeader, Blockchain};
+
+///Most headers in one headers message, a full one means more may follow
+pub const MAX_HEADERS_RESULTS: usize = 2_000;
+
+//locator entries before the steps between them start doubling
+const LOCATOR_DENSE_ENTRIES: usize = 10;
+
+///Hashes describing a chain whose tip is at height: the latest blocks one by one,
+///then exponentially sparser, always ending with the genesis block. The peer
+///answers from the first hash it knows, so a fork is found in few round trips
+pub fn locator(height: u64, hash_at: impl Fn(u64) -> Hash) -> Vec<Hash> {
+    let mut hashes = Vec::new();
+    let mut height = height;
+    let mut step = 1;
+
+    loop {
+        hashes.push(hash_at(height));
+        if height == 0 {
+            return hashes
+        }
+
+        if hashes.len() >= LOCATOR_DENSE_ENTRIES {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+}
+
+///A header accepted into the tree
+#[derive(Clone, Debug)]
+pub struct HeaderEntry {
+    pub header: BlockHeader,
+    pub height: u64,
+    ///Work of the chain up to and including this header
+    pub chain_work: U256
+}
+
+///Validated block headers of every known branch, so that chains can be
+///compared by their work before any of their blocks are downloaded
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    params: ChainParams,
+    entries: HashMap<Hash, HeaderEntry>,
+    ///Hashes of the best chain by height
+    best: Vec<Hash>,
+    ///Headers whose blocks failed validation, their descendants are refused with them
+    invalid: HashSet<Hash>
+}
+
+impl HeaderChain {
+    ///Tree holding the headers of the chain's blocks, which are its best chain
+    pub fn new(chain: &Blockchain) -> Self {
+        let mut headers = HeaderChain {
+            params: chain.params().clone(),
+            entries: HashMap::new(),
+            best: Vec::new(),
+            invalid: HashSet::new()
+        };
+
+        let mut chain_work = U256::zero();
+        for (height, block) in chain.blocks.iter().enumerate() {
+            chain_work = chain_work.saturating_add(block.header().work());
+            let entry = HeaderEntry { header: block.header().clone(), height: height as u64, chain_work };
+
+            headers.entries.insert(block.hash(), entry);
+            headers.best.push(block.hash());
+        }
+
+        headers
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&HeaderEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn best_height(&self) -> u64 {
+        self.best.len() as u64 - 1
+    }
+
+    pub fn best_tip(&self) -> &HeaderEntry {
+        &self.entries[self.best.last().expect("the genesis header is always known")]
+    }
+
+    ///Hash of the best chain's header at height
+    pub fn hash_at(&self, height: u64) -> Option<Hash> {
+        self.best.get(height as usize).copied()
+    }
+
+    pub fn is_on_best_chain(&self, hash: &Hash) -> bool {
+        self.entries
+            .get(hash)
+            .is_some_and(|entry| self.hash_at(entry.height) == Some(*hash))
+    }
+
+    ///Locator of the best chain, asking for the headers that extend it
+    pub fn locator(&self) -> Vec<Hash> {
+        locator(self.best_height(), |height| self.best[height as usize])
+    }
+
+    ///Hash of the header at height on the branch ending with hash
+    fn ancestor(&self, hash: &Hash, height: u64) -> Option<Hash> {
+        let mut hash = *hash;
+        let mut entry = self.entries.get(&hash)?;
+
+        while entry.height > height {
+            //once on the best chain the rest is a lookup
+            if self.hash_at(entry.height) == Some(hash) {
+                return self.hash_at(height)
+            }
+            hash = entry.header.prev_block_hash;
+            entry = self.entries.get(&hash)?;
+        }
+
+        (entry.height == height).then_some(hash)
+    }
+
+    ///Validates the header against the branch it extends: link, timestamp,
+    ///target including retargeting, and proof of work. The best chain moves
+    ///to it when its branch has more work. Returns whether it was new
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<bool> {
+        let hash = header.hash();
+        if self.entries.contains_key(&hash) {
+            return Ok(false)
+        }
+
+        if self.invalid.contains(&hash) || self.invalid.contains(&header.prev_block_hash) {
+            return Err(BtcError::InvalidBlockHeader)
+        }
+
+        let parent = self
+            .entries
+            .get(&header.prev_block_hash)
+            .ok_or(BtcError::MissingPreviousBlock)?;
+        let height = parent.height + 1;
+        let chain_work = parent.chain_work.saturating_add(header.work());
+
+        if header.timestamp <= parent.header.timestamp {
+            return Err(BtcError::InvalidBlockHeader)
+        }
+
+        let target = self.params.next_target(height, |ancestor_height| {
+            let ancestor = self
+                .ancestor(&header.prev_block_hash, ancestor_height)
+                .expect("every ancestor of a known header is known");
+            &self.entries[&ancestor].header
+        });
+
+        if header.target != target || !hash.matches_target(header.target) {
+            return Err(BtcError::InvalidBlockHeader)
+        }
+
+        self.entries.insert(hash, HeaderEntry { header, height, chain_work });
+        if chain_work > self.best_tip().chain_work {
+            self.set_best(hash);
+        }
+
+        Ok(true)
+    }
+
+    ///Forgets the header and its descendants after its block failed validation,
+    ///the best chain falls back to the remaining branch with the most work
+    pub fn invalidate(&mut self, hash: &Hash) {
+        let Some(height) = self.entries.get(hash).map(|entry| entry.height) else {
+            return
+        };
+        if height == 0 {
+            return
+        }
+
+        let descendants: Vec<Hash> = self
+            .entries
+            .iter()
+            .filter(|(other, entry)| {
+                entry.height >= height && self.ancestor(other, height) == Some(*hash)
+            })
+            .map(|(other, _)| *other)
+            .collect();
+
+        for descendant in &descendants {
+            self.entries.remove(descendant);
+        }
+        self.invalid.insert(*hash);
+
+        if self.hash_at(height) == Some(*hash) {
+            self.best.truncate(height as usize);
+            let best = self
+                .entries
+                .iter()
+                .max_by_key(|(_, entry)| entry.chain_work)
+                .map(|(hash, _)| *hash)
+                .expect("the genesis header is always known");
+            self.set_best(best);
+        }
+    }
+
+    fn set_best(&mut self, tip: Hash) {
+        let mut branch = Vec::new();
+        let mut hash = tip;
+        let mut height = self.entries[&hash].height;
+
+        //walk back to where the branch joins the current best chain
+        while self.hash_at(height) != Some(hash) {
+            branch.push(hash);
+            hash = self.entries[&hash].header.prev_block_hash;
+            height -= 1;
+        }
+
+        self.best.truncate(height as usize + 1);
+        self.best.extend(branch.into_iter().rev());
+    }
+}
@@ -0,0 +1,152 @@
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::crypto::{PrivateKey, PublicKey, Signature};
+use crate::script::{self, Op, Script};
+use crate::sha256::Hash;
+use crate::timelock::SEQUENCE_FINAL;
+use crate::types::{LockingCondition, Transaction, TransactionInput};
+
+//fixed preimage size, so a preimage valid on one chain can't be too large for the other
+pub const PREIMAGE_SIZE: usize = 32;
+
+pub type Preimage = [u8; PREIMAGE_SIZE];
+
+pub fn new_preimage() -> Preimage {
+    let mut preimage = [0u8; PREIMAGE_SIZE];
+    rand::thread_rng().fill_bytes(&mut preimage);
+    preimage
+}
+
+pub fn payment_hash(preimage: &Preimage) -> [u8; 32] {
+    Sha256::digest(preimage).into()
+}
+
+///Hash time-locked contract: the recipient can claim the output by revealing the
+///preimage of payment_hash, the refund key can take it back once timeout passed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Htlc {
+    pub payment_hash: [u8; 32],
+    pub recipient: PublicKey,
+    pub refund: PublicKey,
+    ///Absolute lock time (height or timestamp) from which the refund path is open
+    pub timeout: u64
+}
+
+impl Htlc {
+    pub fn new(payment_hash: [u8; 32], recipient: PublicKey, refund: PublicKey, timeout: u64) -> Self {
+        Htlc {
+            payment_hash,
+            recipient,
+            refund,
+            timeout
+        }
+    }
+
+    ///OP_IF OP_SIZE 32 OP_EQUALVERIFY OP_SHA256 <payment_hash> OP_EQUALVERIFY <recipient>
+    ///OP_ELSE <timeout> OP_CHECKLOCKTIMEVERIFY OP_DROP <refund> OP_ENDIF OP_CHECKSIG
+    pub fn script(&self) -> Script {
+        Script::new(vec![
+            Op::If,
+            Op::Size,
+            Op::push_int(PREIMAGE_SIZE as i64),
+            Op::EqualVerify,
+            Op::Sha256,
+            Op::Push(self.payment_hash.to_vec()),
+            Op::EqualVerify,
+            Op::Push(self.recipient.to_bytes()),
+            Op::Else,
+            Op::push_int(self.timeout as i64),
+            Op::CheckLockTimeVerify,
+            Op::Drop,
+            Op::Push(self.refund.to_bytes()),
+            Op::EndIf,
+            Op::CheckSig
+        ])
+    }
+
+    pub fn lock(&self) -> LockingCondition {
+        LockingCondition::Script(self.script())
+    }
+
+    ///Recognizes a locking script built by Htlc::script
+    pub fn from_script(script: &Script) -> Option<Self> {
+        let [
+            Op::If, Op::Size, Op::Push(_), Op::EqualVerify, Op::Sha256, Op::Push(payment_hash),
+            Op::EqualVerify, Op::Push(recipient), Op::Else, Op::Push(timeout),
+            Op::CheckLockTimeVerify, Op::Drop, Op::Push(refund), Op::EndIf, Op::CheckSig
+        ] = script.ops() else {
+            return None
+        };
+
+        let htlc = Htlc {
+            payment_hash: payment_hash.as_slice().try_into().ok()?,
+            recipient: PublicKey::from_bytes(recipient).ok()?,
+            refund: PublicKey::from_bytes(refund).ok()?,
+            timeout: u64::try_from(script::decode_num(timeout, 5).ok()?).ok()?
+        };
+
+        (htlc.script() == *script).then_some(htlc)
+    }
+
+    pub fn from_lock(lock: &LockingCondition) -> Option<Self> {
+        match lock {
+            LockingCondition::Script(script) => Self::from_script(script),
+            _ => None
+        }
+    }
+
+    ///Unsigned input claiming the HTLC output, completed by sign_claim once the
+    ///spending transaction is built
+    pub fn claim_input(utxo_hash: Hash) -> TransactionInput {
+        TransactionInput::with_script(utxo_hash, Script::default())
+    }
+
+    ///Unsigned input taking the HTLC output back, completed by sign_refund.
+    ///The spending transaction needs a lock time of at least timeout
+    pub fn refund_input(utxo_hash: Hash) -> TransactionInput {
+        let mut input = TransactionInput::with_script(utxo_hash, Script::default());
+        //non-final, so the lock time is enforced
+        input.sequence = SEQUENCE_FINAL - 1;
+        input
+    }
+
+    ///Reveals the preimage in the input at index and signs it by the recipient.
+    ///The signature commits to the whole transaction, so the preimage can't be
+    ///reused by someone else for a transaction paying them
+    pub fn sign_claim(&self, transaction: &mut Transaction, index: usize, key: &PrivateKey, preimage: &Preimage) {
+        let signature = Self::sign(transaction, index, key, &self.recipient);
+
+        transaction.inputs_mut()[index].script_sig = Script::new(vec![
+            Op::Push(signature.to_bytes().to_vec()),
+            Op::Push(preimage.to_vec()),
+            Op::push_int(1)
+        ]);
+    }
+
+    ///Signs the input at index by the refund key, the transaction's lock time
+    ///and the input's sequence have to be set before
+    pub fn sign_refund(&self, transaction: &mut Transaction, index: usize, key: &PrivateKey) {
+        let signature = Self::sign(transaction, index, key, &self.refund);
+
+        transaction.inputs_mut()[index].script_sig = Script::new(vec![
+            Op::Push(signature.to_bytes().to_vec()),
+            Op::push_int(0)
+        ]);
+    }
+
+    fn sign(transaction: &Transaction, index: usize, key: &PrivateKey, pubkey: &PublicKey) -> Signature {
+        let sighash = transaction.sighash(&transaction.inputs()[index].prev_transaction_output_hash);
+        Signature::sign_output_for(&sighash, key, pubkey)
+    }
+}
+
+///Preimage revealed by an input claiming an HTLC with the payment hash, if it is one
+pub fn extract_preimage(input: &TransactionInput, payment_hash: &[u8; 32]) -> Option<Preimage> {
+    let [Op::Push(_), Op::Push(preimage), Op::Push(_)] = input.script_sig.ops() else {
+        return None
+    };
+
+    let preimage: Preimage = preimage.as_slice().try_into().ok()?;
+    (self::payment_hash(&preimage) == *payment_hash).then_some(preimage)
+}
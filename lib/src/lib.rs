@@ -1,31 +1,39 @@
-mod sha256;
-mod types;
-mod util;
-mod crypto;
-mod error;
+pub mod sha256;
+pub mod types;
+pub mod util;
+pub mod crypto;
+pub mod error;
+pub mod sigcache;
+pub mod message;
+pub mod script;
+pub mod timelock;
+pub mod htlc;
+pub mod policy;
+pub mod deployment;
+pub mod params;
+pub mod address;
+pub mod network;
+pub mod headers;
+pub mod addrman;
+pub mod banman;
+pub mod orphan;
+pub mod compact;
 
-use serde::{Deserialize, Serialize};
-use uint::construct_uint;
+// the expansion of construct_uint! trips clippy::manual_div_ceil
+#[allow(clippy::manual_div_ceil)]
+mod u256 {
+    use serde::{Deserialize, Serialize};
+    use uint::construct_uint;
 
-construct_uint! {
-    #[derive(Serialize, Deserialize)]
-    pub struct U256(4);
+    construct_uint! {
+        #[derive(Serialize, Deserialize)]
+        pub struct U256(4);
+    }
 }
 
-// initial reward in bitcoin - multiply by 10^8 to get satoshis
-pub const INITIAL_REWARD: u64 = 50;
+pub use u256::U256;
 
-// halving interval in blocks
-pub const HALVING_INTERVAL: u64 = 210;
-
-pub const IDEAL_BLOCK_TIME: u64 = 10;
-
-// minimum target
-pub const MIN_TARGET: U256 = U256([
-    0xFFFF_FFFF_FFFF_FFFF,
-    0xFFFF_FFFF_FFFF_FFFF,
-    0xFFFF_FFFF_FFFF_FFFF,
-    0x0000_FFFF_FFFF_FFFF,
-]);
-
-pub const DIFFICULTY_UPDATE_INTERVAL: u64 = 50;
\ No newline at end of file
+// block limits, weight counts non-witness bytes four times and size every byte once
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+pub const MAX_BLOCK_SIGOPS: usize = 20_000;
\ No newline at end of file
@@ -0,0 +1,111 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ecdsa::{RecoveryId, Signature as ECDSASignature, VerifyingKey};
+use k256::Secp256k1;
+use crate::crypto::{PrivateKey, PublicKey};
+use crate::error::{BtcError, Result};
+use crate::sha256::Hash;
+
+///Prefix hashed together with every signed message, so a message signature
+///can never be replayed as a signature over a transaction output
+pub const MESSAGE_MAGIC: &str = "rsbtc Signed Message:\n";
+
+//header byte of the text format, as in Bitcoin: 27 + recovery id + 4 for compressed keys
+const HEADER_BASE: u8 = 27 + 4;
+
+///Recoverable ECDSA signature over a domain separated message hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageSignature {
+    signature: ECDSASignature<Secp256k1>,
+    recovery_id: RecoveryId
+}
+
+pub fn message_hash(message: &str) -> Hash {
+    Hash::hash(&(MESSAGE_MAGIC, message))
+}
+
+///Signs the message to prove ownership of the private key's public key
+pub fn sign_message(private_key: &PrivateKey, message: &str) -> MessageSignature {
+    let (signature, recovery_id) = private_key
+        .0
+        .sign_prehash_recoverable(&message_hash(message).as_bytes())
+        .expect("signing a 32 byte prehash should not fail");
+
+    MessageSignature {
+        signature,
+        recovery_id
+    }
+}
+
+///Checks that the message was signed by the owner of the public key.
+///Works for Schnorr keys as well, by comparing x-only coordinates
+pub fn verify_message(
+    public_key: &PublicKey,
+    message: &str,
+    signature: &MessageSignature
+) -> bool {
+    let Ok(recovered) = signature.recover(message) else {
+        return false
+    };
+
+    match public_key {
+        PublicKey::Ecdsa(_) => recovered == *public_key,
+        PublicKey::Schnorr(_) => {
+            let x_only = &recovered.to_bytes()[1..];
+            PublicKey::schnorr_from_bytes(x_only).is_ok_and(|key| key == *public_key)
+        }
+    }
+}
+
+impl MessageSignature {
+    ///Recovers the ECDSA public key that produced the signature over the message
+    pub fn recover(&self, message: &str) -> Result<PublicKey> {
+        VerifyingKey::recover_from_prehash(
+            &message_hash(message).as_bytes(),
+            &self.signature,
+            self.recovery_id
+        )
+        .map(PublicKey::Ecdsa)
+        .map_err(|_| BtcError::InvalidSignature)
+    }
+
+    ///65 bytes: header || r || s
+    pub fn to_bytes(&self) -> [u8; 65] {
+        let mut bytes = [0u8; 65];
+        bytes[0] = HEADER_BASE + self.recovery_id.to_byte();
+        bytes[1..].copy_from_slice(&self.signature.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != 65 {
+            return Err(BtcError::InvalidSignature)
+        }
+
+        let recovery_id = bytes[0]
+            .checked_sub(HEADER_BASE)
+            .and_then(RecoveryId::from_byte)
+            .ok_or(BtcError::InvalidSignature)?;
+
+        let signature = ECDSASignature::from_slice(&bytes[1..])
+            .map_err(|_| BtcError::InvalidSignature)?;
+
+        Ok(MessageSignature {
+            signature,
+            recovery_id
+        })
+    }
+
+    ///Compact text format, base64 of the 65 byte encoding
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.to_bytes())
+    }
+
+    pub fn from_base64(text: &str) -> Result<Self> {
+        let bytes = BASE64
+            .decode(text.trim())
+            .map_err(|_| BtcError::InvalidSignature)?;
+
+        Self::from_bytes(&bytes)
+    }
+}
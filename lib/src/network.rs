@@ -0,0 +1,262 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::compact::{BlockTransactions, BlockTransactionsRequest, CompactBlock};
+use crate::headers::MAX_HEADERS_RESULTS;
+use crate::sha256::Hash;
+use crate::types::{Block, BlockHeader, Transaction};
+use crate::util::checksum;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+//frame header: magic, zero padded command, payload length, payload checksum
+pub const COMMAND_SIZE: usize = 12;
+pub const HEADER_SIZE: usize = 4 + COMMAND_SIZE + 4 + 4;
+
+///Largest payload accepted, a full block or inventory with room for its encoding
+pub const MAX_PAYLOAD_SIZE: usize = 4 * crate::MAX_BLOCK_SIZE;
+///Most entries in one inv, getdata or notfound message
+pub const MAX_INVENTORY_SIZE: usize = 50_000;
+///Most addresses in one addr message
+pub const MAX_ADDR_SIZE: usize = 1_000;
+///Most hashes in a locator, more than any u64 height needs
+pub const MAX_LOCATOR_SIZE: usize = 101;
+
+///Reference to a block or transaction, announced before the object itself is sent
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Inventory {
+    Transaction(Hash),
+    Block(Hash)
+}
+
+///First message on a connection, both sides send one before anything else
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub version: u32,
+    pub timestamp: i64,
+    ///Random per node, detects connections to ourselves
+    pub nonce: u64,
+    pub user_agent: String,
+    ///Height of the sender's chain
+    pub height: u64,
+    ///Port the sender accepts connections on, 0 if it doesn't
+    #[serde(default)]
+    pub port: u16,
+    ///Peers on another chain are of no use even with the right magic
+    pub genesis: Hash
+}
+
+///Address of a node accepting connections, as gossiped in addr messages
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetAddress {
+    pub addr: SocketAddr,
+    ///Last time the node was heard of, in unix seconds
+    pub timestamp: i64
+}
+
+///Asks for the headers following the first locator hash the peer knows
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GetHeaders {
+    pub locator: Vec<Hash>,
+    ///Last header wanted, zero for as many as fit in one message
+    pub stop: Hash
+}
+
+#[derive(Clone, Debug)]
+pub enum Message {
+    Version(Version),
+    ///Accepts the peer's version, completing its side of the handshake
+    Verack,
+    Ping(u64),
+    ///Answers the ping with the same nonce
+    Pong(u64),
+    ///Announces objects the sender has
+    Inv(Vec<Inventory>),
+    ///Requests announced objects
+    GetData(Vec<Inventory>),
+    ///Answers the requested objects the sender doesn't have
+    NotFound(Vec<Inventory>),
+    ///Asks for addresses of other nodes
+    GetAddr,
+    Addr(Vec<NetAddress>),
+    GetHeaders(GetHeaders),
+    ///Answers a getheaders with up to MAX_HEADERS_RESULTS consecutive headers
+    Headers(Vec<BlockHeader>),
+    Block(Block),
+    Tx(Transaction),
+    ///Asks to have new blocks pushed as compact blocks rather than announced
+    SendCmpct(bool),
+    CmpctBlock(CompactBlock),
+    ///Asks for the transactions a compact block couldn't be completed without
+    GetBlockTxn(BlockTransactionsRequest),
+    BlockTxn(BlockTransactions)
+}
+
+impl Message {
+    pub fn command(&self) -> &'static str {
+        match self {
+            Message::Version(_) => "version",
+            Message::Verack => "verack",
+            Message::Ping(_) => "ping",
+            Message::Pong(_) => "pong",
+            Message::Inv(_) => "inv",
+            Message::GetData(_) => "getdata",
+            Message::NotFound(_) => "notfound",
+            Message::GetAddr => "getaddr",
+            Message::Addr(_) => "addr",
+            Message::GetHeaders(_) => "getheaders",
+            Message::Headers(_) => "headers",
+            Message::Block(_) => "block",
+            Message::Tx(_) => "tx",
+            Message::SendCmpct(_) => "sendcmpct",
+            Message::CmpctBlock(_) => "cmpctblock",
+            Message::GetBlockTxn(_) => "getblocktxn",
+            Message::BlockTxn(_) => "blocktxn"
+        }
+    }
+
+    fn payload(&self) -> IoResult<Vec<u8>> {
+        match self {
+            Message::Version(version) => to_cbor(version),
+            Message::Verack | Message::GetAddr => Ok(Vec::new()),
+            Message::Ping(nonce) | Message::Pong(nonce) => to_cbor(nonce),
+            Message::Inv(inventory) | Message::GetData(inventory) | Message::NotFound(inventory) => {
+                to_cbor(inventory)
+            }
+            Message::Addr(addresses) => to_cbor(addresses),
+            Message::GetHeaders(get_headers) => to_cbor(get_headers),
+            Message::Headers(headers) => to_cbor(headers),
+            Message::Block(block) => to_cbor(block),
+            Message::Tx(transaction) => to_cbor(transaction),
+            Message::SendCmpct(enabled) => to_cbor(enabled),
+            Message::CmpctBlock(compact) => to_cbor(compact),
+            Message::GetBlockTxn(request) => to_cbor(request),
+            Message::BlockTxn(transactions) => to_cbor(transactions)
+        }
+    }
+
+    fn from_payload(command: &str, payload: &[u8]) -> IoResult<Self> {
+        let message = match command {
+            "version" => Message::Version(from_cbor(payload)?),
+            "verack" => Message::Verack,
+            "ping" => Message::Ping(from_cbor(payload)?),
+            "pong" => Message::Pong(from_cbor(payload)?),
+            "inv" => Message::Inv(inventory(payload)?),
+            "getdata" => Message::GetData(inventory(payload)?),
+            "notfound" => Message::NotFound(inventory(payload)?),
+            "getaddr" => Message::GetAddr,
+            "addr" => {
+                let addresses: Vec<NetAddress> = from_cbor(payload)?;
+                if addresses.len() > MAX_ADDR_SIZE {
+                    return Err(invalid_data("too many addresses".to_string()))
+                }
+                Message::Addr(addresses)
+            }
+            "getheaders" => {
+                let get_headers: GetHeaders = from_cbor(payload)?;
+                if get_headers.locator.len() > MAX_LOCATOR_SIZE {
+                    return Err(invalid_data("locator too long".to_string()))
+                }
+                Message::GetHeaders(get_headers)
+            }
+            "headers" => {
+                let headers: Vec<BlockHeader> = from_cbor(payload)?;
+                if headers.len() > MAX_HEADERS_RESULTS {
+                    return Err(invalid_data("too many headers".to_string()))
+                }
+                Message::Headers(headers)
+            }
+            "block" => Message::Block(from_cbor(payload)?),
+            "tx" => Message::Tx(from_cbor(payload)?),
+            "sendcmpct" => Message::SendCmpct(from_cbor(payload)?),
+            "cmpctblock" => Message::CmpctBlock(from_cbor(payload)?),
+            "getblocktxn" => Message::GetBlockTxn(from_cbor(payload)?),
+            "blocktxn" => Message::BlockTxn(from_cbor(payload)?),
+            _ => return Err(invalid_data(format!("unknown command {command}")))
+        };
+
+        Ok(message)
+    }
+
+    ///Frames the message for the network identified by magic
+    pub fn encode(&self, magic: [u8; 4]) -> IoResult<Vec<u8>> {
+        let payload = self.payload()?;
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(invalid_data("payload too large".to_string()))
+        }
+
+        let mut command = [0u8; COMMAND_SIZE];
+        command[..self.command().len()].copy_from_slice(self.command().as_bytes());
+
+        let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+        frame.extend(magic);
+        frame.extend(command);
+        frame.extend((payload.len() as u32).to_le_bytes());
+        frame.extend(checksum(&payload));
+        frame.extend(payload);
+        Ok(frame)
+    }
+
+    pub async fn send<W: AsyncWrite + Unpin>(&self, writer: &mut W, magic: [u8; 4]) -> IoResult<()> {
+        writer.write_all(&self.encode(magic)?).await?;
+        writer.flush().await
+    }
+
+    ///Reads the next frame, failing on another network's magic, oversized
+    ///payloads, checksum mismatches and unknown commands
+    pub async fn receive<R: AsyncRead + Unpin>(reader: &mut R, magic: [u8; 4]) -> IoResult<Self> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header).await?;
+
+        if header[..4] != magic {
+            return Err(invalid_data("wrong network magic".to_string()))
+        }
+
+        let command = &header[4..4 + COMMAND_SIZE];
+        let command_end = command.iter().position(|byte| *byte == 0).unwrap_or(COMMAND_SIZE);
+        if command[command_end..].iter().any(|byte| *byte != 0) {
+            return Err(invalid_data("malformed command".to_string()))
+        }
+        let command = std::str::from_utf8(&command[..command_end])
+            .map_err(|_| invalid_data("malformed command".to_string()))?;
+
+        let length = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+        if length > MAX_PAYLOAD_SIZE {
+            return Err(invalid_data("payload too large".to_string()))
+        }
+
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload).await?;
+
+        if header[20..24] != checksum(&payload) {
+            return Err(invalid_data("checksum mismatch".to_string()))
+        }
+
+        Self::from_payload(command, &payload)
+    }
+}
+
+fn invalid_data(message: String) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, message)
+}
+
+fn to_cbor<T: Serialize>(value: &T) -> IoResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).map_err(|e| invalid_data(e.to_string()))?;
+    Ok(bytes)
+}
+
+fn from_cbor<T: DeserializeOwned>(payload: &[u8]) -> IoResult<T> {
+    ciborium::from_reader(payload).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn inventory(payload: &[u8]) -> IoResult<Vec<Inventory>> {
+    let inventory: Vec<Inventory> = from_cbor(payload)?;
+    if inventory.len() > MAX_INVENTORY_SIZE {
+        return Err(invalid_data("too many inventory entries".to_string()))
+    }
+
+    Ok(inventory)
+}
@@ -0,0 +1,96 @@
+use rand::seq::IteratorRandom;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use crate::policy::MAX_STANDARD_TRANSACTION_WEIGHT;
+use crate::sha256::Hash;
+use crate::types::{Blockchain, Transaction};
+
+///Most orphans kept, a random one makes room for a new one
+pub const MAX_ORPHANS: usize = 100;
+///Seconds an orphan waits for its inputs before it is dropped
+pub const ORPHAN_EXPIRY: i64 = 20 * 60;
+
+struct Orphan {
+    transaction: Transaction,
+    from: Option<SocketAddr>,
+    expires: i64
+}
+
+///Transactions spending outputs we don't know yet, kept until a block or a
+///mempool transaction creates them. Bounded in count and transaction weight
+///since anyone can send them
+#[derive(Default)]
+pub struct OrphanPool {
+    orphans: HashMap<Hash, Orphan>
+}
+
+impl OrphanPool {
+    pub fn new() -> Self {
+        OrphanPool::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    pub fn contains(&self, txid: &Hash) -> bool {
+        self.orphans.contains_key(txid)
+    }
+
+    ///Keeps a transaction from `from` whose inputs are missing. Returns
+    ///whether it was added, known and oversized transactions aren't
+    pub fn add(&mut self, transaction: Transaction, from: Option<SocketAddr>, now: i64) -> bool {
+        let txid = transaction.hash();
+        if self.orphans.contains_key(&txid) || transaction.weight() > MAX_STANDARD_TRANSACTION_WEIGHT {
+            return false
+        }
+
+        self.expire(now);
+        if self.orphans.len() >= MAX_ORPHANS {
+            let evicted = *self.orphans.keys().choose(&mut rand::thread_rng()).unwrap();
+            self.orphans.remove(&evicted);
+        }
+
+        self.orphans.insert(txid, Orphan { transaction, from, expires: now + ORPHAN_EXPIRY });
+        true
+    }
+
+    ///Drops the orphans that waited too long, returns how many
+    pub fn expire(&mut self, now: i64) -> usize {
+        let before = self.orphans.len();
+        self.orphans.retain(|_, orphan| orphan.expires > now);
+        before - self.orphans.len()
+    }
+
+    ///Drops the orphans a disconnected peer sent
+    pub fn forget_peer(&mut self, peer: SocketAddr) {
+        self.orphans.retain(|_, orphan| orphan.from != Some(peer));
+    }
+
+    ///Takes out the orphans whose inputs are all in the chain's UTXO set or
+    ///mempool now, with the peer each came from
+    pub fn take_ready(&mut self, chain: &Blockchain) -> Vec<(Transaction, Option<SocketAddr>)> {
+        let ready: Vec<Hash> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| {
+                orphan
+                    .transaction
+                    .inputs()
+                    .iter()
+                    .all(|input| chain.unspent_output(&input.prev_transaction_output_hash).is_some())
+            })
+            .map(|(txid, _)| *txid)
+            .collect();
+
+        ready
+            .iter()
+            .filter_map(|txid| self.orphans.remove(txid))
+            .map(|orphan| (orphan.transaction, orphan.from))
+            .collect()
+    }
+}
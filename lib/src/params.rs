@@ -0,0 +1,263 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
+use std::path::Path;
+use std::str::FromStr;
+use uuid::Uuid;
+use chrono::{TimeZone, Utc};
+use crate::U256;
+use crate::sha256::Hash;
+use crate::types::{Block, BlockHeader, Transaction, TransactionOutput};
+use crate::util::MerkleRoot;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Main,
+    Test,
+    ///Local testing, blocks are mined instantly
+    Regtest,
+    ///Loaded from a parameter file
+    Custom
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Network::Main => "main",
+            Network::Test => "test",
+            Network::Regtest => "regtest",
+            Network::Custom => "custom"
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "main" => Ok(Network::Main),
+            "test" => Ok(Network::Test),
+            "regtest" => Ok(Network::Regtest),
+            "custom" => Ok(Network::Custom),
+            _ => Err(format!("unknown network {s}, expected main, test, regtest or custom"))
+        }
+    }
+}
+
+///Consensus rules and network identity of a chain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChainParams {
+    pub network: Network,
+    ///Starts every message on the wire, so nodes of different chains can't talk
+    pub magic: [u8; 4],
+    pub default_port: u16,
+    ///Version byte of addresses, makes them unusable on other networks
+    pub address_prefix: u8,
+    ///Initial reward in bitcoin - multiply by 10^8 to get satoshis
+    pub initial_reward: u64,
+    ///Halving interval in blocks
+    pub halving_interval: u64,
+    ///Seconds between blocks retargeting aims for
+    pub ideal_block_time: u64,
+    ///Easiest target a block may have, hex encoded in parameter files
+    #[serde(with = "hex_target")]
+    pub min_target: U256,
+    ///Blocks between target adjustments
+    pub difficulty_update_interval: u64,
+    ///Without it every block keeps min_target
+    pub retarget: bool,
+    ///Blocks per soft fork signalling period
+    pub deployment_period: u64,
+    ///Signalling blocks per period that lock a deployment in
+    pub deployment_threshold: u64,
+    ///Text embedded in the genesis coinbase, makes every network's genesis unique
+    pub genesis_message: String,
+    pub genesis_timestamp: i64,
+    ///Nonce making the genesis header meet min_target, found by mine-genesis
+    pub genesis_nonce: u64
+}
+
+impl ChainParams {
+    pub fn main() -> Self {
+        ChainParams {
+            network: Network::Main,
+            magic: [0xe3, 0x72, 0x73, 0xb1],
+            default_port: 9633,
+            address_prefix: 0x00,
+            initial_reward: 50,
+            halving_interval: 210,
+            ideal_block_time: 10,
+            min_target: U256([
+                0xFFFF_FFFF_FFFF_FFFF,
+                0xFFFF_FFFF_FFFF_FFFF,
+                0xFFFF_FFFF_FFFF_FFFF,
+                0x0000_FFFF_FFFF_FFFF
+            ]),
+            difficulty_update_interval: 50,
+            retarget: true,
+            //aligned with retargeting, 90% have to signal
+            deployment_period: 50,
+            deployment_threshold: 45,
+            genesis_message: "rsbtc main".to_string(),
+            genesis_timestamp: 1_735_689_600,
+            genesis_nonce: MAIN_GENESIS_NONCE
+        }
+    }
+
+    ///Same rules as main, apart from its identity
+    pub fn test() -> Self {
+        ChainParams {
+            network: Network::Test,
+            magic: [0xe3, 0x72, 0x74, 0xb2],
+            default_port: 19633,
+            address_prefix: 0x6f,
+            genesis_message: "rsbtc test".to_string(),
+            genesis_nonce: TEST_GENESIS_NONCE,
+            ..Self::main()
+        }
+    }
+
+    ///Any hash meets the target and it never changes
+    pub fn regtest() -> Self {
+        ChainParams {
+            network: Network::Regtest,
+            magic: [0xe3, 0x72, 0x72, 0xb3],
+            default_port: 19644,
+            address_prefix: 0x6f,
+            halving_interval: 150,
+            min_target: U256::MAX,
+            retarget: false,
+            genesis_message: "rsbtc regtest".to_string(),
+            genesis_timestamp: 1_296_688_602,
+            genesis_nonce: 0,
+            ..Self::main()
+        }
+    }
+
+    ///Presets by network, custom ones only come from parameter files
+    pub fn for_network(network: Network) -> Option<Self> {
+        match network {
+            Network::Main => Some(Self::main()),
+            Network::Test => Some(Self::test()),
+            Network::Regtest => Some(Self::regtest()),
+            Network::Custom => None
+        }
+    }
+
+    ///Reads a TOML parameter file, every field has to be present. Files only
+    ///describe custom networks, the others are presets
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let params: Self = toml::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| IoError::new(IoErrorKind::InvalidData, e.to_string()))?;
+
+        //a file claiming a preset network would give its nodes and addresses different rules
+        if params.network != Network::Custom {
+            return Err(IoError::new(IoErrorKind::InvalidData, "parameter files are for the custom network"))
+        }
+
+        //each of them is a divisor, zero would panic
+        if params.halving_interval == 0
+            || params.ideal_block_time == 0
+            || params.difficulty_update_interval == 0
+            || params.deployment_period == 0 {
+            return Err(IoError::new(IoErrorKind::InvalidData, "intervals and block time must not be zero"))
+        }
+
+        if params.deployment_threshold == 0 || params.deployment_threshold > params.deployment_period {
+            return Err(IoError::new(IoErrorKind::InvalidData, "deployment threshold must be within its period"))
+        }
+
+        Ok(params)
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> IoResult<()> {
+        let content = toml::to_string(self)
+            .map_err(|e| IoError::new(IoErrorKind::InvalidData, e.to_string()))?;
+        fs::write(path, content)
+    }
+
+    ///Subsidy of the block at height, halving every halving_interval blocks
+    pub fn block_reward(&self, height: u64) -> u64 {
+        (self.initial_reward * 10u64.pow(8))
+            .checked_shr((height / self.halving_interval) as u32)
+            .unwrap_or(0)
+    }
+
+    ///First block of the chain. Its only output carries genesis_message and
+    ///can't be spent, so nobody starts with coins
+    pub fn genesis_block(&self) -> Block {
+        //a fixed output id, fresh ones are random
+        let output = TransactionOutput {
+            unique_id: Uuid::nil(),
+            ..TransactionOutput::data_carrier(self.genesis_message.as_bytes())
+        };
+        let transactions = vec![Transaction::new(vec![], vec![output])];
+
+        let header = BlockHeader::new(
+            Utc.timestamp_opt(self.genesis_timestamp, 0).unwrap(),
+            self.genesis_nonce,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            self.min_target
+        );
+
+        Block::new(header, transactions)
+    }
+
+    ///Target the block at height has to have, header_at giving the headers
+    ///of the chain it extends. It stays the same within a retarget period and
+    ///follows the time the previous period took at its start
+    pub fn next_target<'a>(&self, height: u64, header_at: impl Fn(u64) -> &'a BlockHeader) -> U256 {
+        if height == 0 {
+            return self.min_target
+        }
+
+        let last = header_at(height - 1);
+        if !self.retarget || !height.is_multiple_of(self.difficulty_update_interval) {
+            return last.target
+        }
+
+        let first = header_at(height - self.difficulty_update_interval);
+        let actual_seconds = (last.timestamp - first.timestamp).num_seconds();
+        self.adjusted_target(last.target, actual_seconds)
+    }
+
+    ///Target following a retarget period that took actual_seconds,
+    ///adjusted by at most a factor of 4 either way
+    pub fn adjusted_target(&self, target: U256, actual_seconds: i64) -> U256 {
+        let expected = self.ideal_block_time * self.difficulty_update_interval;
+        let actual = (actual_seconds.max(0) as u64).clamp(expected / 4, expected * 4);
+
+        (target / U256::from(expected))
+            .saturating_mul(U256::from(actual))
+            .min(self.min_target)
+    }
+}
+
+//found with mine-genesis, the genesis headers change with any of their fields
+const MAIN_GENESIS_NONCE: u64 = 29_059;
+const TEST_GENESIS_NONCE: u64 = 76_815;
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        Self::main()
+    }
+}
+
+//U256 has no integer type in TOML, it is written as a hex string
+mod hex_target {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(target: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{target:064x}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        U256::from_str_radix(&hex, 16).map_err(serde::de::Error::custom)
+    }
+}
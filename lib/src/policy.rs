@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use crate::error::{BtcError, Result};
+use crate::sha256::Hash;
+use crate::types::{Transaction, TransactionOutput};
+
+//relay rules only, blocks may contain transactions breaking them
+
+///Largest payload of a standard data carrier output
+pub const MAX_DATA_CARRIER_SIZE: usize = 80;
+pub const MAX_DATA_CARRIER_OUTPUTS: usize = 1;
+//a tenth of the block limits, so no single transaction crowds out the others
+pub const MAX_STANDARD_TRANSACTION_WEIGHT: usize = crate::MAX_BLOCK_WEIGHT / 10;
+pub const MAX_STANDARD_TRANSACTION_SIGOPS: usize = crate::MAX_BLOCK_SIGOPS / 10;
+///Newer versions are left for future soft forks to give meaning to
+pub const MAX_STANDARD_TRANSACTION_VERSION: u32 = 2;
+///Bytes of transactions the mempool holds unless configured otherwise
+pub const DEFAULT_MAX_MEMPOOL_SIZE: usize = 300_000_000;
+
+///Checks the rules the mempool applies on top of consensus
+pub fn check_standard(transaction: &Transaction, utxos: &HashMap<Hash, TransactionOutput>) -> Result<()> {
+    if !(1..=MAX_STANDARD_TRANSACTION_VERSION).contains(&transaction.version()) {
+        return Err(BtcError::NonStandardTransaction("transaction version"))
+    }
+
+    if transaction.weight() > MAX_STANDARD_TRANSACTION_WEIGHT {
+        return Err(BtcError::NonStandardTransaction("transaction weight"))
+    }
+
+    if transaction.sigop_count(utxos) > MAX_STANDARD_TRANSACTION_SIGOPS {
+        return Err(BtcError::NonStandardTransaction("too many signature operations"))
+    }
+
+    let mut data_carriers = 0;
+
+    for output in transaction.outputs() {
+        if !output.lock.is_unspendable() {
+            continue
+        }
+
+        let payload = output
+            .data_carrier_payload()
+            .ok_or(BtcError::NonStandardTransaction("unspendable output is not a data carrier"))?;
+
+        if payload.len() > MAX_DATA_CARRIER_SIZE {
+            return Err(BtcError::NonStandardTransaction("data carrier payload too large"))
+        }
+
+        //the value of an unspendable output would be burned
+        if output.value != 0 {
+            return Err(BtcError::NonStandardTransaction("data carrier with value"))
+        }
+
+        data_carriers += 1;
+    }
+
+    if data_carriers > MAX_DATA_CARRIER_OUTPUTS {
+        return Err(BtcError::NonStandardTransaction("too many data carrier outputs"))
+    }
+
+    Ok(())
+}
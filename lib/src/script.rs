@@ -0,0 +1,725 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use ripemd::Ripemd160;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use crate::crypto::{PublicKey, Signature, SignatureCheck};
+use crate::sha256::Hash;
+use crate::sigcache::SignatureCache;
+use crate::timelock::{
+    self, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG,
+    SEQUENCE_LOCK_MIN_VERSION
+};
+use crate::types::{MAX_MULTISIG_KEYS, TRANSACTION_VERSION};
+
+//limits mirror Bitcoin's consensus limits
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+pub const MAX_ELEMENT_SIZE: usize = 520;
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+pub const MAX_STACK_SIZE: usize = 1_000;
+
+//numbers taken from the stack are at most 4 bytes long
+const MAX_NUM_SIZE: usize = 4;
+//except lock times, which need 5 bytes to reach timestamps past 2038
+const MAX_LOCKTIME_NUM_SIZE: usize = 5;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    ///Pushes the bytes onto the stack
+    Push(Vec<u8>),
+    Dup,
+    Drop,
+    Swap,
+    ///Pushes the size of the top element without removing it
+    Size,
+    Equal,
+    EqualVerify,
+    ///Fails the script unless the top element is true, removing it
+    Verify,
+    ///Marks the script as unspendable
+    Return,
+    Sha256,
+    ///RIPEMD160(SHA256(x))
+    Hash160,
+    ///SHA256(SHA256(x))
+    Hash256,
+    CheckSig,
+    CheckSigVerify,
+    CheckMultiSig,
+    CheckMultiSigVerify,
+    ///Fails unless the transaction's lock time reached the top element, leaving it on the stack
+    CheckLockTimeVerify,
+    ///Fails unless the input's relative lock covers the top element, leaving it on the stack
+    CheckSequenceVerify,
+    If,
+    NotIf,
+    Else,
+    EndIf
+}
+
+impl Op {
+    pub fn push_int(n: i64) -> Self {
+        Op::Push(encode_num(n))
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Push(_) => "OP_PUSH",
+            Op::Dup => "OP_DUP",
+            Op::Drop => "OP_DROP",
+            Op::Swap => "OP_SWAP",
+            Op::Size => "OP_SIZE",
+            Op::Equal => "OP_EQUAL",
+            Op::EqualVerify => "OP_EQUALVERIFY",
+            Op::Verify => "OP_VERIFY",
+            Op::Return => "OP_RETURN",
+            Op::Sha256 => "OP_SHA256",
+            Op::Hash160 => "OP_HASH160",
+            Op::Hash256 => "OP_HASH256",
+            Op::CheckSig => "OP_CHECKSIG",
+            Op::CheckSigVerify => "OP_CHECKSIGVERIFY",
+            Op::CheckMultiSig => "OP_CHECKMULTISIG",
+            Op::CheckMultiSigVerify => "OP_CHECKMULTISIGVERIFY",
+            Op::CheckLockTimeVerify => "OP_CHECKLOCKTIMEVERIFY",
+            Op::CheckSequenceVerify => "OP_CHECKSEQUENCEVERIFY",
+            Op::If => "OP_IF",
+            Op::NotIf => "OP_NOTIF",
+            Op::Else => "OP_ELSE",
+            Op::EndIf => "OP_ENDIF"
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    #[error("Script is larger than {MAX_SCRIPT_SIZE} bytes")]
+    ScriptSize,
+    #[error("Push larger than {MAX_ELEMENT_SIZE} bytes")]
+    PushSize,
+    #[error("More than {MAX_OPS_PER_SCRIPT} operations")]
+    OpCount,
+    #[error("Stack larger than {MAX_STACK_SIZE} elements")]
+    StackSize,
+    #[error("Operation needs more stack elements")]
+    StackUnderflow,
+    #[error("Unbalanced IF/ELSE/ENDIF")]
+    UnbalancedConditional,
+    #[error("OP_RETURN executed")]
+    OpReturn,
+    #[error("Verify failed")]
+    VerifyFailed,
+    #[error("Equal verify failed")]
+    EqualVerifyFailed,
+    #[error("Signature verify failed")]
+    CheckSigVerifyFailed,
+    #[error("Multisig verify failed")]
+    CheckMultiSigVerifyFailed,
+    #[error("Invalid number encoding")]
+    InvalidNumber,
+    #[error("Invalid public key count")]
+    PubKeyCount,
+    #[error("Invalid signature count")]
+    SigCount,
+    #[error("Invalid public key encoding")]
+    InvalidPublicKey,
+    #[error("Invalid signature encoding")]
+    InvalidSignature,
+    #[error("Non-canonical (high-S) signature")]
+    NonCanonicalSignature,
+    #[error("Non-empty signature failed verification")]
+    NullFail,
+    #[error("Negative lock time")]
+    NegativeLockTime,
+    #[error("Lock time requirement not satisfied")]
+    UnsatisfiedLockTime,
+    #[error("Unlocking script is not push only")]
+    NonPushScriptSig,
+    #[error("Script evaluated to false")]
+    EvalFalse,
+    #[error("Stack not clean after evaluation")]
+    CleanStack
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    pub fn new(ops: Vec<Op>) -> Self {
+        Script(ops)
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    ///Size in bytes as if encoded like Bitcoin script: one byte per opcode plus pushed data
+    pub fn size(&self) -> usize {
+        self.0
+            .iter()
+            .map(|op| match op {
+                Op::Push(data) => 1 + data.len(),
+                _ => 1
+            })
+            .sum()
+    }
+
+    ///Signature checks the script can perform. CHECKMULTISIG counts its key count
+    ///when pushed right before it, the maximum otherwise
+    pub fn sigop_count(&self) -> usize {
+        let mut count = 0;
+        let mut previous: Option<&Op> = None;
+
+        for op in self.ops() {
+            match op {
+                Op::CheckSig | Op::CheckSigVerify => count += 1,
+                Op::CheckMultiSig | Op::CheckMultiSigVerify => {
+                    count += match previous {
+                        Some(Op::Push(data)) => decode_num(data, MAX_NUM_SIZE)
+                            .ok()
+                            .filter(|keys| (0..=MAX_MULTISIG_KEYS as i64).contains(keys))
+                            .map_or(MAX_MULTISIG_KEYS, |keys| keys as usize),
+                        _ => MAX_MULTISIG_KEYS
+                    }
+                }
+                _ => {}
+            }
+            previous = Some(op);
+        }
+
+        count
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        self.0.iter().all(|op| matches!(op, Op::Push(_)))
+    }
+
+    ///OP_DUP OP_HASH160 <hash160(pubkey)> OP_EQUALVERIFY OP_CHECKSIG
+    pub fn pay_to_pubkey_hash(pubkey: &PublicKey) -> Self {
+        Script(vec![
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(hash160(&pubkey.to_bytes()).to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig
+        ])
+    }
+
+    pub fn pay_to_pubkey_hash_unlock(signature: &Signature, pubkey: &PublicKey) -> Self {
+        Script(vec![
+            Op::Push(signature.to_bytes().to_vec()),
+            Op::Push(pubkey.to_bytes())
+        ])
+    }
+
+    ///OP_RETURN <data>, an output nobody can spend that only carries data
+    pub fn data_carrier(data: &[u8]) -> Self {
+        Script(vec![Op::Return, Op::Push(data.to_vec())])
+    }
+
+    ///Payload of a script built by data_carrier
+    pub fn data_carrier_payload(&self) -> Option<&[u8]> {
+        match self.ops() {
+            [Op::Return] => Some(&[]),
+            [Op::Return, Op::Push(data)] => Some(data),
+            _ => None
+        }
+    }
+
+    ///Scripts that fail whatever the unlocking script: starting with OP_RETURN or too large
+    pub fn is_unspendable(&self) -> bool {
+        matches!(self.ops().first(), Some(Op::Return)) || self.size() > MAX_SCRIPT_SIZE
+    }
+
+    ///<m> <pubkey>... <n> OP_CHECKMULTISIG
+    pub fn multisig(threshold: u8, pubkeys: &[PublicKey]) -> Self {
+        let mut ops = vec![Op::push_int(threshold as i64)];
+        ops.extend(pubkeys.iter().map(|pubkey| Op::Push(pubkey.to_bytes())));
+        ops.push(Op::push_int(pubkeys.len() as i64));
+        ops.push(Op::CheckMultiSig);
+
+        Script(ops)
+    }
+
+    ///Signatures have to be given in the order of their keys in the locking script.
+    ///Unlike Bitcoin there is no extra dummy element consumed by CHECKMULTISIG
+    pub fn multisig_unlock(signatures: &[Signature]) -> Self {
+        Script(
+            signatures
+                .iter()
+                .map(|signature| Op::Push(signature.to_bytes().to_vec()))
+                .collect()
+        )
+    }
+}
+
+impl Display for Script {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, op) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+
+            match op {
+                Op::Push(data) => write!(f, "{}", hex::encode(data))?,
+                _ => write!(f, "{}", op.name())?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    Ripemd160::digest(Sha256::digest(data)).into()
+}
+
+///Minimal little-endian sign-magnitude encoding used for numbers on the stack
+pub fn encode_num(n: i64) -> Vec<u8> {
+    if n == 0 {
+        return Vec::new()
+    }
+
+    let negative = n < 0;
+    let mut magnitude = n.unsigned_abs();
+    let mut bytes = Vec::new();
+
+    while magnitude > 0 {
+        bytes.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+
+    //the top bit carries the sign, add a byte if the magnitude already uses it
+    let last = bytes.len() - 1;
+    if bytes[last] & 0x80 != 0 {
+        bytes.push(if negative { 0x80 } else { 0x00 });
+    } else if negative {
+        bytes[last] |= 0x80;
+    }
+
+    bytes
+}
+
+pub fn decode_num(bytes: &[u8], max_size: usize) -> Result<i64, ScriptError> {
+    if bytes.len() > max_size {
+        return Err(ScriptError::InvalidNumber)
+    }
+
+    let Some(&last) = bytes.last() else {
+        return Ok(0)
+    };
+
+    //reject non-minimal encodings, they would make the same number malleable
+    if last & 0x7f == 0 && (bytes.len() == 1 || bytes[bytes.len() - 2] & 0x80 == 0) {
+        return Err(ScriptError::InvalidNumber)
+    }
+
+    let mut result: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        result |= (*byte as i64) << (8 * i);
+    }
+
+    if last & 0x80 != 0 {
+        let sign_bit = 0x80i64 << (8 * (bytes.len() - 1));
+        return Ok(-(result & !sign_bit))
+    }
+
+    Ok(result)
+}
+
+pub fn cast_to_bool(bytes: &[u8]) -> bool {
+    for (i, byte) in bytes.iter().enumerate() {
+        if *byte != 0 {
+            //negative zero
+            return !(i == bytes.len() - 1 && *byte == 0x80)
+        }
+    }
+
+    false
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value { vec![1] } else { Vec::new() }
+}
+
+///What a script is evaluated against
+pub struct ScriptContext<'a> {
+    ///Message signed by CHECKSIG signatures, the spending input's sighash (see Transaction::sighash)
+    pub sighash: Hash,
+    pub signature_cache: &'a SignatureCache,
+    ///Lock time of the spending transaction
+    pub lock_time: u64,
+    ///Sequence of the spending input
+    pub sequence: u32,
+    ///Version of the spending transaction, CHECKSEQUENCEVERIFY fails below SEQUENCE_LOCK_MIN_VERSION
+    pub version: u32
+}
+
+impl<'a> ScriptContext<'a> {
+    ///Context of an input without lock time or relative lock
+    pub fn new(sighash: Hash, signature_cache: &'a SignatureCache) -> Self {
+        ScriptContext {
+            sighash,
+            signature_cache,
+            lock_time: 0,
+            sequence: SEQUENCE_FINAL,
+            version: TRANSACTION_VERSION
+        }
+    }
+}
+
+pub struct Interpreter<'a> {
+    context: ScriptContext<'a>,
+    stack: Vec<Vec<u8>>
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(context: ScriptContext<'a>) -> Self {
+        Interpreter {
+            context,
+            stack: Vec::new()
+        }
+    }
+
+    pub fn stack(&self) -> &[Vec<u8>] {
+        &self.stack
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, ScriptError> {
+        Ok(cast_to_bool(&self.pop()?))
+    }
+
+    fn pop_num(&mut self) -> Result<i64, ScriptError> {
+        decode_num(&self.pop()?, MAX_NUM_SIZE)
+    }
+
+    fn top(&self) -> Result<&Vec<u8>, ScriptError> {
+        self.stack.last().ok_or(ScriptError::StackUnderflow)
+    }
+
+    ///Executes the script on top of the current stack
+    pub fn execute(&mut self, script: &Script) -> Result<(), ScriptError> {
+        if script.size() > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::ScriptSize)
+        }
+
+        //one entry per enclosing IF, whether its branch is being executed
+        let mut conditions: Vec<bool> = Vec::new();
+        let mut op_count = 0;
+
+        for op in script.ops() {
+            let executing = conditions.iter().all(|condition| *condition);
+
+            if let Op::Push(data) = op {
+                if data.len() > MAX_ELEMENT_SIZE {
+                    return Err(ScriptError::PushSize)
+                }
+                if executing {
+                    self.stack.push(data.clone());
+                }
+            } else {
+                op_count += 1;
+                if op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(ScriptError::OpCount)
+                }
+
+                match op {
+                    Op::If | Op::NotIf => {
+                        let mut branch = false;
+                        if executing {
+                            branch = self.pop_bool()?;
+                            if *op == Op::NotIf {
+                                branch = !branch;
+                            }
+                        }
+                        conditions.push(branch);
+                    }
+                    Op::Else => {
+                        let condition = conditions
+                            .last_mut()
+                            .ok_or(ScriptError::UnbalancedConditional)?;
+                        *condition = !*condition;
+                    }
+                    Op::EndIf => {
+                        conditions.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                    }
+                    _ if !executing => {}
+                    _ => self.execute_op(op, &mut op_count)?
+                }
+            }
+
+            if self.stack.len() > MAX_STACK_SIZE {
+                return Err(ScriptError::StackSize)
+            }
+        }
+
+        if !conditions.is_empty() {
+            return Err(ScriptError::UnbalancedConditional)
+        }
+
+        Ok(())
+    }
+
+    fn execute_op(&mut self, op: &Op, op_count: &mut usize) -> Result<(), ScriptError> {
+        match op {
+            Op::Dup => {
+                let top = self.top()?.clone();
+                self.stack.push(top);
+            }
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Swap => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            Op::Size => {
+                let size = self.top()?.len();
+                self.stack.push(encode_num(size as i64));
+            }
+            Op::Equal | Op::EqualVerify => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+
+                if *op == Op::EqualVerify {
+                    if a != b {
+                        return Err(ScriptError::EqualVerifyFailed)
+                    }
+                } else {
+                    self.stack.push(encode_bool(a == b));
+                }
+            }
+            Op::Verify => {
+                if !self.pop_bool()? {
+                    return Err(ScriptError::VerifyFailed)
+                }
+            }
+            Op::Return => return Err(ScriptError::OpReturn),
+            Op::Sha256 => {
+                let data = self.pop()?;
+                self.stack.push(Sha256::digest(data).to_vec());
+            }
+            Op::Hash160 => {
+                let data = self.pop()?;
+                self.stack.push(hash160(&data).to_vec());
+            }
+            Op::Hash256 => {
+                let data = self.pop()?;
+                self.stack.push(Sha256::digest(Sha256::digest(data)).to_vec());
+            }
+            Op::CheckSig | Op::CheckSigVerify => {
+                let pubkey = self.pop()?;
+                let signature = self.pop()?;
+                let valid = self.check_signature(&signature, &pubkey)?;
+
+                if !valid && !signature.is_empty() {
+                    return Err(ScriptError::NullFail)
+                }
+
+                if *op == Op::CheckSigVerify {
+                    if !valid {
+                        return Err(ScriptError::CheckSigVerifyFailed)
+                    }
+                } else {
+                    self.stack.push(encode_bool(valid));
+                }
+            }
+            Op::CheckMultiSig | Op::CheckMultiSigVerify => {
+                let valid = self.check_multisig(op_count)?;
+
+                if *op == Op::CheckMultiSigVerify {
+                    if !valid {
+                        return Err(ScriptError::CheckMultiSigVerifyFailed)
+                    }
+                } else {
+                    self.stack.push(encode_bool(valid));
+                }
+            }
+            Op::CheckLockTimeVerify => {
+                let lock_time = self.top_lock_time()?;
+                self.check_lock_time(lock_time)?;
+            }
+            Op::CheckSequenceVerify => {
+                let sequence = self.top_lock_time()?;
+                //a set disable flag leaves the sequence meaningless, like a NOP
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0 {
+                    self.check_sequence(sequence as u32)?;
+                }
+            }
+            Op::Push(_) | Op::If | Op::NotIf | Op::Else | Op::EndIf => {
+                unreachable!("handled by execute")
+            }
+        }
+
+        Ok(())
+    }
+
+    fn top_lock_time(&self) -> Result<i64, ScriptError> {
+        let value = decode_num(self.top()?, MAX_LOCKTIME_NUM_SIZE)?;
+
+        if value < 0 {
+            return Err(ScriptError::NegativeLockTime)
+        }
+
+        Ok(value)
+    }
+
+    ///Same rules as BIP65: both lock times of the same kind and the transaction's at least as late
+    fn check_lock_time(&self, lock_time: i64) -> Result<(), ScriptError> {
+        let tx_lock_time = self.context.lock_time;
+        let lock_time = lock_time as u64;
+
+        if timelock::is_height_lock(lock_time) != timelock::is_height_lock(tx_lock_time)
+            || lock_time > tx_lock_time
+            //a final input would switch the transaction's lock time off
+            || self.context.sequence == SEQUENCE_FINAL {
+            return Err(ScriptError::UnsatisfiedLockTime)
+        }
+
+        Ok(())
+    }
+
+    ///Same rules as BIP112: the input's relative lock is of the same kind and at least as long
+    fn check_sequence(&self, sequence: u32) -> Result<(), ScriptError> {
+        let tx_sequence = self.context.sequence;
+
+        //older transactions have no relative locks to compare against
+        if self.context.version < SEQUENCE_LOCK_MIN_VERSION
+            || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return Err(ScriptError::UnsatisfiedLockTime)
+        }
+
+        let kind_mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+        let required = sequence & kind_mask;
+        let provided = tx_sequence & kind_mask;
+
+        if (required & SEQUENCE_LOCKTIME_TYPE_FLAG) != (provided & SEQUENCE_LOCKTIME_TYPE_FLAG)
+            || (required & SEQUENCE_LOCKTIME_MASK) > (provided & SEQUENCE_LOCKTIME_MASK) {
+            return Err(ScriptError::UnsatisfiedLockTime)
+        }
+
+        Ok(())
+    }
+
+    ///Empty signatures are a valid way to fail a check, malformed ones are an error
+    fn check_signature(&self, signature: &[u8], pubkey: &[u8]) -> Result<bool, ScriptError> {
+        let pubkey = PublicKey::from_bytes(pubkey).map_err(|_| ScriptError::InvalidPublicKey)?;
+
+        if signature.is_empty() {
+            return Ok(false)
+        }
+
+        let signature = Signature::from_bytes(pubkey.scheme(), signature)
+            .map_err(|_| ScriptError::InvalidSignature)?;
+
+        if !signature.is_low_s() {
+            return Err(ScriptError::NonCanonicalSignature)
+        }
+
+        let check = SignatureCheck::new(self.context.sighash, signature, pubkey);
+        Ok(self.context.signature_cache.verify(&check))
+    }
+
+    ///Stack: <sig>... <m> <pubkey>... <n>. Signatures must follow the order of the keys
+    fn check_multisig(&mut self, op_count: &mut usize) -> Result<bool, ScriptError> {
+        let key_count = self.pop_num()?;
+        if key_count < 0 || key_count as usize > MAX_MULTISIG_KEYS {
+            return Err(ScriptError::PubKeyCount)
+        }
+
+        *op_count += key_count as usize;
+        if *op_count > MAX_OPS_PER_SCRIPT {
+            return Err(ScriptError::OpCount)
+        }
+
+        let mut pubkeys = Vec::with_capacity(key_count as usize);
+        for _ in 0..key_count {
+            pubkeys.push(self.pop()?);
+        }
+        pubkeys.reverse();
+
+        let signature_count = self.pop_num()?;
+        if signature_count < 0 || signature_count > key_count {
+            return Err(ScriptError::SigCount)
+        }
+
+        let mut signatures = Vec::with_capacity(signature_count as usize);
+        for _ in 0..signature_count {
+            signatures.push(self.pop()?);
+        }
+        signatures.reverse();
+
+        let mut remaining_keys = pubkeys.iter();
+        let mut valid = true;
+
+        for signature in &signatures {
+            let mut matched = false;
+            //a key of the other scheme may fail to decode the signature, that is only a mismatch.
+            //A signature no key could decode is malformed and keeps the error check_signature gave
+            let mut decoded = false;
+            let mut malformed = None;
+
+            for pubkey in remaining_keys.by_ref() {
+                match self.check_signature(signature, pubkey) {
+                    Ok(true) => {
+                        matched = true;
+                        break
+                    }
+                    Ok(false) => decoded = true,
+                    Err(error @ (ScriptError::InvalidSignature | ScriptError::NonCanonicalSignature)) => {
+                        malformed.get_or_insert(error);
+                    }
+                    Err(error) => return Err(error)
+                }
+            }
+
+            if !matched {
+                if let (false, Some(error)) = (decoded, malformed) {
+                    return Err(error)
+                }
+
+                valid = false;
+                break
+            }
+        }
+
+        if !valid && signatures.iter().any(|signature| !signature.is_empty()) {
+            return Err(ScriptError::NullFail)
+        }
+
+        Ok(valid)
+    }
+}
+
+///Runs the unlocking script, then the locking script on the resulting stack.
+///The spend is valid if exactly one true element is left
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    context: ScriptContext
+) -> Result<(), ScriptError> {
+    if !script_sig.is_push_only() {
+        return Err(ScriptError::NonPushScriptSig)
+    }
+
+    let mut interpreter = Interpreter::new(context);
+    interpreter.execute(script_sig)?;
+    interpreter.execute(script_pubkey)?;
+
+    match interpreter.stack() {
+        [] => Err(ScriptError::EvalFalse),
+        [result] if cast_to_bool(result) => Ok(()),
+        [_] => Err(ScriptError::EvalFalse),
+        _ => Err(ScriptError::CleanStack)
+    }
+}
@@ -3,12 +3,15 @@ use sha256::digest;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use crate::error::BtcError;
 
 
 #[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug, Deserialize, Hash)]
 pub struct Hash(U256);
 
 impl Hash {
+    #[allow(clippy::self_named_constructors)]
     pub fn hash<T: serde::Serialize>(data: &T) -> Self {
         let mut serialized: Vec<u8> = Vec::new();
 
@@ -39,9 +42,12 @@ impl Hash {
     }
 
     pub fn as_bytes(&self) -> [u8; 32] {
-        let mut bytes: Vec<u8> = vec![0; 32];
         self.0.to_little_endian()
     }
+
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Hash(U256::from_little_endian(&bytes))
+    }
 }
 
 impl Display for Hash {
@@ -49,3 +55,14 @@ impl Display for Hash {
         write!(f, "{:x}", self.0)
     }
 }
+
+///Parses the hex form produced by Display
+impl FromStr for Hash {
+    type Err = BtcError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        U256::from_str_radix(s, 16)
+            .map(Hash)
+            .map_err(|_| BtcError::InvalidHash)
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use rayon::prelude::*;
+use crate::crypto::SignatureCheck;
+use crate::sha256::Hash;
+
+//default number of cached signatures, roughly a few full blocks worth of inputs
+pub const DEFAULT_SIGNATURE_CACHE_CAPACITY: usize = 100_000;
+
+///Bounded, thread-safe set of signature checks that already passed verification.
+///Entries are keyed by the hash of (sighash, signature, pubkey) and evicted oldest first
+#[derive(Debug)]
+pub struct SignatureCache {
+    capacity: usize,
+    entries: Mutex<CacheEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    keys: HashSet<Hash>,
+    order: VecDeque<Hash>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize
+}
+
+impl SignatureCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+
+        if lookups == 0 {
+            return 0.0
+        }
+
+        self.hits as f64 / lookups as f64
+    }
+}
+
+impl SignatureCache {
+    pub fn new(capacity: usize) -> Self {
+        SignatureCache {
+            capacity,
+            entries: Mutex::new(CacheEntries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0)
+        }
+    }
+
+    fn key(check: &SignatureCheck) -> Hash {
+        Hash::hash(check)
+    }
+
+    pub fn contains(&self, check: &SignatureCheck) -> bool {
+        let key = Self::key(check);
+        let found = self.entries.lock().unwrap().keys.contains(&key);
+
+        if found {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+
+        found
+    }
+
+    ///Remembers a check that passed verification, evicting the oldest entry when full
+    pub fn insert(&self, check: &SignatureCheck) {
+        if self.capacity == 0 {
+            return
+        }
+
+        let key = Self::key(check);
+        let mut entries = self.entries.lock().unwrap();
+
+        if !entries.keys.insert(key) {
+            return
+        }
+        entries.order.push_back(key);
+
+        while entries.order.len() > self.capacity {
+            if let Some(evicted) = entries.order.pop_front() {
+                entries.keys.remove(&evicted);
+            }
+        }
+    }
+
+    ///Verifies a single check, consulting the cache first
+    pub fn verify(&self, check: &SignatureCheck) -> bool {
+        if self.contains(check) {
+            return true
+        }
+
+        if !check.verify() {
+            return false
+        }
+
+        self.insert(check);
+        true
+    }
+
+    ///Parallel counterpart of crypto::verify_batch that skips cached checks
+    ///and caches the ones that verify
+    pub fn verify_batch(&self, checks: &[SignatureCheck]) -> bool {
+        checks.par_iter().all(|check| self.verify(check))
+    }
+
+    pub fn stats(&self) -> SignatureCacheStats {
+        SignatureCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().keys.len(),
+            capacity: self.capacity
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.keys.clear();
+        entries.order.clear();
+    }
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIGNATURE_CACHE_CAPACITY)
+    }
+}
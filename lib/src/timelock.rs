@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+
+//lock times below this are block heights, from it on unix timestamps
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+
+//number of previous blocks whose median timestamp time locks are compared against
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+///Sequence of an input that doesn't opt into lock time or relative locks
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+///Set in a sequence to disable its relative lock
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+///Set in a sequence to count the relative lock in time instead of blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+//relative time locks are counted in units of 512 seconds
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+///Lowest transaction version whose sequences are relative locks (BIP68),
+///older ones keep sequences without a meaning
+pub const SEQUENCE_LOCK_MIN_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelativeLock {
+    ///Blocks that have to be mined on top of the spent output's block
+    Blocks(u64),
+    ///Seconds that have to pass since the spent output's block
+    Seconds(i64)
+}
+
+impl RelativeLock {
+    ///Relative lock encoded in an input's sequence, if it has one
+    pub fn from_sequence(sequence: u32) -> Option<Self> {
+        if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return None
+        }
+
+        let value = sequence & SEQUENCE_LOCKTIME_MASK;
+
+        if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            Some(RelativeLock::Seconds((value as i64) << SEQUENCE_LOCKTIME_GRANULARITY))
+        } else {
+            Some(RelativeLock::Blocks(value as u64))
+        }
+    }
+
+    ///Sequence encoding the lock, times are rounded up to the 512 second granularity
+    pub fn to_sequence(self) -> u32 {
+        match self {
+            RelativeLock::Blocks(blocks) => blocks.min(SEQUENCE_LOCKTIME_MASK as u64) as u32,
+            RelativeLock::Seconds(seconds) => {
+                let units = (seconds.max(0) as u64).div_ceil(1 << SEQUENCE_LOCKTIME_GRANULARITY);
+                SEQUENCE_LOCKTIME_TYPE_FLAG | units.min(SEQUENCE_LOCKTIME_MASK as u64) as u32
+            }
+        }
+    }
+}
+
+pub fn is_height_lock(lock_time: u64) -> bool {
+    lock_time < LOCKTIME_THRESHOLD
+}
+
+///Median of the timestamps as a unix timestamp, 0 for none
+pub fn median_time(timestamps: &[DateTime<Utc>]) -> i64 {
+    let mut times: Vec<i64> = timestamps
+        .iter()
+        .map(|timestamp| timestamp.timestamp())
+        .collect();
+
+    if times.is_empty() {
+        return 0
+    }
+
+    times.sort_unstable();
+    times[times.len() / 2]
+}
@@ -1,91 +1,804 @@
 use uuid::Uuid;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+use tracing::{debug, instrument};
 use crate::U256;
-use crate::crypto::{Signature, PublicKey};
+use crate::crypto::{self, PrivateKey, Signature, SignatureCheck, PublicKey};
+use crate::sigcache::SignatureCache;
+use crate::script::{self, Script, ScriptContext};
+use crate::timelock::{self, RelativeLock, MEDIAN_TIME_SPAN, SEQUENCE_FINAL, SEQUENCE_LOCK_MIN_VERSION};
 use crate::sha256::Hash;
-use crate::util::MerkleRoot;
+use crate::util::{self, MerkleRoot};
 use crate::error::{BtcError, Result};
+use crate::policy;
+use crate::deployment::{self, Deployment, DeploymentState, VERSIONBITS_TOP_BITS};
+use crate::params::ChainParams;
+use crate::headers;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Blockchain {
     pub utxos: HashMap<Hash, TransactionOutput>,
-    pub blocks: Vec<Block>
+    ///Height of the block that created each UTXO, needed for relative lock times
+    #[serde(default)]
+    pub utxo_heights: HashMap<Hash, u64>,
+    pub blocks: Vec<Block>,
+    ///Unconfirmed transactions that passed validation against the UTXO set
+    ///and the outputs of the other ones
+    pub mempool: Vec<Transaction>,
+    ///Signatures already verified on mempool acceptance, shared with block validation
+    #[serde(skip)]
+    pub signature_cache: Arc<SignatureCache>,
+    ///Soft fork deployments whose activation is tracked
+    #[serde(skip, default = "deployment::default_deployments")]
+    deployments: Vec<Deployment>,
+    ///State of each deployment per period, extended as blocks are added
+    #[serde(skip)]
+    deployment_states: HashMap<&'static str, Vec<DeploymentState>>,
+    ///Statistics of each block, recorded while its spent outputs were known
+    #[serde(default)]
+    block_stats: Vec<BlockStats>,
+    ///Outputs each block spent with the heights that created them, to disconnect it again
+    #[serde(default)]
+    undo: Vec<Vec<(Hash, TransactionOutput, u64)>>,
+    ///Blocks below this height only keep their headers
+    #[serde(default)]
+    pruned_height: u64,
+    ///Rules the chain follows, saved with it so snapshots keep their network
+    #[serde(default)]
+    params: ChainParams,
+    ///Height of each block by hash, rebuilt on load
+    #[serde(skip)]
+    block_index: HashMap<Hash, u64>,
+    ///Position in the mempool of each transaction by txid, rebuilt on load
+    #[serde(skip)]
+    mempool_index: HashMap<Hash, usize>,
+    ///Mempool position and output index of each spendable mempool output by hash, rebuilt on load
+    #[serde(skip)]
+    mempool_outputs: HashMap<Hash, (usize, usize)>
 }
 
 impl Blockchain {
+    ///Main network chain holding its genesis block
     pub fn new() -> Self {
-        Blockchain {
+        Self::with_params(ChainParams::main())
+    }
+
+    ///Chain holding only the genesis block of params
+    pub fn with_params(params: ChainParams) -> Self {
+        let genesis = params.genesis_block();
+        let mut chain = Blockchain {
             utxos: HashMap::new(),
-            blocks: Vec::new()
+            utxo_heights: HashMap::new(),
+            blocks: Vec::new(),
+            mempool: Vec::new(),
+            signature_cache: Arc::new(SignatureCache::default()),
+            deployments: deployment::default_deployments(),
+            deployment_states: HashMap::new(),
+            block_stats: Vec::new(),
+            undo: Vec::new(),
+            pruned_height: 0,
+            params,
+            block_index: HashMap::new(),
+            mempool_index: HashMap::new(),
+            mempool_outputs: HashMap::new()
+        };
+
+        chain.add_block(genesis).expect("the genesis block connects to an empty chain");
+        chain
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    ///Checks the chain starts with the genesis block of params, for chains
+    ///that come from somewhere else, like snapshot files
+    pub fn verify_genesis(&self, params: &ChainParams) -> Result<()> {
+        match self.blocks.first() {
+            Some(genesis) if genesis.hash() == params.genesis_block().hash() => Ok(()),
+            _ => Err(BtcError::GenesisMismatch)
         }
     }
 
+    ///Target the next block has to have
+    pub fn next_target(&self) -> U256 {
+        self.params.next_target(self.block_height(), |height| &self.blocks[height as usize].header)
+    }
+
+    pub fn block_by_hash(&self, hash: &Hash) -> Option<&Block> {
+        self.height_of(hash).map(|height| &self.blocks[height as usize])
+    }
+
+    pub fn height_of(&self, hash: &Hash) -> Option<u64> {
+        self.block_index.get(hash).copied()
+    }
+
+    ///Locator of the chain, see headers::locator
+    pub fn locator(&self) -> Vec<Hash> {
+        headers::locator(self.block_height() - 1, |height| self.blocks[height as usize].hash())
+    }
+
+    ///Headers after the first locator hash found in the chain, up to and including
+    ///stop or max of them. Nothing when the locator shares no block with the chain
+    pub fn headers_after(&self, locator: &[Hash], stop: &Hash, max: usize) -> Vec<BlockHeader> {
+        let Some(fork) = locator.iter().find_map(|hash| self.height_of(hash)) else {
+            return Vec::new()
+        };
+
+        let mut headers = Vec::new();
+        for block in self.blocks.iter().skip(fork as usize + 1).take(max) {
+            headers.push(block.header.clone());
+            if block.hash() == *stop {
+                break
+            }
+        }
+
+        headers
+    }
+
+    pub fn mempool_transaction(&self, txid: &Hash) -> Option<&Transaction> {
+        self.mempool_index.get(txid).map(|index| &self.mempool[*index])
+    }
+
+    ///Builds the hash indexes again, they aren't saved with the chain
+    pub(crate) fn rebuild_indexes(&mut self) {
+        self.block_index = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(height, block)| (block.hash(), height as u64))
+            .collect();
+        self.reindex_mempool();
+    }
+
+    //positions shift whenever transactions leave the mempool
+    fn reindex_mempool(&mut self) {
+        self.mempool_index = self
+            .mempool
+            .iter()
+            .enumerate()
+            .map(|(index, transaction)| (transaction.hash(), index))
+            .collect();
+        self.mempool_outputs = HashMap::new();
+        for index in 0..self.mempool.len() {
+            self.index_mempool_outputs(index);
+        }
+    }
+
+    fn index_mempool_outputs(&mut self, index: usize) {
+        //unspendable outputs never enter the UTXO set, so they can't be spent from the mempool either
+        for (position, output) in self.mempool[index].outputs.iter().enumerate() {
+            if !output.lock.is_unspendable() {
+                self.mempool_outputs.insert(output.hash(), (index, position));
+            }
+        }
+    }
+
+    ///Output by hash from the UTXO set or, unconfirmed, from a mempool transaction.
+    ///A mempool output may already be spent by another mempool transaction
+    pub fn unspent_output(&self, hash: &Hash) -> Option<&TransactionOutput> {
+        self.utxos.get(hash).or_else(|| {
+            self.mempool_outputs
+                .get(hash)
+                .map(|(index, position)| &self.mempool[*index].outputs[*position])
+        })
+    }
+
+    pub fn block_stats(&self, height: u64) -> Option<&BlockStats> {
+        self.block_stats.get(height as usize)
+    }
+
+    ///Next block paying reward and fees to reward_lock, filled from the mempool
+    ///by fee rate within the block limits. Mining only has to find the nonce
+    pub fn block_template(&self, reward_lock: LockingCondition) -> Result<Block> {
+        let height = self.block_height();
+
+        let mut candidates: Vec<(&Transaction, u64, usize)> = self
+            .mempool
+            .iter()
+            .map(|transaction| (transaction, self.mempool_fee(transaction), transaction.virtual_size()))
+            .collect();
+
+        //highest fee per virtual byte first
+        candidates.sort_by(|(_, fee_a, size_a), (_, fee_b, size_b)| {
+            (*fee_b as u128 * *size_a as u128).cmp(&(*fee_a as u128 * *size_b as u128))
+        });
+
+        let mut transactions = Vec::new();
+        let mut weight = COINBASE_RESERVED_WEIGHT;
+        let mut size = COINBASE_RESERVED_SIZE;
+        let mut sigops = 0;
+        let mut fees = 0;
+
+        for (transaction, fee, _) in candidates {
+            let transaction_weight = transaction.weight();
+            let transaction_size = transaction.total_size();
+            let transaction_sigops = transaction.sigop_count(&self.utxos);
+
+            //a block can't spend outputs created in it, children of
+            //unconfirmed transactions wait for the next one
+            let confirmed_inputs = transaction
+                .inputs
+                .iter()
+                .all(|input| self.utxos.contains_key(&input.prev_transaction_output_hash));
+
+            if !confirmed_inputs
+                || weight + transaction_weight > crate::MAX_BLOCK_WEIGHT
+                || size + transaction_size > crate::MAX_BLOCK_SIZE
+                || sigops + transaction_sigops > crate::MAX_BLOCK_SIGOPS
+                || self.check_lock_times(transaction, height).is_err() {
+                continue
+            }
+
+            weight += transaction_weight;
+            size += transaction_size;
+            sigops += transaction_sigops;
+            fees += fee;
+            transactions.push(transaction.clone());
+        }
+
+        let coinbase = Transaction::new(
+            vec![],
+            vec![TransactionOutput::new(self.params.block_reward(height) + fees, reward_lock)]
+        );
+        transactions.insert(0, coinbase);
+        Block::commit_witnesses(&mut transactions);
+
+        let (prev_block_hash, timestamp) = match self.blocks.last() {
+            Some(last) => (
+                last.hash(),
+                Utc::now().max(last.header.timestamp + Duration::seconds(1))
+            ),
+            None => (Hash::zero(), Utc::now())
+        };
+
+        let header = BlockHeader::new(
+            timestamp,
+            0,
+            prev_block_hash,
+            MerkleRoot::calculate(&transactions),
+            self.next_target()
+        )
+        .with_version(self.block_version());
+
+        Ok(Block::new(header, transactions))
+    }
+
+    ///Fee of a mempool transaction, its inputs are in the UTXO set or the mempool
+    fn mempool_fee(&self, transaction: &Transaction) -> u64 {
+        let input_value: u64 = transaction
+            .inputs
+            .iter()
+            .filter_map(|input| self.unspent_output(&input.prev_transaction_output_hash))
+            .map(|output| output.value)
+            .sum();
+        let output_value: u64 = transaction.outputs.iter().map(|output| output.value).sum();
+        input_value.saturating_sub(output_value)
+    }
+
+    ///Evicts the transactions paying the least per virtual byte until the
+    ///mempool takes at most max_size bytes, along with the transactions spending
+    ///their outputs. Returns the evicted ones
+    pub fn trim_mempool(&mut self, max_size: usize) -> Vec<Transaction> {
+        let mut size: usize = self.mempool.iter().map(Transaction::total_size).sum();
+        if size <= max_size {
+            return Vec::new()
+        }
+
+        let mut by_fee_rate: Vec<(usize, u64, usize)> = self
+            .mempool
+            .iter()
+            .enumerate()
+            .map(|(index, transaction)| (index, self.mempool_fee(transaction), transaction.virtual_size()))
+            .collect();
+        //lowest fee per virtual byte first
+        by_fee_rate.sort_by(|(_, fee_a, size_a), (_, fee_b, size_b)| {
+            (*fee_a as u128 * *size_b as u128).cmp(&(*fee_b as u128 * *size_a as u128))
+        });
+
+        let mut evict = HashSet::new();
+        for (index, _, _) in by_fee_rate {
+            if size <= max_size {
+                break
+            }
+            size -= self.mempool[index].total_size();
+            evict.insert(self.mempool[index].hash());
+        }
+
+        let evicted = self.retain_mempool(|transaction| !evict.contains(&transaction.hash()));
+        debug!(evicted = evicted.len(), size, max_size, "trimmed the mempool");
+        evicted
+    }
+
+    ///Keeps the mempool transactions keep accepts whose inputs are still in the UTXO
+    ///set or created by another kept transaction, returns the dropped ones
+    fn retain_mempool(&mut self, keep: impl Fn(&Transaction) -> bool) -> Vec<Transaction> {
+        let (mut kept, mut dropped): (Vec<Transaction>, Vec<Transaction>) = std::mem::take(&mut self.mempool)
+            .into_iter()
+            .partition(|transaction| keep(transaction));
+
+        //each dropped transaction can take the ones spending its outputs with it
+        loop {
+            let outputs: HashSet<Hash> = kept
+                .iter()
+                .flat_map(|transaction| transaction.outputs.iter())
+                .map(TransactionOutput::hash)
+                .collect();
+            let (spendable, orphaned): (Vec<Transaction>, Vec<Transaction>) = kept
+                .into_iter()
+                .partition(|transaction| {
+                    transaction.inputs.iter().all(|input| {
+                        self.utxos.contains_key(&input.prev_transaction_output_hash)
+                            || outputs.contains(&input.prev_transaction_output_hash)
+                    })
+                });
+            kept = spendable;
+            if orphaned.is_empty() {
+                break
+            }
+            dropped.extend(orphaned);
+        }
+
+        self.mempool = kept;
+        self.reindex_mempool();
+        dropped
+    }
+
+    pub fn deployments(&self) -> &[Deployment] {
+        &self.deployments
+    }
+
+    pub fn set_deployments(&mut self, deployments: Vec<Deployment>) {
+        self.deployments = deployments;
+        self.deployment_states.clear();
+    }
+
+    ///State of the named deployment for the block at height, None if it isn't tracked.
+    ///States of future periods assume no more signalling blocks
+    pub fn deployment_state(&self, name: &str, height: u64) -> Option<DeploymentState> {
+        let deployment = self.deployments.iter().find(|deployment| deployment.name == name)?;
+        let period = (height / self.params.deployment_period) as usize;
+        let cached = self
+            .deployment_states
+            .get(deployment.name)
+            .map_or(&[][..], Vec::as_slice);
+
+        if let Some(state) = cached.get(period) {
+            return Some(*state)
+        }
+
+        let mut states = cached.to_vec();
+        self.extend_deployment_states(deployment, &mut states, period);
+        Some(states[period])
+    }
+
+    ///Whether the rules of the named deployment apply to the block at height
+    pub fn is_deployment_active(&self, name: &str, height: u64) -> bool {
+        self.deployment_state(name, height) == Some(DeploymentState::Active)
+    }
+
+    ///Version for the next block, signalling every deployment that is started or locked in
+    pub fn block_version(&self) -> u32 {
+        let height = self.block_height();
+
+        self.deployments
+            .iter()
+            .filter(|deployment| matches!(
+                self.deployment_state(deployment.name, height),
+                Some(DeploymentState::Started | DeploymentState::LockedIn)
+            ))
+            .fold(VERSIONBITS_TOP_BITS, |version, deployment| version | deployment.mask())
+    }
+
+    fn extend_deployment_states(
+        &self,
+        deployment: &Deployment,
+        states: &mut Vec<DeploymentState>,
+        period: usize
+    ) {
+        if states.is_empty() {
+            states.push(DeploymentState::Defined);
+        }
+
+        let period_length = self.params.deployment_period;
+
+        while states.len() <= period {
+            let period_start = states.len() as u64 * period_length;
+            let previous_start = (period_start - period_length) as usize;
+            let previous_end = (period_start as usize).min(self.blocks.len());
+
+            let signalling = self.blocks
+                .get(previous_start..previous_end)
+                .unwrap_or_default()
+                .iter()
+                .filter(|block| deployment.signals(block.header.version))
+                .count() as u64;
+
+            let previous = *states.last().unwrap();
+            states.push(deployment.next_state(
+                previous,
+                period_start,
+                signalling,
+                self.params.deployment_threshold
+            ));
+        }
+    }
+
+    //caches the states up to the next block's period, whose previous period is complete
+    fn update_deployment_states(&mut self) {
+        let height = self.block_height();
+
+        for deployment in self.deployments.clone() {
+            let mut states = self.deployment_states.remove(deployment.name).unwrap_or_default();
+            let period = (height / self.params.deployment_period) as usize;
+            self.extend_deployment_states(&deployment, &mut states, period);
+            self.deployment_states.insert(deployment.name, states);
+        }
+    }
+
+    pub fn block_height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    ///Median timestamp of the (up to) MEDIAN_TIME_SPAN blocks before the block at height.
+    ///Time based lock times are compared against it instead of the block's own timestamp
+    pub fn median_time_past(&self, height: u64) -> i64 {
+        let end = (height as usize).min(self.blocks.len());
+        let start = end.saturating_sub(MEDIAN_TIME_SPAN);
+
+        let timestamps: Vec<DateTime<Utc>> = self.blocks[start..end]
+            .iter()
+            .map(|block| block.header.timestamp)
+            .collect();
+
+        timelock::median_time(&timestamps)
+    }
+
+    ///Checks the transaction's lock time and its inputs' relative locks
+    ///for inclusion in the block at height. Relative locks only apply from
+    ///version SEQUENCE_LOCK_MIN_VERSION on
+    pub fn check_lock_times(&self, transaction: &Transaction, height: u64) -> Result<()> {
+        let median_time_past = self.median_time_past(height);
+
+        if !transaction.is_final(height, median_time_past) {
+            return Err(BtcError::NonFinalTransaction)
+        }
+
+        if transaction.version < SEQUENCE_LOCK_MIN_VERSION {
+            return Ok(())
+        }
+
+        for input in &transaction.inputs {
+            let Some(lock) = RelativeLock::from_sequence(input.sequence) else {
+                continue
+            };
+
+            //unconfirmed outputs count as created by the block at height
+            let utxo_height = match self.utxo_heights.get(&input.prev_transaction_output_hash) {
+                Some(utxo_height) => *utxo_height,
+                None if self.mempool_outputs.contains_key(&input.prev_transaction_output_hash) => height,
+                None => return Err(BtcError::UnsatisfiedSequenceLock)
+            };
+
+            let satisfied = match lock {
+                RelativeLock::Blocks(blocks) => utxo_height + blocks <= height,
+                //measured from the median time past before the block that created the UTXO
+                RelativeLock::Seconds(seconds) => {
+                    self.median_time_past(utxo_height.max(1)) + seconds <= median_time_past
+                }
+            };
+
+            if !satisfied {
+                return Err(BtcError::UnsatisfiedSequenceLock)
+            }
+        }
+
+        Ok(())
+    }
+
+    #[instrument(
+        level = "debug",
+        skip_all,
+        err(level = "debug"),
+        fields(height = self.block_height(), hash = %block.hash())
+    )]
     pub fn add_block(&mut self, block: Block) -> Result<()> {
 
+        //Check if the block's target is the one the chain's rules ask for
+        if block.header.target != self.next_target() {
+            return Err(BtcError::InvalidBlock)
+        }
+
         if self.blocks.is_empty() {
 
-            ///Check if the previous block hash eq to zero
-            if block.header.prev_block_hash != Hash::zero() {
-                println!("zero hash");
-                return Err(BtcError::InvalidBlock)
+            //Check if it is the genesis block of the network
+            if block.hash() != self.params.genesis_block().hash() {
+                return Err(BtcError::GenesisMismatch)
             }
 
         } else {
 
             let last_block = self.blocks.last().unwrap();
 
-            ///Check if the prev block hash is equal to curr_block.header.prev_block_hash
+            //Check if the prev block hash is equal to curr_block.header.prev_block_hash
             if block.header.prev_block_hash != last_block.hash() {
-                println!("prev hash is wrong");
+                debug!("prev hash is wrong");
                 return Err(BtcError::InvalidBlock)
             }
 
-            ///Check if the block's hash does not match the target (need to be hash<target)
+            //Check if the block's hash does not match the target (need to be hash<target)
             if !block
                 .header
                 .hash()
                 .matches_target(block.header.target) {
 
-                println!("does not match target");
+                debug!("does not match target");
                 return Err(BtcError::InvalidBlock)
             }
 
-            ///Check if the Merkle root hash is correct
+            //Check if the Merkle root hash is correct
             let calculated_merkle_root_hash = MerkleRoot::calculate(&block.transactions);
             if calculated_merkle_root_hash != block.header.merkle_root {
-                println!("invalid merkle root hash");
+                debug!("invalid merkle root hash");
                 return Err(BtcError::InvalidMerkleRoot)
             }
 
-            ///Check if the block's timestamp is after last block's timestamp
+            //Check the block's size, weight and signature operations
+            block.check_limits(&self.utxos)?;
+
+            //Check if the coinbase commits to the signatures left out of the Merkle root
+            block.verify_witness_commitment()?;
+
+            //Check if the block's timestamp is after last block's timestamp
             if block.header.timestamp <= last_block.header.timestamp {
                 return Err(BtcError::InvalidBlock)
             }
 
+            //Signatures seen on mempool acceptance are served from the cache
+            block.verify_transactions(
+                &self.params,
+                self.block_height(),
+                &self.utxos,
+                &self.signature_cache
+            )?;
 
+            for transaction in block.transactions.iter().skip(1) {
+                self.check_lock_times(transaction, self.block_height())?;
+            }
         }
 
+        let height = self.block_height();
+        self.block_stats.push(block.stats(height, &self.utxos)?);
+        self.undo.push(
+            block
+                .transactions
+                .iter()
+                .flat_map(|transaction| transaction.inputs.iter())
+                .map(|input| {
+                    let hash = input.prev_transaction_output_hash;
+                    let height = self.utxo_heights.get(&hash).copied().unwrap_or_default();
+                    (hash, self.utxos[&hash].clone(), height)
+                })
+                .collect()
+        );
+        Self::apply_block_to_utxos(&mut self.utxos, &mut self.utxo_heights, &block, height);
+        self.remove_mined_from_mempool(&block);
+
+        self.block_index.insert(block.hash(), height);
         self.blocks.push(block);
+        self.update_deployment_states();
         Ok(())
     }
 
+    ///Undoes the last block and returns it, its transactions go back to the
+    ///mempool if they are still valid. The genesis block can't be disconnected,
+    ///nor can pruned blocks or those connected before undo data was kept
+    #[instrument(
+        level = "debug",
+        skip_all,
+        err(level = "debug"),
+        fields(height = self.block_height().saturating_sub(1))
+    )]
+    pub fn disconnect_tip(&mut self) -> Result<Block> {
+        if self.blocks.len() <= 1
+            || self.undo.len() != self.blocks.len()
+            || self.block_height() <= self.pruned_height {
+            return Err(BtcError::InvalidBlock)
+        }
+
+        let block = self.blocks.pop().unwrap();
+        self.block_index.remove(&block.hash());
+        let spent = self.undo.pop().unwrap();
+        self.block_stats.pop();
+
+        for output in block.transactions.iter().flat_map(|transaction| transaction.outputs.iter()) {
+            let output_hash = output.hash();
+            self.utxos.remove(&output_hash);
+            self.utxo_heights.remove(&output_hash);
+        }
+
+        for (hash, output, height) in spent {
+            self.utxos.insert(hash, output);
+            self.utxo_heights.insert(hash, height);
+        }
+
+        //states of the period the block was in have to be computed again
+        self.deployment_states.clear();
+        self.update_deployment_states();
+
+        for transaction in block.transactions.iter().skip(1) {
+            //those no longer valid at this height are dropped
+            let _ = self.add_to_mempool(transaction.clone());
+        }
+
+        //spends of the block's outputs went away with it, unless their
+        //transaction came back to the mempool
+        self.retain_mempool(|_| true);
+
+        Ok(block)
+    }
+
+    ///Height below which blocks were pruned, 0 when none were
+    pub fn pruned_height(&self) -> u64 {
+        self.pruned_height
+    }
+
+    ///Drops the transactions and undo data of all but the last `keep` blocks,
+    ///never fewer than MIN_BLOCKS_TO_KEEP. Their headers stay for the chain's
+    ///rules, but they can no longer be served or disconnected. Returns how many
+    ///blocks were pruned
+    pub fn prune(&mut self, keep: u64) -> u64 {
+        let end = self.block_height().saturating_sub(keep.max(MIN_BLOCKS_TO_KEEP));
+        if end <= self.pruned_height {
+            return 0
+        }
+
+        for height in self.pruned_height..end {
+            self.blocks[height as usize].transactions = Vec::new();
+            if let Some(undo) = self.undo.get_mut(height as usize) {
+                *undo = Vec::new();
+            }
+        }
+        let pruned = end - self.pruned_height;
+        self.pruned_height = end;
+        debug!(pruned, below = end, "pruned blocks");
+        pruned
+    }
+
+    ///Only valid on a chain that was never pruned
     pub fn rebuild_utxos(&mut self) {
-        for block in &self.blocks {
-            for transaction in &block.transactions {
-                for input in &transaction.inputs {
-                    self.utxos.remove(&input.prev_transaction_output_hash);
-                }
+        for (height, block) in self.blocks.iter().enumerate() {
+            Self::apply_block_to_utxos(&mut self.utxos, &mut self.utxo_heights, block, height as u64);
+        }
+    }
 
-                for output in &transaction.outputs {
-                    self.utxos.insert(
-                        output.hash(),
-                        output.clone()
-                    );
-                }
+    fn apply_block_to_utxos(
+        utxos: &mut HashMap<Hash, TransactionOutput>,
+        utxo_heights: &mut HashMap<Hash, u64>,
+        block: &Block,
+        height: u64
+    ) {
+        for transaction in &block.transactions {
+            for input in &transaction.inputs {
+                utxos.remove(&input.prev_transaction_output_hash);
+                utxo_heights.remove(&input.prev_transaction_output_hash);
+            }
+
+            //unspendable outputs never enter the UTXO set
+            for output in transaction.outputs.iter().filter(|output| !output.lock.is_unspendable()) {
+                let output_hash = output.hash();
+                utxos.insert(
+                    output_hash,
+                    output.clone()
+                );
+                utxo_heights.insert(output_hash, height);
+            }
+        }
+    }
+
+    ///Drops mined transactions and those conflicting with the block's spends,
+    ///along with their descendants
+    fn remove_mined_from_mempool(&mut self, block: &Block) {
+        let mined: HashSet<Hash> = block
+            .transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect();
+
+        self.retain_mempool(|transaction| !mined.contains(&transaction.hash()));
+    }
+
+    ///Validates an unconfirmed transaction against the UTXO set and the mempool
+    ///and keeps it for inclusion in a future block. It can spend outputs of
+    ///mempool transactions, but is only mined after them. Its signatures end up in
+    ///the signature cache, so connecting the block containing it skips them
+    #[instrument(
+        level = "debug",
+        skip_all,
+        err(level = "debug"),
+        fields(txid = %transaction.hash())
+    )]
+    pub fn add_to_mempool(&mut self, transaction: Transaction) -> Result<()> {
+        if transaction.inputs.is_empty() {
+            return Err(BtcError::InvalidTransaction)
+        }
+
+        let transaction_hash = transaction.hash();
+        if self.mempool_index.contains_key(&transaction_hash) {
+            return Err(BtcError::InvalidTransaction)
+        }
+
+        let spent_in_mempool: HashSet<Hash> = self
+            .mempool
+            .iter()
+            .flat_map(|tx| tx.inputs.iter())
+            .map(|input| input.prev_transaction_output_hash)
+            .collect();
+
+        let mut spent: HashSet<Hash> = HashSet::new();
+        let mut prev_outputs: HashMap<Hash, TransactionOutput> = HashMap::new();
+        let mut checks: Vec<SignatureCheck> = Vec::new();
+        let mut input_value = 0;
+
+        for input in &transaction.inputs {
+            let prev_output = self
+                .unspent_output(&input.prev_transaction_output_hash)
+                .ok_or(BtcError::MissingInputs)?;
+
+            //Avoiding double spending, inside the transaction and against the mempool
+            if !spent.insert(input.prev_transaction_output_hash)
+                || spent_in_mempool.contains(&input.prev_transaction_output_hash) {
+                return Err(BtcError::InvalidTransaction)
             }
+
+            checks.extend(input.signature_checks(
+                &transaction_hash,
+                prev_output,
+                transaction.lock_time,
+                transaction.version,
+                &self.signature_cache
+            )?);
+
+            input_value += prev_output.value;
+            prev_outputs.insert(input.prev_transaction_output_hash, prev_output.clone());
+        }
+
+        if transaction.outputs.iter().any(|output| !output.lock.is_valid()) {
+            return Err(BtcError::InvalidTransactionOutput)
+        }
+
+        policy::check_standard(&transaction, &prev_outputs)?;
+
+        let output_value: u64 = transaction
+            .outputs
+            .iter()
+            .map(|output| output.value)
+            .sum();
+
+        if input_value < output_value {
+            return Err(BtcError::InvalidTransaction)
+        }
+
+        //Only transactions that could go into the next block are accepted
+        self.check_lock_times(&transaction, self.block_height())?;
+
+        if !self.signature_cache.verify_batch(&checks) {
+            return Err(BtcError::InvalidSignature)
         }
+
+        self.mempool_index.insert(transaction_hash, self.mempool.len());
+        self.mempool.push(transaction);
+        self.index_mempool_outputs(self.mempool.len() - 1);
+        Ok(())
+    }
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -106,13 +819,171 @@ impl Block {
         }
     }
 
+    ///Blocks are identified by their header, which commits to the transactions,
+    ///so header chains link up before any body is downloaded
     pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+        self.header.hash()
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    ///Whether pruning dropped the transactions, a block always has a coinbase
+    pub fn is_pruned(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    ///Serialized size with witness data
+    pub fn size(&self) -> usize {
+        util::serialized_size(self)
     }
 
-    pub fn verify_transactions(&self, predicted_block_height: u64, utxos: &HashMap<Hash, TransactionOutput>) -> Result<()> {
+    ///Header and transaction weights, witness bytes count a quarter of the others
+    pub fn weight(&self) -> usize {
+        util::serialized_size(&self.header) * WITNESS_SCALE_FACTOR
+            + self.transactions.iter().map(Transaction::weight).sum::<usize>()
+    }
+
+    pub fn witness_commitment(transactions: &[Transaction]) -> Hash {
+        Hash::hash(&MerkleRoot::calculate_witness(transactions))
+    }
+
+    ///Adds (or replaces) the witness commitment output of the coinbase, the first
+    ///transaction. Has to happen before the Merkle root is calculated
+    pub fn commit_witnesses(transactions: &mut [Transaction]) {
+        let commitment = Self::witness_commitment(transactions);
+
+        if let Some(coinbase) = transactions.first_mut() {
+            coinbase
+                .outputs
+                .retain(|output| witness_commitment_of(output).is_none());
+            coinbase.outputs.push(TransactionOutput::witness_commitment(commitment));
+        }
+    }
+
+    ///Signature operations of the block's transactions, counted on the outputs they spend
+    pub fn sigop_count(&self, utxos: &HashMap<Hash, TransactionOutput>) -> usize {
+        self.transactions
+            .iter()
+            .map(|transaction| transaction.sigop_count(utxos))
+            .sum()
+    }
+
+    pub fn check_limits(&self, utxos: &HashMap<Hash, TransactionOutput>) -> Result<()> {
+        if self.size() > crate::MAX_BLOCK_SIZE
+            || self.weight() > crate::MAX_BLOCK_WEIGHT
+            || self.sigop_count(utxos) > crate::MAX_BLOCK_SIGOPS {
+            return Err(BtcError::BlockLimitsExceeded)
+        }
+
+        Ok(())
+    }
+
+    ///Statistics of the block at height, utxos has to still hold the outputs it spends
+    pub fn stats(&self, height: u64, utxos: &HashMap<Hash, TransactionOutput>) -> Result<BlockStats> {
+        Ok(BlockStats {
+            height,
+            transactions: self.transactions.len(),
+            inputs: self.transactions.iter().map(|tx| tx.inputs.len()).sum(),
+            outputs: self.transactions.iter().map(|tx| tx.outputs.len()).sum(),
+            size: self.size(),
+            weight: self.weight(),
+            sigops: self.sigop_count(utxos),
+            fees: self.calculate_miner_fees(utxos)?
+        })
+    }
+
+    ///Checks the body against its header: the Merkle root, then the witness
+    ///commitment. A block failing it may be a corrupted copy of a valid one
+    pub fn verify_commitments(&self) -> Result<()> {
+        if MerkleRoot::calculate(&self.transactions) != self.header.merkle_root {
+            return Err(BtcError::InvalidMerkleRoot)
+        }
+        self.verify_witness_commitment()
+    }
+
+    ///A block with witness data needs a matching commitment in its coinbase,
+    ///a block without may leave it out
+    pub fn verify_witness_commitment(&self) -> Result<()> {
+        let Some(coinbase) = self.transactions.first() else {
+            return Err(BtcError::InvalidBlock)
+        };
+
+        match coinbase.witness_commitment() {
+            Some(commitment) => {
+                if commitment != Self::witness_commitment(&self.transactions) {
+                    return Err(BtcError::InvalidWitnessCommitment)
+                }
+            }
+            None => {
+                let has_witness = self
+                    .transactions
+                    .iter()
+                    .flat_map(|transaction| transaction.inputs.iter())
+                    .any(TransactionInput::has_witness);
+
+                if has_witness {
+                    return Err(BtcError::InvalidWitnessCommitment)
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    ///Verifies the block's transactions, checking the input signatures missing
+    ///from the signature cache in parallel
+    pub fn verify_transactions(
+        &self,
+        params: &ChainParams,
+        predicted_block_height: u64,
+        utxos: &HashMap<Hash, TransactionOutput>,
+        signature_cache: &SignatureCache
+    ) -> Result<()> {
+        let checks = self.collect_signature_checks(params, predicted_block_height, utxos, signature_cache)?;
+
+        if !signature_cache.verify_batch(&checks) {
+            return Err(BtcError::InvalidSignature)
+        }
+
+        Ok(())
+    }
+
+    ///Same as verify_transactions, but checks the signatures one by one on the calling thread
+    pub fn verify_transactions_serial(
+        &self,
+        params: &ChainParams,
+        predicted_block_height: u64,
+        utxos: &HashMap<Hash, TransactionOutput>
+    ) -> Result<()> {
+        //scripts run while collecting, an empty cache keeps them uncached
+        let checks = self.collect_signature_checks(params, predicted_block_height, utxos, &SignatureCache::new(0))?;
+
+        if !crypto::verify_serial(&checks) {
+            return Err(BtcError::InvalidSignature)
+        }
+
+        Ok(())
+    }
+
+    ///Runs every transaction check except signature verification and
+    ///returns the (message, signature, key) triples that still need verifying.
+    ///Script locked inputs are evaluated here, their signatures go through signature_cache
+    pub fn collect_signature_checks(
+        &self,
+        params: &ChainParams,
+        predicted_block_height: u64,
+        utxos: &HashMap<Hash, TransactionOutput>,
+        signature_cache: &SignatureCache
+    ) -> Result<Vec<SignatureCheck>> {
 
         let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
+        let mut checks: Vec<SignatureCheck> = Vec::new();
 
         if self.transactions.is_empty() {
             return Err(BtcError::InvalidBlock)
@@ -120,6 +991,7 @@ impl Block {
 
         // verify coinbase transaction
         self.verify_coinbase_transaction(
+            params,
             predicted_block_height,
             utxos
         )?;
@@ -127,11 +999,12 @@ impl Block {
         for transaction in self.transactions.iter().skip(1) {
             let mut input_value = 0;
             let mut output_value = 0;
+            let txid = transaction.hash();
 
 
             for input in &transaction.inputs {
 
-                ///Check if needed utxo output exists
+                //Check if needed utxo output exists
                 let prev_output = utxos.get(
                     &input.prev_transaction_output_hash
                 );
@@ -142,17 +1015,18 @@ impl Block {
 
                 let prev_output = prev_output.unwrap();
 
-                ///Avoiding double spending
+                //Avoiding double spending
                 if inputs.contains_key(&input.prev_transaction_output_hash) {
                     return Err(BtcError::InvalidTransaction)
                 }
 
-                if !input.signature.verify(
-                    &input.prev_transaction_output_hash,
-                    &prev_output.pubkey
-                ) {
-                    return Err(BtcError::InvalidSignature)
-                }
+                checks.extend(input.signature_checks(
+                    &txid,
+                    prev_output,
+                    transaction.lock_time,
+                    transaction.version,
+                    signature_cache
+                )?);
 
                 input_value += prev_output.value;
                 inputs.insert(input.prev_transaction_output_hash, prev_output.clone());
@@ -160,6 +1034,10 @@ impl Block {
             }
 
             for output in &transaction.outputs {
+                if !output.lock.is_valid() {
+                    return Err(BtcError::InvalidTransactionOutput)
+                }
+
                 output_value += output.value
             }
 
@@ -168,33 +1046,32 @@ impl Block {
             }
         }
 
-        Ok(())
+        Ok(checks)
     }
 
     pub fn verify_coinbase_transaction(
         &self,
+        params: &ChainParams,
         predicted_block_height: u64,
         utxos: &HashMap<Hash, TransactionOutput>
     ) -> Result<()> {
         let coinbase_tx = &self.transactions[0];
 
-        if coinbase_tx.inputs.len() != 0 {
+        if !coinbase_tx.inputs.is_empty() {
             return Err(BtcError::InvalidTransaction)
         }
 
-        if coinbase_tx.outputs.len() == 0 {
+        if coinbase_tx.outputs.is_empty() {
             return Err(BtcError::InvalidTransaction)
         }
 
+        if coinbase_tx.outputs.iter().any(|output| !output.lock.is_valid()) {
+            return Err(BtcError::InvalidTransactionOutput)
+        }
+
         let miner_fees = self.calculate_miner_fees(utxos)?;
 
-        let block_reward = crate::INITIAL_REWARD
-            * 10u64.pow(8)
-            / 2u64.pow(
-            (predicted_block_height
-                / crate::HALVING_INTERVAL)
-                as u32,
-        );
+        let block_reward = params.block_reward(predicted_block_height);
 
         let total_coinbase_outputs: u64 = coinbase_tx
             .outputs
@@ -269,12 +1146,34 @@ impl Block {
             .map(|output| output.value)
             .sum();
 
-        Ok(inputs_value - outputs_value)
+        //checked before the transactions are, outputs can still exceed inputs
+        inputs_value.checked_sub(outputs_value).ok_or(BtcError::InvalidTransaction)
     }
 }
 
+//weight and size kept free for the coinbase when filling a block template
+const COINBASE_RESERVED_WEIGHT: usize = 4_000;
+const COINBASE_RESERVED_SIZE: usize = 1_000;
+
+///Blocks a pruned chain keeps in full, so it can still follow reorganizations
+pub const MIN_BLOCKS_TO_KEEP: u64 = 288;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    pub height: u64,
+    pub transactions: usize,
+    pub inputs: usize,
+    pub outputs: usize,
+    pub size: usize,
+    pub weight: usize,
+    pub sigops: usize,
+    pub fees: u64
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BlockHeader {
+    ///Version bits signalling deployments, see deployment
+    pub version: u32,
     ///Timestamp of the block
     pub timestamp: DateTime<Utc>,
     ///Nonce used to mine the block
@@ -296,6 +1195,7 @@ impl BlockHeader {
         target: U256
     ) -> Self {
         BlockHeader {
+            version: VERSIONBITS_TOP_BITS,
             timestamp,
             nonce,
             prev_block_hash,
@@ -304,15 +1204,81 @@ impl BlockHeader {
         }
     }
 
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
     pub fn hash(&self) -> Hash {
         Hash::hash(self)
     }
+
+    ///Expected number of hashes needed to find a block at the header's target
+    pub fn work(&self) -> U256 {
+        if self.target == U256::MAX {
+            return U256::one()
+        }
+
+        !self.target / (self.target + 1) + 1
+    }
+
+    ///Tries up to steps nonces, returns whether the hash now matches the target
+    pub fn mine(&mut self, steps: usize) -> bool {
+        for _ in 0..steps {
+            if self.hash().matches_target(self.target) {
+                return true
+            }
+            self.nonce = self.nonce.wrapping_add(1);
+        }
+
+        self.hash().matches_target(self.target)
+    }
+}
+
+//bytes outside the witness weigh this much more than witness bytes
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+
+//marks the coinbase output holding the witness commitment
+pub const WITNESS_COMMITMENT_HEADER: [u8; 4] = [0xaa, 0x21, 0xa9, 0xed];
+
+fn witness_commitment_of(output: &TransactionOutput) -> Option<Hash> {
+    let payload = output.data_carrier_payload()?;
+    let commitment = payload.strip_prefix(&WITNESS_COMMITMENT_HEADER)?;
+
+    Some(Hash::from_bytes(commitment.try_into().ok()?))
+}
+
+//Transaction as serialized for its txid, leaving out the inputs' witnesses
+#[derive(Serialize)]
+struct StrippedTransaction<'a> {
+    version: u32,
+    inputs: Vec<StrippedInput>,
+    outputs: &'a [TransactionOutput],
+    lock_time: u64
+}
+
+#[derive(Serialize)]
+struct StrippedInput {
+    prev_transaction_output_hash: Hash,
+    sequence: u32
+}
+
+//version of newly built transactions
+pub const TRANSACTION_VERSION: u32 = 2;
+
+//sighash of an input, the txid of a transaction is computed once for all its inputs
+fn signature_hash(txid: &Hash, prev_transaction_output_hash: &Hash) -> Hash {
+    Hash::hash(&(txid, prev_transaction_output_hash))
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Transaction {
+    version: u32,
     inputs: Vec<TransactionInput>,
-    outputs: Vec<TransactionOutput>
+    outputs: Vec<TransactionOutput>,
+    ///Block height (below timelock::LOCKTIME_THRESHOLD) or unix timestamp
+    ///before which the transaction can't be mined, 0 for none
+    lock_time: u64
 }
 
 impl Transaction {
@@ -321,14 +1287,137 @@ impl Transaction {
         outputs: Vec<TransactionOutput>
     ) -> Transaction {
         Transaction {
+            version: TRANSACTION_VERSION,
             inputs,
-            outputs
+            outputs,
+            lock_time: 0
+        }
+    }
+
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    ///Only has an effect if at least one input's sequence isn't SEQUENCE_FINAL
+    pub fn with_lock_time(mut self, lock_time: u64) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    pub fn lock_time(&self) -> u64 {
+        self.lock_time
+    }
+
+    ///Whether the lock time allows inclusion in the block at height,
+    ///time locks are compared against the median time past
+    pub fn is_final(&self, height: u64, median_time_past: i64) -> bool {
+        if self.lock_time == 0 {
+            return true
+        }
+
+        let reached = if timelock::is_height_lock(self.lock_time) {
+            self.lock_time < height
+        } else {
+            (self.lock_time as i64) < median_time_past
+        };
+
+        reached || self.inputs.iter().all(|input| input.sequence == SEQUENCE_FINAL)
+    }
+
+    fn stripped(&self) -> StrippedTransaction<'_> {
+        StrippedTransaction {
+            version: self.version,
+            inputs: self
+                .inputs
+                .iter()
+                .map(|input| StrippedInput {
+                    prev_transaction_output_hash: input.prev_transaction_output_hash,
+                    sequence: input.sequence
+                })
+                .collect(),
+            outputs: &self.outputs,
+            lock_time: self.lock_time
         }
     }
 
+    ///The txid. Signatures and unlocking scripts are left out, so co-signing or
+    ///re-encoding them doesn't change it
     pub fn hash(&self) -> Hash {
+        Hash::hash(&self.stripped())
+    }
+
+    ///What the signatures of the input spending prev_transaction_output_hash sign.
+    ///The txid commits to every input's outpoint and sequence, the outputs, the
+    ///lock time and the version, so a signature can't be moved onto other outputs,
+    ///and the spent output ties it to its input
+    pub fn sighash(&self, prev_transaction_output_hash: &Hash) -> Hash {
+        signature_hash(&self.hash(), prev_transaction_output_hash)
+    }
+
+    ///Signs the input at index with the key, over its sighash. The public key
+    ///picks the signature scheme, key_index its place in a multisig lock
+    pub fn sign_input(&mut self, index: usize, key_index: u8, private_key: &PrivateKey, public_key: &PublicKey) {
+        let sighash = self.sighash(&self.inputs[index].prev_transaction_output_hash);
+        let signature = Signature::sign_output_for(&sighash, private_key, public_key);
+        self.inputs[index].add_signature(key_index, signature);
+    }
+
+    ///Hash over the whole transaction, witness data included
+    pub fn wtxid(&self) -> Hash {
         Hash::hash(self)
     }
+
+    ///Serialized size without witness data
+    pub fn base_size(&self) -> usize {
+        util::serialized_size(&self.stripped())
+    }
+
+    ///Serialized size with witness data
+    pub fn total_size(&self) -> usize {
+        util::serialized_size(self)
+    }
+
+    ///base size * 3 + total size, so witness bytes count once and the others four times
+    pub fn weight(&self) -> usize {
+        self.base_size() * (WITNESS_SCALE_FACTOR - 1) + self.total_size()
+    }
+
+    ///Weight in units comparable to bytes
+    pub fn virtual_size(&self) -> usize {
+        self.weight().div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
+    ///Signature operations of the inputs, counted on the locks of the outputs they spend
+    pub fn sigop_count(&self, utxos: &HashMap<Hash, TransactionOutput>) -> usize {
+        self.inputs
+            .iter()
+            .filter_map(|input| utxos.get(&input.prev_transaction_output_hash))
+            .map(|prev_output| prev_output.lock.sigop_count())
+            .sum()
+    }
+
+    ///Witness commitment of a coinbase transaction
+    pub fn witness_commitment(&self) -> Option<Hash> {
+        self.outputs.iter().rev().find_map(witness_commitment_of)
+    }
+
+    pub fn inputs(&self) -> &[TransactionInput] {
+        &self.inputs
+    }
+
+    ///Used by co-signers to attach their signatures to a shared transaction
+    pub fn inputs_mut(&mut self) -> &mut [TransactionInput] {
+        &mut self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TransactionOutput] {
+        &self.outputs
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -336,10 +1425,268 @@ impl Transaction {
 pub struct TransactionInput {
     ///Points to exact UTXO (unspent output) {Tx hash and index of exact output}
     pub prev_transaction_output_hash: Hash,
-    ///Signature is used for verifying accessory to specific output (ability to spend)
+    ///Signatures are used for verifying accessory to specific output (ability to spend),
+    ///ordered by the index of the key they belong to
+    pub signatures: Vec<InputSignature>,
+    ///Push-only unlocking script, used instead of signatures for script locked outputs
+    pub script_sig: Script,
+    ///SEQUENCE_FINAL, or a relative lock time (see timelock::RelativeLock)
+    pub sequence: u32
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+///Signature together with the position of its key in the spent output's locking condition
+pub struct InputSignature {
+    pub key_index: u8,
     pub signature: Signature
 }
 
+impl TransactionInput {
+    ///Input spending an output locked to a single key
+    pub fn new(prev_transaction_output_hash: Hash, signature: Signature) -> Self {
+        TransactionInput {
+            prev_transaction_output_hash,
+            signatures: vec![InputSignature {
+                key_index: 0,
+                signature
+            }],
+            script_sig: Script::default(),
+            sequence: SEQUENCE_FINAL
+        }
+    }
+
+    ///Input spending a script locked output
+    pub fn with_script(prev_transaction_output_hash: Hash, script_sig: Script) -> Self {
+        TransactionInput {
+            prev_transaction_output_hash,
+            signatures: Vec::new(),
+            script_sig,
+            sequence: SEQUENCE_FINAL
+        }
+    }
+
+    ///Input without signatures yet, to be co-signed with add_signature
+    pub fn unsigned(prev_transaction_output_hash: Hash) -> Self {
+        TransactionInput {
+            prev_transaction_output_hash,
+            signatures: Vec::new(),
+            script_sig: Script::default(),
+            sequence: SEQUENCE_FINAL
+        }
+    }
+
+    ///Signatures and unlocking script make up the witness, left out of the txid
+    pub fn has_witness(&self) -> bool {
+        !self.signatures.is_empty() || !self.script_sig.is_empty()
+    }
+
+    ///Adds (or replaces) the signature for the key at key_index, keeping signatures sorted
+    pub fn add_signature(&mut self, key_index: u8, signature: Signature) {
+        self.signatures.retain(|existing| existing.key_index != key_index);
+        self.signatures.push(InputSignature {
+            key_index,
+            signature
+        });
+        self.signatures.sort_by_key(|existing| existing.key_index);
+    }
+
+    ///Checks the signatures' form against the spent output's locking condition
+    ///and returns the signature checks that still have to be verified, against
+    ///the input's sighash in the transaction with txid. Script locks are
+    ///evaluated right away, verifying through signature_cache
+    pub fn signature_checks(
+        &self,
+        txid: &Hash,
+        prev_output: &TransactionOutput,
+        lock_time: u64,
+        version: u32,
+        signature_cache: &SignatureCache
+    ) -> Result<Vec<SignatureCheck>> {
+        let sighash = signature_hash(txid, &self.prev_transaction_output_hash);
+
+        let pubkeys = match &prev_output.lock {
+            LockingCondition::PublicKey(pubkey) => std::slice::from_ref(pubkey),
+            LockingCondition::Multisig { pubkeys, .. } => pubkeys.as_slice(),
+            LockingCondition::Script(script_pubkey) => {
+                if !self.signatures.is_empty() {
+                    return Err(BtcError::InvalidSignature)
+                }
+
+                script::verify_script(&self.script_sig, script_pubkey, ScriptContext {
+                    sighash,
+                    signature_cache,
+                    lock_time,
+                    sequence: self.sequence,
+                    version
+                })?;
+
+                return Ok(Vec::new())
+            }
+        };
+
+        if !self.script_sig.is_empty() {
+            return Err(BtcError::InvalidTransactionInput)
+        }
+
+        let required = prev_output.lock.required_signatures();
+
+        if self.signatures.len() != required {
+            return Err(BtcError::InvalidSignature)
+        }
+
+        let mut checks = Vec::with_capacity(required);
+        let mut previous_index: Option<u8> = None;
+
+        for input_signature in &self.signatures {
+            //Strictly increasing indexes: every key signs at most once and the order is canonical
+            if previous_index.is_some_and(|previous| input_signature.key_index <= previous) {
+                return Err(BtcError::InvalidSignature)
+            }
+            previous_index = Some(input_signature.key_index);
+
+            let pubkey = pubkeys
+                .get(input_signature.key_index as usize)
+                .ok_or(BtcError::InvalidSignature)?;
+            let signature = &input_signature.signature;
+
+            // The key type decides the signature scheme
+            if signature.scheme() != pubkey.scheme() {
+                return Err(BtcError::InvalidSignature)
+            }
+
+            //Requiring low-S ECDSA signatures prevents wtxid malleability
+            if !signature.is_low_s() {
+                return Err(BtcError::NonCanonicalSignature)
+            }
+
+            checks.push(SignatureCheck::new(
+                sighash,
+                signature.clone(),
+                pubkey.clone()
+            ));
+        }
+
+        Ok(checks)
+    }
+}
+
+//same limit as bare multisig in Bitcoin
+pub const MAX_MULTISIG_KEYS: usize = 20;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+///Who can spend an output
+pub enum LockingCondition {
+    ///One signature by the key
+    PublicKey(PublicKey),
+    ///Signatures by `threshold` distinct keys out of `pubkeys`
+    Multisig {
+        threshold: u8,
+        pubkeys: Vec<PublicKey>
+    },
+    ///Spendable by an unlocking script that makes the script_pubkey evaluate to true
+    Script(Script)
+}
+
+//a key listed twice would let one signer count twice towards the threshold. The same
+//secret under both schemes shares the x coordinate, so that is a duplicate too
+fn has_duplicate_keys(pubkeys: &[PublicKey]) -> bool {
+    let x_only = |pubkey: &PublicKey| match pubkey {
+        PublicKey::Ecdsa(_) => pubkey.to_bytes()[1..].to_vec(),
+        PublicKey::Schnorr(_) => pubkey.to_bytes()
+    };
+    let keys: Vec<Vec<u8>> = pubkeys.iter().map(x_only).collect();
+
+    keys.iter().enumerate().any(|(i, key)| keys[..i].contains(key))
+}
+
+impl LockingCondition {
+    pub fn multisig(threshold: u8, pubkeys: Vec<PublicKey>) -> Result<Self> {
+        let lock = LockingCondition::Multisig { threshold, pubkeys };
+
+        if !lock.is_valid() {
+            return Err(BtcError::InvalidTransactionOutput)
+        }
+
+        Ok(lock)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match self {
+            LockingCondition::PublicKey(_) => true,
+            LockingCondition::Multisig { threshold, pubkeys } => {
+                *threshold >= 1
+                    && (*threshold as usize) <= pubkeys.len()
+                    && pubkeys.len() <= MAX_MULTISIG_KEYS
+                    && !has_duplicate_keys(pubkeys)
+            }
+            LockingCondition::Script(script_pubkey) => script_pubkey.size() <= script::MAX_SCRIPT_SIZE
+        }
+    }
+
+    pub fn required_signatures(&self) -> usize {
+        match self {
+            LockingCondition::PublicKey(_) => 1,
+            LockingCondition::Multisig { threshold, .. } => *threshold as usize,
+            //scripts carry their own signatures in script_sig
+            LockingCondition::Script(_) => 0
+        }
+    }
+
+    ///Signature checks spending an output with this lock takes
+    pub fn sigop_count(&self) -> usize {
+        match self {
+            LockingCondition::PublicKey(_) => 1,
+            LockingCondition::Multisig { threshold, .. } => *threshold as usize,
+            LockingCondition::Script(script_pubkey) => script_pubkey.sigop_count()
+        }
+    }
+
+    ///Outputs nobody can spend, like data carriers
+    pub fn is_unspendable(&self) -> bool {
+        match self {
+            LockingCondition::Script(script_pubkey) => script_pubkey.is_unspendable(),
+            _ => false
+        }
+    }
+
+    ///Position of the key in the condition, used as the key index of its signature
+    pub fn key_index(&self, pubkey: &PublicKey) -> Option<u8> {
+        match self {
+            LockingCondition::PublicKey(key) => (key == pubkey).then_some(0),
+            LockingCondition::Multisig { pubkeys, .. } => pubkeys
+                .iter()
+                .position(|key| key == pubkey)
+                .map(|index| index as u8),
+            LockingCondition::Script(_) => None
+        }
+    }
+}
+
+impl Display for LockingCondition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LockingCondition::PublicKey(pubkey) => write!(f, "pubkey {}", hex::encode(pubkey.to_bytes())),
+            LockingCondition::Multisig { threshold, pubkeys } => {
+                write!(f, "multisig {threshold}-of-{}", pubkeys.len())?;
+                for pubkey in pubkeys {
+                    write!(f, " {}", hex::encode(pubkey.to_bytes()))?;
+                }
+                Ok(())
+            }
+            LockingCondition::Script(script_pubkey) => match script_pubkey.data_carrier_payload() {
+                Some(data) => write!(f, "data {}", hex::encode(data)),
+                None => write!(f, "script {script_pubkey}")
+            }
+        }
+    }
+}
+
+impl From<PublicKey> for LockingCondition {
+    fn from(pubkey: PublicKey) -> Self {
+        LockingCondition::PublicKey(pubkey)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 ///How much and for whom exact output should be spent
 pub struct TransactionOutput {
@@ -347,13 +1694,40 @@ pub struct TransactionOutput {
     pub value: u64,
     ///ID of specific output (index)
     pub unique_id: Uuid,
-    ///Pubkey of recipient
-    pub pubkey: PublicKey
+    ///Condition the recipient has to satisfy to spend the output
+    pub lock: LockingCondition
 }
 
 impl TransactionOutput {
+    pub fn new(value: u64, lock: LockingCondition) -> Self {
+        TransactionOutput {
+            value,
+            unique_id: Uuid::new_v4(),
+            lock
+        }
+    }
+
+    ///Zero value output carrying data, it never enters the UTXO set
+    pub fn data_carrier(data: &[u8]) -> Self {
+        Self::new(0, LockingCondition::Script(Script::data_carrier(data)))
+    }
+
+    ///Coinbase output committing to the block's witness Merkle root
+    pub fn witness_commitment(commitment: Hash) -> Self {
+        let mut payload = WITNESS_COMMITMENT_HEADER.to_vec();
+        payload.extend_from_slice(&commitment.as_bytes());
+
+        Self::data_carrier(&payload)
+    }
+
+    pub fn data_carrier_payload(&self) -> Option<&[u8]> {
+        match &self.lock {
+            LockingCondition::Script(script_pubkey) => script_pubkey.data_carrier_payload(),
+            _ => None
+        }
+    }
+
     pub fn hash(&self) -> Hash {
         Hash::hash(self)
     }
 }
-
@@ -1,24 +1,43 @@
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
+use std::path::Path;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::crypto::{PrivateKey, PublicKey};
 use crate::sha256::Hash;
-use crate::types::Transaction;
+use crate::types::{Block, Blockchain, Transaction};
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MerkleRoot(Hash);
 
 impl MerkleRoot {
+    ///Root over the transactions' txids, which leave out witness data
     pub fn calculate(transactions: &[Transaction]) -> Self {
-        let mut layer: Vec<Hash> = Vec::new();
+        Self::from_hashes(transactions.iter().map(Transaction::hash).collect())
+    }
+
+    ///Root over the transactions' wtxids, the coinbase counts as the zero hash
+    ///since it holds the commitment to this root
+    pub fn calculate_witness(transactions: &[Transaction]) -> Self {
+        let wtxids = transactions
+            .iter()
+            .enumerate()
+            .map(|(i, tx)| if i == 0 { Hash::zero() } else { tx.wtxid() })
+            .collect();
+
+        Self::from_hashes(wtxids)
+    }
 
-        for tx in transactions {
-            layer.push(Hash::hash(&tx))
-        };
+    fn from_hashes(mut layer: Vec<Hash>) -> Self {
 
         while layer.len() > 1 {
             let mut new_layer: Vec<Hash> = Vec::new();
 
             for pair_hash_of_tx in layer.chunks(2) {
                 let left_tx_hash = pair_hash_of_tx[0];
-                ///if there is no right, use the left hash again
+                //if there is no right, use the left hash again
                 let right_tx_hash = pair_hash_of_tx.get(1).unwrap_or(&pair_hash_of_tx[0]);
 
                 new_layer.push(Hash::hash(&[left_tx_hash, *right_tx_hash]))
@@ -30,3 +49,69 @@ impl MerkleRoot {
     }
 }
 
+impl Display for MerkleRoot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+
+///Length of the value's CBOR serialization
+pub fn serialized_size<T: Serialize>(data: &T) -> usize {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    ciborium::into_writer(data, &mut counter).expect("serializing to a counter can't fail");
+    counter.0
+}
+
+///First four bytes of the data's double SHA-256, guards addresses and network messages
+pub fn checksum(data: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(Sha256::digest(data));
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+///CBOR persistence for keys, transactions and chain state
+pub trait Saveable: Serialize + DeserializeOwned {
+    fn load<I: Read>(reader: I) -> IoResult<Self> {
+        ciborium::from_reader(reader)
+            .map_err(|e| IoError::new(IoErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
+        ciborium::into_writer(self, writer)
+            .map_err(|e| IoError::new(IoErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn load_from_file<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        Self::load(File::open(path)?)
+    }
+
+    fn save_to_file<P: AsRef<Path>>(&self, path: P) -> IoResult<()> {
+        self.save(File::create(path)?)
+    }
+}
+
+impl Saveable for PrivateKey {}
+impl Saveable for PublicKey {}
+impl Saveable for Transaction {}
+impl Saveable for Block {}
+impl Saveable for Blockchain {
+    fn load<I: Read>(reader: I) -> IoResult<Self> {
+        let mut chain: Blockchain = ciborium::from_reader(reader)
+            .map_err(|e| IoError::new(IoErrorKind::InvalidData, e.to_string()))?;
+        chain.rebuild_indexes();
+        Ok(chain)
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use lib::addrman::{
+    address_group, AddrMan, ADDRESS_HORIZON, BUCKET_SIZE, NEW_BUCKETS_PER_SOURCE_GROUP, RETRY_DELAY,
+};
+use lib::network::NetAddress;
+use lib::util::Saveable;
+
+const NOW: i64 = 1_700_000_000;
+
+fn address(addr: &str) -> NetAddress {
+    NetAddress { addr: addr.parse().unwrap(), timestamp: NOW }
+}
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
+
+#[test]
+fn address_groups() {
+    assert_eq!(address_group(ip("1.2.3.4")), address_group(ip("1.2.200.9")));
+    assert_ne!(address_group(ip("1.2.3.4")), address_group(ip("1.3.3.4")));
+    assert_eq!(address_group(ip("::ffff:1.2.3.4")), address_group(ip("1.2.3.4")));
+    assert_eq!(address_group(ip("2001:db8:1::1")), address_group(ip("2001:db8:2::1")));
+    assert_ne!(address_group(ip("2001:db8::1")), address_group(ip("2001:db9::1")));
+    assert_eq!(address_group(ip("127.0.0.1")), address_group(ip("::1")));
+}
+
+#[test]
+fn addresses_move_to_tried_on_success() {
+    let mut book = AddrMan::new();
+    let source = ip("5.6.7.8");
+
+    assert!(book.add(address("1.2.3.4:8333"), source, NOW));
+    assert!(!book.add(address("1.2.3.4:8333"), source, NOW));
+    assert_eq!((book.len(), book.tried_count()), (1, 0));
+
+    // not connectable, or not heard of for too long
+    assert!(!book.add(address("0.0.0.0:8333"), source, NOW));
+    assert!(!book.add(address("1.2.3.5:0"), source, NOW));
+    let stale = NetAddress { timestamp: NOW - ADDRESS_HORIZON - 1, ..address("1.2.3.6:8333") };
+    assert!(!book.add(stale, source, NOW));
+
+    // times in the future are capped
+    let mut other = AddrMan::new();
+    assert!(other.add(NetAddress { timestamp: NOW + 3600, ..address("9.9.9.9:8333") }, source, NOW));
+    assert_eq!(other.get(&"9.9.9.9:8333".parse().unwrap()).unwrap().last_seen, NOW);
+
+    let addr: SocketAddr = "1.2.3.4:8333".parse().unwrap();
+    book.attempt(&addr, NOW);
+    book.good(&addr, NOW + 10);
+    let info = book.get(&addr).unwrap();
+    assert!(info.tried);
+    assert_eq!((info.attempts, info.last_success), (0, NOW + 10));
+    assert_eq!((book.len(), book.tried_count()), (1, 1));
+}
+
+#[test]
+fn one_source_fills_few_buckets() {
+    let mut book = AddrMan::new();
+    let source = ip("5.6.7.8");
+
+    for i in 0..4_000u32 {
+        let [_, a, b, c] = i.to_be_bytes();
+        book.add(address(&format!("{}.{b}.{c}.1:8333", a + 10)), source, NOW);
+    }
+    assert!(book.len() as u64 <= NEW_BUCKETS_PER_SOURCE_GROUP * BUCKET_SIZE);
+
+    // other sources still find room
+    let before = book.len();
+    for i in 0..50u8 {
+        book.add(address(&format!("20.{i}.0.1:8333")), ip(&format!("30.{i}.0.1")), NOW);
+    }
+    assert!(book.len() > before + 25);
+}
+
+#[test]
+fn selection_skips_excluded_groups_and_waits_to_retry() {
+    let mut book = AddrMan::new();
+    let addr: SocketAddr = "1.2.3.4:8333".parse().unwrap();
+    book.add(address("1.2.3.4:8333"), ip("5.6.7.8"), NOW);
+
+    assert_eq!(book.select(&HashSet::new(), &HashSet::new(), NOW), Some(addr));
+    let excluded = HashSet::from([address_group(addr.ip())]);
+    assert_eq!(book.select(&excluded, &HashSet::new(), NOW), None);
+    assert_eq!(book.select(&HashSet::new(), &HashSet::from([addr]), NOW), None);
+
+    // each failure doubles the wait
+    book.attempt(&addr, NOW);
+    assert_eq!(book.select(&HashSet::new(), &HashSet::new(), NOW + RETRY_DELAY - 1), None);
+    assert_eq!(book.select(&HashSet::new(), &HashSet::new(), NOW + RETRY_DELAY), Some(addr));
+    book.attempt(&addr, NOW + RETRY_DELAY);
+    assert_eq!(book.get(&addr).unwrap().retry_delay(), 2 * RETRY_DELAY);
+
+    // never reached after three tries, it is given up
+    book.attempt(&addr, NOW + 10 * RETRY_DELAY);
+    assert_eq!(book.select(&HashSet::new(), &HashSet::new(), NOW + 100 * RETRY_DELAY), None);
+    assert!(book.sample(10, NOW).is_empty());
+}
+
+#[test]
+fn the_book_survives_a_restart() {
+    let mut book = AddrMan::new();
+    let added: Vec<NetAddress> = (0..20u8)
+        .map(|i| address(&format!("1.{i}.0.1:8333")))
+        .filter(|address| book.add(*address, ip("5.6.7.8"), NOW))
+        .collect();
+    let tried = added[0].addr;
+    book.good(&tried, NOW);
+
+    let mut bytes = Vec::new();
+    book.save(&mut bytes).unwrap();
+    let mut loaded = AddrMan::load(bytes.as_slice()).unwrap();
+
+    assert_eq!(loaded.len(), book.len());
+    assert!(loaded.get(&tried).unwrap().tried);
+    // known addresses stay single entries
+    for address in &added {
+        assert!(!loaded.add(*address, ip("5.6.7.8"), NOW));
+    }
+    assert_eq!(loaded.sample(100, NOW).len(), loaded.len());
+}
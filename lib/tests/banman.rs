@@ -0,0 +1,88 @@
+use std::net::IpAddr;
+use lib::banman::{block_misbehavior_score, misbehavior_score, BanList, BAN_THRESHOLD};
+use lib::error::BtcError;
+use lib::script::ScriptError;
+use lib::util::Saveable;
+
+const NOW: i64 = 1_700_000_000;
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
+
+#[test]
+fn invalid_data_is_scored_by_error() {
+    for error in [
+        BtcError::InvalidBlockHeader,
+        BtcError::InvalidMerkleRoot,
+        BtcError::InvalidSignature,
+        BtcError::InvalidScript(ScriptError::VerifyFailed)
+    ] {
+        assert_eq!(misbehavior_score(&error), BAN_THRESHOLD, "{error}");
+    }
+
+    // honest peers send these too
+    for error in [
+        BtcError::MissingPreviousBlock,
+        BtcError::MissingInputs,
+        BtcError::InvalidTransaction,
+        BtcError::NonFinalTransaction,
+        BtcError::NonStandardTransaction("dust")
+    ] {
+        assert_eq!(misbehavior_score(&error), 0, "{error}");
+    }
+}
+
+#[test]
+fn invalid_blocks_are_scored_whatever_the_error() {
+    // a block overpaying its coinbase, spending an output twice or one that doesn't exist
+    for error in [
+        BtcError::InvalidTransaction,
+        BtcError::MissingInputs,
+        BtcError::NonFinalTransaction,
+        BtcError::UnsatisfiedSequenceLock,
+        BtcError::InvalidBlock,
+        BtcError::InvalidSignature
+    ] {
+        assert_eq!(block_misbehavior_score(&error), BAN_THRESHOLD, "{error}");
+    }
+    assert_eq!(block_misbehavior_score(&BtcError::MissingPreviousBlock), 0);
+}
+
+#[test]
+fn bans_expire() {
+    let mut banlist = BanList::new();
+    banlist.ban(ip("1.2.3.4"), NOW + 60);
+
+    assert!(banlist.is_banned(&ip("1.2.3.4"), NOW));
+    assert!(banlist.is_banned(&ip("::ffff:1.2.3.4"), NOW));
+    assert!(!banlist.is_banned(&ip("1.2.3.5"), NOW));
+    assert!(!banlist.is_banned(&ip("1.2.3.4"), NOW + 60));
+
+    // a shorter ban doesn't cut a longer one
+    banlist.ban(ip("1.2.3.4"), NOW + 10);
+    assert_eq!(banlist.entries(NOW), [(ip("1.2.3.4"), NOW + 60)]);
+
+    assert!(!banlist.sweep(NOW + 59));
+    assert!(banlist.sweep(NOW + 60));
+    assert!(banlist.entries(NOW).is_empty());
+}
+
+#[test]
+fn bans_are_lifted_and_survive_a_restart() {
+    let mut banlist = BanList::new();
+    banlist.ban(ip("1.2.3.4"), NOW + 60);
+    banlist.ban(ip("2001:db8::1"), NOW + 30);
+    banlist.ban(ip("5.6.7.8"), NOW + 90);
+
+    assert!(banlist.unban(&ip("5.6.7.8")));
+    assert!(!banlist.unban(&ip("5.6.7.8")));
+
+    let mut bytes = Vec::new();
+    banlist.save(&mut bytes).unwrap();
+    let mut loaded = BanList::load(bytes.as_slice()).unwrap();
+    assert_eq!(loaded.entries(NOW), [(ip("2001:db8::1"), NOW + 30), (ip("1.2.3.4"), NOW + 60)]);
+
+    loaded.clear();
+    assert!(!loaded.is_banned(&ip("1.2.3.4"), NOW));
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use lib::crypto::{self, PrivateKey, Signature, SignatureCheck};
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::sigcache::SignatureCache;
+use lib::types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+// alternates between ECDSA and Schnorr keys
+fn checks(count: usize) -> Vec<SignatureCheck> {
+    (0..count)
+        .map(|i| {
+            let key = PrivateKey::new_key();
+            let message = Hash::hash(&i);
+            if i % 2 == 0 {
+                SignatureCheck::new(message, Signature::sign_output(&message, &key), key.public_key())
+            } else {
+                SignatureCheck::new(message, Signature::sign_output_schnorr(&message, &key), key.schnorr_public_key())
+            }
+        })
+        .collect()
+}
+
+// the same key and message, signed by someone else
+fn forged(check: &SignatureCheck, message: &Hash) -> SignatureCheck {
+    let forger = PrivateKey::new_key();
+    let public_key = check.public_key.clone();
+    SignatureCheck::new(*message, Signature::sign_output_for(message, &forger, &public_key), public_key)
+}
+
+#[test]
+fn one_bad_signature_fails_the_batch() {
+    let valid = checks(64);
+    assert!(crypto::verify_batch(&valid));
+    assert!(SignatureCache::default().verify_batch(&valid));
+
+    for bad in [0, 31, 32, 63] {
+        let mut batch = valid.clone();
+        batch[bad] = forged(&batch[bad], &Hash::hash(&bad));
+
+        assert!(!crypto::verify_batch(&batch), "bad check {bad}");
+        assert!(!SignatureCache::default().verify_batch(&batch), "bad check {bad}");
+    }
+}
+
+#[test]
+fn batch_agrees_with_serial() {
+    let valid = checks(32);
+
+    // every subset of bad checks given by the bits of mask
+    for mask in [0u32, 1, 1 << 31, 0x0000_ffff, 0xaaaa_aaaa, u32::MAX] {
+        let batch: Vec<SignatureCheck> = valid
+            .iter()
+            .enumerate()
+            .map(|(i, check)| if mask & 1 << i != 0 { forged(check, &Hash::hash(&i)) } else { check.clone() })
+            .collect();
+
+        assert_eq!(crypto::verify_batch(&batch), crypto::verify_serial(&batch), "mask {mask:#x}");
+        assert_eq!(crypto::verify_batch(&batch), mask == 0, "mask {mask:#x}");
+    }
+    assert!(crypto::verify_batch(&[]) && crypto::verify_serial(&[]));
+}
+
+// a hundred spends alternating between ECDSA and Schnorr, the one at bad signed by the wrong key
+fn block(bad: Option<usize>) -> (Block, HashMap<Hash, TransactionOutput>) {
+    let owner = PrivateKey::new_key();
+    let mut utxos = HashMap::new();
+    let mut transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(50 * 10u64.pow(8), owner.public_key().into())],
+    )];
+
+    for i in 0..100 {
+        let pubkey = if i % 2 == 0 { owner.public_key() } else { owner.schnorr_public_key() };
+        let utxo = TransactionOutput::new(1_000, pubkey.clone().into());
+        let utxo_hash = utxo.hash();
+        utxos.insert(utxo_hash, utxo);
+
+        let mut transaction = Transaction::new(
+            vec![TransactionInput::unsigned(utxo_hash)],
+            vec![TransactionOutput::new(1_000, owner.public_key().into())],
+        );
+        let signer = if bad == Some(i) { PrivateKey::new_key() } else { owner.clone() };
+        transaction.sign_input(0, 0, &signer, &pubkey);
+        transactions.push(transaction);
+    }
+
+    let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), MerkleRoot::calculate(&transactions), U256::MAX);
+    (Block::new(header, transactions), utxos)
+}
+
+#[test]
+fn blocks_with_one_bad_input_fail_both_paths() {
+    let params = ChainParams::regtest();
+
+    let (valid, utxos) = block(None);
+    assert!(valid.verify_transactions_serial(&params, 1, &utxos).is_ok());
+    assert!(valid.verify_transactions(&params, 1, &utxos, &SignatureCache::default()).is_ok());
+
+    for bad in [0, 42, 99] {
+        let (invalid, utxos) = block(Some(bad));
+        assert!(invalid.verify_transactions_serial(&params, 1, &utxos).is_err(), "bad input {bad}");
+        assert!(invalid.verify_transactions(&params, 1, &utxos, &SignatureCache::default()).is_err(), "bad input {bad}");
+    }
+}
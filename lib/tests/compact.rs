@@ -0,0 +1,103 @@
+use chrono::Utc;
+use lib::compact::{BlockTransactions, BlockTransactionsRequest, CompactBlock, CompactStats, PartialBlock};
+use lib::crypto::PrivateKey;
+use lib::error::BtcError;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+fn payment(key: &PrivateKey, i: u64) -> Transaction {
+    let mut transaction = Transaction::new(
+        vec![TransactionInput::unsigned(Hash::hash(&i))],
+        vec![TransactionOutput::new(i * 1_000, key.public_key().into())],
+    );
+    transaction.sign_input(0, 0, key, &key.public_key());
+    transaction
+}
+
+// a block of a coinbase and `count` payments
+fn block(count: u64) -> Block {
+    let key = PrivateKey::new_key();
+    let mut transactions = vec![Transaction::new(vec![], vec![TransactionOutput::new(50, key.public_key().into())])];
+    transactions.extend((1..=count).map(|i| payment(&key, i)));
+    Block::commit_witnesses(&mut transactions);
+
+    let header = BlockHeader::new(Utc::now(), 0, Hash::zero(), MerkleRoot::calculate(&transactions), U256::MAX);
+    Block::new(header, transactions)
+}
+
+#[test]
+fn blocks_are_rebuilt_from_the_mempool() {
+    let block = block(6);
+    let compact = CompactBlock::new(&block, 7);
+    assert_eq!(compact.prefilled.len(), 1);
+    assert_eq!(compact.short_ids.len(), 6);
+
+    // the mempool lacks two of them and has an unrelated one
+    let transactions = block.transactions();
+    let mut mempool: Vec<Transaction> = transactions[1..5].to_vec();
+    mempool.push(payment(&PrivateKey::new_key(), 99));
+
+    let partial = PartialBlock::new(&compact, &mempool).unwrap();
+    assert_eq!(partial.from_mempool, 4);
+    assert_eq!(partial.missing(), [5, 6]);
+
+    let request = BlockTransactionsRequest { block_hash: block.hash(), indexes: partial.missing() };
+    let answer = BlockTransactions::new(&block, &request).unwrap();
+    let rebuilt = partial.complete(answer.transactions.clone()).unwrap();
+    assert_eq!(rebuilt.hash(), block.hash());
+    assert_eq!(rebuilt.transactions().len(), 7);
+
+    let mut stats = CompactStats::default();
+    stats.record(&compact, &answer.transactions, &rebuilt, 4);
+    assert_eq!((stats.blocks, stats.from_mempool, stats.requested), (1, 4, 2));
+    assert!(stats.saved_bytes() > 0);
+    assert_eq!(stats.saved_bytes(), stats.full_bytes - stats.received_bytes);
+
+    // only indexes in the block are answered
+    let past_end = BlockTransactionsRequest { block_hash: block.hash(), indexes: vec![7] };
+    assert!(BlockTransactions::new(&block, &past_end).is_none());
+}
+
+#[test]
+fn wrong_transactions_are_caught() {
+    let block = block(3);
+    let compact = CompactBlock::new(&block, 1);
+    let transactions = block.transactions();
+
+    let partial = || PartialBlock::new(&compact, &transactions[1..2]).unwrap();
+    assert!(matches!(partial().complete(vec![transactions[2].clone()]), Err(BtcError::InvalidBlock)));
+    assert!(matches!(
+        partial().complete(transactions[1..].to_vec()),
+        Err(BtcError::InvalidBlock)
+    ));
+
+    // the right count of the wrong transactions doesn't match the header
+    let swapped = vec![transactions[3].clone(), transactions[2].clone()];
+    assert!(matches!(partial().complete(swapped), Err(BtcError::InvalidMerkleRoot)));
+    assert!(partial().complete(transactions[2..].to_vec()).is_ok());
+}
+
+#[test]
+fn ambiguous_short_ids_are_requested() {
+    let block = block(3);
+    let transactions = block.transactions();
+
+    // a repeated short ID can't say which transaction goes where
+    let mut compact = CompactBlock::new(&block, 1);
+    compact.short_ids[2] = compact.short_ids[0];
+    let partial = PartialBlock::new(&compact, transactions).unwrap();
+    assert_eq!(partial.missing(), [1, 3]);
+    assert_eq!(partial.from_mempool, 1);
+
+    // prefilled indexes out of order or past the end are malformed
+    let mut compact = CompactBlock::new(&block, 1);
+    compact.prefilled[0].index = 4;
+    assert!(matches!(PartialBlock::new(&compact, transactions), Err(BtcError::InvalidBlock)));
+
+    let mut compact = CompactBlock::new(&block, 1);
+    compact.prefilled.push(compact.prefilled[0].clone());
+    compact.short_ids.pop();
+    assert!(matches!(PartialBlock::new(&compact, transactions), Err(BtcError::InvalidBlock)));
+}
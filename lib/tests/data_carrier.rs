@@ -0,0 +1,126 @@
+use chrono::{TimeZone, Utc};
+use lib::crypto::PrivateKey;
+use lib::error::BtcError;
+use lib::params::ChainParams;
+use lib::policy::MAX_DATA_CARRIER_SIZE;
+use lib::script::{Op, Script};
+use lib::sha256::Hash;
+use lib::types::{
+    Block, BlockHeader, Blockchain, LockingCondition, Transaction, TransactionInput, TransactionOutput,
+};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+const REWARD: u64 = 50 * 100_000_000;
+
+struct Setup {
+    chain: Blockchain,
+    key: PrivateKey,
+    utxo_hash: Hash,
+}
+
+// chain with a genesis block paying the key
+fn setup() -> Setup {
+    let key = PrivateKey::new_key();
+    let funding = TransactionOutput::new(REWARD, key.public_key().into());
+    let utxo_hash = funding.hash();
+
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    chain.add_block(block(&chain, vec![Transaction::new(vec![], vec![funding])])).unwrap();
+
+    Setup { chain, key, utxo_hash }
+}
+
+fn block(chain: &Blockchain, mut transactions: Vec<Transaction>) -> Block {
+    Block::commit_witnesses(&mut transactions);
+    let prev = chain.blocks.last().map_or(Hash::zero(), |block| block.hash());
+    let timestamp = Utc.timestamp_opt(1_700_000_000 + chain.block_height() as i64 * 600, 0).unwrap();
+    let header = BlockHeader::new(timestamp, 0, prev, MerkleRoot::calculate(&transactions), U256::MAX);
+    Block::new(header, transactions)
+}
+
+fn spend(setup: &Setup, mut outputs: Vec<TransactionOutput>) -> Transaction {
+    let data_value: u64 = outputs.iter().map(|output| output.value).sum();
+    outputs.push(TransactionOutput::new(REWARD - data_value, setup.key.public_key().into()));
+
+    let mut transaction = Transaction::new(vec![TransactionInput::unsigned(setup.utxo_hash)], outputs);
+    transaction.sign_input(0, 0, &setup.key, &setup.key.public_key());
+    transaction
+}
+
+fn data_output(data: &[u8]) -> TransactionOutput {
+    TransactionOutput::data_carrier(data)
+}
+
+#[test]
+fn payload_roundtrip() {
+    let output = data_output(b"document hash");
+    assert_eq!(output.data_carrier_payload(), Some(&b"document hash"[..]));
+    assert!(output.lock.is_unspendable());
+    assert_eq!(output.lock.to_string(), format!("data {}", hex::encode(b"document hash")));
+
+    // any script starting with OP_RETURN is unspendable, even if it isn't a plain data carrier
+    let odd = Script::new(vec![Op::Return, Op::Dup]);
+    assert!(odd.is_unspendable());
+    assert_eq!(odd.data_carrier_payload(), None);
+
+    assert!(!LockingCondition::from(PrivateKey::new_key().public_key()).is_unspendable());
+}
+
+#[test]
+fn data_outputs_stay_out_of_the_utxo_set() {
+    let mut setup = setup();
+    let data = data_output(&[0xab; 32]);
+    let data_hash = data.hash();
+    let transaction = spend(&setup, vec![data]);
+
+    setup.chain.add_to_mempool(transaction.clone()).unwrap();
+    let coinbase = Transaction::new(vec![], vec![TransactionOutput::new(REWARD, setup.key.public_key().into())]);
+    let block = block(&setup.chain, vec![coinbase, transaction]);
+    setup.chain.add_block(block).unwrap();
+
+    assert!(!setup.chain.utxos.contains_key(&data_hash));
+    assert!(!setup.chain.utxo_heights.contains_key(&data_hash));
+    assert_eq!(setup.chain.utxos.len(), 2);
+
+    // and so can't be spent
+    let spend_data = Transaction::new(
+        vec![TransactionInput::with_script(data_hash, Script::default())],
+        vec![TransactionOutput::new(0, setup.key.public_key().into())],
+    );
+    assert!(matches!(setup.chain.add_to_mempool(spend_data), Err(BtcError::MissingInputs)));
+}
+
+#[test]
+fn mempool_policy() {
+    let mut setup = setup();
+
+    let non_standard = [
+        spend(&setup, vec![data_output(&[0; MAX_DATA_CARRIER_SIZE + 1])]),
+        spend(&setup, vec![data_output(b"one"), data_output(b"two")]),
+        spend(&setup, vec![TransactionOutput::new(10, LockingCondition::Script(Script::data_carrier(b"burn")))]),
+        spend(&setup, vec![TransactionOutput::new(0, LockingCondition::Script(Script::new(vec![Op::Return, Op::Dup])))]),
+    ];
+
+    for transaction in non_standard {
+        assert!(matches!(
+            setup.chain.add_to_mempool(transaction),
+            Err(BtcError::NonStandardTransaction(_))
+        ));
+    }
+
+    setup
+        .chain
+        .add_to_mempool(spend(&setup, vec![data_output(&[0; MAX_DATA_CARRIER_SIZE])]))
+        .unwrap();
+}
+
+#[test]
+fn blocks_accept_non_standard_data_carriers() {
+    let mut setup = setup();
+    let transaction = spend(&setup, vec![data_output(&[0; 500]), data_output(b"second")]);
+    let coinbase = Transaction::new(vec![], vec![TransactionOutput::new(REWARD, setup.key.public_key().into())]);
+
+    let block = block(&setup.chain, vec![coinbase, transaction]);
+    setup.chain.add_block(block).unwrap();
+}
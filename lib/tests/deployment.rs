@@ -0,0 +1,169 @@
+use chrono::{TimeZone, Utc};
+use lib::crypto::PrivateKey;
+use lib::deployment::{Deployment, DeploymentState, VERSIONBITS_TOP_BITS, DEPLOYMENTS};
+use lib::error::BtcError;
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction, TransactionInput, TransactionOutput};
+use lib::util::{MerkleRoot, Saveable};
+use lib::U256;
+
+const REWARD: u64 = 50 * 100_000_000;
+
+const TEST_DEPLOYMENT: Deployment = Deployment {
+    name: "test",
+    bit: 3,
+    start_height: 10,
+    timeout_height: 60,
+};
+
+fn chain() -> Blockchain {
+    let params = ChainParams { deployment_period: 10, deployment_threshold: 8, ..ChainParams::regtest() };
+    let mut chain = Blockchain::with_params(params);
+    chain.set_deployments(vec![TEST_DEPLOYMENT]);
+    chain
+}
+
+fn mine(chain: &mut Blockchain, version: u32, transactions: Vec<Transaction>) -> Result<(), BtcError> {
+    let key = PrivateKey::new_key();
+    let mut all = vec![Transaction::new(vec![], vec![TransactionOutput::new(REWARD, key.public_key().into())])];
+    all.extend(transactions);
+    Block::commit_witnesses(&mut all);
+
+    let prev = chain.blocks.last().map_or(Hash::zero(), |block| block.hash());
+    let timestamp = Utc.timestamp_opt(1_700_000_000 + chain.block_height() as i64 * 600, 0).unwrap();
+    let header = BlockHeader::new(timestamp, 0, prev, MerkleRoot::calculate(&all), U256::MAX)
+        .with_version(version);
+    chain.add_block(Block::new(header, all))
+}
+
+// mines `count` blocks, the first `signalling` of them setting the deployment's bit
+fn mine_period(chain: &mut Blockchain, count: usize, signalling: usize) {
+    for i in 0..count {
+        let version = if i < signalling {
+            VERSIONBITS_TOP_BITS | TEST_DEPLOYMENT.mask()
+        } else {
+            VERSIONBITS_TOP_BITS
+        };
+        mine(chain, version, vec![]).unwrap();
+    }
+}
+
+fn state(chain: &Blockchain, height: u64) -> DeploymentState {
+    chain.deployment_state(TEST_DEPLOYMENT.name, height).unwrap()
+}
+
+#[test]
+fn signalling_needs_the_top_bits() {
+    assert!(TEST_DEPLOYMENT.signals(VERSIONBITS_TOP_BITS | 1 << 3));
+    assert!(!TEST_DEPLOYMENT.signals(VERSIONBITS_TOP_BITS | 1 << 4));
+    assert!(!TEST_DEPLOYMENT.signals(1 << 3));
+    assert!(!TEST_DEPLOYMENT.signals(0x6000_0000 | 1 << 3));
+}
+
+#[test]
+fn state_transitions() {
+    use DeploymentState::*;
+    let d = TEST_DEPLOYMENT;
+
+    assert_eq!(d.next_state(Defined, 0, 10, 8), Defined);
+    assert_eq!(d.next_state(Defined, 10, 0, 8), Started);
+    assert_eq!(d.next_state(Defined, 60, 0, 8), Failed);
+    assert_eq!(d.next_state(Started, 20, 7, 8), Started);
+    assert_eq!(d.next_state(Started, 20, 8, 8), LockedIn);
+    // locking in during the last period wins over the timeout
+    assert_eq!(d.next_state(Started, 60, 8, 8), LockedIn);
+    assert_eq!(d.next_state(Started, 60, 7, 8), Failed);
+    assert_eq!(d.next_state(LockedIn, 30, 0, 8), Active);
+    assert_eq!(d.next_state(Active, 40, 0, 8), Active);
+    assert_eq!(d.next_state(Failed, 40, 10, 8), Failed);
+}
+
+#[test]
+fn activation_through_signalling() {
+    let mut chain = chain();
+
+    // the genesis block opens the first period
+    mine_period(&mut chain, 9, 9);
+    // signalling before the start doesn't count
+    assert_eq!(state(&chain, 9), DeploymentState::Defined);
+    assert_eq!(state(&chain, 10), DeploymentState::Started);
+    assert_eq!(chain.block_version(), VERSIONBITS_TOP_BITS | TEST_DEPLOYMENT.mask());
+
+    mine_period(&mut chain, 10, 7);
+    assert_eq!(state(&chain, 20), DeploymentState::Started);
+
+    mine_period(&mut chain, 10, 8);
+    assert_eq!(state(&chain, 30), DeploymentState::LockedIn);
+    assert!(!chain.is_deployment_active(TEST_DEPLOYMENT.name, 30));
+
+    // active from the next period, whatever the signalling
+    assert!(chain.is_deployment_active(TEST_DEPLOYMENT.name, 40));
+    mine_period(&mut chain, 10, 0);
+    assert!(chain.is_deployment_active(TEST_DEPLOYMENT.name, 45));
+    assert_eq!(chain.block_version(), VERSIONBITS_TOP_BITS);
+
+    // earlier heights keep their state
+    assert_eq!(state(&chain, 25), DeploymentState::Started);
+    assert!(chain.deployment_state("unknown", 10).is_none());
+}
+
+#[test]
+fn timeout_without_enough_signalling() {
+    let mut chain = chain();
+
+    for _ in 0..6 {
+        mine_period(&mut chain, 10, 5);
+    }
+
+    assert_eq!(state(&chain, 59), DeploymentState::Started);
+    assert_eq!(state(&chain, 60), DeploymentState::Failed);
+    assert_eq!(chain.block_version(), VERSIONBITS_TOP_BITS);
+}
+
+#[test]
+fn states_survive_a_snapshot() {
+    let mut chain = chain();
+    mine_period(&mut chain, 10, 0);
+    mine_period(&mut chain, 10, 10);
+    mine_period(&mut chain, 5, 0);
+
+    let mut bytes = Vec::new();
+    chain.save(&mut bytes).unwrap();
+    let mut loaded = Blockchain::load(bytes.as_slice()).unwrap();
+    // deployments are configuration, not chain data
+    assert_eq!(loaded.deployments(), DEPLOYMENTS);
+    loaded.set_deployments(vec![TEST_DEPLOYMENT]);
+
+    for height in 0..40 {
+        assert_eq!(state(&loaded, height), state(&chain, height), "height {height}");
+    }
+}
+
+#[test]
+fn unknown_transaction_versions_are_not_relayed() {
+    let key = PrivateKey::new_key();
+    let funding = TransactionOutput::new(REWARD, key.public_key().into());
+    let funding_hash = funding.hash();
+
+    let mut chain = chain();
+    chain.utxos.insert(funding_hash, funding);
+
+    let spend = |version: u32| {
+        let mut transaction = Transaction::new(
+            vec![TransactionInput::unsigned(funding_hash)],
+            vec![TransactionOutput::new(REWARD, key.public_key().into())],
+        )
+        .with_version(version);
+        transaction.sign_input(0, 0, &key, &key.public_key());
+        transaction
+    };
+
+    for version in [0, 3] {
+        assert!(matches!(
+            chain.add_to_mempool(spend(version)),
+            Err(BtcError::NonStandardTransaction(_))
+        ));
+    }
+    chain.add_to_mempool(spend(1)).unwrap();
+}
@@ -0,0 +1,303 @@
+use std::collections::HashSet;
+use chrono::Duration;
+use lib::crypto::PrivateKey;
+use lib::error::BtcError;
+use lib::headers::{self, HeaderChain};
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction, TransactionInput, TransactionOutput, MIN_BLOCKS_TO_KEEP};
+use lib::util::{MerkleRoot, Saveable};
+use lib::U256;
+
+const REWARD: u64 = 50 * 100_000_000;
+
+fn next_block(prev: &BlockHeader, target: U256, seconds: i64, transactions: Vec<Transaction>) -> Block {
+    let key = PrivateKey::new_key();
+    let mut all = vec![Transaction::new(vec![], vec![TransactionOutput::new(REWARD, key.public_key().into())])];
+    all.extend(transactions);
+    Block::commit_witnesses(&mut all);
+
+    let timestamp = prev.timestamp + Duration::seconds(seconds);
+    let mut header = BlockHeader::new(timestamp, 0, prev.hash(), MerkleRoot::calculate(&all), target);
+    assert!(header.mine(1_000_000));
+    Block::new(header, all)
+}
+
+// mines a block `seconds` after the tip at the target the chain expects
+fn mine(chain: &mut Blockchain, seconds: i64, transactions: Vec<Transaction>) -> Block {
+    let prev = chain.blocks.last().unwrap().header().clone();
+    let block = next_block(&prev, chain.next_target(), seconds, transactions);
+    chain.add_block(block.clone()).unwrap();
+    block
+}
+
+fn branch(base: &Blockchain, count: usize, seconds: i64) -> Blockchain {
+    let mut chain = base.clone();
+    for _ in 0..count {
+        mine(&mut chain, seconds, vec![]);
+    }
+    chain
+}
+
+fn headers_of(chain: &Blockchain) -> Vec<BlockHeader> {
+    chain.blocks.iter().skip(1).map(|block| block.header().clone()).collect()
+}
+
+#[test]
+fn best_chain_has_the_most_work() {
+    let genesis = Blockchain::with_params(ChainParams::regtest());
+    let a = branch(&genesis, 3, 600);
+    let b = branch(&genesis, 5, 300);
+
+    let mut tree = HeaderChain::new(&genesis);
+    for header in headers_of(&a) {
+        assert!(tree.add_header(header).unwrap());
+    }
+    assert_eq!(tree.best_height(), 3);
+    assert_eq!(tree.hash_at(3), Some(a.blocks[3].hash()));
+
+    // as much work as the best chain isn't enough, the first seen stays
+    for header in &headers_of(&b)[..3] {
+        tree.add_header(header.clone()).unwrap();
+    }
+    assert_eq!(tree.hash_at(3), Some(a.blocks[3].hash()));
+
+    for header in &headers_of(&b)[3..] {
+        tree.add_header(header.clone()).unwrap();
+    }
+    assert_eq!(tree.best_height(), 5);
+    assert_eq!(tree.best_tip().chain_work, U256::from(6));
+    assert_eq!(tree.hash_at(1), Some(b.blocks[1].hash()));
+    assert!(!tree.is_on_best_chain(&a.blocks[2].hash()));
+    assert!(tree.contains(&a.blocks[2].hash()));
+
+    // known headers aren't new
+    assert!(!tree.add_header(b.blocks[5].header().clone()).unwrap());
+}
+
+#[test]
+fn headers_are_validated() {
+    let genesis = Blockchain::with_params(ChainParams::regtest());
+    let a = branch(&genesis, 2, 600);
+    let mut tree = HeaderChain::new(&genesis);
+
+    assert!(matches!(
+        tree.add_header(a.blocks[2].header().clone()),
+        Err(BtcError::MissingPreviousBlock)
+    ));
+
+    let parent = genesis.blocks[0].header();
+    let same_time = next_block(parent, U256::MAX, 0, vec![]);
+    let wrong_target = next_block(parent, U256::MAX >> 1, 600, vec![]);
+    for header in [same_time.header(), wrong_target.header()] {
+        assert!(matches!(tree.add_header(header.clone()), Err(BtcError::InvalidBlockHeader)));
+    }
+
+    tree.add_header(a.blocks[1].header().clone()).unwrap();
+    tree.add_header(a.blocks[2].header().clone()).unwrap();
+    assert_eq!(tree.best_height(), 2);
+}
+
+#[test]
+fn retargeting_is_checked_on_headers() {
+    let params = ChainParams {
+        min_target: U256::MAX >> 4,
+        retarget: true,
+        difficulty_update_interval: 4,
+        ..ChainParams::regtest()
+    };
+    let genesis = Blockchain::with_params(params);
+
+    // blocks ten times faster than ideal make the target drop at each retarget
+    let chain = branch(&genesis, 8, 1);
+    assert!(chain.blocks[4].header().target < chain.blocks[3].header().target / 3);
+    assert!(chain.blocks[8].header().target < chain.blocks[4].header().target);
+
+    let mut tree = HeaderChain::new(&genesis);
+    for header in &headers_of(&chain)[..3] {
+        tree.add_header(header.clone()).unwrap();
+    }
+
+    // the previous target past a retarget
+    let stale = next_block(chain.blocks[3].header(), chain.blocks[3].header().target, 1, vec![]);
+    assert!(matches!(tree.add_header(stale.header().clone()), Err(BtcError::InvalidBlockHeader)));
+
+    // the right target without the work
+    let mut unmined = chain.blocks[4].header().clone();
+    while unmined.hash().matches_target(unmined.target) {
+        unmined.nonce += 1;
+    }
+    assert!(matches!(tree.add_header(unmined), Err(BtcError::InvalidBlockHeader)));
+
+    for header in &headers_of(&chain)[3..] {
+        tree.add_header(header.clone()).unwrap();
+    }
+    assert_eq!(tree.best_height(), 8);
+    assert!(tree.best_tip().chain_work > U256::from(8 * 16));
+}
+
+#[test]
+fn invalidated_branches_are_dropped() {
+    let genesis = Blockchain::with_params(ChainParams::regtest());
+    let a = branch(&genesis, 3, 600);
+    let b = branch(&genesis, 5, 300);
+
+    let mut tree = HeaderChain::new(&genesis);
+    for header in headers_of(&a).into_iter().chain(headers_of(&b)) {
+        tree.add_header(header).unwrap();
+    }
+    assert_eq!(tree.hash_at(5), Some(b.blocks[5].hash()));
+
+    tree.invalidate(&b.blocks[2].hash());
+    assert_eq!(tree.best_height(), 3);
+    assert_eq!(tree.hash_at(3), Some(a.blocks[3].hash()));
+    assert!(!tree.contains(&b.blocks[4].hash()));
+    assert!(tree.contains(&b.blocks[1].hash()));
+
+    // the branch isn't accepted again, nor are children of the invalid header
+    for height in [2, 3] {
+        assert!(matches!(
+            tree.add_header(b.blocks[height].header().clone()),
+            Err(BtcError::InvalidBlockHeader)
+        ));
+    }
+    assert!(matches!(
+        tree.add_header(b.blocks[4].header().clone()),
+        Err(BtcError::MissingPreviousBlock)
+    ));
+}
+
+#[test]
+fn locators_find_the_fork() {
+    let genesis = Blockchain::with_params(ChainParams::regtest());
+    let chain = branch(&genesis, 30, 600);
+
+    let locator = chain.locator();
+    let heights: Vec<u64> = locator.iter().map(|hash| chain.height_of(hash).unwrap()).collect();
+    assert_eq!(heights, [30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 19, 15, 7, 0]);
+
+    // a peer at height 20 of the same chain gets the rest
+    let behind = headers::locator(20, |height| chain.blocks[height as usize].hash());
+    let after = chain.headers_after(&behind, &Hash::zero(), 2_000);
+    assert_eq!(after.len(), 10);
+    assert_eq!(after[0].hash(), chain.blocks[21].hash());
+
+    let stop = chain.blocks[25].hash();
+    assert_eq!(chain.headers_after(&behind, &stop, 2_000).len(), 5);
+    assert_eq!(chain.headers_after(&behind, &Hash::zero(), 3).len(), 3);
+
+    // a fork at 16 is found through the sparse part, at 13
+    let other = {
+        let mut other = chain.clone();
+        for _ in 0..14 {
+            other.disconnect_tip().unwrap();
+        }
+        branch(&other, 20, 300)
+    };
+    let after = chain.headers_after(&other.locator(), &Hash::zero(), 2_000);
+    assert_eq!(after[0].prev_block_hash, chain.blocks[13].hash());
+    assert_eq!(after.len(), 17);
+
+    // nothing in common
+    assert!(chain.headers_after(&[Hash::zero()], &Hash::zero(), 2_000).is_empty());
+}
+
+#[test]
+fn disconnecting_the_tip() {
+    let key = PrivateKey::new_key();
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+
+    let prev = chain.blocks[0].header().clone();
+    let coinbase = Transaction::new(vec![], vec![TransactionOutput::new(REWARD, key.public_key().into())]);
+    let mut funding = vec![coinbase];
+    Block::commit_witnesses(&mut funding);
+    let mut header = BlockHeader::new(
+        prev.timestamp + Duration::seconds(600),
+        0,
+        prev.hash(),
+        MerkleRoot::calculate(&funding),
+        U256::MAX,
+    );
+    header.mine(1);
+    chain.add_block(Block::new(header, funding.clone())).unwrap();
+
+    let utxo_hash = funding[0].outputs()[0].hash();
+    let mut spend = Transaction::new(
+        vec![TransactionInput::unsigned(utxo_hash)],
+        vec![TransactionOutput::new(REWARD, key.public_key().into())],
+    );
+    spend.sign_input(0, 0, &key, &key.public_key());
+
+    let utxos: HashSet<Hash> = chain.utxos.keys().copied().collect();
+    let utxo_heights = chain.utxo_heights.clone();
+
+    chain.add_to_mempool(spend.clone()).unwrap();
+    assert!(chain.mempool_transaction(&spend.hash()).is_some());
+    let block = mine(&mut chain, 600, vec![spend.clone()]);
+    assert!(chain.mempool.is_empty());
+    assert!(chain.mempool_transaction(&spend.hash()).is_none());
+    assert!(!chain.utxos.contains_key(&utxo_hash));
+    assert_eq!(chain.height_of(&block.hash()), Some(2));
+
+    let disconnected = chain.disconnect_tip().unwrap();
+    assert_eq!(disconnected.hash(), block.hash());
+    assert_eq!(chain.block_height(), 2);
+    assert_eq!(chain.utxos.keys().copied().collect::<HashSet<_>>(), utxos);
+    assert_eq!(chain.utxo_heights, utxo_heights);
+    assert_eq!(chain.block_stats(2), None);
+
+    assert!(chain.block_by_hash(&block.hash()).is_none());
+
+    // the block's transaction is unconfirmed again
+    assert_eq!(chain.mempool.len(), 1);
+    assert_eq!(chain.mempool[0].hash(), spend.hash());
+
+    // lookups by hash work on a loaded chain too
+    let mut bytes = Vec::new();
+    chain.save(&mut bytes).unwrap();
+    let loaded = Blockchain::load(bytes.as_slice()).unwrap();
+    assert!(loaded.mempool_transaction(&spend.hash()).is_some());
+    assert_eq!(loaded.height_of(&chain.blocks[1].hash()), Some(1));
+
+    // and the block connects again
+    chain.add_block(disconnected).unwrap();
+    assert!(chain.mempool.is_empty());
+
+    chain.disconnect_tip().unwrap();
+    chain.disconnect_tip().unwrap();
+    assert!(matches!(chain.disconnect_tip(), Err(BtcError::InvalidBlock)));
+    assert_eq!(chain.block_height(), 1);
+}
+
+#[test]
+fn pruned_blocks_keep_their_headers() {
+    let key = PrivateKey::new_key();
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    // templates pay the reward of their height, it halves along the way
+    let extend = |chain: &mut Blockchain| {
+        let template = chain.block_template(key.public_key().into()).unwrap();
+        chain.add_block(template).unwrap();
+    };
+    for _ in 0..MIN_BLOCKS_TO_KEEP + 10 {
+        extend(&mut chain);
+    }
+    let hashes: Vec<Hash> = chain.blocks.iter().map(Block::hash).collect();
+
+    // fewer than MIN_BLOCKS_TO_KEEP are never kept
+    assert_eq!(chain.prune(10), 11);
+    assert_eq!(chain.pruned_height(), 11);
+    assert!(chain.blocks[10].is_pruned());
+    assert!(!chain.blocks[11].is_pruned());
+    assert_eq!(chain.blocks.iter().map(Block::hash).collect::<Vec<_>>(), hashes);
+    assert_eq!(chain.prune(10), 0);
+
+    extend(&mut chain);
+    assert_eq!(chain.prune(MIN_BLOCKS_TO_KEEP), 1);
+
+    // the blocks kept can be disconnected, the pruned ones can't
+    for _ in 0..MIN_BLOCKS_TO_KEEP {
+        chain.disconnect_tip().unwrap();
+    }
+    assert!(matches!(chain.disconnect_tip(), Err(BtcError::InvalidBlock)));
+    assert_eq!(chain.block_height(), chain.pruned_height());
+}
@@ -0,0 +1,253 @@
+use chrono::{TimeZone, Utc};
+use lib::crypto::PrivateKey;
+use lib::error::BtcError;
+use lib::htlc::{self, Htlc};
+use lib::params::ChainParams;
+use lib::script::ScriptError;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction, TransactionInput, TransactionOutput};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+const REWARD: u64 = 50 * 100_000_000;
+
+// an independent chain whose block 1 pays `funder`, later rewards go to a separate miner
+struct Chain {
+    chain: Blockchain,
+    miner: PrivateKey,
+    start: i64,
+}
+
+impl Chain {
+    fn new(funder: &PrivateKey, start: i64) -> Self {
+        let params = ChainParams { genesis_timestamp: start, ..ChainParams::regtest() };
+        let mut chain = Chain { chain: Blockchain::with_params(params), miner: funder.clone(), start };
+        chain.mine(vec![]).unwrap();
+        chain.miner = PrivateKey::new_key();
+        chain
+    }
+
+    fn mine(&mut self, transactions: Vec<Transaction>) -> Result<(), BtcError> {
+        let height = self.chain.block_height();
+        let fees: u64 = transactions
+            .iter()
+            .map(|tx| {
+                let inputs: u64 = tx
+                    .inputs()
+                    .iter()
+                    .map(|input| self.chain.utxos[&input.prev_transaction_output_hash].value)
+                    .sum();
+                inputs - tx.outputs().iter().map(|output| output.value).sum::<u64>()
+            })
+            .sum();
+
+        let coinbase = Transaction::new(
+            vec![],
+            vec![TransactionOutput::new(REWARD + fees, self.miner.public_key().into())],
+        );
+        let mut all = vec![coinbase];
+        all.extend(transactions);
+        Block::commit_witnesses(&mut all);
+
+        let prev = self.chain.blocks.last().map_or(Hash::zero(), |block| block.hash());
+        let timestamp = Utc.timestamp_opt(self.start + height as i64 * 600, 0).unwrap();
+        let header = BlockHeader::new(timestamp, 0, prev, MerkleRoot::calculate(&all), U256::MAX);
+        self.chain.add_block(Block::new(header, all))
+    }
+
+    fn mine_empty(&mut self, count: usize) {
+        for _ in 0..count {
+            self.mine(vec![]).unwrap();
+        }
+    }
+
+    // broadcasts and confirms a transaction
+    fn confirm(&mut self, transaction: Transaction) -> Result<(), BtcError> {
+        self.chain.add_to_mempool(transaction.clone())?;
+        self.mine(vec![transaction])
+    }
+
+    // locks the funder's output of block 1 into the HTLC, returning the HTLC UTXO hash
+    fn fund(&mut self, funder: &PrivateKey, htlc: &Htlc, amount: u64) -> Hash {
+        let (hash, output) = self
+            .chain
+            .utxos
+            .iter()
+            .find(|(hash, _)| self.chain.utxo_heights[*hash] == 1)
+            .map(|(hash, output)| (*hash, output.clone()))
+            .unwrap();
+
+        let locked = TransactionOutput::new(amount, htlc.lock());
+        let locked_hash = locked.hash();
+        let change = TransactionOutput::new(output.value - amount, funder.public_key().into());
+        let mut funding = Transaction::new(vec![TransactionInput::unsigned(hash)], vec![locked, change]);
+        funding.sign_input(0, 0, funder, &funder.public_key());
+
+        self.confirm(funding).unwrap();
+        locked_hash
+    }
+
+    fn balance(&self, key: &PrivateKey) -> u64 {
+        self.chain
+            .utxos
+            .values()
+            .filter(|output| {
+                output.lock == key.public_key().into() || output.lock == key.schnorr_public_key().into()
+            })
+            .map(|output| output.value)
+            .sum()
+    }
+}
+
+fn pay_to(utxo: TransactionInput, value: u64, key: &PrivateKey) -> Transaction {
+    Transaction::new(vec![utxo], vec![TransactionOutput::new(value, key.public_key().into())])
+}
+
+// claims the HTLC output, paying value to the claiming key
+fn claim(htlc: &Htlc, utxo: Hash, key: &PrivateKey, preimage: &htlc::Preimage, value: u64) -> Transaction {
+    let mut transaction = pay_to(Htlc::claim_input(utxo), value, key);
+    htlc.sign_claim(&mut transaction, 0, key, preimage);
+    transaction
+}
+
+fn refund(htlc: &Htlc, utxo: Hash, key: &PrivateKey, value: u64, lock_time: u64) -> Transaction {
+    let mut transaction = pay_to(Htlc::refund_input(utxo), value, key).with_lock_time(lock_time);
+    htlc.sign_refund(&mut transaction, 0, key);
+    transaction
+}
+
+#[test]
+fn script_roundtrip() {
+    let htlc = Htlc::new(
+        htlc::payment_hash(&htlc::new_preimage()),
+        PrivateKey::new_key().public_key(),
+        PrivateKey::new_key().schnorr_public_key(),
+        1_234_567_890,
+    );
+
+    assert_eq!(Htlc::from_script(&htlc.script()), Some(htlc.clone()));
+    assert_eq!(Htlc::from_lock(&htlc.lock()), Some(htlc));
+    assert_eq!(Htlc::from_lock(&PrivateKey::new_key().public_key().into()), None);
+}
+
+#[test]
+fn atomic_swap_across_two_chains() {
+    let alice = PrivateKey::new_key();
+    let bob = PrivateKey::new_key();
+    let mut chain_a = Chain::new(&alice, 1_700_000_000);
+    let mut chain_b = Chain::new(&bob, 1_600_000_000);
+
+    // Alice knows the secret and locks first, with the longer timeout
+    let preimage = htlc::new_preimage();
+    let payment_hash = htlc::payment_hash(&preimage);
+
+    let htlc_a = Htlc::new(payment_hash, bob.public_key(), alice.public_key(), chain_a.chain.block_height() + 20);
+    let chain_a_funding = chain_a.fund(&alice, &htlc_a, 1_000);
+
+    // Bob checks Alice's HTLC and locks the same hash on his chain, timing out sooner
+    assert_eq!(Htlc::from_lock(&chain_a.chain.utxos[&chain_a_funding].lock), Some(htlc_a.clone()));
+    let htlc_b = Htlc::new(payment_hash, alice.schnorr_public_key(), bob.public_key(), chain_b.chain.block_height() + 10);
+    let chain_b_funding = chain_b.fund(&bob, &htlc_b, 2_000);
+
+    // Alice claims on chain B, which reveals the preimage
+    let claim_b = claim(&htlc_b, chain_b_funding, &alice, &preimage, 1_990);
+    chain_b.confirm(claim_b).unwrap();
+
+    // Bob reads it from the mined claim and claims on chain A
+    let revealed = chain_b
+        .chain
+        .blocks
+        .last()
+        .unwrap()
+        .transactions()
+        .iter()
+        .flat_map(|tx| tx.inputs())
+        .find_map(|input| htlc::extract_preimage(input, &payment_hash))
+        .unwrap();
+    assert_eq!(revealed, preimage);
+
+    let claim_a = claim(&htlc_a, chain_a_funding, &bob, &revealed, 990);
+    chain_a.confirm(claim_a).unwrap();
+
+    assert_eq!(chain_a.balance(&bob), 990);
+    assert_eq!(chain_b.balance(&alice), 1_990);
+    assert!(!chain_a.chain.utxos.contains_key(&chain_a_funding));
+    assert!(!chain_b.chain.utxos.contains_key(&chain_b_funding));
+}
+
+#[test]
+fn refund_after_timeout() {
+    let alice = PrivateKey::new_key();
+    let bob = PrivateKey::new_key();
+    let mut chain = Chain::new(&alice, 1_700_000_000);
+
+    let preimage = htlc::new_preimage();
+    let timeout = chain.chain.block_height() + 5;
+    let htlc = Htlc::new(htlc::payment_hash(&preimage), bob.public_key(), alice.public_key(), timeout);
+    let funding = chain.fund(&alice, &htlc, 1_000);
+
+    // the wrong secret doesn't open the claim path
+    let wrong = claim(&htlc, funding, &bob, &htlc::new_preimage(), 990);
+    assert!(matches!(
+        chain.chain.add_to_mempool(wrong),
+        Err(BtcError::InvalidScript(ScriptError::EqualVerifyFailed))
+    ));
+
+    // neither does the recipient's key in the refund path
+    let stolen = refund(&htlc, funding, &bob, 990, timeout);
+    assert!(matches!(
+        chain.chain.add_to_mempool(stolen),
+        Err(BtcError::InvalidScript(ScriptError::NullFail))
+    ));
+
+    // the script demands the timeout as lock time, the chain has to reach it
+    let early = refund(&htlc, funding, &alice, 990, timeout - 1);
+    assert!(matches!(
+        chain.chain.add_to_mempool(early),
+        Err(BtcError::InvalidScript(ScriptError::UnsatisfiedLockTime))
+    ));
+
+    let refund = refund(&htlc, funding, &alice, 990, timeout);
+    assert!(matches!(
+        chain.chain.add_to_mempool(refund.clone()),
+        Err(BtcError::NonFinalTransaction)
+    ));
+
+    while chain.chain.block_height() <= timeout {
+        chain.mine_empty(1);
+    }
+    chain.confirm(refund).unwrap();
+    assert!(!chain.chain.utxos.contains_key(&funding));
+}
+
+#[test]
+fn a_claim_moved_to_other_outputs_fails() {
+    let alice = PrivateKey::new_key();
+    let bob = PrivateKey::new_key();
+    let mallory = PrivateKey::new_key();
+    let mut chain = Chain::new(&alice, 1_700_000_000);
+
+    let preimage = htlc::new_preimage();
+    let timeout = chain.chain.block_height() + 20;
+    let htlc = Htlc::new(htlc::payment_hash(&preimage), bob.public_key(), alice.public_key(), timeout);
+    let funding = chain.fund(&alice, &htlc, 1_000);
+
+    // Mallory copies Bob's preimage and signature out of the relayed claim
+    let claim = claim(&htlc, funding, &bob, &preimage, 990);
+    let mut copied = pay_to(Htlc::claim_input(funding), 990, &mallory);
+    copied.inputs_mut()[0].script_sig = claim.inputs()[0].script_sig.clone();
+    assert!(matches!(
+        chain.chain.add_to_mempool(copied.clone()),
+        Err(BtcError::InvalidScript(ScriptError::NullFail))
+    ));
+    assert!(chain.mine(vec![copied]).is_err());
+
+    // a higher fee doesn't let it through either
+    let mut higher_fee = pay_to(Htlc::claim_input(funding), 980, &bob);
+    higher_fee.inputs_mut()[0].script_sig = claim.inputs()[0].script_sig.clone();
+    assert!(chain.chain.add_to_mempool(higher_fee).is_err());
+
+    chain.confirm(claim).unwrap();
+    assert_eq!(chain.balance(&bob), 990);
+    assert_eq!(chain.balance(&mallory), 0);
+}
@@ -0,0 +1,213 @@
+use chrono::{TimeZone, Utc};
+use lib::crypto::PrivateKey;
+use lib::error::BtcError;
+use lib::params::ChainParams;
+use lib::script::{Op, Script};
+use lib::sha256::Hash;
+use lib::types::{
+    Block, BlockHeader, Blockchain, LockingCondition, Transaction, TransactionInput,
+    TransactionOutput,
+};
+use lib::util::MerkleRoot;
+use lib::{MAX_BLOCK_SIGOPS, MAX_BLOCK_SIZE, MAX_BLOCK_WEIGHT, U256};
+
+// block 1 pays the key
+fn funded_chain(key: &PrivateKey) -> Blockchain {
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    let coinbase = Transaction::new(vec![], vec![TransactionOutput::new(chain.params().block_reward(1), key.public_key().into())]);
+    chain.add_block(block(&chain, vec![coinbase])).unwrap();
+    chain
+}
+
+fn block(chain: &Blockchain, mut transactions: Vec<Transaction>) -> Block {
+    Block::commit_witnesses(&mut transactions);
+    let prev = chain.blocks.last().map_or(Hash::zero(), |block| block.hash());
+    let timestamp = Utc.timestamp_opt(1_700_000_000 + chain.block_height() as i64 * 600, 0).unwrap();
+    let header = BlockHeader::new(timestamp, 0, prev, MerkleRoot::calculate(&transactions), U256::MAX);
+    Block::new(header, transactions)
+}
+
+// spendable with an empty script_sig, but counts 20 sigops per CHECKMULTISIG
+fn sigop_heavy_lock(multisigs: usize) -> LockingCondition {
+    let mut ops = vec![Op::NotIf];
+    ops.extend(std::iter::repeat_n(Op::CheckMultiSig, multisigs));
+    ops.extend([Op::EndIf, Op::push_int(1)]);
+    LockingCondition::Script(Script::new(ops))
+}
+
+fn spend_heavy(chain: &mut Blockchain, key: &PrivateKey, multisigs: usize, value: u64, fee: u64) -> Transaction {
+    let utxo = TransactionOutput::new(value, sigop_heavy_lock(multisigs));
+    let utxo_hash = utxo.hash();
+    chain.utxos.insert(utxo_hash, utxo);
+
+    Transaction::new(
+        vec![TransactionInput::with_script(utxo_hash, Script::new(vec![Op::push_int(1)]))],
+        vec![TransactionOutput::new(value - fee, key.public_key().into())],
+    )
+}
+
+#[test]
+fn sigop_counting() {
+    let keys: Vec<PrivateKey> = (0..3).map(|_| PrivateKey::new_key()).collect();
+    let pubkeys: Vec<_> = keys.iter().map(PrivateKey::public_key).collect();
+
+    assert_eq!(Script::pay_to_pubkey_hash(&pubkeys[0]).sigop_count(), 1);
+    assert_eq!(Script::multisig(2, &pubkeys).sigop_count(), 3);
+    assert_eq!(Script::new(vec![Op::CheckMultiSig]).sigop_count(), 20);
+    assert_eq!(Script::new(vec![Op::CheckSigVerify, Op::CheckSig]).sigop_count(), 2);
+    assert_eq!(Script::data_carrier(b"data").sigop_count(), 0);
+
+    assert_eq!(LockingCondition::from(pubkeys[0].clone()).sigop_count(), 1);
+    assert_eq!(LockingCondition::multisig(2, pubkeys).unwrap().sigop_count(), 2);
+    assert_eq!(sigop_heavy_lock(100).sigop_count(), 2_000);
+}
+
+#[test]
+fn blocks_over_the_sigop_limit_are_rejected() {
+    let key = PrivateKey::new_key();
+    let mut chain = funded_chain(&key);
+
+    // 201 ops per script at most, 6 scripts of 199 CHECKMULTISIGs go over
+    let spends: Vec<Transaction> = (0..6).map(|_| spend_heavy(&mut chain, &key, 199, 1_000, 0)).collect();
+    let sigops: usize = spends.iter().map(|tx| tx.sigop_count(&chain.utxos)).sum();
+    assert!(sigops > MAX_BLOCK_SIGOPS);
+
+    let coinbase = Transaction::new(vec![], vec![TransactionOutput::new(chain.params().block_reward(2), key.public_key().into())]);
+    let mut transactions = vec![coinbase];
+    transactions.extend(spends);
+
+    assert!(matches!(
+        chain.add_block(block(&chain, transactions)),
+        Err(BtcError::BlockLimitsExceeded)
+    ));
+}
+
+#[test]
+fn blocks_over_the_weight_limit_are_rejected() {
+    let key = PrivateKey::new_key();
+    let mut chain = funded_chain(&key);
+
+    let mut outputs = vec![TransactionOutput::new(chain.params().block_reward(2), key.public_key().into())];
+    outputs.extend((0..101).map(|_| TransactionOutput::data_carrier(&[0; 9_990])));
+    let heavy = block(&chain, vec![Transaction::new(vec![], outputs)]);
+
+    assert!(heavy.weight() > MAX_BLOCK_WEIGHT);
+    assert!(matches!(chain.add_block(heavy), Err(BtcError::BlockLimitsExceeded)));
+}
+
+#[test]
+fn blocks_over_the_size_limit_are_rejected() {
+    let key = PrivateKey::new_key();
+    let mut chain = funded_chain(&key);
+
+    // script_sig pushes are witness data, light on weight but not on size
+    let lock = LockingCondition::Script(Script::new(
+        std::iter::repeat_n(Op::Drop, 18).chain([Op::push_int(1)]).collect(),
+    ));
+    let unlock = Script::new(vec![Op::Push(vec![0; 520]); 18]);
+    let inputs = (0..120)
+        .map(|_| {
+            let utxo = TransactionOutput::new(1_000, lock.clone());
+            let utxo_hash = utxo.hash();
+            chain.utxos.insert(utxo_hash, utxo);
+            TransactionInput::with_script(utxo_hash, unlock.clone())
+        })
+        .collect();
+    let spend = Transaction::new(inputs, vec![TransactionOutput::new(120_000, key.public_key().into())]);
+
+    let coinbase = Transaction::new(vec![], vec![TransactionOutput::new(chain.params().block_reward(2), key.public_key().into())]);
+    let large = block(&chain, vec![coinbase, spend]);
+
+    assert!(large.weight() <= MAX_BLOCK_WEIGHT);
+    assert!(large.size() > MAX_BLOCK_SIZE);
+    assert!(matches!(chain.add_block(large), Err(BtcError::BlockLimitsExceeded)));
+}
+
+#[test]
+fn blocks_spending_more_than_their_inputs_are_rejected() {
+    let key = PrivateKey::new_key();
+    let mut chain = funded_chain(&key);
+    let funding = chain.blocks[1].transactions()[0].outputs()[0].clone();
+
+    let mut overspend = Transaction::new(
+        vec![TransactionInput::unsigned(funding.hash())],
+        vec![TransactionOutput::new(funding.value + 1, key.public_key().into())],
+    );
+    overspend.sign_input(0, 0, &key, &key.public_key());
+    let coinbase = Transaction::new(vec![], vec![TransactionOutput::new(chain.params().block_reward(2), key.public_key().into())]);
+
+    let invalid = block(&chain, vec![coinbase, overspend]);
+    assert!(matches!(chain.add_block(invalid), Err(BtcError::InvalidTransaction)));
+    assert_eq!(chain.block_height(), 2);
+}
+
+#[test]
+fn template_orders_by_fee_rate_within_limits() {
+    let key = PrivateKey::new_key();
+    let mut chain = funded_chain(&key);
+
+    // eleven transactions of 1980 sigops, only ten fit, the cheapest is left out
+    for fee in 1..=11 {
+        let transaction = spend_heavy(&mut chain, &key, 99, 10_000, fee * 100);
+        chain.add_to_mempool(transaction).unwrap();
+    }
+
+    // a plain payment paying more per byte goes first
+    let funding = chain.blocks[1].transactions()[0].outputs()[0].hash();
+    let mut payment = Transaction::new(
+        vec![TransactionInput::unsigned(funding)],
+        vec![TransactionOutput::new(chain.params().block_reward(1) - 5_000, key.public_key().into())],
+    );
+    payment.sign_input(0, 0, &key, &key.public_key());
+    chain.add_to_mempool(payment.clone()).unwrap();
+
+    let template = chain.block_template(key.public_key().into()).unwrap();
+    let transactions = template.transactions();
+
+    assert_eq!(transactions.len(), 12);
+    assert_eq!(transactions[1].hash(), payment.hash());
+    assert!(transactions.iter().all(|tx| tx.outputs()[0].value != 10_000 - 100));
+    assert!(template.sigop_count(&chain.utxos) <= MAX_BLOCK_SIGOPS);
+
+    let fees = 5_000 + (2..=11).map(|fee| fee * 100).sum::<u64>();
+    assert_eq!(transactions[0].outputs()[0].value, chain.params().block_reward(2) + fees);
+
+    chain.add_block(template).unwrap();
+    assert_eq!(chain.mempool.len(), 1);
+
+    let stats = chain.block_stats(2).unwrap();
+    assert_eq!(stats.transactions, 12);
+    assert_eq!(stats.inputs, 11);
+    assert_eq!(stats.fees, fees);
+    assert_eq!(stats.sigops, 1 + 10 * 1_980);
+    assert_eq!(stats.weight, chain.blocks[2].weight());
+}
+
+#[test]
+fn the_mempool_is_trimmed_by_fee_rate() {
+    let key = PrivateKey::new_key();
+    let mut chain = funded_chain(&key);
+
+    let transactions: Vec<Transaction> = [300, 100, 200]
+        .into_iter()
+        .map(|fee| spend_heavy(&mut chain, &key, 1, 10_000, fee))
+        .collect();
+    for transaction in &transactions {
+        chain.add_to_mempool(transaction.clone()).unwrap();
+    }
+    let size: usize = transactions.iter().map(Transaction::total_size).sum();
+    assert!(chain.trim_mempool(size).is_empty());
+
+    // a byte too many costs the lowest fee rate its place
+    let evicted = chain.trim_mempool(size - 1);
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].hash(), transactions[1].hash());
+    assert_eq!(chain.mempool.len(), 2);
+    assert!(chain.mempool_transaction(&transactions[1].hash()).is_none());
+    // the others moved up, their lookup follows them
+    assert_eq!(chain.mempool_transaction(&transactions[2].hash()).unwrap().hash(), transactions[2].hash());
+
+    let evicted = chain.trim_mempool(0);
+    assert_eq!(evicted.len(), 2);
+    assert!(chain.mempool.is_empty());
+}
@@ -0,0 +1,39 @@
+use lib::crypto::{PrivateKey, Signature};
+use lib::message::{self, MessageSignature};
+
+#[test]
+fn signed_message_recovers_signer() {
+    let key = PrivateKey::new_key();
+    let signature = message::sign_message(&key, "I own this key");
+
+    assert_eq!(signature.recover("I own this key").unwrap(), key.public_key());
+    assert!(message::verify_message(&key.public_key(), "I own this key", &signature));
+    assert!(message::verify_message(&key.schnorr_public_key(), "I own this key", &signature));
+    assert!(!message::verify_message(&key.public_key(), "I own that key", &signature));
+    assert!(!message::verify_message(&PrivateKey::new_key().public_key(), "I own this key", &signature));
+}
+
+#[test]
+fn base64_roundtrip() {
+    let key = PrivateKey::new_key();
+    let signature = message::sign_message(&key, "roundtrip");
+    let text = signature.to_base64();
+
+    assert_eq!(text.len(), 88);
+    assert_eq!(MessageSignature::from_base64(&text).unwrap(), signature);
+    assert!(MessageSignature::from_base64("not a signature").is_err());
+}
+
+#[test]
+fn message_signatures_are_domain_separated() {
+    let key = PrivateKey::new_key();
+    let signature = message::sign_message(&key, "payload");
+
+    // the same key signing the raw hash of the message must not produce a valid message signature
+    let raw = Signature::sign_output(&lib::sha256::Hash::hash(&"payload"), &key);
+    let mut forged = signature.to_bytes();
+    forged[1..].copy_from_slice(&raw.to_bytes());
+
+    let forged = MessageSignature::from_bytes(&forged).unwrap();
+    assert!(!message::verify_message(&key.public_key(), "payload", &forged));
+}
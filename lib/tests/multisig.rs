@@ -0,0 +1,119 @@
+use lib::crypto::{PrivateKey, Signature};
+use lib::error::BtcError;
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::types::{
+    Blockchain, InputSignature, LockingCondition, Transaction, TransactionInput, TransactionOutput,
+};
+
+struct Setup {
+    keys: Vec<PrivateKey>,
+    chain: Blockchain,
+    utxo_hash: Hash,
+}
+
+// 2-of-3 multisig UTXO, the second key locked to its Schnorr key
+fn setup() -> Setup {
+    let keys: Vec<PrivateKey> = (0..3).map(|_| PrivateKey::new_key()).collect();
+    let pubkeys = vec![
+        keys[0].public_key(),
+        keys[1].schnorr_public_key(),
+        keys[2].public_key(),
+    ];
+    let utxo = TransactionOutput::new(1_000, LockingCondition::multisig(2, pubkeys).unwrap());
+    let utxo_hash = utxo.hash();
+
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    chain.utxos.insert(utxo_hash, utxo);
+
+    Setup { keys, chain, utxo_hash }
+}
+
+// spend signed by the (key index, signature) pairs `sign` makes over the sighash
+fn spend(setup: &Setup, sign: impl Fn(&Hash) -> Vec<(u8, Signature)>) -> Transaction {
+    let mut transaction = Transaction::new(
+        vec![TransactionInput::unsigned(setup.utxo_hash)],
+        vec![TransactionOutput::new(900, setup.keys[0].public_key().into())],
+    );
+
+    let sighash = transaction.sighash(&setup.utxo_hash);
+    for (key_index, signature) in sign(&sighash) {
+        transaction.inputs_mut()[0].add_signature(key_index, signature);
+    }
+    transaction
+}
+
+#[test]
+fn threshold_signatures_spend() {
+    let mut setup = setup();
+    let transaction = spend(&setup, |hash| {
+        vec![
+            (2, Signature::sign_output(hash, &setup.keys[2])),
+            (1, Signature::sign_output_schnorr(hash, &setup.keys[1])),
+        ]
+    });
+
+    // signatures end up sorted by key index regardless of signing order
+    assert_eq!(transaction.inputs()[0].signatures[0].key_index, 1);
+    setup.chain.add_to_mempool(transaction).unwrap();
+}
+
+#[test]
+fn too_few_signatures_are_rejected() {
+    let mut setup = setup();
+    let transaction = spend(&setup, |hash| vec![(0, Signature::sign_output(hash, &setup.keys[0]))]);
+
+    assert!(matches!(
+        setup.chain.add_to_mempool(transaction),
+        Err(BtcError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn same_key_cannot_sign_twice() {
+    let mut setup = setup();
+    let mut transaction = spend(&setup, |_| Vec::new());
+    let signature = Signature::sign_output(&transaction.sighash(&setup.utxo_hash), &setup.keys[0]);
+
+    transaction.inputs_mut()[0].signatures = vec![
+        InputSignature { key_index: 0, signature: signature.clone() },
+        InputSignature { key_index: 0, signature },
+    ];
+
+    assert!(matches!(
+        setup.chain.add_to_mempool(transaction),
+        Err(BtcError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn signature_by_wrong_key_is_rejected() {
+    let mut setup = setup();
+    let transaction = spend(&setup, |hash| {
+        vec![
+            (0, Signature::sign_output(hash, &setup.keys[0])),
+            (2, Signature::sign_output(hash, &PrivateKey::new_key())),
+        ]
+    });
+
+    assert!(matches!(
+        setup.chain.add_to_mempool(transaction),
+        Err(BtcError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn invalid_multisig_conditions() {
+    let key = PrivateKey::new_key();
+
+    assert!(LockingCondition::multisig(0, vec![key.public_key()]).is_err());
+    assert!(LockingCondition::multisig(2, vec![key.public_key()]).is_err());
+    assert!(LockingCondition::multisig(1, vec![key.public_key(); 21]).is_err());
+
+    // one signer must not count twice, whichever scheme the key is listed under
+    let other = PrivateKey::new_key();
+    assert!(LockingCondition::multisig(2, vec![key.public_key(), other.public_key(), key.public_key()]).is_err());
+    assert!(LockingCondition::multisig(2, vec![key.public_key(), key.schnorr_public_key()]).is_err());
+    assert!(LockingCondition::multisig(2, vec![key.public_key(), other.schnorr_public_key()]).is_ok());
+}
+
@@ -0,0 +1,110 @@
+use lib::compact::{BlockTransactions, BlockTransactionsRequest, CompactBlock};
+use lib::crypto::PrivateKey;
+use lib::network::{Inventory, Message, NetAddress, Version, HEADER_SIZE, MAX_PAYLOAD_SIZE, PROTOCOL_VERSION};
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::types::{Transaction, TransactionOutput};
+use std::io::ErrorKind;
+
+const MAGIC: [u8; 4] = [0xfa, 0xbf, 0xb5, 0xda];
+
+async fn roundtrip(message: &Message) -> Message {
+    let frame = message.encode(MAGIC).unwrap();
+    let mut reader = frame.as_slice();
+    let received = Message::receive(&mut reader, MAGIC).await.unwrap();
+    assert!(reader.is_empty());
+    received
+}
+
+async fn receive(frame: &[u8]) -> ErrorKind {
+    let mut reader = frame;
+    Message::receive(&mut reader, MAGIC).await.unwrap_err().kind()
+}
+
+#[tokio::test]
+async fn messages_survive_framing() {
+    let params = ChainParams::regtest();
+    let version = Version {
+        version: PROTOCOL_VERSION,
+        timestamp: 1_700_000_000,
+        nonce: 7,
+        user_agent: "/test/".to_string(),
+        height: 3,
+        port: 19644,
+        genesis: params.genesis_block().hash(),
+    };
+    assert!(matches!(roundtrip(&Message::Version(version.clone())).await, Message::Version(v) if v == version));
+    assert!(matches!(roundtrip(&Message::Verack).await, Message::Verack));
+    assert!(matches!(roundtrip(&Message::Ping(42)).await, Message::Ping(42)));
+    assert!(matches!(roundtrip(&Message::Pong(42)).await, Message::Pong(42)));
+
+    let inventory = vec![Inventory::Block(Hash::hash(&1)), Inventory::Transaction(Hash::hash(&2))];
+    assert!(matches!(roundtrip(&Message::Inv(inventory.clone())).await, Message::Inv(i) if i == inventory));
+    assert!(matches!(roundtrip(&Message::GetData(inventory.clone())).await, Message::GetData(i) if i == inventory));
+
+    let addresses = vec![NetAddress { addr: "10.0.0.1:8333".parse().unwrap(), timestamp: 1_700_000_000 }];
+    assert!(matches!(roundtrip(&Message::GetAddr).await, Message::GetAddr));
+    assert!(matches!(roundtrip(&Message::Addr(addresses.clone())).await, Message::Addr(a) if a == addresses));
+
+    let genesis = params.genesis_block();
+    assert!(matches!(roundtrip(&Message::Block(genesis.clone())).await, Message::Block(b) if b.hash() == genesis.hash()));
+
+    let key = PrivateKey::new_key();
+    let transaction = Transaction::new(vec![], vec![TransactionOutput::new(1, key.public_key().into())]);
+    assert!(matches!(roundtrip(&Message::Tx(transaction.clone())).await, Message::Tx(t) if t.hash() == transaction.hash()));
+
+    assert!(matches!(roundtrip(&Message::SendCmpct(true)).await, Message::SendCmpct(true)));
+    let compact = CompactBlock::new(&genesis, 3);
+    assert!(matches!(
+        roundtrip(&Message::CmpctBlock(compact)).await,
+        Message::CmpctBlock(c) if c.header.hash() == genesis.hash() && c.nonce == 3
+    ));
+    let request = BlockTransactionsRequest { block_hash: genesis.hash(), indexes: vec![0] };
+    assert!(matches!(roundtrip(&Message::GetBlockTxn(request.clone())).await, Message::GetBlockTxn(r) if r == request));
+    let answer = BlockTransactions::new(&genesis, &request).unwrap();
+    assert!(matches!(
+        roundtrip(&Message::BlockTxn(answer)).await,
+        Message::BlockTxn(b) if b.transactions[0].hash() == genesis.transactions()[0].hash()
+    ));
+}
+
+#[tokio::test]
+async fn frame_layout() {
+    let frame = Message::Ping(1).encode(MAGIC).unwrap();
+
+    assert_eq!(frame[..4], MAGIC);
+    assert_eq!(&frame[4..16], b"ping\0\0\0\0\0\0\0\0");
+    let length = u32::from_le_bytes(frame[16..20].try_into().unwrap()) as usize;
+    assert_eq!(frame.len(), HEADER_SIZE + length);
+    assert_eq!(frame[20..24], lib::util::checksum(&frame[HEADER_SIZE..]));
+
+    // an empty payload still has a checksum
+    assert_eq!(Message::Verack.encode(MAGIC).unwrap().len(), HEADER_SIZE);
+}
+
+#[tokio::test]
+async fn broken_frames_are_rejected() {
+    let frame = Message::Ping(1).encode(MAGIC).unwrap();
+
+    let other_network = Message::Ping(1).encode(ChainParams::main().magic).unwrap();
+    assert_eq!(receive(&other_network).await, ErrorKind::InvalidData);
+
+    let mut corrupted = frame.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert_eq!(receive(&corrupted).await, ErrorKind::InvalidData);
+
+    let mut unknown = frame.clone();
+    unknown[4..16].copy_from_slice(b"gossip\0\0\0\0\0\0");
+    assert_eq!(receive(&unknown).await, ErrorKind::InvalidData);
+
+    let mut padded = frame.clone();
+    padded[15] = b'x';
+    assert_eq!(receive(&padded).await, ErrorKind::InvalidData);
+
+    // the length is checked before reading the payload
+    let mut oversized = frame.clone();
+    oversized[16..20].copy_from_slice(&(MAX_PAYLOAD_SIZE as u32 + 1).to_le_bytes());
+    assert_eq!(receive(&oversized).await, ErrorKind::InvalidData);
+
+    assert_eq!(receive(&frame[..frame.len() - 1]).await, ErrorKind::UnexpectedEof);
+}
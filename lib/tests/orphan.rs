@@ -0,0 +1,161 @@
+use std::net::SocketAddr;
+use chrono::{Duration, Utc};
+use lib::crypto::PrivateKey;
+use lib::error::BtcError;
+use lib::orphan::{OrphanPool, MAX_ORPHANS, ORPHAN_EXPIRY};
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction, TransactionInput, TransactionOutput};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+const NOW: i64 = 1_700_000_000;
+const COIN: u64 = 100_000_000;
+
+fn output(value: u64, owner: &PrivateKey) -> TransactionOutput {
+    TransactionOutput::new(value, owner.public_key().into())
+}
+
+fn spend(utxo_hash: Hash, owner: &PrivateKey, value: u64) -> Transaction {
+    let mut transaction = Transaction::new(vec![TransactionInput::unsigned(utxo_hash)], vec![output(value, owner)]);
+    transaction.sign_input(0, 0, owner, &owner.public_key());
+    transaction
+}
+
+fn block(prev_block_hash: Hash, mut transactions: Vec<Transaction>) -> Block {
+    Block::commit_witnesses(&mut transactions);
+    let header = BlockHeader::new(
+        Utc::now() + Duration::seconds(1),
+        0,
+        prev_block_hash,
+        MerkleRoot::calculate(&transactions),
+        U256::MAX,
+    );
+    Block::new(header, transactions)
+}
+
+fn peer(port: u16) -> Option<SocketAddr> {
+    Some(SocketAddr::from(([10, 0, 0, 1], port)))
+}
+
+#[test]
+fn orphans_wait_for_their_inputs() {
+    let alice = PrivateKey::new_key();
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+
+    // a payment from a block we haven't seen yet
+    let funding = output(50 * COIN, &alice);
+    let orphan = spend(funding.hash(), &alice, 49 * COIN);
+    assert!(matches!(chain.add_to_mempool(orphan.clone()), Err(BtcError::MissingInputs)));
+
+    let mut pool = OrphanPool::new();
+    assert!(pool.add(orphan.clone(), peer(1), NOW));
+    assert!(!pool.add(orphan.clone(), peer(2), NOW));
+    assert!(pool.contains(&orphan.hash()));
+    assert!(pool.take_ready(&chain).is_empty());
+
+    let coinbase = Transaction::new(vec![], vec![funding]);
+    chain.add_block(block(chain.blocks[0].hash(), vec![coinbase])).unwrap();
+
+    let ready = pool.take_ready(&chain);
+    assert_eq!(ready.len(), 1);
+    assert_eq!((ready[0].0.hash(), ready[0].1), (orphan.hash(), peer(1)));
+    assert!(pool.is_empty());
+    chain.add_to_mempool(ready[0].0.clone()).unwrap();
+}
+
+#[test]
+fn the_pool_is_bounded() {
+    let alice = PrivateKey::new_key();
+    let orphans: Vec<Transaction> = (0..MAX_ORPHANS as u64 + 10)
+        .map(|i| spend(Hash::hash(&i), &alice, COIN))
+        .collect();
+
+    let mut pool = OrphanPool::new();
+    for orphan in &orphans {
+        assert!(pool.add(orphan.clone(), peer(1), NOW));
+    }
+    assert_eq!(pool.len(), MAX_ORPHANS);
+
+    // a disconnected peer's orphans go with it
+    let other = spend(Hash::hash(&"other"), &alice, COIN);
+    pool.add(other.clone(), peer(2), NOW);
+    pool.forget_peer(peer(1).unwrap());
+    assert_eq!(pool.len(), 1);
+    assert!(pool.contains(&other.hash()));
+
+    assert_eq!(pool.expire(NOW + ORPHAN_EXPIRY - 1), 0);
+    assert_eq!(pool.expire(NOW + ORPHAN_EXPIRY), 1);
+    assert!(pool.is_empty());
+}
+
+#[test]
+fn orphans_wait_for_unconfirmed_parents() {
+    let alice = PrivateKey::new_key();
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    let funding = output(50 * COIN, &alice);
+    let coinbase = Transaction::new(vec![], vec![funding.clone()]);
+    chain.add_block(block(chain.blocks[0].hash(), vec![coinbase])).unwrap();
+
+    // a chain of payments arriving child first
+    let parent = spend(funding.hash(), &alice, 49 * COIN);
+    let child = spend(parent.outputs()[0].hash(), &alice, 48 * COIN);
+    let grandchild = spend(child.outputs()[0].hash(), &alice, 47 * COIN);
+    assert!(matches!(chain.add_to_mempool(child.clone()), Err(BtcError::MissingInputs)));
+
+    let mut pool = OrphanPool::new();
+    pool.add(child.clone(), peer(1), NOW);
+    pool.add(grandchild.clone(), peer(1), NOW);
+    assert!(pool.take_ready(&chain).is_empty());
+
+    chain.add_to_mempool(parent.clone()).unwrap();
+    let ready = pool.take_ready(&chain);
+    assert_eq!(ready.len(), 1);
+    assert_eq!(ready[0].0.hash(), child.hash());
+    chain.add_to_mempool(ready[0].0.clone()).unwrap();
+
+    let ready = pool.take_ready(&chain);
+    assert_eq!(ready.len(), 1);
+    assert_eq!(ready[0].0.hash(), grandchild.hash());
+    chain.add_to_mempool(ready[0].0.clone()).unwrap();
+    assert!(pool.is_empty());
+    assert_eq!(chain.mempool.len(), 3);
+
+    // a block can't spend outputs created in it, each generation waits for the next one
+    let template = chain.block_template(alice.public_key().into()).unwrap();
+    assert_eq!(template.transactions()[1].hash(), parent.hash());
+    assert_eq!(template.transactions().len(), 2);
+    chain.add_block(template).unwrap();
+
+    assert_eq!(chain.mempool.len(), 2);
+    let template = chain.block_template(alice.public_key().into()).unwrap();
+    assert_eq!(template.transactions()[1].hash(), child.hash());
+    assert_eq!(template.transactions().len(), 2);
+}
+
+#[test]
+fn descendants_leave_the_mempool_with_their_parent() {
+    let alice = PrivateKey::new_key();
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    let funding = output(50 * COIN, &alice);
+    let coinbase = Transaction::new(vec![], vec![funding.clone()]);
+    chain.add_block(block(chain.blocks[0].hash(), vec![coinbase])).unwrap();
+
+    // the parent pays the lowest fee rate, its child a high one
+    let parent = spend(funding.hash(), &alice, 50 * COIN - 1_000);
+    let child = spend(parent.outputs()[0].hash(), &alice, 40 * COIN);
+    chain.add_to_mempool(parent.clone()).unwrap();
+    chain.add_to_mempool(child.clone()).unwrap();
+
+    let evicted = chain.trim_mempool(child.total_size());
+    assert_eq!(evicted.len(), 2);
+    assert!(chain.mempool.is_empty());
+
+    // a block spending the parent's input some other way conflicts with both
+    chain.add_to_mempool(parent).unwrap();
+    chain.add_to_mempool(child).unwrap();
+    let conflict = spend(funding.hash(), &alice, 49 * COIN);
+    let coinbase = Transaction::new(vec![], vec![output(51 * COIN, &alice)]);
+    chain.add_block(block(chain.blocks[1].hash(), vec![coinbase, conflict])).unwrap();
+    assert!(chain.mempool.is_empty());
+}
@@ -0,0 +1,221 @@
+use chrono::{DateTime, TimeZone, Utc};
+use lib::address::Address;
+use lib::crypto::PrivateKey;
+use lib::error::BtcError;
+use lib::params::{ChainParams, Network};
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction, TransactionOutput};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+fn block_time(height: u64, spacing: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(1_700_000_000 + height as i64 * spacing, 0).unwrap()
+}
+
+// mines the next block with the given target
+fn block(chain: &Blockchain, key: &PrivateKey, target: U256, spacing: i64) -> Block {
+    let height = chain.block_height();
+    let transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput::new(chain.params().block_reward(height), key.public_key().into())],
+    )];
+    let prev = chain.blocks.last().map_or(Hash::zero(), |block| block.hash());
+    let mut header = BlockHeader::new(
+        block_time(height, spacing),
+        0,
+        prev,
+        MerkleRoot::calculate(&transactions),
+        target,
+    );
+    assert!(header.mine(1_000));
+    Block::new(header, transactions)
+}
+
+// easy enough to mine in a test, but still retargeting every 4 blocks
+fn retargeting() -> ChainParams {
+    ChainParams {
+        network: Network::Custom,
+        min_target: U256::MAX >> 1,
+        difficulty_update_interval: 4,
+        ideal_block_time: 600,
+        retarget: true,
+        genesis_timestamp: 1_700_000_000,
+        ..ChainParams::regtest()
+    }
+}
+
+#[test]
+fn presets_differ_in_identity() {
+    let (main, test, regtest) = (ChainParams::main(), ChainParams::test(), ChainParams::regtest());
+
+    assert_ne!(main.magic, test.magic);
+    assert_ne!(main.magic, regtest.magic);
+    assert_ne!(test.magic, regtest.magic);
+    assert_ne!(main.default_port, test.default_port);
+    assert_ne!(test.default_port, regtest.default_port);
+
+    // Bitcoin's own networks, whose nodes must not take us for peers
+    let bitcoin = [
+        ([0xf9, 0xbe, 0xb4, 0xd9], 8333),
+        ([0x0b, 0x11, 0x09, 0x07], 18333),
+        ([0xfa, 0xbf, 0xb5, 0xda], 18444),
+    ];
+    for (magic, port) in bitcoin {
+        for params in [&main, &test, &regtest] {
+            assert_ne!(params.magic, magic);
+            assert_ne!(params.default_port, port);
+        }
+    }
+    assert_ne!(main.address_prefix, test.address_prefix);
+    assert_eq!(ChainParams::for_network(Network::Test), Some(test));
+    assert_eq!(ChainParams::for_network(Network::Custom), None);
+    assert_eq!("regtest".parse::<Network>(), Ok(Network::Regtest));
+
+    assert_eq!(main.block_reward(0), 50 * 100_000_000);
+    assert_eq!(main.block_reward(210), 25 * 100_000_000);
+    assert_eq!(regtest.block_reward(150), 25 * 100_000_000);
+    assert_eq!(main.block_reward(210 * 64), 0);
+}
+
+#[test]
+fn adjusted_target_is_clamped() {
+    let params = retargeting();
+    let expected = 600 * 4;
+    let target = U256::MAX >> 8;
+
+    assert_eq!(params.adjusted_target(target, expected), target / 2400 * 2400);
+    // at most four times harder or easier
+    assert_eq!(params.adjusted_target(target, 1), target / 2400 * 600);
+    assert_eq!(params.adjusted_target(target, expected * 100), target / 2400 * 9600);
+    // never easier than the minimum
+    assert_eq!(params.adjusted_target(params.min_target, expected * 2), params.min_target);
+}
+
+#[test]
+fn blocks_need_the_expected_target() {
+    let key = PrivateKey::new_key();
+    let params = retargeting();
+    let mut chain = Blockchain::with_params(params.clone());
+
+    // the first period stays at the minimum
+    let too_easy = block(&chain, &key, U256::MAX, 1);
+    assert!(matches!(chain.add_block(too_easy), Err(BtcError::InvalidBlock)));
+
+    for _ in 0..3 {
+        let target = chain.next_target();
+        chain.add_block(block(&chain, &key, target, 1)).unwrap();
+    }
+
+    // the period was far too fast, the target drops by the maximum factor
+    let next_target = params.adjusted_target(params.min_target, 3);
+    assert_eq!(chain.next_target(), next_target);
+    assert!(next_target < params.min_target / 3);
+    let stale = block(&chain, &key, params.min_target, 1);
+    assert!(matches!(chain.add_block(stale), Err(BtcError::InvalidBlock)));
+
+    // keeps it until the next retarget
+    for _ in 0..3 {
+        let target = chain.next_target();
+        assert_eq!(target, next_target);
+        chain.add_block(block(&chain, &key, target, 1)).unwrap();
+    }
+
+    // regtest never retargets
+    let mut regtest = Blockchain::with_params(ChainParams::regtest());
+    for _ in 0..200 {
+        regtest.add_block(block(&regtest, &key, U256::MAX, 1)).unwrap();
+    }
+    assert_eq!(regtest.next_target(), U256::MAX);
+}
+
+#[test]
+fn parameter_files() {
+    let path = std::env::temp_dir().join(format!("params-{}.toml", std::process::id()));
+    let params = retargeting();
+
+    params.save_to_file(&path).unwrap();
+    assert_eq!(ChainParams::load_from_file(&path).unwrap(), params);
+
+    let zero_interval = ChainParams {
+        difficulty_update_interval: 0,
+        ..params.clone()
+    };
+    zero_interval.save_to_file(&path).unwrap();
+    assert!(ChainParams::load_from_file(&path).is_err());
+
+    let unreachable_threshold = ChainParams {
+        deployment_threshold: params.deployment_period + 1,
+        ..params
+    };
+    unreachable_threshold.save_to_file(&path).unwrap();
+    assert!(ChainParams::load_from_file(&path).is_err());
+
+    // only custom networks come from files, the others are presets
+    let claiming_main = ChainParams {
+        network: Network::Main,
+        ..retargeting()
+    };
+    claiming_main.save_to_file(&path).unwrap();
+    assert!(ChainParams::load_from_file(&path).is_err());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn addresses_are_tied_to_a_network() {
+    let key = PrivateKey::new_key();
+    let (main, test) = (ChainParams::main(), ChainParams::test());
+
+    for pubkey in [key.public_key(), key.schnorr_public_key()] {
+        let address = Address::new(pubkey.clone(), &main).to_string();
+        assert_eq!(Address::parse(&address, &main).unwrap().pubkey, pubkey);
+        assert!(matches!(Address::parse(&address, &test), Err(BtcError::InvalidAddress)));
+    }
+
+    // version byte 0 shows as a leading '1'
+    let address = Address::new(key.public_key(), &main).to_string();
+    assert!(address.starts_with('1'));
+
+    // a changed character fails the checksum
+    let mut corrupted = address.into_bytes();
+    let last = corrupted.len() - 1;
+    corrupted[last] = if corrupted[last] == b'2' { b'3' } else { b'2' };
+    let corrupted = String::from_utf8(corrupted).unwrap();
+    assert!(matches!(Address::parse(&corrupted, &main), Err(BtcError::InvalidAddress)));
+    assert!(Address::parse("0OIl", &main).is_err());
+}
+
+#[test]
+fn genesis_blocks_are_fixed() {
+    for params in [ChainParams::main(), ChainParams::test(), ChainParams::regtest()] {
+        let genesis = params.genesis_block();
+        assert_eq!(genesis.hash(), params.genesis_block().hash());
+        assert!(genesis.header().hash().matches_target(params.min_target), "{}", params.network);
+
+        let chain = Blockchain::with_params(params.clone());
+        assert_eq!(chain.block_height(), 1);
+        assert_eq!(chain.blocks[0].hash(), genesis.hash());
+        // its output carries the message, nobody can spend it
+        assert!(chain.utxos.is_empty());
+        chain.verify_genesis(&params).unwrap();
+    }
+
+    assert_ne!(ChainParams::main().genesis_block().hash(), ChainParams::test().genesis_block().hash());
+}
+
+#[test]
+fn chains_have_to_share_the_genesis() {
+    let key = PrivateKey::new_key();
+    let chain = Blockchain::with_params(ChainParams::regtest());
+    let other = ChainParams { genesis_message: "other".to_string(), ..ChainParams::regtest() };
+
+    // a block with a zero previous hash is no genesis anymore
+    let mut orphan = Blockchain::with_params(ChainParams::regtest());
+    orphan.blocks.clear();
+    assert!(matches!(orphan.add_block(block(&orphan, &key, U256::MAX, 1)), Err(BtcError::GenesisMismatch)));
+    assert!(matches!(orphan.add_block(other.genesis_block()), Err(BtcError::GenesisMismatch)));
+    orphan.add_block(ChainParams::regtest().genesis_block()).unwrap();
+
+    chain.verify_genesis(&ChainParams::regtest()).unwrap();
+    assert!(matches!(chain.verify_genesis(&other), Err(BtcError::GenesisMismatch)));
+}
@@ -0,0 +1,427 @@
+use lib::crypto::{PrivateKey, Signature};
+use lib::error::BtcError;
+use lib::params::ChainParams;
+use lib::script::{
+    self, cast_to_bool, decode_num, encode_num, hash160, Interpreter, Op, Script, ScriptContext,
+    ScriptError, MAX_ELEMENT_SIZE, MAX_OPS_PER_SCRIPT, MAX_SCRIPT_SIZE, MAX_STACK_SIZE,
+};
+use lib::sha256::Hash;
+use lib::sigcache::SignatureCache;
+use lib::types::{Blockchain, LockingCondition, Transaction, TransactionInput, TransactionOutput};
+use sha2::{Digest, Sha256};
+
+fn sighash() -> Hash {
+    Hash::hash(&"spent output")
+}
+
+fn run(script_sig: Script, script_pubkey: Script) -> Result<(), ScriptError> {
+    let cache = SignatureCache::new(0);
+    script::verify_script(
+        &script_sig,
+        &script_pubkey,
+        ScriptContext::new(sighash(), &cache),
+    )
+}
+
+// executes a single script and returns the final stack
+fn eval(ops: Vec<Op>) -> Result<Vec<Vec<u8>>, ScriptError> {
+    let cache = SignatureCache::new(0);
+    let mut interpreter =
+        Interpreter::new(ScriptContext::new(sighash(), &cache));
+    interpreter.execute(&Script::new(ops))?;
+    Ok(interpreter.stack().to_vec())
+}
+
+fn push(data: &[u8]) -> Op {
+    Op::Push(data.to_vec())
+}
+
+// negating s gives the high-S twin of a valid ECDSA signature
+fn high_s(signature: &[u8; 64]) -> [u8; 64] {
+    let order = hex::decode("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141").unwrap();
+    let mut high_s = *signature;
+    let mut borrow = 0i16;
+    for i in (0..32).rev() {
+        let difference = order[i] as i16 - signature[32 + i] as i16 - borrow;
+        high_s[32 + i] = difference.rem_euclid(256) as u8;
+        borrow = (difference < 0) as i16;
+    }
+    high_s
+}
+
+#[test]
+fn numbers_use_minimal_encoding() {
+    let cases: &[(i64, &[u8])] = &[
+        (0, &[]),
+        (1, &[0x01]),
+        (-1, &[0x81]),
+        (127, &[0x7f]),
+        (128, &[0x80, 0x00]),
+        (-128, &[0x80, 0x80]),
+        (255, &[0xff, 0x00]),
+        (256, &[0x00, 0x01]),
+        (-256, &[0x00, 0x81]),
+        (0x7fff_ffff, &[0xff, 0xff, 0xff, 0x7f]),
+    ];
+
+    for (n, bytes) in cases {
+        assert_eq!(encode_num(*n), bytes.to_vec(), "encoding {n}");
+        assert_eq!(decode_num(bytes, 4), Ok(*n), "decoding {n}");
+    }
+
+    // zero padding and negative zero are not minimal
+    assert_eq!(decode_num(&[0x00], 4), Err(ScriptError::InvalidNumber));
+    assert_eq!(decode_num(&[0x80], 4), Err(ScriptError::InvalidNumber));
+    assert_eq!(decode_num(&[0x01, 0x00], 4), Err(ScriptError::InvalidNumber));
+    assert_eq!(decode_num(&[1, 2, 3, 4, 5], 4), Err(ScriptError::InvalidNumber));
+}
+
+#[test]
+fn bool_casting() {
+    assert!(!cast_to_bool(&[]));
+    assert!(!cast_to_bool(&[0]));
+    assert!(!cast_to_bool(&[0, 0]));
+    assert!(!cast_to_bool(&[0, 0x80]));
+    assert!(cast_to_bool(&[1]));
+    assert!(cast_to_bool(&[0x80, 0]));
+    assert!(cast_to_bool(&[0, 1]));
+}
+
+#[test]
+fn stack_operations() {
+    assert_eq!(eval(vec![push(b"a"), Op::Dup]).unwrap(), vec![b"a".to_vec(), b"a".to_vec()]);
+    assert_eq!(eval(vec![push(b"a"), push(b"b"), Op::Drop]).unwrap(), vec![b"a".to_vec()]);
+    assert_eq!(
+        eval(vec![push(b"a"), push(b"b"), Op::Swap]).unwrap(),
+        vec![b"b".to_vec(), b"a".to_vec()]
+    );
+    assert_eq!(
+        eval(vec![push(&[0; 300]), Op::Size]).unwrap(),
+        vec![vec![0; 300], encode_num(300)]
+    );
+
+    for op in [Op::Dup, Op::Drop, Op::Size, Op::Verify, Op::Sha256] {
+        assert_eq!(eval(vec![op]), Err(ScriptError::StackUnderflow));
+    }
+    assert_eq!(eval(vec![push(b"a"), Op::Swap]), Err(ScriptError::StackUnderflow));
+    assert_eq!(eval(vec![push(b"a"), Op::Equal]), Err(ScriptError::StackUnderflow));
+}
+
+#[test]
+fn equal_and_verify() {
+    assert_eq!(eval(vec![push(b"a"), push(b"a"), Op::Equal]).unwrap(), vec![vec![1]]);
+    assert_eq!(eval(vec![push(b"a"), push(b"b"), Op::Equal]).unwrap(), vec![Vec::<u8>::new()]);
+    assert!(eval(vec![push(b"a"), push(b"a"), Op::EqualVerify]).unwrap().is_empty());
+    assert_eq!(
+        eval(vec![push(b"a"), push(b"b"), Op::EqualVerify]),
+        Err(ScriptError::EqualVerifyFailed)
+    );
+    assert!(eval(vec![push(&[1]), Op::Verify]).unwrap().is_empty());
+    assert_eq!(eval(vec![push(&[]), Op::Verify]), Err(ScriptError::VerifyFailed));
+    assert_eq!(eval(vec![Op::Return]), Err(ScriptError::OpReturn));
+}
+
+#[test]
+fn hashing_operations() {
+    let data = b"preimage";
+    let sha = Sha256::digest(data).to_vec();
+    let double_sha = Sha256::digest(Sha256::digest(data)).to_vec();
+
+    assert_eq!(eval(vec![push(data), Op::Sha256]).unwrap(), vec![sha]);
+    assert_eq!(eval(vec![push(data), Op::Hash256]).unwrap(), vec![double_sha]);
+    assert_eq!(eval(vec![push(data), Op::Hash160]).unwrap(), vec![hash160(data).to_vec()]);
+
+    // known vector: HASH160 of the empty string
+    assert_eq!(hex::encode(hash160(&[])), "b472a266d0bd89c13706a4132ccfb16f7c3b9fcb");
+}
+
+#[test]
+fn conditionals() {
+    let branch = |condition: &[u8], op: Op| {
+        eval(vec![push(condition), op, push(b"then"), Op::Else, push(b"else"), Op::EndIf])
+    };
+
+    assert_eq!(branch(&[1], Op::If).unwrap(), vec![b"then".to_vec()]);
+    assert_eq!(branch(&[], Op::If).unwrap(), vec![b"else".to_vec()]);
+    assert_eq!(branch(&[1], Op::NotIf).unwrap(), vec![b"else".to_vec()]);
+    assert_eq!(branch(&[], Op::NotIf).unwrap(), vec![b"then".to_vec()]);
+
+    // nested branches and skipped ops inside untaken branches
+    let nested = eval(vec![
+        push(&[]),
+        Op::If,
+        Op::Return,
+        push(&[1]),
+        Op::If,
+        Op::Return,
+        Op::EndIf,
+        Op::Else,
+        push(&[1]),
+        Op::If,
+        push(b"inner"),
+        Op::EndIf,
+        Op::EndIf,
+    ]);
+    assert_eq!(nested.unwrap(), vec![b"inner".to_vec()]);
+
+    assert_eq!(eval(vec![Op::If]), Err(ScriptError::StackUnderflow));
+    assert_eq!(eval(vec![push(&[1]), Op::If]), Err(ScriptError::UnbalancedConditional));
+    assert_eq!(eval(vec![Op::Else]), Err(ScriptError::UnbalancedConditional));
+    assert_eq!(eval(vec![Op::EndIf]), Err(ScriptError::UnbalancedConditional));
+}
+
+#[test]
+fn limits() {
+    assert!(eval(vec![push(&[0; MAX_ELEMENT_SIZE])]).is_ok());
+    assert_eq!(eval(vec![push(&[0; MAX_ELEMENT_SIZE + 1])]), Err(ScriptError::PushSize));
+
+    let at_limit: Vec<Op> = std::iter::once(push(&[1]))
+        .chain(std::iter::repeat_n(Op::Dup, MAX_OPS_PER_SCRIPT))
+        .collect();
+    assert!(eval(at_limit.clone()).is_ok());
+
+    let mut over_limit = at_limit;
+    over_limit.push(Op::Drop);
+    assert_eq!(eval(over_limit), Err(ScriptError::OpCount));
+
+    // ops in untaken branches still count
+    let skipped: Vec<Op> = std::iter::once(push(&[]))
+        .chain(std::iter::once(Op::If))
+        .chain(std::iter::repeat_n(Op::Dup, MAX_OPS_PER_SCRIPT))
+        .chain(std::iter::once(Op::EndIf))
+        .collect();
+    assert_eq!(eval(skipped), Err(ScriptError::OpCount));
+
+    let deep: Vec<Op> = std::iter::repeat_n(push(&[1]), MAX_STACK_SIZE + 1).collect();
+    assert_eq!(eval(deep), Err(ScriptError::StackSize));
+
+    let huge: Vec<Op> = std::iter::repeat_n(push(&[0; 100]), MAX_SCRIPT_SIZE / 100).collect();
+    assert_eq!(eval(huge), Err(ScriptError::ScriptSize));
+}
+
+#[test]
+fn evaluation_result() {
+    assert!(run(Script::new(vec![push(&[1])]), Script::default()).is_ok());
+    assert_eq!(run(Script::default(), Script::default()), Err(ScriptError::EvalFalse));
+    assert_eq!(run(Script::new(vec![push(&[0x80])]), Script::default()), Err(ScriptError::EvalFalse));
+    assert_eq!(
+        run(Script::new(vec![push(&[1]), push(&[1])]), Script::default()),
+        Err(ScriptError::CleanStack)
+    );
+    assert_eq!(
+        run(Script::new(vec![push(&[1]), Op::Dup]), Script::new(vec![Op::Drop])),
+        Err(ScriptError::NonPushScriptSig)
+    );
+
+    // hash lock: script_pubkey sees the stack left by script_sig
+    let lock = Script::new(vec![Op::Sha256, push(&Sha256::digest(b"secret")), Op::Equal]);
+    assert!(run(Script::new(vec![push(b"secret")]), lock.clone()).is_ok());
+    assert_eq!(run(Script::new(vec![push(b"guess")]), lock), Err(ScriptError::EvalFalse));
+}
+
+#[test]
+fn pay_to_pubkey_hash() {
+    for (key, pubkey, signature) in [
+        {
+            let key = PrivateKey::new_key();
+            let pubkey = key.public_key();
+            let signature = Signature::sign_output(&sighash(), &key);
+            (key, pubkey, signature)
+        },
+        {
+            let key = PrivateKey::new_key();
+            let pubkey = key.schnorr_public_key();
+            let signature = Signature::sign_output_schnorr(&sighash(), &key);
+            (key, pubkey, signature)
+        },
+    ] {
+        let lock = Script::pay_to_pubkey_hash(&pubkey);
+        assert!(run(Script::pay_to_pubkey_hash_unlock(&signature, &pubkey), lock.clone()).is_ok());
+
+        // someone else's key does not match the hash
+        let other = PrivateKey::new_key();
+        let other_signature = Signature::sign_output(&sighash(), &other);
+        assert_eq!(
+            run(Script::pay_to_pubkey_hash_unlock(&other_signature, &other.public_key()), lock.clone()),
+            Err(ScriptError::EqualVerifyFailed)
+        );
+
+        // signature over another message fails, and non-empty failing signatures are errors
+        let wrong = Signature::sign_output_for(&Hash::hash(&"other"), &key, &pubkey);
+        assert_eq!(
+            run(Script::pay_to_pubkey_hash_unlock(&wrong, &pubkey), lock.clone()),
+            Err(ScriptError::NullFail)
+        );
+
+        // an empty signature is a plain false
+        let empty = Script::new(vec![push(&[]), Op::Push(pubkey.to_bytes())]);
+        assert_eq!(run(empty, lock), Err(ScriptError::EvalFalse));
+    }
+}
+
+#[test]
+fn checksig_rejects_malformed_input() {
+    let key = PrivateKey::new_key();
+    let signature = Signature::sign_output(&sighash(), &key).to_bytes();
+
+    assert_eq!(
+        eval(vec![push(&signature), push(&[5; 33]), Op::CheckSig]),
+        Err(ScriptError::InvalidPublicKey)
+    );
+    assert_eq!(
+        eval(vec![push(&signature[..63]), Op::Push(key.public_key().to_bytes()), Op::CheckSig]),
+        Err(ScriptError::InvalidSignature)
+    );
+
+    assert_eq!(
+        eval(vec![push(&high_s(&signature)), Op::Push(key.public_key().to_bytes()), Op::CheckSig]),
+        Err(ScriptError::NonCanonicalSignature)
+    );
+
+    assert!(
+        eval(vec![push(&signature), Op::Push(key.public_key().to_bytes()), Op::CheckSigVerify])
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        eval(vec![push(&[]), Op::Push(key.public_key().to_bytes()), Op::CheckSigVerify]),
+        Err(ScriptError::CheckSigVerifyFailed)
+    );
+}
+
+#[test]
+fn checkmultisig() {
+    let keys: Vec<PrivateKey> = (0..3).map(|_| PrivateKey::new_key()).collect();
+    let pubkeys = vec![keys[0].public_key(), keys[1].schnorr_public_key(), keys[2].public_key()];
+    let signatures: Vec<Signature> = keys
+        .iter()
+        .zip(&pubkeys)
+        .map(|(key, pubkey)| Signature::sign_output_for(&sighash(), key, pubkey))
+        .collect();
+    let lock = Script::multisig(2, &pubkeys);
+
+    for pair in [[0, 1], [0, 2], [1, 2]] {
+        let unlock = Script::multisig_unlock(&[signatures[pair[0]].clone(), signatures[pair[1]].clone()]);
+        assert!(run(unlock, lock.clone()).is_ok(), "signers {pair:?}");
+    }
+
+    // out of key order
+    let unlock = Script::multisig_unlock(&[signatures[2].clone(), signatures[0].clone()]);
+    assert_eq!(run(unlock, lock.clone()), Err(ScriptError::NullFail));
+
+    // same signature twice
+    let unlock = Script::multisig_unlock(&[signatures[0].clone(), signatures[0].clone()]);
+    assert_eq!(run(unlock, lock.clone()), Err(ScriptError::NullFail));
+
+    // too few signatures leave the stack short
+    let unlock = Script::multisig_unlock(&[signatures[0].clone()]);
+    assert_eq!(run(unlock, lock.clone()), Err(ScriptError::StackUnderflow));
+
+    // all empty signatures fail without an error
+    let unlock = Script::new(vec![push(&[]), push(&[])]);
+    assert_eq!(run(unlock, lock), Err(ScriptError::EvalFalse));
+
+    // key counts are bounded and each key counts as an op
+    assert_eq!(eval(vec![Op::push_int(21), Op::CheckMultiSig]), Err(ScriptError::PubKeyCount));
+    assert_eq!(eval(vec![Op::push_int(-1), Op::CheckMultiSig]), Err(ScriptError::PubKeyCount));
+    let too_many_sigs = vec![Op::push_int(2), push(&[5; 33]), Op::push_int(1), Op::CheckMultiSig];
+    assert_eq!(eval(too_many_sigs), Err(ScriptError::SigCount));
+
+    let mut ops: Vec<Op> = std::iter::once(push(&[1]))
+        .chain(std::iter::repeat_n(Op::Dup, MAX_OPS_PER_SCRIPT - 20))
+        .collect();
+    ops.push(Op::push_int(0));
+    ops.extend(std::iter::repeat_n(push(&[5; 33]), 20));
+    ops.extend([Op::push_int(20), Op::CheckMultiSig]);
+    assert_eq!(eval(ops), Err(ScriptError::OpCount));
+}
+
+#[test]
+fn checkmultisig_rejects_malformed_signatures() {
+    let keys: Vec<PrivateKey> = (0..2).map(|_| PrivateKey::new_key()).collect();
+    let pubkeys: Vec<_> = keys.iter().map(PrivateKey::public_key).collect();
+    let lock = Script::multisig(1, &pubkeys);
+    let signature = Signature::sign_output(&sighash(), &keys[1]).to_bytes();
+
+    let unlock = Script::new(vec![push(&signature)]);
+    assert!(run(unlock, lock.clone()).is_ok());
+
+    // every key reads the signature the same way, so its form is checked as for CHECKSIG
+    let unlock = Script::new(vec![push(&high_s(&signature))]);
+    assert_eq!(run(unlock, lock.clone()), Err(ScriptError::NonCanonicalSignature));
+    let unlock = Script::new(vec![push(&signature[..63])]);
+    assert_eq!(run(unlock, lock), Err(ScriptError::InvalidSignature));
+}
+
+#[test]
+fn checkmultisig_mixes_schemes() {
+    // a Schnorr signature read as ECDSA is high-S about half the time, the
+    // ECDSA keys before the matching Schnorr key must only count as mismatches
+    for _ in 0..16 {
+        let keys: Vec<PrivateKey> = (0..3).map(|_| PrivateKey::new_key()).collect();
+        let pubkeys = vec![keys[0].public_key(), keys[1].public_key(), keys[2].schnorr_public_key()];
+        let lock = Script::multisig(2, &pubkeys);
+        let ecdsa = Signature::sign_output(&sighash(), &keys[0]);
+        let schnorr = Signature::sign_output_schnorr(&sighash(), &keys[2]);
+
+        let unlock = Script::multisig_unlock(&[ecdsa.clone(), schnorr.clone()]);
+        assert!(run(unlock, lock.clone()).is_ok());
+
+        // a Schnorr signature by an ECDSA key's owner matches no key
+        let wrong_scheme = Signature::sign_output_schnorr(&sighash(), &keys[1]);
+        let unlock = Script::multisig_unlock(&[ecdsa.clone(), wrong_scheme]);
+        assert_eq!(run(unlock, lock.clone()), Err(ScriptError::NullFail));
+
+        // the high-S twin decodes against the Schnorr key, so it is a plain failure
+        let unlock = Script::new(vec![push(&high_s(&ecdsa.to_bytes())), push(&schnorr.to_bytes())]);
+        assert_eq!(run(unlock, lock), Err(ScriptError::NullFail));
+    }
+}
+
+#[test]
+fn display_uses_asm() {
+    let script = Script::new(vec![Op::Dup, push(&[0xab, 0xcd]), Op::CheckSig]);
+    assert_eq!(script.to_string(), "OP_DUP abcd OP_CHECKSIG");
+}
+
+#[test]
+fn script_locked_outputs_in_mempool_and_blocks() {
+    let key = PrivateKey::new_key();
+    let utxo = TransactionOutput::new(
+        1_000,
+        LockingCondition::Script(Script::pay_to_pubkey_hash(&key.public_key())),
+    );
+    let utxo_hash = utxo.hash();
+
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    chain.utxos.insert(utxo_hash, utxo);
+
+    // the unlocking script is left out of the sighash, so one signature fits every variant
+    let unsigned = Transaction::new(
+        vec![TransactionInput::with_script(utxo_hash, Script::default())],
+        vec![TransactionOutput::new(900, key.public_key().into())],
+    );
+    let signature = Signature::sign_output(&unsigned.sighash(&utxo_hash), &key);
+    let spend = |script_sig: Script| {
+        let mut transaction = unsigned.clone();
+        transaction.inputs_mut()[0].script_sig = script_sig;
+        transaction
+    };
+
+    let bad = spend(Script::pay_to_pubkey_hash_unlock(&signature, &PrivateKey::new_key().public_key()));
+    assert!(matches!(
+        chain.add_to_mempool(bad),
+        Err(BtcError::InvalidScript(ScriptError::EqualVerifyFailed))
+    ));
+
+    // plain signatures can't stand in for the unlocking script
+    let mut with_signatures = spend(Script::default());
+    with_signatures.inputs_mut()[0] = TransactionInput::new(utxo_hash, signature.clone());
+    assert!(matches!(
+        chain.add_to_mempool(with_signatures),
+        Err(BtcError::InvalidSignature)
+    ));
+
+    let good = spend(Script::pay_to_pubkey_hash_unlock(&signature, &key.public_key()));
+    chain.add_to_mempool(good).unwrap();
+}
@@ -0,0 +1,76 @@
+use chrono::{Duration, Utc};
+use lib::crypto::{PrivateKey, SignatureCheck, Signature};
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::sigcache::SignatureCache;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction, TransactionInput, TransactionOutput};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+fn output(value: u64, owner: &PrivateKey) -> TransactionOutput {
+    TransactionOutput::new(value, owner.public_key().into())
+}
+
+fn block(prev_block_hash: Hash, offset: i64, mut transactions: Vec<Transaction>) -> Block {
+    Block::commit_witnesses(&mut transactions);
+    let header = BlockHeader::new(
+        Utc::now() + Duration::seconds(offset),
+        0,
+        prev_block_hash,
+        MerkleRoot::calculate(&transactions),
+        U256::MAX,
+    );
+    Block::new(header, transactions)
+}
+
+#[test]
+fn cache_evicts_oldest_entries() {
+    let key = PrivateKey::new_key();
+    let cache = SignatureCache::new(2);
+
+    let checks: Vec<SignatureCheck> = (0..3u8)
+        .map(|i| {
+            let message = Hash::hash(&i);
+            SignatureCheck::new(message, Signature::sign_output(&message, &key), key.public_key())
+        })
+        .collect();
+
+    for check in &checks {
+        assert!(cache.verify(check));
+    }
+
+    assert!(!cache.contains(&checks[0]));
+    assert!(cache.contains(&checks[2]));
+    assert_eq!(cache.stats().entries, 2);
+}
+
+#[test]
+fn block_connection_reuses_mempool_verification() {
+    let alice = PrivateKey::new_key();
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+
+    let funding = output(50 * 10u64.pow(8), &alice);
+    let funding_hash = funding.hash();
+    let first = block(chain.blocks[0].hash(), 0, vec![Transaction::new(vec![], vec![funding])]);
+    let first_hash = first.hash();
+    chain.add_block(first).unwrap();
+
+    let mut spend = Transaction::new(
+        vec![TransactionInput::unsigned(funding_hash)],
+        vec![output(49 * 10u64.pow(8), &alice)],
+    );
+    spend.sign_input(0, 0, &alice, &alice.public_key());
+    chain.add_to_mempool(spend.clone()).unwrap();
+    let after_mempool = chain.signature_cache.stats();
+    assert_eq!(after_mempool.misses, 1);
+
+    let coinbase = Transaction::new(vec![], vec![output(51 * 10u64.pow(8), &alice)]);
+    chain
+        .add_block(block(first_hash, 1, vec![coinbase, spend]))
+        .unwrap();
+
+    let after_block = chain.signature_cache.stats();
+    assert_eq!(after_block.hits, after_mempool.hits + 1);
+    assert_eq!(after_block.misses, after_mempool.misses);
+    assert!(chain.mempool.is_empty());
+}
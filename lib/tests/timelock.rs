@@ -0,0 +1,318 @@
+use chrono::{DateTime, TimeZone, Utc};
+use lib::crypto::{PrivateKey, Signature};
+use lib::error::BtcError;
+use lib::params::ChainParams;
+use lib::script::{self, Op, Script, ScriptContext, ScriptError};
+use lib::sha256::Hash;
+use lib::sigcache::SignatureCache;
+use lib::timelock::{
+    median_time, RelativeLock, LOCKTIME_THRESHOLD, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG,
+    SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+use lib::types::{
+    Block, BlockHeader, Blockchain, Transaction, TransactionInput, TransactionOutput, TRANSACTION_VERSION,
+};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+const REWARD: u64 = 50 * 100_000_000;
+// blocks are 10 minutes apart starting here
+const START: i64 = 1_700_000_000;
+
+fn block_time(height: u64) -> DateTime<Utc> {
+    Utc.timestamp_opt(START + height as i64 * 600, 0).unwrap()
+}
+
+struct Chain {
+    chain: Blockchain,
+    key: PrivateKey,
+}
+
+impl Chain {
+    // block 1 pays the key, one UTXO
+    fn new() -> Self {
+        let key = PrivateKey::new_key();
+        let params = ChainParams { genesis_timestamp: START, ..ChainParams::regtest() };
+        let mut chain = Chain { chain: Blockchain::with_params(params), key };
+        chain.mine(vec![]).unwrap();
+        chain
+    }
+
+    fn mine(&mut self, transactions: Vec<Transaction>) -> Result<(), BtcError> {
+        let height = self.chain.block_height();
+        let fees: u64 = transactions
+            .iter()
+            .map(|tx| {
+                let inputs: u64 = tx
+                    .inputs()
+                    .iter()
+                    .map(|input| self.chain.utxos[&input.prev_transaction_output_hash].value)
+                    .sum();
+                inputs - tx.outputs().iter().map(|output| output.value).sum::<u64>()
+            })
+            .sum();
+
+        let coinbase =
+            Transaction::new(vec![], vec![TransactionOutput::new(REWARD + fees, self.key.public_key().into())]);
+        let mut all = vec![coinbase];
+        all.extend(transactions);
+        Block::commit_witnesses(&mut all);
+
+        let prev = self.chain.blocks.last().map_or(Hash::zero(), |block| block.hash());
+        let header =
+            BlockHeader::new(block_time(height), 0, prev, MerkleRoot::calculate(&all), U256::MAX);
+        self.chain.add_block(Block::new(header, all))
+    }
+
+    fn mine_empty(&mut self, count: usize) {
+        for _ in 0..count {
+            self.mine(vec![]).unwrap();
+        }
+    }
+
+    // spends the output of block 1
+    fn spend(&self, sequence: u32, lock_time: u64) -> Transaction {
+        self.spend_with_version(TRANSACTION_VERSION, sequence, lock_time)
+    }
+
+    fn spend_with_version(&self, version: u32, sequence: u32, lock_time: u64) -> Transaction {
+        let (hash, _) = self
+            .chain
+            .utxos
+            .iter()
+            .find(|(hash, _)| self.chain.utxo_heights[*hash] == 1)
+            .unwrap();
+        let mut input = TransactionInput::unsigned(*hash);
+        input.sequence = sequence;
+
+        let mut transaction =
+            Transaction::new(vec![input], vec![TransactionOutput::new(REWARD - 100, self.key.public_key().into())])
+                .with_lock_time(lock_time)
+                .with_version(version);
+        transaction.sign_input(0, 0, &self.key, &self.key.public_key());
+        transaction
+    }
+}
+
+#[test]
+fn relative_lock_encoding() {
+    assert_eq!(RelativeLock::from_sequence(SEQUENCE_FINAL), None);
+    assert_eq!(RelativeLock::from_sequence(SEQUENCE_LOCKTIME_DISABLE_FLAG | 10), None);
+    assert_eq!(RelativeLock::from_sequence(10), Some(RelativeLock::Blocks(10)));
+    assert_eq!(
+        RelativeLock::from_sequence(SEQUENCE_LOCKTIME_TYPE_FLAG | 2),
+        Some(RelativeLock::Seconds(1024))
+    );
+
+    assert_eq!(RelativeLock::Blocks(144).to_sequence(), 144);
+    // rounded up to whole 512 second units
+    assert_eq!(RelativeLock::Seconds(1000).to_sequence(), SEQUENCE_LOCKTIME_TYPE_FLAG | 2);
+}
+
+#[test]
+fn median_of_timestamps() {
+    assert_eq!(median_time(&[]), 0);
+    let times: Vec<DateTime<Utc>> = [5, 1, 3].iter().map(|t| Utc.timestamp_opt(*t, 0).unwrap()).collect();
+    assert_eq!(median_time(&times), 3);
+}
+
+#[test]
+fn median_time_past_ignores_the_new_block() {
+    let mut chain = Chain::new();
+    chain.mine_empty(20);
+
+    // the 11 blocks before height 21 are 10..=20, median is block 15
+    assert_eq!(chain.chain.median_time_past(21), block_time(15).timestamp());
+    assert_eq!(chain.chain.median_time_past(1), block_time(0).timestamp());
+}
+
+#[test]
+fn is_final_rules() {
+    let chain = Chain::new();
+
+    assert!(chain.spend(0, 0).is_final(1, 0));
+    // height locks are reached once the block is above them
+    assert!(!chain.spend(0, 10).is_final(10, 0));
+    assert!(chain.spend(0, 10).is_final(11, 0));
+    // time locks compare with the median time past
+    let time = LOCKTIME_THRESHOLD + 1_000;
+    assert!(!chain.spend(0, time).is_final(1, time as i64));
+    assert!(chain.spend(0, time).is_final(1, time as i64 + 1));
+    // final sequences switch the lock time off
+    assert!(chain.spend(SEQUENCE_FINAL, 10).is_final(1, 0));
+}
+
+#[test]
+fn absolute_height_lock_in_mempool_and_blocks() {
+    let mut chain = Chain::new();
+    let spend = chain.spend(0, 3);
+
+    assert!(matches!(
+        chain.chain.add_to_mempool(spend.clone()),
+        Err(BtcError::NonFinalTransaction)
+    ));
+    assert!(matches!(chain.mine(vec![spend.clone()]), Err(BtcError::NonFinalTransaction)));
+
+    // has to wait for the block at height 4
+    chain.mine_empty(2);
+    assert_eq!(chain.chain.block_height(), 4);
+    chain.chain.add_to_mempool(spend.clone()).unwrap();
+    chain.mine(vec![spend]).unwrap();
+}
+
+#[test]
+fn absolute_time_lock_uses_median_time_past() {
+    let mut chain = Chain::new();
+    chain.mine_empty(9);
+
+    let lock_time = block_time(8).timestamp() as u64;
+    let spend = chain.spend(0, lock_time);
+
+    // the next block's own timestamp is well past the lock, but the median isn't
+    assert!(matches!(
+        chain.chain.add_to_mempool(spend.clone()),
+        Err(BtcError::NonFinalTransaction)
+    ));
+
+    chain.mine_empty(6);
+    chain.mine(vec![spend]).unwrap();
+}
+
+#[test]
+fn relative_block_lock() {
+    let mut chain = Chain::new();
+    let spend = chain.spend(RelativeLock::Blocks(5).to_sequence(), 0);
+
+    chain.mine_empty(3);
+    assert!(matches!(
+        chain.chain.add_to_mempool(spend.clone()),
+        Err(BtcError::UnsatisfiedSequenceLock)
+    ));
+    assert!(matches!(chain.mine(vec![spend.clone()]), Err(BtcError::UnsatisfiedSequenceLock)));
+
+    chain.mine_empty(1);
+    chain.chain.add_to_mempool(spend).unwrap();
+}
+
+#[test]
+fn version_1_sequences_are_not_relative_locks() {
+    let mut chain = Chain::new();
+    let sequence = RelativeLock::Blocks(5).to_sequence();
+
+    assert!(matches!(
+        chain.chain.add_to_mempool(chain.spend(sequence, 0)),
+        Err(BtcError::UnsatisfiedSequenceLock)
+    ));
+    let spend = chain.spend_with_version(1, sequence, 0);
+    chain.chain.add_to_mempool(spend.clone()).unwrap();
+    chain.mine(vec![spend]).unwrap();
+}
+
+#[test]
+fn relative_time_lock() {
+    let mut chain = Chain::new();
+    chain.mine_empty(9);
+    let spend = chain.spend(RelativeLock::Seconds(3_600).to_sequence(), 0);
+
+    assert!(matches!(
+        chain.chain.add_to_mempool(spend.clone()),
+        Err(BtcError::UnsatisfiedSequenceLock)
+    ));
+
+    // 3600s round up to 4096s, the median passes that one block later
+    chain.mine_empty(1);
+    assert!(chain.chain.add_to_mempool(spend.clone()).is_err());
+    chain.mine_empty(1);
+    chain.chain.add_to_mempool(spend).unwrap();
+}
+
+fn run(lock: Script, lock_time: u64, sequence: u32) -> Result<(), ScriptError> {
+    let cache = SignatureCache::new(0);
+    let context = ScriptContext { lock_time, sequence, ..ScriptContext::new(Hash::zero(), &cache) };
+    script::verify_script(&Script::default(), &lock, context)
+}
+
+#[test]
+fn check_lock_time_verify() {
+    let lock = |n: i64| Script::new(vec![Op::push_int(n), Op::CheckLockTimeVerify]);
+
+    assert!(run(lock(100), 100, 0).is_ok());
+    assert!(run(lock(100), 150, 0).is_ok());
+    assert_eq!(run(lock(100), 99, 0), Err(ScriptError::UnsatisfiedLockTime));
+    // heights and timestamps don't compare
+    assert_eq!(
+        run(lock(100), LOCKTIME_THRESHOLD + 100, 0),
+        Err(ScriptError::UnsatisfiedLockTime)
+    );
+    let time = LOCKTIME_THRESHOLD as i64 + 100;
+    assert!(run(lock(time), time as u64, 0).is_ok());
+    // a final sequence would disable the lock time
+    assert_eq!(run(lock(100), 100, SEQUENCE_FINAL), Err(ScriptError::UnsatisfiedLockTime));
+    assert_eq!(run(lock(-1), 100, 0), Err(ScriptError::NegativeLockTime));
+    // timestamps past 2038 need the fifth byte
+    assert!(run(lock(1 << 32), 1 << 32, 0).is_ok());
+}
+
+#[test]
+fn check_sequence_verify() {
+    let lock = |n: u32| Script::new(vec![Op::push_int(n as i64), Op::CheckSequenceVerify]);
+    let blocks = |n| RelativeLock::Blocks(n).to_sequence();
+    let seconds = |n| RelativeLock::Seconds(n).to_sequence();
+
+    assert!(run(lock(blocks(10)), 0, blocks(10)).is_ok());
+    assert!(run(lock(blocks(10)), 0, blocks(20)).is_ok());
+    assert_eq!(run(lock(blocks(10)), 0, blocks(9)), Err(ScriptError::UnsatisfiedLockTime));
+    assert!(run(lock(seconds(1024)), 0, seconds(2048)).is_ok());
+    assert_eq!(run(lock(seconds(1024)), 0, blocks(100)), Err(ScriptError::UnsatisfiedLockTime));
+    assert_eq!(run(lock(blocks(1)), 0, SEQUENCE_FINAL), Err(ScriptError::UnsatisfiedLockTime));
+    // disabled locks in the script pass like a NOP
+    assert!(run(lock(SEQUENCE_LOCKTIME_DISABLE_FLAG), 0, SEQUENCE_FINAL).is_ok());
+
+    // version 1 transactions have no relative locks to satisfy it
+    let cache = SignatureCache::new(0);
+    let context = ScriptContext { sequence: blocks(10), version: 1, ..ScriptContext::new(Hash::zero(), &cache) };
+    assert_eq!(
+        script::verify_script(&Script::default(), &lock(blocks(10)), context),
+        Err(ScriptError::UnsatisfiedLockTime)
+    );
+}
+
+#[test]
+fn script_timelocked_output_on_chain() {
+    let mut chain = Chain::new();
+    let owner = PrivateKey::new_key();
+    // <10 blocks> CSV DROP <pubkey> CHECKSIG
+    let lock = Script::new(vec![
+        Op::push_int(10),
+        Op::CheckSequenceVerify,
+        Op::Drop,
+        Op::Push(owner.public_key().to_bytes()),
+        Op::CheckSig,
+    ]);
+
+    let funding = chain.spend(SEQUENCE_FINAL, 0);
+    let locked = TransactionOutput::new(REWARD - 100, lib::types::LockingCondition::Script(lock));
+    let locked_hash = locked.hash();
+    let mut funding = Transaction::new(funding.inputs().to_vec(), vec![locked]);
+    funding.sign_input(0, 0, &chain.key, &chain.key.public_key());
+    chain.mine(vec![funding]).unwrap();
+
+    // the sequence is part of the sighash, each spend is signed on its own
+    let spend = |sequence: u32| {
+        let mut input = TransactionInput::with_script(locked_hash, Script::default());
+        input.sequence = sequence;
+        let mut transaction =
+            Transaction::new(vec![input], vec![TransactionOutput::new(REWARD - 200, owner.public_key().into())]);
+        let signature = Signature::sign_output(&transaction.sighash(&locked_hash), &owner);
+        transaction.inputs_mut()[0].script_sig = Script::new(vec![Op::Push(signature.to_bytes().to_vec())]);
+        transaction
+    };
+
+    // the script wants 10 blocks, the input only commits to 5
+    chain.mine_empty(10);
+    assert!(matches!(
+        chain.chain.add_to_mempool(spend(RelativeLock::Blocks(5).to_sequence())),
+        Err(BtcError::InvalidScript(ScriptError::UnsatisfiedLockTime))
+    ));
+    chain.chain.add_to_mempool(spend(RelativeLock::Blocks(10).to_sequence())).unwrap();
+}
@@ -0,0 +1,158 @@
+use chrono::{TimeZone, Utc};
+use lib::crypto::{PrivateKey, Signature};
+use lib::error::BtcError;
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::types::{
+    Block, BlockHeader, Blockchain, LockingCondition, Transaction, TransactionInput, TransactionOutput,
+    WITNESS_SCALE_FACTOR,
+};
+use lib::util::MerkleRoot;
+use lib::U256;
+
+const REWARD: u64 = 50 * 100_000_000;
+
+struct Setup {
+    chain: Blockchain,
+    key: PrivateKey,
+    utxo_hash: Hash,
+}
+
+fn setup() -> Setup {
+    let key = PrivateKey::new_key();
+    let funding = TransactionOutput::new(REWARD, key.public_key().into());
+    let utxo_hash = funding.hash();
+
+    let mut chain = Blockchain::with_params(ChainParams::regtest());
+    let genesis = vec![Transaction::new(vec![], vec![funding])];
+    chain.add_block(block(&chain, genesis)).unwrap();
+
+    Setup { chain, key, utxo_hash }
+}
+
+fn block(chain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+    let prev = chain.blocks.last().map_or(Hash::zero(), |block| block.hash());
+    let timestamp = Utc.timestamp_opt(1_700_000_000 + chain.block_height() as i64 * 600, 0).unwrap();
+    let header = BlockHeader::new(timestamp, 0, prev, MerkleRoot::calculate(&transactions), U256::MAX);
+    Block::new(header, transactions)
+}
+
+fn coinbase(setup: &Setup) -> Transaction {
+    Transaction::new(vec![], vec![TransactionOutput::new(REWARD, setup.key.public_key().into())])
+}
+
+fn spend(setup: &Setup) -> Transaction {
+    let mut transaction = Transaction::new(
+        vec![TransactionInput::unsigned(setup.utxo_hash)],
+        vec![TransactionOutput::new(REWARD, setup.key.public_key().into())],
+    );
+    transaction.sign_input(0, 0, &setup.key, &setup.key.public_key());
+    transaction
+}
+
+#[test]
+fn txid_leaves_out_signatures() {
+    let keys: Vec<PrivateKey> = (0..2).map(|_| PrivateKey::new_key()).collect();
+    let lock = LockingCondition::multisig(2, keys.iter().map(PrivateKey::public_key).collect()).unwrap();
+    let utxo_hash = TransactionOutput::new(1_000, lock).hash();
+
+    let mut transaction = Transaction::new(
+        vec![TransactionInput::unsigned(utxo_hash)],
+        vec![TransactionOutput::new(900, keys[0].public_key().into())],
+    );
+    let txid = transaction.hash();
+    let wtxid = transaction.wtxid();
+
+    // co-signing keeps the txid everyone refers to, but not the wtxid
+    transaction.sign_input(0, 1, &keys[1], &keys[1].public_key());
+    assert_eq!(transaction.hash(), txid);
+    assert_ne!(transaction.wtxid(), wtxid);
+
+    // what the txid covers still changes it
+    assert_ne!(transaction.clone().with_lock_time(5).hash(), txid);
+    transaction.inputs_mut()[0].sequence = 0;
+    assert_ne!(transaction.hash(), txid);
+}
+
+#[test]
+fn merkle_root_covers_txids_only() {
+    let setup = setup();
+    let transaction = spend(&setup);
+    let mut resigned = transaction.clone();
+    resigned.inputs_mut()[0].signatures[0].signature =
+        Signature::sign_output_schnorr(&transaction.sighash(&setup.utxo_hash), &setup.key);
+
+    assert_eq!(
+        MerkleRoot::calculate(std::slice::from_ref(&transaction)),
+        MerkleRoot::calculate(std::slice::from_ref(&resigned))
+    );
+    assert_ne!(
+        MerkleRoot::calculate_witness(&[coinbase(&setup), transaction]),
+        MerkleRoot::calculate_witness(&[coinbase(&setup), resigned])
+    );
+}
+
+#[test]
+fn blocks_need_a_matching_witness_commitment() {
+    let mut setup = setup();
+
+    // without a commitment
+    let missing = block(&setup.chain, vec![coinbase(&setup), spend(&setup)]);
+    assert!(matches!(
+        setup.chain.add_block(missing),
+        Err(BtcError::InvalidWitnessCommitment)
+    ));
+
+    // witness swapped after committing, the Merkle root doesn't notice
+    let mut transactions = vec![coinbase(&setup), spend(&setup)];
+    Block::commit_witnesses(&mut transactions);
+    transactions[1].inputs_mut()[0].signatures[0].signature =
+        Signature::sign_output(&Hash::hash(&"other"), &setup.key);
+    let tampered = block(&setup.chain, transactions);
+    assert!(matches!(
+        setup.chain.add_block(tampered),
+        Err(BtcError::InvalidWitnessCommitment)
+    ));
+
+    let mut transactions = vec![coinbase(&setup), spend(&setup)];
+    Block::commit_witnesses(&mut transactions);
+    // committing again replaces the commitment instead of adding one
+    Block::commit_witnesses(&mut transactions);
+    assert_eq!(transactions[0].outputs().len(), 2);
+    assert_eq!(
+        transactions[0].witness_commitment(),
+        Some(Block::witness_commitment(&transactions))
+    );
+    let valid = block(&setup.chain, transactions);
+    setup.chain.add_block(valid).unwrap();
+
+    // blocks without witness data don't need one
+    let empty = block(&setup.chain, vec![coinbase(&setup)]);
+    setup.chain.add_block(empty).unwrap();
+}
+
+#[test]
+fn witness_bytes_weigh_less() {
+    let setup = setup();
+    let unsigned = Transaction::new(
+        vec![TransactionInput::unsigned(setup.utxo_hash)],
+        vec![TransactionOutput::new(REWARD, setup.key.public_key().into())],
+    );
+    let signed = spend(&setup);
+
+    assert_eq!(signed.base_size(), unsigned.base_size());
+    assert!(signed.total_size() > unsigned.total_size());
+    assert_eq!(signed.weight(), signed.base_size() * 3 + signed.total_size());
+    assert_eq!(
+        signed.weight() - unsigned.weight(),
+        signed.total_size() - unsigned.total_size()
+    );
+    assert_eq!(signed.virtual_size(), signed.weight().div_ceil(WITNESS_SCALE_FACTOR));
+
+    let mut transactions = vec![coinbase(&setup), signed];
+    Block::commit_witnesses(&mut transactions);
+    let block = block(&setup.chain, transactions.clone());
+    let transaction_weight: usize = transactions.iter().map(Transaction::weight).sum();
+    assert!(block.weight() > transaction_weight);
+    assert!(block.weight() < block.size() * WITNESS_SCALE_FACTOR);
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use lib::params::{ChainParams, Network};
+
+#[derive(Parser)]
+#[command(author, version, about = "Mine the genesis block of a custom network")]
+struct Cli {
+    /// Parameter file of the network, updated with the genesis found
+    #[arg(long)]
+    params: PathBuf,
+    /// Start from a preset's parameters instead of the file's
+    #[arg(long)]
+    from: Option<Network>,
+    /// Text embedded in the genesis coinbase
+    #[arg(long)]
+    message: Option<String>,
+    /// Unix timestamp of the genesis block, defaults to now
+    #[arg(long)]
+    timestamp: Option<i64>,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let mut params = match cli.from {
+        Some(network) => ChainParams {
+            network: Network::Custom,
+            ..ChainParams::for_network(network).context("custom networks have no preset")?
+        },
+        None => ChainParams::load_from_file(&cli.params)
+            .with_context(|| format!("failed to read {}", cli.params.display()))?,
+    };
+
+    if let Some(message) = cli.message {
+        params.genesis_message = message;
+    }
+    params.genesis_timestamp = cli
+        .timestamp
+        .unwrap_or_else(|| chrono::Utc::now().timestamp());
+
+    let mut header = params.genesis_block().header().clone();
+    header.nonce = 0;
+    while !header.mine(1_000_000) {}
+    params.genesis_nonce = header.nonce;
+
+    params
+        .save_to_file(&cli.params)
+        .with_context(|| format!("failed to write {}", cli.params.display()))?;
+
+    println!("genesis {}", params.genesis_block().hash());
+    println!("timestamp {}", params.genesis_timestamp);
+    println!("nonce {}", params.genesis_nonce);
+    Ok(())
+}
@@ -1,3 +1,91 @@
-fn main() {
-    println!("Hello, world!");
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use lib::address::Address;
+use lib::crypto::PublicKey;
+use lib::params::{ChainParams, Network};
+use lib::types::{Block, Blockchain};
+use lib::util::Saveable;
+
+// nonces tried before the template is refreshed, so its timestamp stays recent
+const MINING_STEPS: usize = 2_000_000;
+
+#[derive(Parser)]
+#[command(author, version, about = "rsbtc miner")]
+struct Cli {
+    /// Chain snapshot to extend, created when missing
+    #[arg(long)]
+    chain: PathBuf,
+    /// Address or public key file receiving the rewards
+    #[arg(long)]
+    to: String,
+    /// Number of blocks to mine
+    #[arg(long, default_value_t = 1)]
+    blocks: u64,
+    #[arg(long, default_value_t = Network::Main)]
+    network: Network,
+    /// Parameter file of a custom network, overrides --network
+    #[arg(long)]
+    params: Option<PathBuf>,
+}
+
+fn chain_params(cli: &Cli) -> Result<ChainParams> {
+    match &cli.params {
+        Some(path) => ChainParams::load_from_file(path)
+            .with_context(|| format!("failed to read {}", path.display())),
+        None => ChainParams::for_network(cli.network)
+            .context("the custom network needs a --params file"),
+    }
+}
+
+fn load_chain(path: &PathBuf, params: &ChainParams) -> Result<Blockchain> {
+    if !path.exists() {
+        return Ok(Blockchain::with_params(params.clone()));
+    }
+
+    let chain = Blockchain::load_from_file(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    if chain.params().magic != params.magic {
+        bail!(
+            "{} holds a {} chain, not {}",
+            path.display(),
+            chain.params().network,
+            params.network
+        );
+    }
+    chain
+        .verify_genesis(params)
+        .with_context(|| format!("{} has a different genesis block", path.display()))?;
+    Ok(chain)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let params = chain_params(&cli)?;
+    let mut chain = load_chain(&cli.chain, &params)?;
+
+    let reward_key = match Address::parse(&cli.to, &params) {
+        Ok(address) => address.pubkey,
+        Err(_) => PublicKey::load_from_file(&cli.to)
+            .with_context(|| format!("{} is neither a {} address nor a key file", cli.to, params.network))?,
+    };
+
+    for _ in 0..cli.blocks {
+        let block = loop {
+            let template = chain.block_template(reward_key.clone().into())?;
+            let mut header = template.header().clone();
+            if header.mine(MINING_STEPS) {
+                break Block::new(header, template.transactions().to_vec());
+            }
+        };
+
+        println!("mined block {} at height {}", block.hash(), chain.block_height());
+        chain.add_block(block)?;
+        chain
+            .save_to_file(&cli.chain)
+            .with_context(|| format!("failed to write {}", cli.chain.display()))?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,535 @@
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use lib::banman::DEFAULT_BAN_TIME;
+use lib::params::{ChainParams, Network};
+use lib::policy::DEFAULT_MAX_MEMPOOL_SIZE;
+use lib::types::MIN_BLOCKS_TO_KEEP;
+use serde::{Deserialize, Serialize};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+use crate::connman::{DEFAULT_MAX_INBOUND, DEFAULT_MAX_OUTBOUND};
+
+/// Config file looked for in the data directory when --config isn't given
+pub const CONFIG_FILE_NAME: &str = "node.toml";
+const MEGABYTE: usize = 1_000_000;
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human readable line per event
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the event and its spans
+    Json,
+}
+
+/// Settings as given on the command line or in the config file, a setting on
+/// the command line replaces the file's. Unset ones take their defaults in
+/// `resolve`
+#[derive(Parser, Serialize, Deserialize, Default)]
+#[command(author, version, about = "rsbtc node")]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// TOML file with the settings not given here, defaults to node.toml in the data directory if there is one
+    #[arg(long)]
+    #[serde(skip)]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration as a config file and exit
+    #[arg(long)]
+    #[serde(skip)]
+    pub print_config: bool,
+    /// main, test, regtest or custom [default: main]
+    #[arg(long)]
+    pub network: Option<Network>,
+    /// Parameter file of a custom network, overrides --network
+    #[arg(long)]
+    pub params: Option<PathBuf>,
+    /// Directory the node's files go in by default [default: .]
+    #[arg(long)]
+    pub datadir: Option<PathBuf>,
+    /// Chain snapshot, created when missing and saved as blocks arrive [default: <network>.chain in the data directory]
+    #[arg(long)]
+    pub chain: Option<PathBuf>,
+    /// Address to accept peers on [default: the network's port on all interfaces]
+    #[arg(long)]
+    pub listen: Option<SocketAddr>,
+    /// Peers to stay connected to, as host:port
+    #[arg(long)]
+    pub connect: Vec<String>,
+    /// Nodes to learn addresses from, as host:port, added to the address book
+    #[arg(long)]
+    pub seed: Vec<String>,
+    /// Address book [default: the chain file with a .peers extension]
+    #[arg(long)]
+    pub address_book: Option<PathBuf>,
+    /// Outbound connections picked from the address book, besides --connect peers [default: 8]
+    #[arg(long)]
+    pub max_outbound: Option<usize>,
+    /// [default: 117]
+    #[arg(long)]
+    pub max_inbound: Option<usize>,
+    /// Banned addresses [default: the chain file with a .banlist extension]
+    #[arg(long)]
+    pub ban_list: Option<PathBuf>,
+    /// Seconds misbehaving peers are banned for [default: 86400]
+    #[arg(long)]
+    pub ban_time: Option<i64>,
+    /// Addresses of trusted peers, never scored or banned
+    #[arg(long)]
+    pub whitelist: Vec<IpAddr>,
+    /// Address to publish block and mempool events on as newline delimited JSON, none without it
+    #[arg(long)]
+    pub notify_bind: Option<SocketAddr>,
+    /// Address to answer JSON-RPC requests on, no RPC server without it
+    #[arg(long)]
+    pub rpc_bind: Option<SocketAddr>,
+    /// RPC user name, without it clients authenticate with the cookie file
+    #[arg(long)]
+    pub rpc_user: Option<String>,
+    #[arg(long)]
+    pub rpc_password: Option<String>,
+    /// Where RPC credentials are written for local clients [default: the chain file with a .cookie extension]
+    #[arg(long)]
+    pub rpc_cookie_file: Option<PathBuf>,
+    /// Megabytes of transactions the mempool holds [default: 300]
+    #[arg(long)]
+    pub max_mempool: Option<usize>,
+    /// Level of the node's own events, dependencies only log warnings and errors [default: info]
+    #[arg(long)]
+    pub log_level: Option<LogLevel>,
+    /// Filter directives like `info,node::peer=debug,lib=trace`, replaces --log-level
+    #[arg(long)]
+    pub log_filter: Option<String>,
+    /// text or json [default: text]
+    #[arg(long)]
+    pub log_format: Option<LogFormat>,
+    /// Blocks kept in full, at least 288, older ones only keep their headers [default: all]
+    #[arg(long)]
+    pub prune: Option<u64>,
+}
+
+/// How RPC clients authenticate
+pub enum RpcAuth {
+    Password { user: String, password: String },
+    /// Credentials generated at startup, written to the file
+    Cookie(PathBuf),
+}
+
+pub struct Rpc {
+    pub bind: SocketAddr,
+    pub auth: RpcAuth,
+}
+
+/// Checked settings with the defaults filled in
+pub struct Settings {
+    pub params: ChainParams,
+    /// Parameter file the params came from, if any
+    pub params_path: Option<PathBuf>,
+    pub datadir: PathBuf,
+    pub chain: PathBuf,
+    pub listen: SocketAddr,
+    pub peers: Vec<String>,
+    pub seeds: Vec<String>,
+    pub address_book: PathBuf,
+    pub max_outbound: usize,
+    pub max_inbound: usize,
+    pub ban_list: PathBuf,
+    pub ban_time: i64,
+    pub whitelist: HashSet<IpAddr>,
+    pub notify_bind: Option<SocketAddr>,
+    pub rpc: Option<Rpc>,
+    /// In bytes
+    pub max_mempool: usize,
+    pub log_level: LogLevel,
+    pub log_filter: Option<String>,
+    pub log_format: LogFormat,
+    pub prune: Option<u64>,
+}
+
+impl Config {
+    /// The command line over the config file
+    pub fn load() -> Result<Self> {
+        let cli = Config::parse();
+        let path = match &cli.config {
+            Some(path) => Some(path.clone()),
+            None => Some(cli.datadir.clone().unwrap_or_default().join(CONFIG_FILE_NAME))
+                .filter(|path| path.exists()),
+        };
+
+        let file = match path {
+            Some(path) => Self::read(&path)?,
+            None => Config::default(),
+        };
+        Ok(cli.over(file))
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// These settings, those missing taken from `file`
+    fn over(self, file: Config) -> Config {
+        fn list<T>(cli: Vec<T>, file: Vec<T>) -> Vec<T> {
+            if cli.is_empty() {
+                file
+            } else {
+                cli
+            }
+        }
+
+        Config {
+            config: self.config,
+            print_config: self.print_config,
+            network: self.network.or(file.network),
+            params: self.params.or(file.params),
+            datadir: self.datadir.or(file.datadir),
+            chain: self.chain.or(file.chain),
+            listen: self.listen.or(file.listen),
+            connect: list(self.connect, file.connect),
+            seed: list(self.seed, file.seed),
+            address_book: self.address_book.or(file.address_book),
+            max_outbound: self.max_outbound.or(file.max_outbound),
+            max_inbound: self.max_inbound.or(file.max_inbound),
+            ban_list: self.ban_list.or(file.ban_list),
+            ban_time: self.ban_time.or(file.ban_time),
+            whitelist: list(self.whitelist, file.whitelist),
+            notify_bind: self.notify_bind.or(file.notify_bind),
+            rpc_bind: self.rpc_bind.or(file.rpc_bind),
+            rpc_user: self.rpc_user.or(file.rpc_user),
+            rpc_password: self.rpc_password.or(file.rpc_password),
+            rpc_cookie_file: self.rpc_cookie_file.or(file.rpc_cookie_file),
+            max_mempool: self.max_mempool.or(file.max_mempool),
+            log_level: self.log_level.or(file.log_level),
+            log_filter: self.log_filter.or(file.log_filter),
+            log_format: self.log_format.or(file.log_format),
+            prune: self.prune.or(file.prune),
+        }
+    }
+
+    /// Checks the settings and fills in the defaults
+    pub fn resolve(self) -> Result<Settings> {
+        let params = match &self.params {
+            Some(path) => ChainParams::load_from_file(path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+            None => {
+                let network = self.network.unwrap_or(Network::Main);
+                ChainParams::for_network(network).context("the custom network needs a params file")?
+            }
+        };
+
+        let datadir = self.datadir.unwrap_or_else(|| PathBuf::from("."));
+        let chain = self
+            .chain
+            .unwrap_or_else(|| datadir.join(format!("{}.chain", params.network)));
+        let listen = self
+            .listen
+            .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, params.default_port)));
+
+        let ban_time = self.ban_time.unwrap_or(DEFAULT_BAN_TIME);
+        if ban_time <= 0 {
+            bail!("ban-time must be positive, not {ban_time}");
+        }
+        let max_mempool = self.max_mempool.map_or(DEFAULT_MAX_MEMPOOL_SIZE, |size| size.saturating_mul(MEGABYTE));
+        if max_mempool == 0 {
+            bail!("max-mempool must be at least 1 MB");
+        }
+        if let Some(filter) = &self.log_filter {
+            EnvFilter::try_new(filter).with_context(|| format!("invalid log-filter {filter}"))?;
+        }
+        if let Some(prune) = self.prune {
+            if prune < MIN_BLOCKS_TO_KEEP {
+                bail!("prune must keep at least {MIN_BLOCKS_TO_KEEP} blocks, not {prune}");
+            }
+        }
+
+        let rpc = match self.rpc_bind {
+            Some(bind) => {
+                let auth = match (self.rpc_user, self.rpc_password) {
+                    (Some(user), Some(password)) => {
+                        if user.contains(':') {
+                            bail!("rpc-user can't contain a colon");
+                        }
+                        if self.rpc_cookie_file.is_some() {
+                            bail!("rpc-cookie-file is only used without rpc-user and rpc-password");
+                        }
+                        RpcAuth::Password { user, password }
+                    }
+                    (None, None) => RpcAuth::Cookie(
+                        self.rpc_cookie_file
+                            .unwrap_or_else(|| chain.with_extension("cookie")),
+                    ),
+                    _ => bail!("rpc-user and rpc-password are set together"),
+                };
+                Some(Rpc { bind, auth })
+            }
+            None => {
+                if self.rpc_user.is_some() || self.rpc_password.is_some() || self.rpc_cookie_file.is_some() {
+                    bail!("RPC credentials are set but rpc-bind isn't");
+                }
+                None
+            }
+        };
+
+        // one port can't serve two protocols
+        let binds = [
+            ("listen", Some(listen)),
+            ("rpc-bind", rpc.as_ref().map(|rpc| rpc.bind)),
+            ("notify-bind", self.notify_bind),
+        ];
+        for (i, (name, bind)) in binds.iter().enumerate() {
+            for (other, other_bind) in &binds[i + 1..] {
+                if let (Some(bind), Some(other_bind)) = (bind, other_bind) {
+                    if bind.port() != 0 && bind == other_bind {
+                        bail!("{name} and {other} are both {bind}");
+                    }
+                }
+            }
+        }
+
+        Ok(Settings {
+            params_path: self.params,
+            address_book: self
+                .address_book
+                .unwrap_or_else(|| chain.with_extension("peers")),
+            ban_list: self.ban_list.unwrap_or_else(|| chain.with_extension("banlist")),
+            params,
+            datadir,
+            chain,
+            listen,
+            peers: self.connect,
+            seeds: self.seed,
+            max_outbound: self.max_outbound.unwrap_or(DEFAULT_MAX_OUTBOUND),
+            max_inbound: self.max_inbound.unwrap_or(DEFAULT_MAX_INBOUND),
+            ban_time,
+            whitelist: self.whitelist.iter().map(|ip| ip.to_canonical()).collect(),
+            notify_bind: self.notify_bind,
+            rpc,
+            max_mempool,
+            log_level: self.log_level.unwrap_or_default(),
+            log_filter: self.log_filter,
+            log_format: self.log_format.unwrap_or_default(),
+            prune: self.prune,
+        })
+    }
+}
+
+impl Settings {
+    /// The effective configuration as a config file. The RPC password is
+    /// masked, it shouldn't end up in terminals and logs
+    pub fn to_toml(&self) -> String {
+        let (rpc_user, rpc_password, rpc_cookie_file) = match self.rpc.as_ref().map(|rpc| &rpc.auth) {
+            Some(RpcAuth::Password { user, .. }) => (Some(user.clone()), Some("********".to_string()), None),
+            Some(RpcAuth::Cookie(path)) => (None, None, Some(path.clone())),
+            None => (None, None, None),
+        };
+        let mut whitelist: Vec<IpAddr> = self.whitelist.iter().copied().collect();
+        whitelist.sort();
+
+        let config = Config {
+            config: None,
+            print_config: false,
+            network: Some(self.params.network),
+            params: self.params_path.clone(),
+            datadir: Some(self.datadir.clone()),
+            chain: Some(self.chain.clone()),
+            listen: Some(self.listen),
+            connect: self.peers.clone(),
+            seed: self.seeds.clone(),
+            address_book: Some(self.address_book.clone()),
+            max_outbound: Some(self.max_outbound),
+            max_inbound: Some(self.max_inbound),
+            ban_list: Some(self.ban_list.clone()),
+            ban_time: Some(self.ban_time),
+            whitelist,
+            notify_bind: self.notify_bind,
+            rpc_bind: self.rpc.as_ref().map(|rpc| rpc.bind),
+            rpc_user,
+            rpc_password,
+            rpc_cookie_file,
+            max_mempool: Some(self.max_mempool / MEGABYTE),
+            log_level: Some(self.log_level),
+            log_filter: self.log_filter.clone(),
+            log_format: Some(self.log_format),
+            prune: self.prune,
+        };
+        toml::to_string(&config).expect("settings always serialize")
+    }
+
+    /// Events let through to the log. The node's and the library's at the
+    /// configured level, the dependencies' at warn unless the level is lower
+    pub fn log_filter(&self) -> EnvFilter {
+        if let Some(filter) = &self.log_filter {
+            return EnvFilter::try_new(filter).expect("checked in resolve");
+        }
+        let level = LevelFilter::from(self.log_level);
+        // a default directive only applies when the string has none, so it goes in the string
+        let dependencies = level.min(LevelFilter::WARN);
+        EnvFilter::try_new(format!("{dependencies},node={level},lib={level}"))
+            .expect("levels always parse")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(args: &[&str]) -> Config {
+        Config::try_parse_from(std::iter::once("node").chain(args.iter().copied())).unwrap()
+    }
+
+    fn resolved(args: &[&str]) -> Settings {
+        config(args).resolve().unwrap()
+    }
+
+    fn error(args: &[&str]) -> String {
+        format!("{:#}", config(args).resolve().err().unwrap())
+    }
+
+    #[test]
+    fn the_command_line_replaces_the_file() {
+        let file: Config = toml::from_str(
+            r#"
+            network = "regtest"
+            max-outbound = 4
+            max-inbound = 20
+            connect = ["10.0.0.1:19644"]
+            seed = ["10.0.0.2:19644"]
+            "#,
+        )
+        .unwrap();
+        let merged = config(&["--max-outbound", "2", "--seed", "10.0.0.3:19644"]).over(file);
+
+        assert_eq!(merged.network, Some(Network::Regtest));
+        assert_eq!(merged.max_outbound, Some(2));
+        assert_eq!(merged.max_inbound, Some(20));
+        // lists are replaced whole, not added to
+        assert_eq!(merged.connect, ["10.0.0.1:19644"]);
+        assert_eq!(merged.seed, ["10.0.0.3:19644"]);
+
+        assert!(toml::from_str::<Config>("max-outbund = 4").is_err());
+        assert!(toml::from_str::<Config>("config = \"other.toml\"").is_err());
+    }
+
+    #[test]
+    fn defaults_follow_the_network_and_chain_file() {
+        let settings = resolved(&["--network", "regtest", "--datadir", "data"]);
+        assert_eq!(settings.chain, Path::new("data").join("regtest.chain"));
+        assert_eq!(settings.address_book, Path::new("data").join("regtest.peers"));
+        assert_eq!(settings.ban_list, Path::new("data").join("regtest.banlist"));
+        assert_eq!(settings.listen.port(), ChainParams::regtest().default_port);
+        assert_eq!(settings.max_outbound, DEFAULT_MAX_OUTBOUND);
+        assert_eq!(settings.max_mempool, DEFAULT_MAX_MEMPOOL_SIZE);
+        assert!(settings.rpc.is_none());
+
+        let settings = resolved(&["--chain", "other.chain", "--rpc-bind", "127.0.0.1:8332"]);
+        assert_eq!(settings.params.network, Network::Main);
+        assert_eq!(settings.address_book, Path::new("other.peers"));
+        assert!(matches!(
+            settings.rpc.map(|rpc| rpc.auth),
+            Some(RpcAuth::Cookie(path)) if path == Path::new("other.cookie")
+        ));
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        assert!(error(&["--network", "custom"]).contains("params file"));
+        assert!(error(&["--ban-time", "0"]).contains("ban-time"));
+        assert!(error(&["--max-mempool", "0"]).contains("max-mempool"));
+        assert!(error(&["--log-filter", "node=loud"]).contains("log-filter"));
+        assert!(error(&["--prune", "10"]).contains("prune"));
+        assert!(error(&["--rpc-user", "me"]).contains("rpc-bind"));
+
+        let rpc = ["--rpc-bind", "127.0.0.1:8332"];
+        assert!(error(&[&rpc[..], &["--rpc-user", "me"]].concat()).contains("together"));
+        assert!(error(&[&rpc[..], &["--rpc-user", "a:b", "--rpc-password", "x"]].concat()).contains("colon"));
+        assert!(error(&[&rpc[..], &["--listen", "127.0.0.1:8332"]].concat()).contains("both"));
+        // the operating system picks different ports
+        resolved(&["--listen", "127.0.0.1:0", "--rpc-bind", "127.0.0.1:0"]);
+    }
+
+    #[test]
+    fn printed_settings_resolve_to_the_same() {
+        let settings = resolved(&[
+            "--network",
+            "regtest",
+            "--whitelist",
+            "10.0.0.2",
+            "--rpc-bind",
+            "127.0.0.1:18443",
+            "--rpc-user",
+            "me",
+            "--rpc-password",
+            "secret",
+            "--max-mempool",
+            "50",
+            "--prune",
+            "1000",
+        ]);
+        let printed = settings.to_toml();
+        assert!(!printed.contains("secret"));
+
+        let again: Config = toml::from_str(&printed).unwrap();
+        let again = again.resolve().unwrap();
+        assert_eq!(again.to_toml(), printed);
+        assert_eq!(again.chain, settings.chain);
+        assert_eq!(again.max_mempool, 50 * MEGABYTE);
+        assert_eq!(again.prune, Some(1000));
+        assert_eq!(again.whitelist, settings.whitelist);
+    }
+
+    #[test]
+    fn dependencies_log_warnings_unless_asked_for_less() {
+        use tracing::{enabled, Level};
+
+        let debug = resolved(&["--log-level", "debug"]).log_filter();
+        let subscriber = tracing_subscriber::fmt().with_env_filter(debug).finish();
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(enabled!(target: "node::peer", Level::DEBUG));
+            assert!(enabled!(target: "lib::types", Level::DEBUG));
+            assert!(!enabled!(target: "lib::types", Level::TRACE));
+            assert!(enabled!(target: "hyper", Level::WARN));
+            assert!(!enabled!(target: "hyper", Level::INFO));
+        });
+
+        let error = resolved(&["--log-level", "error"]).log_filter();
+        let subscriber = tracing_subscriber::fmt().with_env_filter(error).finish();
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(!enabled!(target: "node::peer", Level::WARN));
+            assert!(!enabled!(target: "hyper", Level::WARN));
+        });
+
+        let filter = resolved(&["--log-filter", "node::peer=trace"]).log_filter();
+        assert_eq!(filter.to_string(), "node::peer=trace");
+    }
+}
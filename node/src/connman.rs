@@ -0,0 +1,235 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::Utc;
+use lib::addrman::address_group;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+use tracing::{debug, info, warn};
+
+use crate::node::Node;
+use crate::peer;
+
+/// Automatic outbound connections kept open
+pub const DEFAULT_MAX_OUTBOUND: usize = 8;
+pub const DEFAULT_MAX_INBOUND: usize = 117;
+
+/// How often missing outbound connections are opened
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the address book is written out
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait before reconnecting to a manual peer, doubling up to MAX_RECONNECT_DELAY
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+/// A session lasting this long resets the reconnect delay
+const STABLE_SESSION: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    /// Picked from the address book, one per address group
+    Outbound,
+    /// Given on the command line, reconnected whenever it drops and not
+    /// counted against the outbound limit
+    Manual,
+}
+
+/// Keeps the outbound connections filled from the address book, each in a
+/// different address group so no single network can surround the node
+pub async fn maintain(node: Arc<Node>) {
+    let mut tick = time::interval(MAINTENANCE_INTERVAL);
+    let mut last_save = Instant::now();
+
+    loop {
+        tick.tick().await;
+        for addr in select_outbound(&node, Utc::now().timestamp()).await {
+            tokio::spawn(connect_outbound(node.clone(), addr));
+        }
+
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            node.save_addresses().await;
+            node.sweep_bans().await;
+            last_save = Instant::now();
+        }
+    }
+}
+
+/// Picks addresses for the missing outbound connections and records them as
+/// outbound. Each is in a group no other outbound peer is in, banned ones are
+/// passed over
+async fn select_outbound(node: &Node, now: i64) -> Vec<SocketAddr> {
+    let connected = node.listening_addresses().await;
+
+    let mut outbound = node.outbound.lock().await;
+    let mut groups = outbound.iter().map(|addr| address_group(addr.ip())).collect();
+    let mut book = node.addrman.lock().await;
+    let banlist = node.banlist.lock().await;
+
+    let mut selected = Vec::new();
+    while outbound.len() < node.options.max_outbound {
+        let Some(addr) = book.select(&groups, &connected, now) else {
+            break;
+        };
+        // counted as a failure, so it is not picked again right away
+        book.attempt(&addr, now);
+        if banlist.is_banned(&addr.ip(), now) && !node.is_whitelisted(addr.ip()) {
+            continue;
+        }
+        groups.insert(address_group(addr.ip()));
+        outbound.insert(addr);
+        selected.push(addr);
+    }
+    selected
+}
+
+async fn connect_outbound(node: Arc<Node>, addr: SocketAddr) {
+    match time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => peer::run(node.clone(), stream, addr, Direction::Outbound).await,
+        Ok(Err(e)) => debug!(%addr, error = %e, "failed to connect"),
+        Err(_) => debug!(%addr, "failed to connect: timed out"),
+    }
+    node.outbound.lock().await.remove(&addr);
+}
+
+/// Stays connected to a peer given as host:port, retrying with backoff
+pub async fn manual(node: Arc<Node>, peer: String) {
+    let mut delay = RECONNECT_DELAY;
+
+    loop {
+        let started = Instant::now();
+        match connect(&peer).await {
+            Ok((stream, addr)) => peer::run(node.clone(), stream, addr, Direction::Manual).await,
+            Err(e) => warn!(%peer, error = %e, "failed to connect"),
+        }
+
+        if started.elapsed() >= STABLE_SESSION {
+            delay = RECONNECT_DELAY;
+        }
+        info!(%peer, delay = delay.as_secs(), "reconnecting");
+        time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+async fn connect(peer: &str) -> Result<(TcpStream, SocketAddr)> {
+    let stream = time::timeout(CONNECT_TIMEOUT, TcpStream::connect(peer)).await??;
+    let addr = stream.peer_addr()?;
+    Ok((stream, addr))
+}
+
+/// Accepts peers until the inbound limit, further connections and those from
+/// banned addresses are closed right away
+pub async fn accept(node: Arc<Node>, listener: TcpListener) -> Result<()> {
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        if node.is_banned(addr.ip()).await {
+            info!(%addr, "refusing a banned peer");
+            continue;
+        }
+
+        let mut inbound = node.inbound.lock().await;
+        if inbound.len() >= node.options.max_inbound {
+            info!(%addr, inbound = inbound.len(), "refusing a peer over the inbound limit");
+            continue;
+        }
+        inbound.insert(addr);
+        drop(inbound);
+
+        let node = node.clone();
+        tokio::spawn(async move {
+            peer::run(node.clone(), stream, addr, Direction::Inbound).await;
+            node.inbound.lock().await.remove(&addr);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use lib::addrman::AddrMan;
+    use lib::network::NetAddress;
+
+    use super::*;
+    use crate::node::tests::node;
+
+    /// Gives the node a book holding the addresses, each heard of from a
+    /// different source. Books are keyed at random, so one where two of them
+    /// land in the same slot is started over
+    async fn learn(node: &Node, addrs: &[&str], now: i64) -> Vec<SocketAddr> {
+        let addrs: Vec<SocketAddr> = addrs.iter().map(|addr| addr.parse().unwrap()).collect();
+        loop {
+            let mut book = AddrMan::new();
+            let added = addrs.iter().enumerate().all(|(i, &addr)| {
+                let source = format!("30.{i}.0.1").parse().unwrap();
+                book.add(NetAddress { addr, timestamp: now }, source, now)
+            });
+            if added {
+                *node.addrman.lock().await = book;
+                return addrs;
+            }
+        }
+    }
+
+    fn groups(addrs: &[SocketAddr]) -> HashSet<Vec<u8>> {
+        addrs.iter().map(|addr| address_group(addr.ip())).collect()
+    }
+
+    #[tokio::test]
+    async fn outbound_peers_are_in_different_groups() {
+        let mut node = node();
+        node.options.max_outbound = 4;
+        let now = Utc::now().timestamp();
+        // six groups of two addresses each
+        let addrs: Vec<String> =
+            (0..12).map(|i| format!("{}.{}.{}.1:19644", 10 + i / 2, i / 2, i % 2)).collect();
+        learn(&node, &addrs.iter().map(String::as_str).collect::<Vec<_>>(), now).await;
+
+        let selected = select_outbound(&node, now).await;
+        assert_eq!(selected.len(), 4);
+        assert_eq!(groups(&selected).len(), 4);
+        assert_eq!(*node.outbound.lock().await, selected.iter().copied().collect());
+
+        // full, until a connection closes
+        assert!(select_outbound(&node, now).await.is_empty());
+        node.outbound.lock().await.remove(&selected[0]);
+        let replacement = select_outbound(&node, now).await;
+        assert_eq!(replacement.len(), 1);
+        let others = groups(&selected[1..]);
+        assert!(!others.contains(&address_group(replacement[0].ip())));
+    }
+
+    #[tokio::test]
+    async fn groups_are_not_shared_even_when_short_of_peers() {
+        let node = node();
+        let now = Utc::now().timestamp();
+        learn(&node, &["10.1.0.1:19644", "10.1.0.2:19644", "10.1.7.1:19644"], now).await;
+
+        let selected = select_outbound(&node, now).await;
+        assert_eq!(selected.len(), 1);
+        assert!(select_outbound(&node, now).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn banned_addresses_are_not_dialed() {
+        let mut node = node();
+        let now = Utc::now().timestamp();
+        let addrs = learn(&node, &["10.1.0.1:19644", "11.1.0.1:19644", "12.1.0.1:19644"], now).await;
+        node.options.whitelist.insert(addrs[2].ip());
+        {
+            let mut banlist = node.banlist.lock().await;
+            banlist.ban(addrs[1].ip(), now + 3_600);
+            banlist.ban(addrs[2].ip(), now + 3_600);
+        }
+
+        let selected: HashSet<SocketAddr> = select_outbound(&node, now).await.into_iter().collect();
+        assert_eq!(selected, HashSet::from([addrs[0], addrs[2]]));
+
+        // the banned one was attempted, so it waits before being picked again
+        let book = node.addrman.lock().await;
+        assert_eq!(book.get(&addrs[1]).unwrap().attempts, 1);
+    }
+}
@@ -0,0 +1,64 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use chrono::{TimeZone, Utc};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+use crate::node::Node;
+
+const HELP: &str = "commands:
+  ban <ip> [seconds]  disconnect and refuse the address, for --ban-time by default
+  unban <ip>          lift a ban
+  banned              list the current bans";
+
+/// Reads operator commands from stdin until it closes
+pub async fn run(node: Arc<Node>) {
+    let mut lines = BufReader::new(io::stdin()).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        if let Err(e) = execute(&node, &words).await {
+            println!("{e:#}");
+        }
+    }
+}
+
+async fn execute(node: &Node, words: &[&str]) -> Result<()> {
+    match words {
+        ["ban", ip] => node.ban(parse_ip(ip)?, node.options.ban_time).await,
+        ["ban", ip, seconds] => {
+            let seconds = seconds.parse().context("invalid ban time")?;
+            node.ban(parse_ip(ip)?, seconds).await
+        }
+        ["unban", ip] => {
+            let ip = parse_ip(ip)?;
+            if node.unban(ip).await {
+                println!("unbanned {ip}");
+            } else {
+                println!("{ip} is not banned");
+            }
+        }
+        ["banned"] => {
+            let bans = node.banned().await;
+            if bans.is_empty() {
+                println!("no bans");
+            }
+            for (ip, until) in bans {
+                let until = Utc.timestamp_opt(until, 0).unwrap();
+                println!("{ip} until {until}");
+            }
+        }
+        ["help"] => println!("{HELP}"),
+        _ => bail!("unknown command, {HELP}"),
+    }
+
+    Ok(())
+}
+
+fn parse_ip(ip: &str) -> Result<IpAddr> {
+    ip.parse().with_context(|| format!("invalid address {ip}"))
+}
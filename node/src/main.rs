@@ -1,2 +1,202 @@
-fn main() {
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use lib::addrman::AddrMan;
+use lib::banman::BanList;
+use lib::network::NetAddress;
+use lib::params::ChainParams;
+use lib::types::Blockchain;
+use lib::util::Saveable;
+use tokio::net::{self, TcpListener};
+use tokio::time;
+use tracing::{error, info, warn};
+
+mod config;
+mod connman;
+mod console;
+mod node;
+mod notify;
+mod peer;
+mod relay;
+mod rpc;
+mod sync;
+
+use config::{Config, LogFormat, RpcAuth, Settings};
+use node::{Node, Options};
+
+/// How often timed out block requests are checked for
+const SYNC_TICK: Duration = Duration::from_secs(1);
+
+fn load_chain(path: &PathBuf, params: &ChainParams) -> Result<Blockchain> {
+    if !path.exists() {
+        return Ok(Blockchain::with_params(params.clone()));
+    }
+
+    let chain = Blockchain::load_from_file(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    if chain.params().magic != params.magic {
+        bail!(
+            "{} holds a {} chain, not {}",
+            path.display(),
+            chain.params().network,
+            params.network
+        );
+    }
+    chain
+        .verify_genesis(params)
+        .with_context(|| format!("{} has a different genesis block", path.display()))?;
+    Ok(chain)
+}
+
+fn load_address_book(path: &PathBuf) -> Result<AddrMan> {
+    if !path.exists() {
+        return Ok(AddrMan::new());
+    }
+    AddrMan::load_from_file(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn load_ban_list(path: &PathBuf) -> Result<BanList> {
+    if !path.exists() {
+        return Ok(BanList::new());
+    }
+    let mut banlist =
+        BanList::load_from_file(path).with_context(|| format!("failed to read {}", path.display()))?;
+    banlist.sweep(Utc::now().timestamp());
+    Ok(banlist)
+}
+
+fn init_logging(settings: &Settings) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(settings.log_filter())
+        .with_ansi(io::stdout().is_terminal());
+    match settings.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).init(),
+    }
+}
+
+/// Resolves the seeds into the address book, a seed that doesn't resolve is skipped
+async fn add_seeds(book: &mut AddrMan, seeds: &[String]) {
+    let now = Utc::now().timestamp();
+    for seed in seeds {
+        match net::lookup_host(seed).await {
+            Ok(addrs) => {
+                for addr in addrs {
+                    book.add(NetAddress { addr, timestamp: now }, addr.ip(), now);
+                }
+            }
+            Err(e) => warn!(%seed, error = %e, "failed to resolve seed"),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Config::load()?;
+    let print_config = config.print_config;
+    let settings = config.resolve()?;
+    if print_config {
+        print!("{}", settings.to_toml());
+        return Ok(());
+    }
+    init_logging(&settings);
+
+    let params = settings.params;
+    let mut chain = load_chain(&settings.chain, &params)?;
+    if let Some(keep) = settings.prune {
+        chain.prune(keep);
+    }
+    info!(network = %params.network, height = chain.block_height(), "chain loaded");
+
+    let listener = TcpListener::bind(settings.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", settings.listen))?;
+    info!(addr = %listener.local_addr()?, "listening for peers");
+
+    let mut book = load_address_book(&settings.address_book)?;
+    add_seeds(&mut book, &settings.seeds).await;
+    info!(addresses = book.len(), "address book loaded");
+
+    let banlist = load_ban_list(&settings.ban_list)?;
+    info!(bans = banlist.entries(Utc::now().timestamp()).len(), "ban list loaded");
+
+    let options = Options {
+        chain_path: settings.chain,
+        address_book_path: settings.address_book,
+        listen_port: listener.local_addr()?.port(),
+        max_outbound: settings.max_outbound,
+        max_inbound: settings.max_inbound,
+        ban_list_path: settings.ban_list,
+        ban_time: settings.ban_time,
+        whitelist: settings.whitelist,
+        max_mempool: settings.max_mempool,
+        prune: settings.prune,
+    };
+    let node = Arc::new(Node::new(params, chain, book, banlist, options));
+
+    let sync_node = node.clone();
+    tokio::spawn(async move {
+        let mut tick = time::interval(SYNC_TICK);
+        loop {
+            tick.tick().await;
+            sync_node.sync_tick().await;
+        }
+    });
+
+    let mut cookie = None;
+    if let Some(rpc) = settings.rpc {
+        let listener = TcpListener::bind(rpc.bind)
+            .await
+            .with_context(|| format!("failed to listen for RPC on {}", rpc.bind))?;
+        let credentials = match rpc.auth {
+            RpcAuth::Password { user, password } => format!("{user}:{password}"),
+            RpcAuth::Cookie(path) => {
+                let credentials = rpc::write_cookie(&path)?;
+                cookie = Some(path);
+                credentials
+            }
+        };
+        info!(addr = %listener.local_addr()?, "listening for RPC requests");
+        let rpc_node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = rpc::serve(rpc_node, listener, credentials).await {
+                error!("{e:#}");
+            }
+        });
+    }
+
+    if let Some(bind) = settings.notify_bind {
+        let listener = TcpListener::bind(bind)
+            .await
+            .with_context(|| format!("failed to listen for subscribers on {bind}"))?;
+        info!(addr = %listener.local_addr()?, "publishing events");
+        let events = node.events.clone();
+        tokio::spawn(async move {
+            if let Err(e) = notify::serve(events, listener).await {
+                error!("{e:#}");
+            }
+        });
+    }
+
+    tokio::spawn(connman::maintain(node.clone()));
+    tokio::spawn(console::run(node.clone()));
+    for peer in settings.peers {
+        tokio::spawn(connman::manual(node.clone(), peer));
+    }
+
+    tokio::select! {
+        result = connman::accept(node.clone(), listener) => result,
+        _ = tokio::signal::ctrl_c() => {
+            node.save_addresses().await;
+            // the credentials are only good while we run
+            if let Some(path) = cookie {
+                let _ = std::fs::remove_file(path);
+            }
+            Ok(())
+        }
+    }
 }
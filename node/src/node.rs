@@ -0,0 +1,1298 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use lib::addrman::AddrMan;
+use lib::banman::{block_misbehavior_score, misbehavior_score, BanList, BAN_THRESHOLD};
+use lib::compact::{BlockTransactions, BlockTransactionsRequest, CompactBlock, PartialBlock};
+use lib::error::BtcError;
+use lib::headers::MAX_HEADERS_RESULTS;
+use lib::network::{
+    GetHeaders, Inventory, Message, NetAddress, Version, MAX_ADDR_SIZE, MAX_INVENTORY_SIZE, PROTOCOL_VERSION,
+};
+use lib::params::ChainParams;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction};
+use lib::util::Saveable;
+use rand::seq::IteratorRandom;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, Mutex, Notify, RwLock};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::connman::Direction;
+use crate::notify::{EvictReason, Event, Notifier};
+use crate::relay::{KnownInventory, PendingBlock, Relay};
+use crate::sync::{Progress, Sync};
+
+/// Largest addr message passed on to other peers, bigger ones answer a getaddr
+const MAX_ADDR_RELAY: usize = 10;
+/// Peers a newly learnt address is passed on to
+const ADDR_RELAY_PEERS: usize = 2;
+
+pub struct Options {
+    /// Where the chain is saved after every new block
+    pub chain_path: PathBuf,
+    pub address_book_path: PathBuf,
+    /// Port we accept connections on, told to peers so they can gossip it
+    pub listen_port: u16,
+    pub max_outbound: usize,
+    pub max_inbound: usize,
+    pub ban_list_path: PathBuf,
+    /// Seconds a misbehaving peer is banned for
+    pub ban_time: i64,
+    /// Peers never scored, banned or refused
+    pub whitelist: HashSet<IpAddr>,
+    /// Bytes of transactions the mempool holds, those paying the least per byte go first
+    pub max_mempool: usize,
+    /// Blocks kept in full, older ones only keep their headers. None keeps them all
+    pub prune: Option<u64>,
+}
+
+/// A peer that completed the handshake
+struct Peer {
+    /// Bounded by peer::SEND_QUEUE_SIZE
+    sender: mpsc::Sender<Message>,
+    /// Address it accepts connections on, if it does
+    listening: Option<SocketAddr>,
+    /// Misbehavior points collected, banned at BAN_THRESHOLD
+    score: u32,
+    /// Ends the session
+    disconnect: Arc<Notify>,
+    known: KnownInventory,
+    /// Transactions to announce at the next trickle
+    queued: Vec<Hash>,
+    /// Wants new blocks pushed as compact blocks
+    compact: bool,
+}
+
+impl Peer {
+    /// Queues a message for the peer. One whose queue is full stopped reading
+    /// and is disconnected, a closed queue means its session is ending already
+    fn send(&self, addr: SocketAddr, message: Message) {
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(message) {
+            warn!(%addr, "send queue full, disconnecting");
+            self.disconnect.notify_one();
+        }
+    }
+}
+
+/// State shared by every peer session. Locks are taken in field order:
+/// sync, then chain, then relay, then outbound, then addrman, then banlist,
+/// then peers. Events are published under any of them
+pub struct Node {
+    pub params: ChainParams,
+    pub options: Options,
+    pub sync: Mutex<Sync>,
+    pub chain: RwLock<Blockchain>,
+    pub relay: Mutex<Relay>,
+    /// Automatic outbound connections, from dialing until they close
+    pub outbound: Mutex<HashSet<SocketAddr>>,
+    pub inbound: Mutex<HashSet<SocketAddr>>,
+    pub addrman: Mutex<AddrMan>,
+    pub banlist: Mutex<BanList>,
+    /// Sent in our version messages, a peer echoing it back is ourselves
+    pub nonce: u64,
+    pub events: Arc<Notifier>,
+    peers: Mutex<HashMap<SocketAddr, Peer>>,
+}
+
+impl Node {
+    pub fn new(
+        params: ChainParams,
+        chain: Blockchain,
+        addrman: AddrMan,
+        banlist: BanList,
+        options: Options,
+    ) -> Self {
+        Node {
+            params,
+            options,
+            sync: Mutex::new(Sync::new(&chain)),
+            chain: RwLock::new(chain),
+            relay: Mutex::new(Relay::default()),
+            outbound: Mutex::new(HashSet::new()),
+            inbound: Mutex::new(HashSet::new()),
+            addrman: Mutex::new(addrman),
+            banlist: Mutex::new(banlist),
+            nonce: rand::random(),
+            events: Arc::new(Notifier::new()),
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn version(&self) -> Version {
+        Version {
+            version: PROTOCOL_VERSION,
+            timestamp: Utc::now().timestamp(),
+            nonce: self.nonce,
+            user_agent: format!("/rsbtc:{}/", env!("CARGO_PKG_VERSION")),
+            height: self.chain.read().await.block_height(),
+            port: self.options.listen_port,
+            genesis: self.params.genesis_block().hash(),
+        }
+    }
+
+    /// Registers a peer that completed the handshake and asks it for the
+    /// headers following our best header chain. Peers we connected to go to
+    /// the tried addresses and are asked for more; inbound peers that accept
+    /// connections have their address added and passed on
+    pub async fn connected(
+        &self,
+        addr: SocketAddr,
+        version: &Version,
+        direction: Direction,
+        sender: mpsc::Sender<Message>,
+        disconnect: Arc<Notify>,
+    ) {
+        {
+            let mut sync = self.sync.lock().await;
+            sync.add_peer(addr, version.height.saturating_sub(1));
+            // the queue is still empty
+            let _ = sender.try_send(Self::get_headers(&sync));
+        }
+
+        let now = Utc::now().timestamp();
+        let listening = match direction {
+            Direction::Outbound | Direction::Manual => Some(addr),
+            Direction::Inbound => (version.port != 0).then(|| SocketAddr::new(addr.ip(), version.port)),
+        };
+
+        match direction {
+            Direction::Outbound | Direction::Manual => {
+                let mut book = self.addrman.lock().await;
+                book.add(NetAddress { addr, timestamp: now }, addr.ip(), now);
+                book.good(&addr, now);
+                let _ = sender.try_send(Message::GetAddr);
+                // the peers we picked push us new blocks, mostly made of transactions we have
+                let _ = sender.try_send(Message::SendCmpct(true));
+            }
+            Direction::Inbound => {
+                if let Some(listening) = listening {
+                    self.receive_addr(addr, vec![NetAddress { addr: listening, timestamp: now }]).await;
+                }
+            }
+        }
+
+        let peer = Peer {
+            sender,
+            listening,
+            score: 0,
+            disconnect,
+            known: KnownInventory::default(),
+            queued: Vec::new(),
+            compact: false,
+        };
+        self.peers.lock().await.insert(addr, peer);
+    }
+
+    /// Forgets a peer, the blocks it was downloading are requested from the
+    /// others and the orphans it sent are dropped
+    pub async fn disconnected(&self, addr: SocketAddr) {
+        let mut sync = self.sync.lock().await;
+        sync.remove_peer(addr);
+        self.relay.lock().await.remove_peer(addr);
+        self.peers.lock().await.remove(&addr);
+        self.request_blocks(&mut sync).await;
+    }
+
+    /// Announces a new tip right away to the peers that don't know it, as a
+    /// compact block to those asking for them
+    async fn announce_block(&self, block: &Block) {
+        let item = Inventory::Block(block.hash());
+        let compact = Message::CmpctBlock(CompactBlock::new(block, rand::random()));
+
+        for (addr, peer) in self.peers.lock().await.iter_mut() {
+            if !peer.known.contains(&item) {
+                peer.known.insert(item);
+                let message = if peer.compact { compact.clone() } else { Message::Inv(vec![item]) };
+                peer.send(*addr, message);
+            }
+        }
+    }
+
+    /// The peer does or no longer wants new blocks pushed as compact blocks
+    pub async fn send_compact(&self, addr: SocketAddr, enabled: bool) {
+        if let Some(peer) = self.peers.lock().await.get_mut(&addr) {
+            peer.compact = enabled;
+        }
+    }
+
+    /// Queues transactions for announcement to the peers that don't know them,
+    /// they go out with each peer's next trickle
+    async fn queue_transactions(&self, txids: &[Hash]) {
+        for peer in self.peers.lock().await.values_mut() {
+            for txid in txids {
+                if !peer.known.contains(&Inventory::Transaction(*txid)) {
+                    peer.queued.push(*txid);
+                }
+            }
+        }
+    }
+
+    /// Announces the queued transactions still in the mempool to the peer, at
+    /// random intervals its session draws
+    pub async fn trickle(&self, addr: SocketAddr) {
+        let chain = self.chain.read().await;
+        let mut peers = self.peers.lock().await;
+        let Some(peer) = peers.get_mut(&addr) else {
+            return;
+        };
+
+        let mut inventory = Vec::new();
+        for txid in std::mem::take(&mut peer.queued) {
+            let item = Inventory::Transaction(txid);
+            if !peer.known.contains(&item) && chain.mempool_transaction(&txid).is_some() {
+                peer.known.insert(item);
+                inventory.push(item);
+            }
+        }
+        for chunk in inventory.chunks(MAX_INVENTORY_SIZE) {
+            peer.send(addr, Message::Inv(chunk.to_vec()));
+        }
+    }
+
+    /// Notes that the peer has the objects, so they aren't announced to it
+    async fn learned(&self, addr: SocketAddr, inventory: &[Inventory]) {
+        if let Some(peer) = self.peers.lock().await.get_mut(&addr) {
+            for item in inventory {
+                peer.known.insert(*item);
+            }
+        }
+    }
+
+    pub async fn send_to(&self, addr: SocketAddr, message: Message) {
+        if let Some(peer) = self.peers.lock().await.get(&addr) {
+            peer.send(addr, message);
+        }
+    }
+
+    /// Handles an inv: returns the transactions to ask for, those neither
+    /// known nor asked of another peer already. Blocks are fetched headers
+    /// first, so unknown ones make us ask for headers instead
+    pub async fn announced(&self, addr: SocketAddr, inventory: &[Inventory]) -> Vec<Inventory> {
+        let mut sync = self.sync.lock().await;
+        let chain = self.chain.read().await;
+        let mut relay = self.relay.lock().await;
+        let now = Instant::now();
+        let mut unknown_block = false;
+        let mut missing = Vec::new();
+
+        for item in inventory {
+            match item {
+                Inventory::Block(hash) if sync.headers.contains(hash) => sync.peer_has(addr, hash),
+                Inventory::Block(_) => unknown_block = true,
+                Inventory::Transaction(txid) => {
+                    if chain.mempool_transaction(txid).is_none() && relay.request(*txid, addr, now) {
+                        missing.push(*item);
+                    }
+                }
+            }
+        }
+        drop(relay);
+        self.learned(addr, inventory).await;
+
+        if unknown_block {
+            self.send_to(addr, Self::get_headers(&sync)).await;
+        }
+        missing
+    }
+
+    /// Answers a getdata: the objects we have, then a notfound for the rest
+    pub async fn lookup(&self, inventory: &[Inventory]) -> Vec<Message> {
+        let chain = self.chain.read().await;
+        let mut messages = Vec::new();
+        let mut not_found = Vec::new();
+
+        for item in inventory {
+            match item {
+                Inventory::Block(hash) => match chain.block_by_hash(hash).filter(|block| !block.is_pruned()) {
+                    Some(block) => messages.push(Message::Block(block.clone())),
+                    None => not_found.push(*item),
+                },
+                Inventory::Transaction(txid) => match chain.mempool_transaction(txid) {
+                    Some(transaction) => messages.push(Message::Tx(transaction.clone())),
+                    None => not_found.push(*item),
+                },
+            }
+        }
+
+        if !not_found.is_empty() {
+            messages.push(Message::NotFound(not_found));
+        }
+        messages
+    }
+
+    /// Adds gossiped addresses to the address book. Those newly learnt from a
+    /// small announcement are passed on to a couple of other peers, answers to
+    /// getaddr and addresses we knew already stop here
+    pub async fn receive_addr(&self, from: SocketAddr, addresses: Vec<NetAddress>) {
+        let now = Utc::now().timestamp();
+        let added: Vec<NetAddress> = {
+            let mut book = self.addrman.lock().await;
+            addresses
+                .iter()
+                .filter(|address| book.add(**address, from.ip(), now))
+                .copied()
+                .collect()
+        };
+
+        if added.is_empty() || addresses.len() > MAX_ADDR_RELAY {
+            return;
+        }
+
+        let peers = self.peers.lock().await;
+        let relays = peers
+            .iter()
+            .filter(|(addr, _)| **addr != from)
+            .choose_multiple(&mut rand::thread_rng(), ADDR_RELAY_PEERS);
+        for (addr, peer) in relays {
+            peer.send(*addr, Message::Addr(added.clone()));
+        }
+    }
+
+    /// Addresses the connected peers accept connections on, not to be dialed again
+    pub async fn listening_addresses(&self) -> HashSet<SocketAddr> {
+        self.peers.lock().await.values().filter_map(|peer| peer.listening).collect()
+    }
+
+    pub fn is_whitelisted(&self, ip: IpAddr) -> bool {
+        self.options.whitelist.contains(&ip.to_canonical())
+    }
+
+    /// Whether connections from or to the address are refused
+    pub async fn is_banned(&self, ip: IpAddr) -> bool {
+        !self.is_whitelisted(ip) && self.banlist.lock().await.is_banned(&ip, Utc::now().timestamp())
+    }
+
+    /// Scores a peer for sending something rejected with the error. A peer
+    /// reaching BAN_THRESHOLD is banned for the configured time
+    pub async fn misbehaving(&self, addr: SocketAddr, error: &BtcError) {
+        self.score(addr, error, misbehavior_score(error)).await;
+    }
+
+    /// Scores a peer for sending a block that failed validation with the error
+    pub async fn sent_invalid_block(&self, addr: SocketAddr, error: &BtcError) {
+        self.score(addr, error, block_misbehavior_score(error)).await;
+    }
+
+    async fn score(&self, addr: SocketAddr, error: &BtcError, points: u32) {
+        if points == 0 {
+            return;
+        }
+        if self.is_whitelisted(addr.ip()) {
+            info!(%addr, %error, "whitelisted peer misbehaving");
+            return;
+        }
+
+        let score = {
+            let mut peers = self.peers.lock().await;
+            let Some(peer) = peers.get_mut(&addr) else {
+                return;
+            };
+            peer.score += points;
+            peer.score
+        };
+        warn!(%addr, %error, score, "peer misbehaving");
+
+        if score >= BAN_THRESHOLD {
+            self.ban(addr.ip(), self.options.ban_time).await;
+        }
+    }
+
+    /// Bans an address for `seconds` and disconnects its peers. Whitelisted
+    /// addresses can be banned too, the ban only applies once they are not
+    pub async fn ban(&self, ip: IpAddr, seconds: i64) {
+        let until = Utc::now().timestamp() + seconds;
+        {
+            let mut banlist = self.banlist.lock().await;
+            banlist.ban(ip, until);
+            self.save_ban_list(&banlist);
+        }
+        warn!(%ip, seconds, "banned");
+
+        if self.is_whitelisted(ip) {
+            return;
+        }
+        for (addr, peer) in self.peers.lock().await.iter() {
+            if addr.ip().to_canonical() == ip.to_canonical() {
+                peer.disconnect.notify_one();
+            }
+        }
+    }
+
+    /// Lifts a ban, returns whether there was one
+    pub async fn unban(&self, ip: IpAddr) -> bool {
+        let mut banlist = self.banlist.lock().await;
+        let unbanned = banlist.unban(&ip);
+        if unbanned {
+            self.save_ban_list(&banlist);
+        }
+        unbanned
+    }
+
+    /// Forgets the bans that ran out
+    pub async fn sweep_bans(&self) {
+        let mut banlist = self.banlist.lock().await;
+        if banlist.sweep(Utc::now().timestamp()) {
+            self.save_ban_list(&banlist);
+        }
+    }
+
+    /// Current bans with the unix time they end at
+    pub async fn banned(&self) -> Vec<(IpAddr, i64)> {
+        self.banlist.lock().await.entries(Utc::now().timestamp())
+    }
+
+    /// Answers a getaddr from the address book
+    pub async fn addresses(&self) -> Message {
+        let now = Utc::now().timestamp();
+        Message::Addr(self.addrman.lock().await.sample(MAX_ADDR_SIZE, now))
+    }
+
+    /// Answers a getheaders from our connected blocks
+    pub async fn headers(&self, get_headers: &GetHeaders) -> Message {
+        let chain = self.chain.read().await;
+        Message::Headers(chain.headers_after(&get_headers.locator, &get_headers.stop, MAX_HEADERS_RESULTS))
+    }
+
+    /// Validates headers from a peer, asks for more if the message was full and
+    /// starts downloading the blocks of a better chain
+    pub async fn receive_headers(&self, addr: SocketAddr, headers: Vec<BlockHeader>) -> Result<(), BtcError> {
+        let inventory: Vec<Inventory> = headers.iter().map(|header| Inventory::Block(header.hash())).collect();
+        self.learned(addr, &inventory).await;
+
+        let mut sync = self.sync.lock().await;
+        let previous_best = sync.headers.best_height();
+        let full = sync.add_headers(addr, headers)?;
+
+        if sync.headers.best_height() != previous_best {
+            info!(height = sync.headers.best_height(), "best header chain");
+        }
+        if full {
+            self.send_to(addr, Self::get_headers(&sync)).await;
+        }
+
+        self.request_blocks(&mut sync).await;
+        Ok(())
+    }
+
+    /// The peer doesn't have some objects we asked for
+    pub async fn not_found(&self, addr: SocketAddr, inventory: &[Inventory]) {
+        let mut hashes = Vec::new();
+        let mut txids = Vec::new();
+        for item in inventory {
+            match item {
+                Inventory::Block(hash) => hashes.push(*hash),
+                Inventory::Transaction(txid) => txids.push(*txid),
+            }
+        }
+
+        let mut sync = self.sync.lock().await;
+        {
+            let mut relay = self.relay.lock().await;
+            for txid in &txids {
+                relay.received(txid);
+            }
+        }
+        sync.not_found(addr, &hashes);
+        self.request_blocks(&mut sync).await;
+    }
+
+    /// Takes a block from `from`, connects whatever it completes along the best
+    /// header chain, saves the chain and announces the new tip to the other peers.
+    /// A block whose parent is unknown makes us ask `from` for headers, the
+    /// peers that sent blocks failing to connect are scored for it. A block
+    /// without a peer, submitted over RPC, returns why it failed to connect
+    #[instrument(level = "debug", name = "block", skip_all, fields(hash = %block.hash()))]
+    pub async fn receive_block(&self, block: Block, from: Option<SocketAddr>) -> Result<(), BtcError> {
+        let hash = block.hash();
+        if let Some(addr) = from {
+            self.learned(addr, &[Inventory::Block(hash)]).await;
+        }
+        let mut sync = self.sync.lock().await;
+
+        match sync.add_block(block, from) {
+            Ok(()) => {
+                // arrived whole, a compact block still waiting for transactions is no longer needed
+                self.relay.lock().await.pending_blocks.remove(&hash);
+            }
+            Err(BtcError::MissingPreviousBlock) => {
+                if let Some(addr) = from {
+                    self.send_to(addr, Self::get_headers(&sync)).await;
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+
+        let mut rejection = None;
+        let mut invalid_blocks = Vec::new();
+        let mut misbehaved = Vec::new();
+        let mut adopted = Vec::new();
+        let tip = {
+            let mut chain = self.chain.write().await;
+            let old_tip = chain.blocks.last().map(Block::hash).expect("the chain holds its genesis block");
+            let mempool: HashSet<Hash> = chain.mempool.iter().map(Transaction::hash).collect();
+            let progress = sync.connect(&mut chain);
+            self.publish_progress(&sync, &chain, old_tip, &progress, mempool);
+
+            if !progress.disconnected.is_empty() {
+                warn!(disconnected = progress.disconnected.len(), "reorganization");
+            }
+            for (invalid, e, peer) in progress.invalid {
+                warn!(hash = %invalid, error = %e, "invalid block");
+                match peer {
+                    Some(peer) => invalid_blocks.push((peer, e)),
+                    None if invalid == hash => rejection = Some(e),
+                    None => {}
+                }
+            }
+
+            if progress.connected.is_empty() {
+                None
+            } else {
+                let height = chain.block_height() - 1;
+                let tip = chain.blocks[height as usize].clone();
+                info!(hash = %tip.hash(), height, "block connected");
+
+                if let Some(keep) = self.options.prune {
+                    chain.prune(keep);
+                }
+                if let Err(e) = self.save(&chain) {
+                    error!("{e:#}");
+                }
+
+                // the new outputs may be the inputs orphans were waiting for
+                let mut relay = self.relay.lock().await;
+                self.adopt_orphans(&mut chain, &mut relay, &mut adopted, &mut misbehaved);
+                // transactions back from disconnected blocks can overfill it
+                self.trim_mempool(&mut chain);
+
+                // no announcements while catching up, peers would only ask for old blocks
+                sync.is_synced(&chain).then_some(tip)
+            }
+        };
+
+        for (peer, e) in invalid_blocks {
+            self.sent_invalid_block(peer, &e).await;
+        }
+        for (peer, e) in misbehaved {
+            self.misbehaving(peer, &e).await;
+        }
+        if let Some(tip) = tip {
+            self.announce_block(&tip).await;
+        }
+        self.queue_transactions(&adopted).await;
+        self.request_blocks(&mut sync).await;
+        rejection.map_or(Ok(()), Err)
+    }
+
+    /// Publishes what connecting blocks did: a reorganization first, then the
+    /// blocks disconnected and connected, then the transactions that left the
+    /// mempool or came back to it from disconnected blocks
+    fn publish_progress(
+        &self,
+        sync: &Sync,
+        chain: &Blockchain,
+        old_tip: Hash,
+        progress: &Progress,
+        mempool: HashSet<Hash>,
+    ) {
+        if progress.connected.is_empty() && progress.disconnected.is_empty() {
+            return;
+        }
+        // the header tree still knows the branch the chain left
+        let height = |hash: &Hash| sync.headers.get(hash).map(|entry| entry.height);
+
+        if let Some(fork) = progress.disconnected.iter().filter_map(height).min() {
+            let new_tip = chain.blocks.last().expect("the chain holds its genesis block").hash();
+            self.events.publish(Event::Reorg {
+                old_tip: old_tip.to_string(),
+                new_tip: new_tip.to_string(),
+                fork_height: fork - 1,
+                disconnected: progress.disconnected.len(),
+                connected: progress.connected.len(),
+            });
+        }
+        for hash in &progress.disconnected {
+            if let Some(height) = height(hash) {
+                self.events.publish(Event::BlockDisconnected { hash: hash.to_string(), height });
+            }
+        }
+        for hash in &progress.connected {
+            if let Some(height) = height(hash) {
+                self.events.publish(Event::BlockConnected { hash: hash.to_string(), height });
+            }
+        }
+
+        let now: HashSet<Hash> = chain.mempool.iter().map(Transaction::hash).collect();
+        let mined: HashSet<Hash> = progress
+            .connected
+            .iter()
+            .filter_map(|hash| chain.block_by_hash(hash))
+            .flat_map(|block| block.transactions().iter().map(Transaction::hash))
+            .collect();
+        for txid in mempool.difference(&now) {
+            let reason = if mined.contains(txid) {
+                EvictReason::Confirmed
+            } else {
+                EvictReason::Conflict
+            };
+            self.events.publish(Event::MempoolEvict { txid: txid.to_string(), reason });
+        }
+        for txid in now.difference(&mempool) {
+            self.events.publish(Event::MempoolAccept { txid: txid.to_string() });
+        }
+    }
+
+    /// Takes a compact block from a peer: its header goes through the header
+    /// tree, then the block is rebuilt from the mempool and the transactions
+    /// missing from it are requested from the peer
+    pub async fn receive_compact_block(&self, addr: SocketAddr, compact: CompactBlock) -> Result<(), BtcError> {
+        let hash = compact.header.hash();
+        self.learned(addr, &[Inventory::Block(hash)]).await;
+
+        let mut sync = self.sync.lock().await;
+        match sync.add_headers(addr, vec![compact.header.clone()]) {
+            Ok(_) => {}
+            Err(BtcError::MissingPreviousBlock) => {
+                self.send_to(addr, Self::get_headers(&sync)).await;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        }
+
+        let partial = {
+            let chain = self.chain.read().await;
+            if chain.height_of(&hash).is_some() || !sync.headers.is_on_best_chain(&hash) {
+                return Ok(());
+            }
+            PartialBlock::new(&compact, &chain.mempool)?
+        };
+        sync.expect(addr, hash);
+        drop(sync);
+
+        let missing = partial.missing();
+        if missing.is_empty() {
+            return self.complete_compact_block(addr, compact, partial, Vec::new()).await;
+        }
+
+        self.relay
+            .lock()
+            .await
+            .pending_blocks
+            .insert(hash, PendingBlock { peer: addr, compact, partial });
+        let request = BlockTransactionsRequest {
+            block_hash: hash,
+            indexes: missing,
+        };
+        self.send_to(addr, Message::GetBlockTxn(request)).await;
+        Ok(())
+    }
+
+    /// Completes the compact block waiting for these transactions from the peer
+    pub async fn receive_block_transactions(
+        &self,
+        addr: SocketAddr,
+        transactions: BlockTransactions,
+    ) -> Result<(), BtcError> {
+        let pending = {
+            let mut relay = self.relay.lock().await;
+            match relay.pending_blocks.get(&transactions.block_hash) {
+                Some(pending) if pending.peer == addr => relay.pending_blocks.remove(&transactions.block_hash),
+                // not asked for, or the block arrived some other way meanwhile
+                _ => None,
+            }
+        };
+
+        match pending {
+            Some(PendingBlock { compact, partial, .. }) => {
+                self.complete_compact_block(addr, compact, partial, transactions.transactions)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn complete_compact_block(
+        &self,
+        addr: SocketAddr,
+        compact: CompactBlock,
+        partial: PartialBlock,
+        requested: Vec<Transaction>,
+    ) -> Result<(), BtcError> {
+        let hash = partial.hash();
+        let from_mempool = partial.from_mempool;
+        let count = partial.transaction_count();
+
+        let block = match partial.complete(requested.clone()) {
+            Ok(block) => block,
+            // the peer sent a wrong number of transactions
+            Err(BtcError::InvalidBlock) => return Err(BtcError::InvalidBlock),
+            // most likely a short ID collision with the mempool, the full block settles it
+            Err(e) => {
+                self.relay.lock().await.compact_stats.failures += 1;
+                info!(%hash, error = %e, "compact block failed to rebuild, downloading it whole");
+                self.send_to(addr, Message::GetData(vec![Inventory::Block(hash)])).await;
+                return Ok(());
+            }
+        };
+
+        let stats = {
+            let mut relay = self.relay.lock().await;
+            relay.compact_stats.record(&compact, &requested, &block, from_mempool);
+            relay.compact_stats
+        };
+        debug!(
+            %hash,
+            from_mempool,
+            count,
+            requested = requested.len(),
+            saved_bytes = stats.saved_bytes(),
+            full_bytes = stats.full_bytes,
+            "block rebuilt from the mempool",
+        );
+
+        self.receive_block(block, Some(addr)).await
+    }
+
+    /// Answers a getblocktxn with the requested transactions of a block on our
+    /// chain. None if the block is unknown or an index is past its end
+    pub async fn block_transactions(&self, request: &BlockTransactionsRequest) -> Option<Message> {
+        let chain = self.chain.read().await;
+        let block = chain.block_by_hash(&request.block_hash)?;
+        BlockTransactions::new(block, request).map(Message::BlockTxn)
+    }
+
+    /// Runs periodically: requests that timed out are sent to other peers
+    #[instrument(level = "trace", name = "sync", skip_all)]
+    pub async fn sync_tick(&self) {
+        let mut sync = self.sync.lock().await;
+        for addr in sync.expire(Instant::now()) {
+            info!(%addr, "peer stalled the block download");
+        }
+        self.request_blocks(&mut sync).await;
+    }
+
+    /// Sends the block requests the download window has room for
+    async fn request_blocks(&self, sync: &mut Sync) {
+        let requests = {
+            let chain = self.chain.read().await;
+            sync.schedule(&chain)
+        };
+
+        for (addr, hashes) in requests {
+            let inventory = hashes.into_iter().map(Inventory::Block).collect();
+            self.send_to(addr, Message::GetData(inventory)).await;
+        }
+    }
+
+    fn get_headers(sync: &Sync) -> Message {
+        Message::GetHeaders(GetHeaders {
+            locator: sync.headers.locator(),
+            stop: Hash::zero(),
+        })
+    }
+
+    /// Adds a transaction from `from` to the mempool and queues its
+    /// announcement to the other peers, along with the orphans it completes.
+    /// One spending outputs we don't know is kept as an orphan until a block
+    /// or another transaction creates them
+    #[instrument(level = "debug", name = "transaction", skip_all, fields(txid = %transaction.hash()))]
+    pub async fn accept_transaction(
+        &self,
+        transaction: Transaction,
+        from: Option<SocketAddr>,
+    ) -> Result<(), BtcError> {
+        let txid = transaction.hash();
+        if let Some(addr) = from {
+            self.learned(addr, &[Inventory::Transaction(txid)]).await;
+        }
+
+        let mut adopted = vec![txid];
+        let mut misbehaved = Vec::new();
+        {
+            let mut chain = self.chain.write().await;
+            let mut relay = self.relay.lock().await;
+            relay.received(&txid);
+            if chain.mempool_transaction(&txid).is_some() || relay.orphans.contains(&txid) {
+                return Ok(());
+            }
+
+            match chain.add_to_mempool(transaction.clone()) {
+                Ok(()) => {
+                    if self.trim_mempool(&mut chain).contains(&txid) {
+                        debug!("evicted right away, the mempool is full");
+                        return Err(BtcError::MempoolFull);
+                    }
+                    debug!("accepted to the mempool");
+                    self.events.publish(Event::MempoolAccept { txid: txid.to_string() });
+
+                    // its outputs may be the inputs orphans were waiting for
+                    self.adopt_orphans(&mut chain, &mut relay, &mut adopted, &mut misbehaved);
+                    self.trim_mempool(&mut chain);
+                }
+                Err(BtcError::MissingInputs) => {
+                    if relay.orphans.add(transaction, from, Utc::now().timestamp()) {
+                        debug!(orphans = relay.orphans.len(), "kept as an orphan");
+                    }
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        for (peer, e) in misbehaved {
+            self.misbehaving(peer, &e).await;
+        }
+        self.queue_transactions(&adopted).await;
+        Ok(())
+    }
+
+    /// Moves the orphans whose inputs the chain or its mempool holds now to the
+    /// mempool, then those spending their outputs in turn. Adds their txids to
+    /// `adopted` and the peers that sent rejected ones to `misbehaved`
+    fn adopt_orphans(
+        &self,
+        chain: &mut Blockchain,
+        relay: &mut Relay,
+        adopted: &mut Vec<Hash>,
+        misbehaved: &mut Vec<(SocketAddr, BtcError)>,
+    ) {
+        loop {
+            let ready = relay.orphans.take_ready(chain);
+            if ready.is_empty() {
+                break;
+            }
+
+            for (transaction, peer) in ready {
+                let txid = transaction.hash();
+                match chain.add_to_mempool(transaction) {
+                    Ok(()) => {
+                        debug!(%txid, "orphan transaction accepted to the mempool");
+                        self.events.publish(Event::MempoolAccept { txid: txid.to_string() });
+                        adopted.push(txid);
+                    }
+                    Err(e) => {
+                        debug!(%txid, error = %e, "orphan transaction rejected");
+                        misbehaved.extend(peer.map(|peer| (peer, e)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Evicts the transactions over the mempool's size limit, returns their txids
+    fn trim_mempool(&self, chain: &mut Blockchain) -> Vec<Hash> {
+        let evicted: Vec<Hash> = chain
+            .trim_mempool(self.options.max_mempool)
+            .iter()
+            .map(Transaction::hash)
+            .collect();
+        for txid in &evicted {
+            debug!(%txid, "evicted from the full mempool");
+            self.events.publish(Event::MempoolEvict {
+                txid: txid.to_string(),
+                reason: EvictReason::SizeLimit,
+            });
+        }
+        evicted
+    }
+
+    pub async fn save_addresses(&self) {
+        let path = &self.options.address_book_path;
+        if let Err(e) = self.addrman.lock().await.save_to_file(path) {
+            error!(path = %path.display(), error = %e, "failed to write the address book");
+        }
+    }
+
+    fn save_ban_list(&self, banlist: &BanList) {
+        let path = &self.options.ban_list_path;
+        if let Err(e) = banlist.save_to_file(path) {
+            error!(path = %path.display(), error = %e, "failed to write the ban list");
+        }
+    }
+
+    fn save(&self, chain: &Blockchain) -> Result<()> {
+        let path = &self.options.chain_path;
+        chain
+            .save_to_file(path)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use lib::crypto::PrivateKey;
+    use lib::types::{TransactionInput, TransactionOutput};
+    use lib::util::MerkleRoot;
+
+    use super::*;
+
+    /// Regtest node on a fresh chain, writing its files to a new temporary directory
+    pub fn node() -> Node {
+        let dir = std::env::temp_dir().join(format!("node-test-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let params = ChainParams::regtest();
+        let options = Options {
+            chain_path: dir.join("chain.cbor"),
+            address_book_path: dir.join("peers.cbor"),
+            listen_port: params.default_port,
+            max_outbound: 8,
+            max_inbound: 8,
+            ban_list_path: dir.join("banlist.cbor"),
+            ban_time: 60,
+            whitelist: HashSet::new(),
+            max_mempool: 1_000_000,
+            prune: None,
+        };
+        Node::new(
+            params.clone(),
+            Blockchain::with_params(params),
+            AddrMan::new(),
+            BanList::new(),
+            options,
+        )
+    }
+
+    /// Registers an inbound peer with a send queue of `capacity` messages,
+    /// returning the queue and the notification ending its session
+    pub async fn connect(
+        node: &Node,
+        addr: SocketAddr,
+        capacity: usize,
+    ) -> (mpsc::Receiver<Message>, Arc<Notify>) {
+        let version = Version {
+            version: PROTOCOL_VERSION,
+            timestamp: 0,
+            nonce: 0,
+            user_agent: "/test/".to_string(),
+            height: 1,
+            port: 0,
+            genesis: node.params.genesis_block().hash(),
+        };
+        let (sender, queue) = mpsc::channel(capacity);
+        let disconnect = Arc::new(Notify::new());
+        node.connected(addr, &version, Direction::Inbound, sender, disconnect.clone())
+            .await;
+        (queue, disconnect)
+    }
+
+    async fn notified(disconnect: &Notify) -> bool {
+        tokio::time::timeout(std::time::Duration::from_millis(100), disconnect.notified())
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn peers_not_reading_are_disconnected() {
+        let node = node();
+        let addr: SocketAddr = "10.0.0.1:9633".parse().unwrap();
+        // room for the getheaders sent on connecting and one more
+        let (mut queue, disconnect) = connect(&node, addr, 2).await;
+
+        node.send_to(addr, Message::Ping(1)).await;
+        assert!(!notified(&disconnect).await);
+
+        node.send_to(addr, Message::Ping(2)).await;
+        assert!(notified(&disconnect).await);
+
+        // what was queued is still delivered
+        assert!(matches!(queue.recv().await, Some(Message::GetHeaders(_))));
+        assert!(matches!(queue.recv().await, Some(Message::Ping(1))));
+    }
+
+    #[tokio::test]
+    async fn closed_queues_are_left_to_their_session() {
+        let node = node();
+        let addr: SocketAddr = "10.0.0.2:9633".parse().unwrap();
+        let (queue, disconnect) = connect(&node, addr, 1).await;
+        drop(queue);
+
+        node.send_to(addr, Message::Ping(1)).await;
+        assert!(!notified(&disconnect).await);
+    }
+
+    #[tokio::test]
+    async fn invalid_data_bans_the_address() {
+        let node = node();
+        let addr: SocketAddr = "10.0.0.3:9633".parse().unwrap();
+        let other: SocketAddr = "10.0.0.3:9634".parse().unwrap();
+        let (_queue, disconnect) = connect(&node, addr, 8).await;
+        let (_other_queue, other_disconnect) = connect(&node, other, 8).await;
+
+        // anyone can send these
+        node.misbehaving(addr, &BtcError::MissingInputs).await;
+        node.misbehaving(addr, &BtcError::MempoolFull).await;
+        assert!(!node.is_banned(addr.ip()).await);
+        assert!(!notified(&disconnect).await);
+
+        node.misbehaving(addr, &BtcError::InvalidBlock).await;
+        assert!(node.is_banned(addr.ip()).await);
+        assert!(notified(&disconnect).await);
+        assert!(notified(&other_disconnect).await);
+
+        // and the ban outlives the node
+        let saved = BanList::load_from_file(&node.options.ban_list_path).unwrap();
+        assert!(saved.is_banned(&addr.ip(), Utc::now().timestamp()));
+        assert!(node.unban(addr.ip()).await);
+        assert!(!node.is_banned(addr.ip()).await);
+    }
+
+    #[tokio::test]
+    async fn whitelisted_peers_are_not_banned() {
+        let mut node = node();
+        let addr: SocketAddr = "10.0.0.4:9633".parse().unwrap();
+        node.options.whitelist.insert(addr.ip());
+        let (_queue, disconnect) = connect(&node, addr, 8).await;
+
+        node.misbehaving(addr, &BtcError::InvalidBlock).await;
+        assert!(node.banned().await.is_empty());
+        assert!(!notified(&disconnect).await);
+
+        // a ban given by hand is kept, but only applies once it is off the whitelist
+        node.ban(addr.ip(), 3_600).await;
+        assert_eq!(node.banned().await.len(), 1);
+        assert!(!node.is_banned(addr.ip()).await);
+        assert!(!notified(&disconnect).await);
+    }
+
+    /// Adds a transaction spending a made up output to the mempool
+    fn add_to_mempool(chain: &mut Blockchain) -> Transaction {
+        let key = PrivateKey::new_key();
+        let utxo = TransactionOutput::new(1_000_000, key.public_key().into());
+        let utxo_hash = utxo.hash();
+        let mut transaction = Transaction::new(
+            vec![TransactionInput::unsigned(utxo_hash)],
+            vec![TransactionOutput::new(990_000, key.public_key().into())],
+        );
+        transaction.sign_input(0, 0, &key, &key.public_key());
+
+        chain.utxos.insert(utxo_hash, utxo);
+        chain.add_to_mempool(transaction.clone()).unwrap();
+        transaction
+    }
+
+    /// Transactions announced in the messages waiting in the queue
+    fn announcements(queue: &mut mpsc::Receiver<Message>) -> Vec<Hash> {
+        let mut txids = Vec::new();
+        while let Ok(message) = queue.try_recv() {
+            if let Message::Inv(inventory) = message {
+                for item in inventory {
+                    if let Inventory::Transaction(txid) = item {
+                        txids.push(txid);
+                    }
+                }
+            }
+        }
+        txids
+    }
+
+    #[tokio::test]
+    async fn transactions_trickle_to_peers_not_knowing_them() {
+        let node = node();
+        let first: SocketAddr = "10.0.0.5:9633".parse().unwrap();
+        let second: SocketAddr = "10.0.0.6:9633".parse().unwrap();
+        let (mut first_queue, _) = connect(&node, first, 8).await;
+        let (mut second_queue, _) = connect(&node, second, 8).await;
+        announcements(&mut first_queue);
+        announcements(&mut second_queue);
+
+        let txids = {
+            let mut chain = node.chain.write().await;
+            [add_to_mempool(&mut chain).hash(), add_to_mempool(&mut chain).hash()]
+        };
+        let gone = Hash::hash(&"not in the mempool");
+        // the second peer sent us the first one
+        node.learned(second, &[Inventory::Transaction(txids[0])]).await;
+
+        node.queue_transactions(&[txids[0], txids[1], gone]).await;
+        // nothing goes out before the trickle
+        assert!(announcements(&mut first_queue).is_empty());
+
+        node.trickle(first).await;
+        node.trickle(second).await;
+        assert_eq!(announcements(&mut first_queue), txids);
+        assert_eq!(announcements(&mut second_queue), [txids[1]]);
+
+        // announced once only
+        node.queue_transactions(&txids).await;
+        node.trickle(first).await;
+        assert!(announcements(&mut first_queue).is_empty());
+    }
+
+    #[tokio::test]
+    async fn announced_transactions_are_requested_once() {
+        let node = node();
+        let first: SocketAddr = "10.0.0.7:9633".parse().unwrap();
+        let second: SocketAddr = "10.0.0.8:9633".parse().unwrap();
+        let (_first_queue, _) = connect(&node, first, 8).await;
+        let (_second_queue, _) = connect(&node, second, 8).await;
+
+        let known = Inventory::Transaction(add_to_mempool(&mut *node.chain.write().await).hash());
+        let unknown = Inventory::Transaction(Hash::hash(&"unknown"));
+        assert_eq!(node.announced(first, &[known, unknown]).await, [unknown]);
+        assert!(node.announced(second, &[unknown]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn orphans_are_adopted_when_their_parent_arrives() {
+        let node = node();
+        let sender: SocketAddr = "10.0.0.9:9633".parse().unwrap();
+        let other: SocketAddr = "10.0.0.10:9633".parse().unwrap();
+        let (mut sender_queue, _) = connect(&node, sender, 8).await;
+        let (mut other_queue, _) = connect(&node, other, 8).await;
+
+        let key = PrivateKey::new_key();
+        let utxo = TransactionOutput::new(1_000_000, key.public_key().into());
+        let mut parent = Transaction::new(
+            vec![TransactionInput::unsigned(utxo.hash())],
+            vec![TransactionOutput::new(990_000, key.public_key().into())],
+        );
+        parent.sign_input(0, 0, &key, &key.public_key());
+        let mut child = Transaction::new(
+            vec![TransactionInput::unsigned(parent.outputs()[0].hash())],
+            vec![TransactionOutput::new(980_000, key.public_key().into())],
+        );
+        child.sign_input(0, 0, &key, &key.public_key());
+        node.chain.write().await.utxos.insert(utxo.hash(), utxo);
+
+        // the child arrives first and waits for the output its parent creates
+        node.accept_transaction(child.clone(), Some(sender)).await.unwrap();
+        assert!(node.relay.lock().await.orphans.contains(&child.hash()));
+        assert!(node.chain.read().await.mempool.is_empty());
+
+        node.accept_transaction(parent.clone(), None).await.unwrap();
+        assert!(node.relay.lock().await.orphans.is_empty());
+        assert!(node.chain.read().await.mempool_transaction(&child.hash()).is_some());
+
+        announcements(&mut sender_queue);
+        announcements(&mut other_queue);
+        node.trickle(sender).await;
+        node.trickle(other).await;
+        assert_eq!(announcements(&mut sender_queue), [parent.hash()]);
+        assert_eq!(announcements(&mut other_queue), [parent.hash(), child.hash()]);
+    }
+
+    /// A block on the node's tip holding a transaction in the node's mempool
+    /// and one that is not, returned with the second. The node knows the
+    /// output it spends, so it can connect the block
+    async fn next_block(node: &Node) -> (Block, Transaction) {
+        let mut chain = node.chain.write().await;
+        add_to_mempool(&mut chain);
+        let mut other = chain.clone();
+        let unknown = add_to_mempool(&mut other);
+        let spent = unknown.inputs()[0].prev_transaction_output_hash;
+        chain.utxos.insert(spent, other.utxos[&spent].clone());
+
+        let key = PrivateKey::new_key();
+        (other.block_template(key.public_key().into()).unwrap(), unknown)
+    }
+
+    #[tokio::test]
+    async fn compact_blocks_ask_for_the_missing_transactions() {
+        let node = node();
+        let addr: SocketAddr = "10.0.0.9:9633".parse().unwrap();
+        let other: SocketAddr = "10.0.0.10:9633".parse().unwrap();
+        let (mut queue, _) = connect(&node, addr, 8).await;
+        let (_other_queue, _) = connect(&node, other, 8).await;
+        while queue.try_recv().is_ok() {}
+
+        let height = node.chain.read().await.block_height();
+        let (block, unknown) = next_block(&node).await;
+        let hash = block.hash();
+        assert_eq!(block.transactions().len(), 3);
+        let missing = block.transactions().iter().position(|tx| tx.hash() == unknown.hash()).unwrap();
+
+        node.receive_compact_block(addr, CompactBlock::new(&block, 7)).await.unwrap();
+        match queue.try_recv() {
+            Ok(Message::GetBlockTxn(request)) => {
+                assert_eq!(request.block_hash, hash);
+                assert_eq!(request.indexes, [missing as u32]);
+            }
+            other => panic!("expected a getblocktxn, got {other:?}"),
+        }
+
+        // only the peer asked can answer
+        let reply = BlockTransactions { block_hash: hash, transactions: vec![unknown] };
+        node.receive_block_transactions(other, reply.clone()).await.unwrap();
+        assert_eq!(node.chain.read().await.block_height(), height);
+
+        node.receive_block_transactions(addr, reply).await.unwrap();
+        assert_eq!(node.chain.read().await.blocks.last().unwrap().hash(), hash);
+        assert!(node.relay.lock().await.pending_blocks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn compact_blocks_failing_to_rebuild_are_downloaded_whole() {
+        let node = node();
+        let addr: SocketAddr = "10.0.0.11:9633".parse().unwrap();
+        let (mut queue, _) = connect(&node, addr, 8).await;
+        while queue.try_recv().is_ok() {}
+
+        let height = node.chain.read().await.block_height();
+        let (block, unknown) = next_block(&node).await;
+        let hash = block.hash();
+        node.receive_compact_block(addr, CompactBlock::new(&block, 7)).await.unwrap();
+        assert!(matches!(queue.try_recv(), Ok(Message::GetBlockTxn(_))));
+
+        // the wrong number of transactions is the peer's fault
+        let too_many = BlockTransactions { block_hash: hash, transactions: vec![unknown.clone(), unknown] };
+        assert!(matches!(
+            node.receive_block_transactions(addr, too_many).await,
+            Err(BtcError::InvalidBlock)
+        ));
+
+        // one not matching the header is taken for a short ID collision
+        node.receive_compact_block(addr, CompactBlock::new(&block, 7)).await.unwrap();
+        assert!(matches!(queue.try_recv(), Ok(Message::GetBlockTxn(_))));
+        let wrong = add_to_mempool(&mut node.chain.read().await.clone());
+        let reply = BlockTransactions { block_hash: hash, transactions: vec![wrong] };
+        node.receive_block_transactions(addr, reply).await.unwrap();
+
+        match queue.try_recv() {
+            Ok(Message::GetData(inventory)) => assert_eq!(inventory, [Inventory::Block(hash)]),
+            other => panic!("expected a getdata, got {other:?}"),
+        }
+        assert_eq!(node.relay.lock().await.compact_stats.failures, 1);
+        assert_eq!(node.chain.read().await.block_height(), height);
+    }
+
+    /// The next block on the node's tip, with a coinbase paying `extra` more than it may
+    pub async fn overpaying_block(node: &Node, extra: u64) -> Block {
+        let key = PrivateKey::new_key();
+        let chain = node.chain.read().await;
+        let template = chain.block_template(key.public_key().into()).unwrap();
+        let reward = template.transactions()[0].outputs()[0].value;
+
+        let mut transactions =
+            vec![Transaction::new(vec![], vec![TransactionOutput::new(reward + extra, key.public_key().into())])];
+        Block::commit_witnesses(&mut transactions);
+        let mut header = template.header().clone();
+        header.merkle_root = MerkleRoot::calculate(&transactions);
+        assert!(header.mine(1_000_000));
+        Block::new(header, transactions)
+    }
+
+    #[tokio::test]
+    async fn blocks_overpaying_their_coinbase_ban_their_sender() {
+        let honest = node();
+        let node = node();
+        let addr: SocketAddr = "10.0.0.12:9633".parse().unwrap();
+        let (_queue, disconnect) = connect(&node, addr, 8).await;
+        let height = node.chain.read().await.block_height();
+
+        // the same block paying what it may connects
+        honest.receive_block(overpaying_block(&honest, 0).await, Some(addr)).await.unwrap();
+        assert_eq!(honest.chain.read().await.block_height(), height + 1);
+
+        node.receive_block(overpaying_block(&node, 1).await, Some(addr)).await.unwrap();
+        assert_eq!(node.chain.read().await.block_height(), height);
+        assert!(node.is_banned(addr.ip()).await);
+        assert!(notified(&disconnect).await);
+    }
+}
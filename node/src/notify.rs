@@ -0,0 +1,375 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time;
+use tracing::debug;
+
+/// Events kept for subscribers resuming after a disconnect
+pub const NOTIFY_HISTORY: usize = 10_000;
+/// Time a new subscriber has to send its subscribe line
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a transaction left the mempool
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictReason {
+    /// Mined in a connected block
+    Confirmed,
+    /// Spends an output a connected block spent, or one a disconnected block created
+    Conflict,
+    /// Paid the lowest fee rate of a full mempool
+    SizeLimit,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    BlockConnected {
+        hash: String,
+        height: u64,
+    },
+    BlockDisconnected {
+        hash: String,
+        height: u64,
+    },
+    /// The chain switched branches, published before the blocks disconnected and connected
+    Reorg {
+        old_tip: String,
+        new_tip: String,
+        fork_height: u64,
+        disconnected: usize,
+        connected: usize,
+    },
+    MempoolAccept {
+        txid: String,
+    },
+    MempoolEvict {
+        txid: String,
+        reason: EvictReason,
+    },
+}
+
+/// Event with its place in the stream
+#[derive(Serialize, Debug)]
+pub struct Notification {
+    pub sequence: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+struct History {
+    /// Sequence number of the next event
+    next: u64,
+    events: VecDeque<Arc<Notification>>,
+}
+
+/// Numbers the node's events and hands them to subscribers. Sequence numbers
+/// start over with every run of the node, the stream ID tells runs apart
+pub struct Notifier {
+    pub stream: String,
+    // taken briefly and last, events can be published while any node lock is held
+    history: Mutex<History>,
+    sender: broadcast::Sender<Arc<Notification>>,
+}
+
+/// First line of a subscriber. `from` is the sequence number of the last event
+/// it saw in `stream`, the events after it are sent again. Without it only new
+/// events are sent
+#[derive(Deserialize, Default)]
+struct Subscribe {
+    stream: Option<String>,
+    from: Option<u64>,
+}
+
+struct Subscription {
+    /// Sequence number of the last event published
+    last: u64,
+    replay: Vec<Arc<Notification>>,
+    receiver: broadcast::Receiver<Arc<Notification>>,
+}
+
+/// Lines sent to subscribers besides the events
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Control<'a> {
+    Hello { stream: &'a str, sequence: u64 },
+    Error { message: String },
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(NOTIFY_HISTORY);
+        Notifier {
+            stream: hex::encode(rand::random::<[u8; 8]>()),
+            history: Mutex::new(History {
+                next: 1,
+                events: VecDeque::new(),
+            }),
+            sender,
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        let mut history = self.history.lock().unwrap();
+        let notification = Arc::new(Notification {
+            sequence: history.next,
+            event,
+        });
+        history.next += 1;
+        history.events.push_back(notification.clone());
+        if history.events.len() > NOTIFY_HISTORY {
+            history.events.pop_front();
+        }
+        // nobody may be listening
+        let _ = self.sender.send(notification);
+    }
+
+    /// Events after `from` still kept, and a receiver of those published from
+    /// now on, so none is missed or sent twice. Fails when events after
+    /// `from` were already dropped, or `from` wasn't reached yet
+    fn subscribe(&self, from: Option<u64>) -> Result<Subscription> {
+        let history = self.history.lock().unwrap();
+        let last = history.next - 1;
+        let replay = match from {
+            None => Vec::new(),
+            Some(from) if from > last => {
+                bail!("event {from} wasn't published yet, the last is {last}")
+            }
+            Some(from) => {
+                let oldest = history
+                    .events
+                    .front()
+                    .map_or(history.next, |event| event.sequence);
+                if from + 1 < oldest {
+                    bail!("events after {from} are no longer kept, the oldest is {oldest}");
+                }
+                history
+                    .events
+                    .iter()
+                    .filter(|event| event.sequence > from)
+                    .cloned()
+                    .collect()
+            }
+        };
+        Ok(Subscription {
+            last,
+            replay,
+            receiver: self.sender.subscribe(),
+        })
+    }
+}
+
+/// Serves newline delimited JSON event streams to the subscribers connecting
+pub async fn serve(notifier: Arc<Notifier>, listener: TcpListener) -> Result<()> {
+    loop {
+        let (stream, addr) = listener
+            .accept()
+            .await
+            .context("failed to accept a subscriber")?;
+        let notifier = notifier.clone();
+        tokio::spawn(async move {
+            if let Err(e) = subscriber(&notifier, stream).await {
+                debug!(%addr, error = format!("{e:#}"), "subscriber left");
+            }
+        });
+    }
+}
+
+async fn send<T: Serialize>(writer: &mut (impl AsyncWriteExt + Unpin), line: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(line)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    Ok(())
+}
+
+async fn subscriber(notifier: &Notifier, stream: TcpStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let line = time::timeout(SUBSCRIBE_TIMEOUT, lines.next_line())
+        .await
+        .context("no subscribe line")??
+        .context("closed before subscribing")?;
+    let request: Subscribe = if line.trim().is_empty() {
+        Subscribe::default()
+    } else {
+        serde_json::from_str(&line).context("malformed subscribe line")?
+    };
+
+    // sequence numbers of another run mean nothing in this one
+    let from = match request.stream {
+        Some(stream) if stream != notifier.stream => {
+            let message = format!("stream {stream} ended, {} replaced it", notifier.stream);
+            send(&mut writer, &Control::Error { message }).await?;
+            return Ok(());
+        }
+        _ => request.from,
+    };
+
+    let Subscription {
+        last,
+        replay,
+        mut receiver,
+    } = match notifier.subscribe(from) {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            send(
+                &mut writer,
+                &Control::Error {
+                    message: e.to_string(),
+                },
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+    send(
+        &mut writer,
+        &Control::Hello {
+            stream: &notifier.stream,
+            sequence: last,
+        },
+    )
+    .await?;
+    for notification in replay {
+        send(&mut writer, &*notification).await?;
+    }
+
+    let mut sent = last;
+    loop {
+        tokio::select! {
+            notification = receiver.recv() => match notification {
+                Ok(notification) => {
+                    send(&mut writer, &*notification).await?;
+                    sent = notification.sequence;
+                }
+                Err(RecvError::Lagged(_)) => {
+                    let message = format!("too slow, resume from {sent}");
+                    send(&mut writer, &Control::Error { message }).await?;
+                    return Ok(());
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            // nothing more is expected from the subscriber, only whether it left
+            line = lines.next_line() => {
+                if line?.is_none() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accepted(n: u64) -> Event {
+        Event::MempoolAccept { txid: n.to_string() }
+    }
+
+    fn sequences(notifications: &[Arc<Notification>]) -> Vec<u64> {
+        notifications.iter().map(|notification| notification.sequence).collect()
+    }
+
+    #[test]
+    fn subscribers_resume_after_the_last_event_seen() {
+        let notifier = Notifier::new();
+        for n in 1..=5 {
+            notifier.publish(accepted(n));
+        }
+
+        let fresh = notifier.subscribe(None).unwrap();
+        assert_eq!(fresh.last, 5);
+        assert!(fresh.replay.is_empty());
+
+        let resumed = notifier.subscribe(Some(2)).unwrap();
+        assert_eq!(sequences(&resumed.replay), [3, 4, 5]);
+        assert!(notifier.subscribe(Some(5)).unwrap().replay.is_empty());
+        assert!(notifier.subscribe(Some(6)).is_err());
+    }
+
+    #[test]
+    fn events_published_after_subscribing_are_received_in_order() {
+        let notifier = Notifier::new();
+        notifier.publish(accepted(1));
+        let mut subscription = notifier.subscribe(Some(0)).unwrap();
+        notifier.publish(accepted(2));
+        notifier.publish(accepted(3));
+
+        // the replay and the receiver meet without a gap or an overlap
+        assert_eq!(sequences(&subscription.replay), [1]);
+        let received: Vec<u64> = (0..2).map(|_| subscription.receiver.try_recv().unwrap().sequence).collect();
+        assert_eq!(received, [2, 3]);
+        assert!(subscription.receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn only_recent_events_are_kept() {
+        let notifier = Notifier::new();
+        let published = NOTIFY_HISTORY as u64 + 10;
+        for n in 1..=published {
+            notifier.publish(accepted(n));
+        }
+
+        // event 11 is the oldest kept, so resuming after 10 still works
+        assert!(notifier.subscribe(Some(9)).is_err());
+        let resumed = notifier.subscribe(Some(10)).unwrap();
+        assert_eq!(resumed.replay.len(), NOTIFY_HISTORY);
+        assert_eq!(resumed.replay[0].sequence, 11);
+        assert_eq!(resumed.last, published);
+    }
+
+    #[test]
+    fn notifications_are_flat_json() {
+        let notification = Notification {
+            sequence: 7,
+            event: Event::MempoolEvict {
+                txid: "ab".to_string(),
+                reason: EvictReason::SizeLimit,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap(),
+            serde_json::json!({"sequence": 7, "type": "mempool_evict", "txid": "ab", "reason": "size_limit"})
+        );
+    }
+
+    /// Sends a subscribe line, returning the first `count` lines received
+    async fn subscribe(addr: std::net::SocketAddr, line: &str, count: usize) -> Vec<serde_json::Value> {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("{line}\n").as_bytes()).await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let mut replies = Vec::new();
+        for _ in 0..count {
+            let line = lines.next_line().await.unwrap().unwrap();
+            replies.push(serde_json::from_str(&line).unwrap());
+        }
+        replies
+    }
+
+    #[tokio::test]
+    async fn subscribers_of_another_stream_are_told_it_ended() {
+        let notifier = Arc::new(Notifier::new());
+        notifier.publish(accepted(1));
+        notifier.publish(accepted(2));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(notifier.clone(), listener));
+
+        let line = format!(r#"{{"stream": "{}", "from": 1}}"#, notifier.stream);
+        let replies = subscribe(addr, &line, 2).await;
+        assert_eq!(replies[0]["type"], "hello");
+        assert_eq!(replies[0]["sequence"], 2);
+        assert_eq!(replies[1]["sequence"], 2);
+
+        let replies = subscribe(addr, r#"{"stream": "0000", "from": 1}"#, 1).await;
+        assert_eq!(replies[0]["type"], "error");
+    }
+}
@@ -0,0 +1,233 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use lib::network::{Message, Version, PROTOCOL_VERSION};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::connman::Direction;
+use crate::node::Node;
+use crate::relay;
+
+/// Time the peer has to complete the version/verack handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// A ping is sent this often, the previous one has to be answered by then
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+/// Messages waiting to be written to a peer. Once they fill up it isn't
+/// reading, its answers wait and messages from the node disconnect it
+const SEND_QUEUE_SIZE: usize = 1_000;
+/// Time an answer waits for room in the send queue
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs a peer session until the connection closes or the peer breaks the protocol
+pub async fn run(node: Arc<Node>, stream: TcpStream, addr: SocketAddr, direction: Direction) {
+    let span = info_span!("peer", %addr, ?direction);
+    async {
+        match session(&node, stream, addr, direction).await {
+            Ok(()) => info!("disconnected"),
+            Err(e) => info!(error = format!("{e:#}"), "disconnected"),
+        }
+        node.disconnected(addr).await;
+    }
+    .instrument(span)
+    .await
+}
+
+async fn session(node: &Arc<Node>, stream: TcpStream, addr: SocketAddr, direction: Direction) -> Result<()> {
+    let magic = node.params.magic;
+    let (mut reader, mut writer) = stream.into_split();
+
+    let version = time::timeout(HANDSHAKE_TIMEOUT, handshake(node, &mut reader, &mut writer))
+        .await
+        .context("handshake timed out")??;
+    info!(user_agent = version.user_agent, height = version.height, "connected");
+
+    // the writer owns the socket's write half, everyone else goes through its queue
+    let (outgoing, mut queue) = mpsc::channel::<Message>(SEND_QUEUE_SIZE);
+    let writer_task = tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            if message.send(&mut writer, magic).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // receiving isn't cancel safe, so it runs on its own and hands over whole messages
+    let (incoming_sender, mut incoming) = mpsc::channel::<Result<Message>>(16);
+    let reader_task = tokio::spawn(async move {
+        loop {
+            let message = Message::receive(&mut reader, magic).await.map_err(Into::into);
+            let failed = message.is_err();
+            if incoming_sender.send(message).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let disconnect = Arc::new(Notify::new());
+    node.connected(addr, &version, direction, outgoing.clone(), disconnect.clone())
+        .await;
+
+    let result = serve(node, addr, direction, &outgoing, &mut incoming, &disconnect).await;
+    writer_task.abort();
+    reader_task.abort();
+    result
+}
+
+/// Exchanges version and verack messages, returning the peer's version
+async fn handshake(
+    node: &Node,
+    reader: &mut OwnedReadHalf,
+    writer: &mut OwnedWriteHalf,
+) -> Result<Version> {
+    let magic = node.params.magic;
+    Message::Version(node.version().await).send(writer, magic).await?;
+
+    let version = match Message::receive(reader, magic).await? {
+        Message::Version(version) => version,
+        message => bail!("expected version, got {}", message.command()),
+    };
+
+    if version.nonce == node.nonce {
+        bail!("connected to ourselves");
+    }
+    if version.genesis != node.params.genesis_block().hash() {
+        bail!("peer is on a chain with genesis {}", version.genesis);
+    }
+    if version.version < PROTOCOL_VERSION {
+        bail!("protocol version {} is too old", version.version);
+    }
+    Message::Verack.send(writer, magic).await?;
+
+    match Message::receive(reader, magic).await? {
+        Message::Verack => Ok(version),
+        message => bail!("expected verack, got {}", message.command()),
+    }
+}
+
+async fn serve(
+    node: &Arc<Node>,
+    addr: SocketAddr,
+    direction: Direction,
+    outgoing: &mpsc::Sender<Message>,
+    incoming: &mut mpsc::Receiver<Result<Message>>,
+    disconnect: &Notify,
+) -> Result<()> {
+    let mut ping = time::interval_at(time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut pending_ping: Option<u64> = None;
+    let trickle = time::sleep(relay::trickle_delay(direction));
+    tokio::pin!(trickle);
+
+    loop {
+        tokio::select! {
+            message = incoming.recv() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                handle(node, addr, outgoing, &mut pending_ping, message?).await?;
+            }
+            _ = disconnect.notified() => bail!("dropped by the node"),
+            () = &mut trickle => {
+                node.trickle(addr).await;
+                trickle.as_mut().reset(time::Instant::now() + relay::trickle_delay(direction));
+            }
+            _ = ping.tick() => {
+                if pending_ping.is_some() {
+                    bail!("ping timed out");
+                }
+                let nonce = rand::random();
+                pending_ping = Some(nonce);
+                send(outgoing, Message::Ping(nonce)).await?;
+            }
+        }
+    }
+}
+
+async fn handle(
+    node: &Arc<Node>,
+    addr: SocketAddr,
+    outgoing: &mpsc::Sender<Message>,
+    pending_ping: &mut Option<u64>,
+    message: Message,
+) -> Result<()> {
+    match message {
+        Message::Ping(nonce) => send(outgoing, Message::Pong(nonce)).await?,
+        Message::Pong(nonce) => {
+            if *pending_ping == Some(nonce) {
+                *pending_ping = None;
+            }
+        }
+        Message::Inv(inventory) => {
+            let missing = node.announced(addr, &inventory).await;
+            if !missing.is_empty() {
+                send(outgoing, Message::GetData(missing)).await?;
+            }
+        }
+        Message::GetData(inventory) => {
+            for message in node.lookup(&inventory).await {
+                send(outgoing, message).await?;
+            }
+        }
+        Message::NotFound(inventory) => node.not_found(addr, &inventory).await,
+        Message::GetAddr => send(outgoing, node.addresses().await).await?,
+        Message::Addr(addresses) => node.receive_addr(addr, addresses).await,
+        Message::GetHeaders(get_headers) => send(outgoing, node.headers(&get_headers).await).await?,
+        Message::Headers(headers) => {
+            if let Err(e) = node.receive_headers(addr, headers).await {
+                warn!(error = %e, "headers rejected");
+                node.misbehaving(addr, &e).await;
+            }
+        }
+        Message::Block(block) => {
+            let hash = block.hash();
+            if let Err(e) = node.receive_block(block, Some(addr)).await {
+                warn!(%hash, error = %e, "block rejected");
+                node.sent_invalid_block(addr, &e).await;
+            }
+        }
+        Message::SendCmpct(enabled) => node.send_compact(addr, enabled).await,
+        Message::CmpctBlock(compact) => {
+            let hash = compact.header.hash();
+            if let Err(e) = node.receive_compact_block(addr, compact).await {
+                warn!(%hash, error = %e, "compact block rejected");
+                node.sent_invalid_block(addr, &e).await;
+            }
+        }
+        Message::GetBlockTxn(request) => match node.block_transactions(&request).await {
+            Some(message) => send(outgoing, message).await?,
+            None => debug!(hash = %request.block_hash, "can't answer getblocktxn"),
+        },
+        Message::BlockTxn(transactions) => {
+            let hash = transactions.block_hash;
+            if let Err(e) = node.receive_block_transactions(addr, transactions).await {
+                warn!(%hash, error = %e, "block transactions rejected");
+                node.sent_invalid_block(addr, &e).await;
+            }
+        }
+        Message::Tx(transaction) => {
+            let txid = transaction.hash();
+            if let Err(e) = node.accept_transaction(transaction, Some(addr)).await {
+                debug!(%txid, error = %e, "transaction rejected");
+                node.misbehaving(addr, &e).await;
+            }
+        }
+        Message::Version(_) | Message::Verack => {
+            bail!("{} after the handshake", message.command())
+        }
+    }
+
+    Ok(())
+}
+
+async fn send(outgoing: &mpsc::Sender<Message>, message: Message) -> Result<()> {
+    time::timeout(SEND_TIMEOUT, outgoing.send(message))
+        .await
+        .context("send queue stayed full")?
+        .context("connection closed")
+}
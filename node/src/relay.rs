@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use lib::compact::{CompactBlock, CompactStats, PartialBlock};
+use lib::network::Inventory;
+use lib::orphan::OrphanPool;
+use lib::sha256::Hash;
+use rand::Rng;
+
+use crate::connman::Direction;
+
+/// Average wait between transaction announcements to a peer. Inbound peers
+/// wait longer, so a spy connecting to everyone learns less about where a
+/// transaction came from
+pub const INBOUND_TRICKLE_INTERVAL: Duration = Duration::from_secs(5);
+pub const OUTBOUND_TRICKLE_INTERVAL: Duration = Duration::from_secs(2);
+/// Inventory remembered per peer, the oldest is forgotten first
+pub const MAX_KNOWN_INVENTORY: usize = 50_000;
+/// A transaction not delivered by then may be requested from another peer announcing it
+pub const TX_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Random wait until the next announcements to a peer. The waits are
+/// exponentially distributed, so announcement times don't give away which
+/// peer heard of a transaction first
+pub fn trickle_delay(direction: Direction) -> Duration {
+    let mean = match direction {
+        Direction::Inbound => INBOUND_TRICKLE_INTERVAL,
+        Direction::Outbound | Direction::Manual => OUTBOUND_TRICKLE_INTERVAL,
+    };
+    let uniform: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.0);
+    mean.mul_f64(-uniform.ln())
+}
+
+/// Inventory a peer is known to have, because it announced or sent it to us
+/// or we announced it to the peer. Nothing in here is announced to it again
+#[derive(Default)]
+pub struct KnownInventory {
+    items: HashSet<Inventory>,
+    order: VecDeque<Inventory>,
+}
+
+impl KnownInventory {
+    pub fn insert(&mut self, item: Inventory) {
+        if !self.items.insert(item) {
+            return;
+        }
+        self.order.push_back(item);
+        if self.order.len() > MAX_KNOWN_INVENTORY {
+            let oldest = self.order.pop_front().unwrap();
+            self.items.remove(&oldest);
+        }
+    }
+
+    pub fn contains(&self, item: &Inventory) -> bool {
+        self.items.contains(item)
+    }
+}
+
+/// Compact block waiting for the transactions asked of the peer that sent it
+pub struct PendingBlock {
+    pub peer: SocketAddr,
+    pub compact: CompactBlock,
+    pub partial: PartialBlock,
+}
+
+/// Transaction and compact block relay state shared by the peers
+#[derive(Default)]
+pub struct Relay {
+    pub orphans: OrphanPool,
+    /// Transactions asked for, with the peer asked and when
+    requested: HashMap<Hash, (SocketAddr, Instant)>,
+    pub pending_blocks: HashMap<Hash, PendingBlock>,
+    pub compact_stats: CompactStats,
+}
+
+impl Relay {
+    /// Whether to ask the peer for a transaction it announced: not while
+    /// another peer's answer is pending, unless that timed out
+    pub fn request(&mut self, txid: Hash, peer: SocketAddr, now: Instant) -> bool {
+        if self.orphans.contains(&txid) {
+            return false;
+        }
+        if let Some((_, sent)) = self.requested.get(&txid) {
+            if now.duration_since(*sent) < TX_REQUEST_TIMEOUT {
+                return false;
+            }
+        }
+
+        self.requested.insert(txid, (peer, now));
+        true
+    }
+
+    /// The transaction arrived or the peer doesn't have it, another announcement may be followed
+    pub fn received(&mut self, txid: &Hash) {
+        self.requested.remove(txid);
+    }
+
+    /// Forgets the peer's pending requests and the orphans it sent
+    pub fn remove_peer(&mut self, peer: SocketAddr) {
+        self.requested.retain(|_, (asked, _)| *asked != peer);
+        self.pending_blocks.retain(|_, pending| pending.peer != peer);
+        self.orphans.forget_peer(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(n: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, n], 9633))
+    }
+
+    #[test]
+    fn known_inventory_forgets_the_oldest() {
+        let mut known = KnownInventory::default();
+        let item = |i: usize| Inventory::Transaction(Hash::hash(&i));
+
+        for i in 0..MAX_KNOWN_INVENTORY {
+            known.insert(item(i));
+        }
+        // inserting one already known doesn't push anything out
+        known.insert(item(0));
+        assert!(known.contains(&item(0)));
+
+        known.insert(item(MAX_KNOWN_INVENTORY));
+        assert!(!known.contains(&item(0)));
+        assert!(known.contains(&item(1)));
+        assert!(known.contains(&item(MAX_KNOWN_INVENTORY)));
+        assert_eq!(known.items.len(), MAX_KNOWN_INVENTORY);
+    }
+
+    #[test]
+    fn transactions_are_requested_from_one_peer_at_a_time() {
+        let mut relay = Relay::default();
+        let txid = Hash::hash(&1);
+        let now = Instant::now();
+
+        assert!(relay.request(txid, peer(1), now));
+        assert!(!relay.request(txid, peer(2), now));
+        assert!(!relay.request(txid, peer(2), now + TX_REQUEST_TIMEOUT / 2));
+        // the first peer took too long
+        assert!(relay.request(txid, peer(2), now + TX_REQUEST_TIMEOUT));
+
+        relay.received(&txid);
+        assert!(relay.request(txid, peer(3), now));
+
+        // a disconnecting peer's requests can go to the others right away
+        relay.remove_peer(peer(3));
+        assert!(relay.request(txid, peer(1), now));
+    }
+
+    #[test]
+    fn inbound_peers_trickle_slower() {
+        let mean = |direction| {
+            let total: Duration = (0..10_000).map(|_| trickle_delay(direction)).sum();
+            total / 10_000
+        };
+        let close = |mean: Duration, expected: Duration| {
+            mean > expected.mul_f64(0.9) && mean < expected.mul_f64(1.1)
+        };
+
+        assert!(close(mean(Direction::Inbound), INBOUND_TRICKLE_INTERVAL));
+        assert!(close(mean(Direction::Outbound), OUTBOUND_TRICKLE_INTERVAL));
+        assert!(close(mean(Direction::Manual), OUTBOUND_TRICKLE_INTERVAL));
+    }
+}
@@ -0,0 +1,695 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use lib::address::Address;
+use lib::error::BtcError;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain, LockingCondition, Transaction};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tracing::{debug, debug_span, Instrument};
+
+use crate::node::Node;
+
+/// User name of the credentials written to the cookie file
+pub const COOKIE_USER: &str = "__cookie__";
+/// Largest request accepted, enough for a hex encoded block of the maximum size
+const MAX_REQUEST_SIZE: usize = 4 * lib::MAX_BLOCK_SIZE;
+
+// error codes, the same as bitcoind's so existing clients understand them
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const MISC_ERROR: i64 = -1;
+const NOT_FOUND: i64 = -5;
+const DESERIALIZATION_ERROR: i64 = -22;
+const VERIFY_ERROR: i64 = -25;
+const VERIFY_REJECTED: i64 = -26;
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+type RpcResult = Result<Value, RpcError>;
+
+/// Writes fresh random credentials to the cookie file, readable by the owner
+/// only, for clients on this machine. Returns them as `user:password`
+pub fn write_cookie(path: &Path) -> Result<String> {
+    let credentials = format!("{COOKIE_USER}:{}", hex::encode(rand::random::<[u8; 32]>()));
+
+    // a stale cookie is removed first, the mode only applies to files being created
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("failed to remove {}", path.display()));
+        }
+        _ => {}
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(credentials.as_bytes()))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(credentials)
+}
+
+struct Server {
+    node: Arc<Node>,
+    /// Expected `user:password` of HTTP basic authentication
+    credentials: String,
+}
+
+/// Answers JSON-RPC requests POSTed to `/`, single or batched, from clients
+/// authenticating with `credentials`
+pub async fn serve(node: Arc<Node>, listener: TcpListener, credentials: String) -> Result<()> {
+    let server = Arc::new(Server { node, credentials });
+    let app = Router::new()
+        .route("/", post(handle))
+        .layer(DefaultBodyLimit::max(MAX_REQUEST_SIZE))
+        .with_state(server);
+    axum::serve(listener, app)
+        .await
+        .context("RPC server failed")
+}
+
+async fn handle(State(server): State<Arc<Server>>, headers: HeaderMap, body: Bytes) -> Response {
+    if !server.authorized(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"jsonrpc\"")],
+        )
+            .into_response();
+    }
+
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return Json(reply(
+                Value::Null,
+                Err(RpcError::new(PARSE_ERROR, e.to_string())),
+            ))
+            .into_response()
+        }
+    };
+
+    match request {
+        Value::Array(batch) if !batch.is_empty() => {
+            let mut replies = Vec::with_capacity(batch.len());
+            for request in batch {
+                replies.push(server.call(request).await);
+            }
+            Json(Value::Array(replies)).into_response()
+        }
+        request => Json(server.call(request).await).into_response(),
+    }
+}
+
+/// Response to a request, with both `result` and `error` like bitcoind's
+fn reply(id: Value, result: RpcResult) -> Value {
+    match result {
+        Ok(result) => json!({ "result": result, "error": null, "id": id }),
+        Err(error) => json!({ "result": null, "error": error, "id": id }),
+    }
+}
+
+/// Positional parameter `index`, None when missing or null
+fn param<T: DeserializeOwned>(
+    params: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<Option<T>, RpcError> {
+    match params.get(index) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => serde_json::from_value(value.clone())
+            .map(Some)
+            .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid {name}: {e}"))),
+    }
+}
+
+fn required<T: DeserializeOwned>(
+    params: &[Value],
+    index: usize,
+    name: &str,
+) -> Result<T, RpcError> {
+    param(params, index, name)?
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("missing {name}")))
+}
+
+fn hash_param(params: &[Value], index: usize, name: &str) -> Result<Hash, RpcError> {
+    let hex: String = required(params, index, name)?;
+    hex.parse()
+        .map_err(|_| RpcError::new(INVALID_PARAMS, format!("{name} must be a hex hash")))
+}
+
+/// Hex of the CBOR encoding, the form blocks and transactions take in and out of the RPC
+fn encode<T: Serialize>(value: &T) -> String {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).expect("serializing to memory can't fail");
+    hex::encode(bytes)
+}
+
+fn decode<T: DeserializeOwned>(hex: &str, what: &str) -> Result<T, RpcError> {
+    let bytes = hex::decode(hex)
+        .map_err(|_| RpcError::new(DESERIALIZATION_ERROR, format!("{what} decode failed")))?;
+    ciborium::from_reader(bytes.as_slice())
+        .map_err(|_| RpcError::new(DESERIALIZATION_ERROR, format!("{what} decode failed")))
+}
+
+fn rejected(code: i64, e: BtcError) -> RpcError {
+    RpcError::new(code, e.to_string())
+}
+
+fn header_json(chain: &Blockchain, header: &BlockHeader, height: u64) -> Value {
+    let hash = header.hash();
+    let tip = chain.block_height() - 1;
+    json!({
+        "hash": hash.to_string(),
+        "height": height,
+        "confirmations": tip - height + 1,
+        "version": header.version,
+        "time": header.timestamp.timestamp(),
+        "mediantime": chain.median_time_past(height + 1),
+        "nonce": header.nonce,
+        "target": format!("{:x}", header.target),
+        "merkleroot": header.merkle_root.to_string(),
+        "previousblockhash": (height > 0).then(|| header.prev_block_hash.to_string()),
+        "nextblockhash": chain.blocks.get(height as usize + 1).map(|next| next.hash().to_string()),
+    })
+}
+
+fn transaction_json(transaction: &Transaction) -> Value {
+    json!({
+        "txid": transaction.hash().to_string(),
+        "wtxid": transaction.wtxid().to_string(),
+        "version": transaction.version(),
+        "locktime": transaction.lock_time(),
+        "size": transaction.total_size(),
+        "vsize": transaction.virtual_size(),
+        "weight": transaction.weight(),
+        "inputs": transaction
+            .inputs()
+            .iter()
+            .map(|input| input.prev_transaction_output_hash.to_string())
+            .collect::<Vec<_>>(),
+        "outputs": transaction
+            .outputs()
+            .iter()
+            .map(|output| json!({ "hash": output.hash().to_string(), "value": output.value }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+impl Server {
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        let Some(credentials) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        else {
+            return false;
+        };
+
+        // compared in full every time, the time taken doesn't tell how much matched
+        let expected = self.credentials.as_bytes();
+        credentials.len() == expected.len()
+            && credentials
+                .iter()
+                .zip(expected)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+
+    async fn call(&self, request: Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return reply(id, Err(RpcError::new(INVALID_REQUEST, "missing method")));
+        };
+        let params = match request.get("params") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(params)) => params.clone(),
+            Some(_) => {
+                return reply(
+                    id,
+                    Err(RpcError::new(INVALID_REQUEST, "params must be an array")),
+                );
+            }
+        };
+
+        let result = async {
+            let result = match method {
+                "getblockchaininfo" => self.get_blockchain_info().await,
+                "getbestblockhash" => self.get_best_block_hash().await,
+                "getblockhash" => self.get_block_hash(&params).await,
+                "getblock" => self.get_block(&params).await,
+                "getblockheader" => self.get_block_header(&params).await,
+                "getrawtransaction" => self.get_raw_transaction(&params).await,
+                "sendrawtransaction" => self.send_raw_transaction(&params).await,
+                "getmempoolinfo" => self.get_mempool_info().await,
+                "getrawmempool" => self.get_raw_mempool().await,
+                "getutxo" => self.get_utxo(&params).await,
+                "getblocktemplate" => self.get_block_template(&params).await,
+                "submitblock" => self.submit_block(&params).await,
+                _ => Err(RpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("unknown method {method}"),
+                )),
+            };
+            if let Err(e) = &result {
+                debug!(code = e.code, error = e.message, "request failed");
+            }
+            result
+        }
+        .instrument(debug_span!("rpc", method))
+        .await;
+        reply(id, result)
+    }
+
+    async fn get_blockchain_info(&self) -> RpcResult {
+        let sync = self.node.sync.lock().await;
+        let chain = self.node.chain.read().await;
+        let height = chain.block_height() - 1;
+        let tip = chain.blocks[height as usize].header();
+        Ok(json!({
+            "chain": self.node.params.network.to_string(),
+            "blocks": height,
+            "headers": sync.headers.best_height(),
+            "bestblockhash": tip.hash().to_string(),
+            "target": format!("{:x}", tip.target),
+            "mediantime": chain.median_time_past(height + 1),
+            "initialblockdownload": !sync.is_synced(&chain),
+        }))
+    }
+
+    async fn get_best_block_hash(&self) -> RpcResult {
+        let chain = self.node.chain.read().await;
+        Ok(json!(chain.blocks.last().map(|tip| tip.hash().to_string())))
+    }
+
+    async fn get_block_hash(&self, params: &[Value]) -> RpcResult {
+        let height: u64 = required(params, 0, "height")?;
+        let chain = self.node.chain.read().await;
+        chain
+            .blocks
+            .get(height as usize)
+            .map(|block| json!(block.hash().to_string()))
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "block height out of range"))
+    }
+
+    /// Block as hex, or as JSON with its txids when `verbose` (the default)
+    async fn get_block(&self, params: &[Value]) -> RpcResult {
+        let hash = hash_param(params, 0, "blockhash")?;
+        let verbose = param(params, 1, "verbose")?.unwrap_or(true);
+        let chain = self.node.chain.read().await;
+        let (Some(block), Some(height)) = (chain.block_by_hash(&hash), chain.height_of(&hash))
+        else {
+            return Err(RpcError::new(NOT_FOUND, "block not found"));
+        };
+        if block.is_pruned() {
+            return Err(RpcError::new(
+                MISC_ERROR,
+                "block not available (pruned data)",
+            ));
+        }
+        if !verbose {
+            return Ok(json!(encode(block)));
+        }
+
+        let mut result = header_json(&chain, block.header(), height);
+        result["size"] = json!(block.size());
+        result["weight"] = json!(block.weight());
+        result["tx"] = json!(block
+            .transactions()
+            .iter()
+            .map(|transaction| transaction.hash().to_string())
+            .collect::<Vec<_>>());
+        Ok(result)
+    }
+
+    async fn get_block_header(&self, params: &[Value]) -> RpcResult {
+        let hash = hash_param(params, 0, "blockhash")?;
+        let verbose = param(params, 1, "verbose")?.unwrap_or(true);
+        let chain = self.node.chain.read().await;
+        let (Some(block), Some(height)) = (chain.block_by_hash(&hash), chain.height_of(&hash))
+        else {
+            return Err(RpcError::new(NOT_FOUND, "block not found"));
+        };
+
+        if verbose {
+            Ok(header_json(&chain, block.header(), height))
+        } else {
+            Ok(json!(encode(block.header())))
+        }
+    }
+
+    /// A mempool transaction or one in a block. Without a transaction index,
+    /// blocks are searched from the tip down
+    async fn get_raw_transaction(&self, params: &[Value]) -> RpcResult {
+        let txid = hash_param(params, 0, "txid")?;
+        let verbose = param(params, 1, "verbose")?.unwrap_or(false);
+        let chain = self.node.chain.read().await;
+
+        let found = match chain.mempool_transaction(&txid) {
+            Some(transaction) => Some((transaction, None)),
+            None => chain
+                .blocks
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(height, block)| {
+                    block
+                        .transactions()
+                        .iter()
+                        .find(|transaction| transaction.hash() == txid)
+                        .map(|transaction| (transaction, Some((block.hash(), height as u64))))
+                }),
+        };
+        let Some((transaction, block)) = found else {
+            return Err(RpcError::new(
+                NOT_FOUND,
+                "no such mempool or blockchain transaction",
+            ));
+        };
+        if !verbose {
+            return Ok(json!(encode(transaction)));
+        }
+
+        let mut result = transaction_json(transaction);
+        result["hex"] = json!(encode(transaction));
+        if let Some((hash, height)) = block {
+            result["blockhash"] = json!(hash.to_string());
+            result["confirmations"] = json!(chain.block_height() - height);
+        }
+        Ok(result)
+    }
+
+    /// Submits a transaction to the mempool and relays it, returns its txid
+    async fn send_raw_transaction(&self, params: &[Value]) -> RpcResult {
+        let hex: String = required(params, 0, "hexstring")?;
+        let transaction: Transaction = decode(&hex, "TX")?;
+        let txid = transaction.hash();
+
+        self.node
+            .accept_transaction(transaction, None)
+            .await
+            .map_err(|e| rejected(VERIFY_REJECTED, e))?;
+
+        // accepted as an orphan isn't accepted, the sender should know its inputs are missing
+        if self
+            .node
+            .chain
+            .read()
+            .await
+            .mempool_transaction(&txid)
+            .is_none()
+        {
+            return Err(rejected(VERIFY_ERROR, BtcError::MissingInputs));
+        }
+        Ok(json!(txid.to_string()))
+    }
+
+    async fn get_mempool_info(&self) -> RpcResult {
+        let chain = self.node.chain.read().await;
+        let relay = self.node.relay.lock().await;
+        Ok(json!({
+            "size": chain.mempool.len(),
+            "bytes": chain.mempool.iter().map(Transaction::total_size).sum::<usize>(),
+            "vsize": chain.mempool.iter().map(Transaction::virtual_size).sum::<usize>(),
+            "orphans": relay.orphans.len(),
+        }))
+    }
+
+    async fn get_raw_mempool(&self) -> RpcResult {
+        let chain = self.node.chain.read().await;
+        Ok(json!(chain
+            .mempool
+            .iter()
+            .map(|transaction| transaction.hash().to_string())
+            .collect::<Vec<_>>()))
+    }
+
+    /// An unspent output by its hash, null when spent or unknown
+    async fn get_utxo(&self, params: &[Value]) -> RpcResult {
+        let hash = hash_param(params, 0, "hash")?;
+        let chain = self.node.chain.read().await;
+        let Some(output) = chain.utxos.get(&hash) else {
+            return Ok(Value::Null);
+        };
+
+        let height = chain.utxo_heights.get(&hash).copied();
+        let lock = match &output.lock {
+            LockingCondition::PublicKey(pubkey) => json!({
+                "type": "pubkey",
+                "address": Address::new(pubkey.clone(), &self.node.params).to_string(),
+            }),
+            LockingCondition::Multisig { threshold, pubkeys } => json!({
+                "type": "multisig",
+                "threshold": threshold,
+                "addresses": pubkeys
+                    .iter()
+                    .map(|pubkey| Address::new(pubkey.clone(), &self.node.params).to_string())
+                    .collect::<Vec<_>>(),
+            }),
+            LockingCondition::Script(_) => json!({ "type": "script" }),
+        };
+        Ok(json!({
+            "value": output.value,
+            "height": height,
+            "confirmations": height.map(|height| chain.block_height() - height),
+            "lock": lock,
+        }))
+    }
+
+    /// Block paying `address` that only lacks a nonce, for miners to solve
+    /// and hand back through submitblock
+    async fn get_block_template(&self, params: &[Value]) -> RpcResult {
+        let address: String = required(params, 0, "address")?;
+        let address = Address::parse(&address, &self.node.params).map_err(|_| {
+            RpcError::new(
+                INVALID_PARAMS,
+                format!("not a {} address", self.node.params.network),
+            )
+        })?;
+
+        let chain = self.node.chain.read().await;
+        let template = chain
+            .block_template(address.lock())
+            .map_err(|e| RpcError::new(VERIFY_ERROR, e.to_string()))?;
+        let header = template.header();
+        Ok(json!({
+            "hex": encode(&template),
+            "height": chain.block_height(),
+            "previousblockhash": header.prev_block_hash.to_string(),
+            "target": format!("{:x}", header.target),
+            "transactions": template.transactions().len(),
+            "coinbasevalue": template.transactions()[0].outputs()[0].value,
+        }))
+    }
+
+    /// Takes a mined block like one from a peer. Null once it is connected,
+    /// "duplicate" for a known one and "inconclusive" when it didn't extend
+    /// the best chain, such as one whose parent is unknown
+    async fn submit_block(&self, params: &[Value]) -> RpcResult {
+        let hex: String = required(params, 0, "hexdata")?;
+        let block: Block = decode(&hex, "Block")?;
+        let hash = block.hash();
+        if self.node.chain.read().await.height_of(&hash).is_some() {
+            return Ok(json!("duplicate"));
+        }
+
+        self.node
+            .receive_block(block, None)
+            .await
+            .map_err(|e| rejected(VERIFY_ERROR, e))?;
+        if self.node.chain.read().await.height_of(&hash).is_some() {
+            Ok(Value::Null)
+        } else {
+            Ok(json!("inconclusive"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::node::tests::{node, overpaying_block};
+
+    const CREDENTIALS: &str = "alice:secret";
+
+    fn server() -> Arc<Server> {
+        Arc::new(Server {
+            node: Arc::new(node()),
+            credentials: CREDENTIALS.to_string(),
+        })
+    }
+
+    fn basic_auth(credentials: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Basic {}", STANDARD.encode(credentials));
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&value).unwrap(),
+        );
+        headers
+    }
+
+    async fn post(server: &Arc<Server>, headers: HeaderMap, body: &str) -> (StatusCode, Value) {
+        let response = handle(
+            State(server.clone()),
+            headers,
+            Bytes::from(body.to_string()),
+        )
+        .await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let value = if body.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&body).unwrap()
+        };
+        (status, value)
+    }
+
+    #[tokio::test]
+    async fn requests_need_credentials() {
+        let server = server();
+        let request = r#"{"method":"getbestblockhash","id":1}"#;
+
+        let response = handle(
+            State(server.clone()),
+            HeaderMap::new(),
+            Bytes::from(request),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        for wrong in ["alice:secreT", "alice:secret2", "alice:", "bob:secret", ""] {
+            let (status, _) = post(&server, basic_auth(wrong), request).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{wrong:?}");
+        }
+
+        let mut not_basic = HeaderMap::new();
+        not_basic.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer alice:secret"),
+        );
+        assert_eq!(
+            post(&server, not_basic, request).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let (status, reply) = post(&server, basic_auth(CREDENTIALS), request).await;
+        assert_eq!(status, StatusCode::OK);
+        let genesis = json!(server.node.params.genesis_block().hash().to_string());
+        assert_eq!(reply, json!({ "result": genesis, "error": null, "id": 1 }));
+    }
+
+    #[tokio::test]
+    async fn batches_get_a_reply_per_request() {
+        let server = server();
+        let batch = r#"[
+            {"method":"getbestblockhash","id":"a"},
+            {"method":"nosuchmethod","id":"b"},
+            {"params":[],"id":"c"},
+            {"method":"getblockhash","params":[0],"id":"d"}
+        ]"#;
+
+        let (status, reply) = post(&server, basic_auth(CREDENTIALS), batch).await;
+        assert_eq!(status, StatusCode::OK);
+        let replies = reply.as_array().unwrap();
+        assert_eq!(replies.len(), 4);
+
+        let ids: Vec<&Value> = replies.iter().map(|reply| &reply["id"]).collect();
+        assert_eq!(ids, [&json!("a"), &json!("b"), &json!("c"), &json!("d")]);
+        let genesis = json!(server.node.params.genesis_block().hash().to_string());
+        assert_eq!(replies[0]["result"], genesis);
+        assert_eq!(replies[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(replies[2]["error"]["code"], INVALID_REQUEST);
+        assert_eq!(replies[3]["result"], genesis);
+
+        // an empty batch is not a request
+        let (_, reply) = post(&server, basic_auth(CREDENTIALS), "[]").await;
+        assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+
+        let (_, reply) = post(&server, basic_auth(CREDENTIALS), "[{").await;
+        assert_eq!(reply["error"]["code"], PARSE_ERROR);
+        assert_eq!(reply["id"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn submitted_blocks_are_rejected_with_the_reason() {
+        let server = server();
+        let submit = |block: &Block| {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(block, &mut bytes).unwrap();
+            format!(r#"{{"method":"submitblock","params":["{}"],"id":1}}"#, hex::encode(bytes))
+        };
+
+        let invalid = overpaying_block(&server.node, 1).await;
+        let (_, reply) = post(&server, basic_auth(CREDENTIALS), &submit(&invalid)).await;
+        assert_eq!(reply["error"]["code"], VERIFY_ERROR);
+        assert_eq!(reply["error"]["message"], BtcError::InvalidTransaction.to_string());
+
+        let valid = overpaying_block(&server.node, 0).await;
+        let (_, reply) = post(&server, basic_auth(CREDENTIALS), &submit(&valid)).await;
+        assert_eq!(reply, json!({ "result": null, "error": null, "id": 1 }));
+        let (_, reply) = post(&server, basic_auth(CREDENTIALS), &submit(&valid)).await;
+        assert_eq!(reply["result"], "duplicate");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cookies_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("cookie-{:016x}", rand::random::<u64>()));
+        // left readable by everyone, as by an older version
+        std::fs::write(&path, "stale").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let credentials = write_cookie(&path).unwrap();
+        assert!(credentials.starts_with(&format!("{COOKIE_USER}:")));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), credentials);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert_ne!(write_cookie(&path).unwrap(), credentials);
+        std::fs::remove_file(path).unwrap();
+    }
+}
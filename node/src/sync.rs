@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use lib::error::BtcError;
+use lib::headers::{HeaderChain, MAX_HEADERS_RESULTS};
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Blockchain};
+use tracing::warn;
+
+/// Blocks past the last connected one that may be downloading at once
+pub const BLOCK_DOWNLOAD_WINDOW: u64 = 128;
+/// Block requests a single peer may have outstanding
+pub const MAX_BLOCKS_IN_FLIGHT: usize = 16;
+/// A block not delivered by then is requested again, from another peer if there is one
+pub const BLOCK_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A block request waiting for its answer
+struct Request {
+    peer: SocketAddr,
+    sent: Instant,
+}
+
+/// What connecting the downloaded blocks did to the chain
+#[derive(Default)]
+pub struct Progress {
+    /// Blocks connected, in order
+    pub connected: Vec<Hash>,
+    /// Blocks taken off the tip to switch to a branch with more work
+    pub disconnected: Vec<Hash>,
+    /// Blocks that failed validation, with the peer that sent them. Unless
+    /// only the body was wrong, their headers are dropped with their descendants
+    pub invalid: Vec<(Hash, BtcError, Option<SocketAddr>)>,
+}
+
+/// Headers-first download: headers are validated into a tree first, then the
+/// blocks of the best header chain are fetched from several peers at once
+/// within a sliding window and connected in order
+pub struct Sync {
+    pub headers: HeaderChain,
+    /// Best height each peer is known to have
+    peer_heights: HashMap<SocketAddr, u64>,
+    in_flight: HashMap<Hash, Request>,
+    /// Peer whose request for a block failed, asked last when it is requested again
+    stalled: HashMap<Hash, SocketAddr>,
+    /// Downloaded blocks waiting for their parent to be connected, with the peer they came from
+    received: HashMap<Hash, (Block, Option<SocketAddr>)>,
+}
+
+impl Sync {
+    pub fn new(chain: &Blockchain) -> Self {
+        Sync {
+            headers: HeaderChain::new(chain),
+            peer_heights: HashMap::new(),
+            in_flight: HashMap::new(),
+            stalled: HashMap::new(),
+            received: HashMap::new(),
+        }
+    }
+
+    /// Whether every block of the best header chain is connected
+    pub fn is_synced(&self, chain: &Blockchain) -> bool {
+        self.fork_height(chain) == self.headers.best_height()
+    }
+
+    pub fn add_peer(&mut self, peer: SocketAddr, height: u64) {
+        self.peer_heights.insert(peer, height);
+    }
+
+    /// Forgets the peer, its outstanding requests go to the others
+    pub fn remove_peer(&mut self, peer: SocketAddr) {
+        self.peer_heights.remove(&peer);
+        self.in_flight.retain(|_, request| request.peer != peer);
+    }
+
+    /// Notes that the peer has the block, if its header is known
+    pub fn peer_has(&mut self, peer: SocketAddr, hash: &Hash) {
+        if let Some(entry) = self.headers.get(hash) {
+            if let Some(height) = self.peer_heights.get_mut(&peer) {
+                *height = (*height).max(entry.height);
+            }
+        }
+    }
+
+    /// Validates the headers a peer sent into the tree. Returns whether the
+    /// message was full, so the peer may have more
+    pub fn add_headers(&mut self, peer: SocketAddr, headers: Vec<BlockHeader>) -> Result<bool, BtcError> {
+        let full = headers.len() == MAX_HEADERS_RESULTS;
+
+        for header in headers {
+            let hash = header.hash();
+            self.headers.add_header(header)?;
+            self.peer_has(peer, &hash);
+        }
+
+        Ok(full)
+    }
+
+    /// Takes a downloaded block, adding its header first when it came unannounced
+    pub fn add_block(&mut self, block: Block, from: Option<SocketAddr>) -> Result<(), BtcError> {
+        let hash = block.hash();
+        if self.in_flight.get(&hash).is_some_and(|request| Some(request.peer) == from) {
+            self.in_flight.remove(&hash);
+        }
+        self.stalled.remove(&hash);
+
+        self.headers.add_header(block.header().clone())?;
+        if let Some(peer) = from {
+            self.peer_has(peer, &hash);
+        }
+
+        self.received.insert(hash, (block, from));
+        Ok(())
+    }
+
+    /// Counts a block the peer is completing from a compact block as requested
+    /// from it, so it isn't downloaded in full meanwhile unless that times out
+    pub fn expect(&mut self, peer: SocketAddr, hash: Hash) {
+        if !self.received.contains_key(&hash) {
+            self.in_flight.insert(hash, Request { peer, sent: Instant::now() });
+        }
+    }
+
+    /// The peer doesn't have these blocks, they are requested elsewhere
+    pub fn not_found(&mut self, peer: SocketAddr, hashes: &[Hash]) {
+        for hash in hashes {
+            if self.in_flight.get(hash).is_some_and(|request| request.peer == peer) {
+                self.in_flight.remove(hash);
+                self.stalled.insert(*hash, peer);
+            }
+        }
+    }
+
+    /// Drops requests unanswered for BLOCK_DOWNLOAD_TIMEOUT so they are scheduled
+    /// again, returning the peers that stalled
+    pub fn expire(&mut self, now: Instant) -> Vec<SocketAddr> {
+        let expired: Vec<(Hash, SocketAddr)> = self
+            .in_flight
+            .iter()
+            .filter(|(_, request)| now.duration_since(request.sent) >= BLOCK_DOWNLOAD_TIMEOUT)
+            .map(|(hash, request)| (*hash, request.peer))
+            .collect();
+
+        let mut peers = Vec::new();
+        for (hash, peer) in expired {
+            self.in_flight.remove(&hash);
+            self.stalled.insert(hash, peer);
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+        }
+
+        peers
+    }
+
+    /// Assigns the blocks of the download window that are neither downloaded nor
+    /// requested to the least busy peers having them. Returns the requests to send
+    pub fn schedule(&mut self, chain: &Blockchain) -> Vec<(SocketAddr, Vec<Hash>)> {
+        let fork = self.fork_height(chain);
+        let end = (fork + BLOCK_DOWNLOAD_WINDOW).min(self.headers.best_height());
+
+        let mut load: HashMap<SocketAddr, usize> =
+            self.peer_heights.keys().map(|peer| (*peer, 0)).collect();
+        for request in self.in_flight.values() {
+            *load.entry(request.peer).or_default() += 1;
+        }
+
+        let now = Instant::now();
+        let mut requests: HashMap<SocketAddr, Vec<Hash>> = HashMap::new();
+
+        for height in fork + 1..=end {
+            let hash = self.headers.hash_at(height).expect("heights up to the best are known");
+            if self.received.contains_key(&hash) || self.in_flight.contains_key(&hash) {
+                continue;
+            }
+
+            let stalled = self.stalled.get(&hash).copied();
+            let peer = self
+                .peer_heights
+                .iter()
+                .filter(|(peer, peer_height)| **peer_height >= height && load[*peer] < MAX_BLOCKS_IN_FLIGHT)
+                .min_by_key(|(peer, _)| (Some(**peer) == stalled, load[*peer]))
+                .map(|(peer, _)| *peer);
+
+            let Some(peer) = peer else {
+                continue;
+            };
+
+            *load.get_mut(&peer).unwrap() += 1;
+            self.in_flight.insert(hash, Request { peer, sent: now });
+            requests.entry(peer).or_default().push(hash);
+        }
+
+        requests.into_iter().collect()
+    }
+
+    /// Connects the downloaded blocks that continue the chain along the best
+    /// header chain. When that branches off below the tip, the chain is rolled
+    /// back to the fork first; the blocks taken off are kept in case the new
+    /// branch turns out invalid and the old one is best again
+    pub fn connect(&mut self, chain: &mut Blockchain) -> Progress {
+        let mut progress = Progress::default();
+
+        loop {
+            let fork = self.fork_height(chain);
+            let Some(hash) = self.headers.hash_at(fork + 1) else {
+                break;
+            };
+            let Some((block, from)) = self.received.remove(&hash) else {
+                break;
+            };
+            // the body doesn't match its header, the right one can still be
+            // downloaded and nothing is disconnected for this one
+            if let Err(e) = block.verify_commitments() {
+                progress.invalid.push((hash, e, from));
+                continue;
+            }
+
+            while chain.block_height() - 1 > fork {
+                let Ok(old) = chain.disconnect_tip() else {
+                    // a pruned chain can't go back below the blocks it keeps
+                    warn!(%hash, fork, "can't reorganize, the fork is below the pruned blocks");
+                    self.received.insert(hash, (block, from));
+                    return progress;
+                };
+                progress.disconnected.push(old.hash());
+                self.received.insert(old.hash(), (old, None));
+            }
+
+            match chain.add_block(block) {
+                Ok(()) => progress.connected.push(hash),
+                Err(e) => {
+                    self.headers.invalidate(&hash);
+                    progress.invalid.push((hash, e, from));
+                }
+            }
+        }
+
+        // what is left off the best chain won't be connected, unless downloaded again
+        let fork = self.fork_height(chain);
+        let headers = &self.headers;
+        self.received.retain(|hash, _| {
+            headers.is_on_best_chain(hash) && headers.get(hash).is_some_and(|entry| entry.height > fork)
+        });
+
+        progress
+    }
+
+    /// Height of the last block the chain shares with the best header chain
+    fn fork_height(&self, chain: &Blockchain) -> u64 {
+        let mut height = (chain.block_height() - 1).min(self.headers.best_height());
+        while self.headers.hash_at(height) != Some(chain.blocks[height as usize].hash()) {
+            height -= 1;
+        }
+        height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lib::crypto::PrivateKey;
+    use lib::params::ChainParams;
+
+    use super::*;
+
+    fn peer(n: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, n], 9633))
+    }
+
+    /// Mines `count` blocks onto the chain, returning them
+    fn extend(chain: &mut Blockchain, count: usize) -> Vec<Block> {
+        let key = PrivateKey::new_key();
+        (0..count)
+            .map(|_| {
+                let block = chain.block_template(key.public_key().into()).unwrap();
+                chain.add_block(block.clone()).unwrap();
+                block
+            })
+            .collect()
+    }
+
+    fn headers(blocks: &[Block]) -> Vec<BlockHeader> {
+        blocks.iter().map(|block| block.header().clone()).collect()
+    }
+
+    /// A fresh chain syncing from a peer that has `count` more blocks, returned with them
+    fn behind(count: usize) -> (Blockchain, Sync, Vec<Block>) {
+        let chain = Blockchain::with_params(ChainParams::regtest());
+        let blocks = extend(&mut chain.clone(), count);
+        let mut sync = Sync::new(&chain);
+        sync.add_peer(peer(1), count as u64);
+        sync.add_headers(peer(1), headers(&blocks)).unwrap();
+        (chain, sync, blocks)
+    }
+
+    fn scheduled(requests: &[(SocketAddr, Vec<Hash>)]) -> HashMap<Hash, SocketAddr> {
+        requests
+            .iter()
+            .flat_map(|(peer, hashes)| hashes.iter().map(|hash| (*hash, *peer)))
+            .collect()
+    }
+
+    #[test]
+    fn schedule_spreads_requests_over_peers() {
+        let (chain, mut sync, blocks) = behind(40);
+        sync.add_peer(peer(2), 40);
+        // only has the first ten
+        sync.add_peer(peer(3), 10);
+
+        let requests = scheduled(&sync.schedule(&chain));
+        let count = |n| requests.values().filter(|p| **p == peer(n)).count();
+        // more blocks than the first two can take at once
+        assert_eq!(count(1), MAX_BLOCKS_IN_FLIGHT);
+        assert_eq!(count(2), MAX_BLOCKS_IN_FLIGHT);
+        assert!(count(3) > 0);
+        for (index, block) in blocks.iter().enumerate() {
+            match requests.get(&block.hash()) {
+                Some(p) if *p == peer(3) => assert!(index < 10),
+                Some(_) => {}
+                None => assert!(index >= 10),
+            }
+        }
+
+        // requested blocks aren't asked for twice, the rest wait for a free slot
+        assert!(sync.schedule(&chain).is_empty());
+    }
+
+    #[test]
+    fn the_window_limits_how_far_ahead_blocks_are_requested() {
+        let (chain, mut sync, blocks) = behind(BLOCK_DOWNLOAD_WINDOW as usize + 10);
+        for n in 2..=20 {
+            sync.add_peer(peer(n), blocks.len() as u64);
+        }
+
+        let requests = scheduled(&sync.schedule(&chain));
+        assert_eq!(requests.len(), BLOCK_DOWNLOAD_WINDOW as usize);
+        assert!(!requests.contains_key(&blocks[BLOCK_DOWNLOAD_WINDOW as usize].hash()));
+    }
+
+    #[test]
+    fn expired_requests_go_to_another_peer() {
+        let (chain, mut sync, _) = behind(2 * MAX_BLOCKS_IN_FLIGHT);
+        sync.add_peer(peer(2), 2 * MAX_BLOCKS_IN_FLIGHT as u64);
+        let first = scheduled(&sync.schedule(&chain));
+        assert_eq!(first.len(), 2 * MAX_BLOCKS_IN_FLIGHT);
+
+        assert!(sync.expire(Instant::now()).is_empty());
+        let mut stalled = sync.expire(Instant::now() + BLOCK_DOWNLOAD_TIMEOUT);
+        stalled.sort();
+        assert_eq!(stalled, [peer(1), peer(2)]);
+
+        // each block goes to the peer that didn't let it time out
+        let second = scheduled(&sync.schedule(&chain));
+        assert_eq!(second.len(), first.len());
+        for (hash, peer) in &second {
+            assert_ne!(first[hash], *peer);
+        }
+    }
+
+    #[test]
+    fn removed_peers_hand_their_requests_over() {
+        let (chain, mut sync, _) = behind(4);
+        sync.add_peer(peer(2), 4);
+        sync.schedule(&chain);
+
+        sync.remove_peer(peer(1));
+        let requests = scheduled(&sync.schedule(&chain));
+        assert!(requests.values().all(|p| *p == peer(2)));
+        assert!(sync.schedule(&chain).is_empty());
+    }
+
+    #[test]
+    fn blocks_connect_in_order() {
+        let (mut chain, mut sync, blocks) = behind(3);
+
+        sync.add_block(blocks[1].clone(), Some(peer(1))).unwrap();
+        sync.add_block(blocks[2].clone(), Some(peer(1))).unwrap();
+        assert!(sync.connect(&mut chain).connected.is_empty());
+        assert!(!sync.is_synced(&chain));
+
+        sync.add_block(blocks[0].clone(), Some(peer(1))).unwrap();
+        let progress = sync.connect(&mut chain);
+        let hashes: Vec<Hash> = blocks.iter().map(Block::hash).collect();
+        assert_eq!(progress.connected, hashes);
+        assert!(progress.disconnected.is_empty());
+        assert!(sync.is_synced(&chain));
+    }
+
+    #[test]
+    fn a_branch_with_more_work_reorganizes() {
+        let mut chain = Blockchain::with_params(ChainParams::regtest());
+        let mut branch = chain.clone();
+        let ours = extend(&mut chain, 2);
+        let theirs = extend(&mut branch, 3);
+
+        let mut sync = Sync::new(&chain);
+        sync.add_peer(peer(1), 3);
+        sync.add_headers(peer(1), headers(&theirs)).unwrap();
+        for block in &theirs {
+            sync.add_block(block.clone(), Some(peer(1))).unwrap();
+        }
+
+        let progress = sync.connect(&mut chain);
+        let disconnected: Vec<Hash> = ours.iter().rev().map(Block::hash).collect();
+        assert_eq!(progress.disconnected, disconnected);
+        let connected: Vec<Hash> = theirs.iter().map(Block::hash).collect();
+        assert_eq!(progress.connected, connected);
+        assert_eq!(chain.blocks.last().unwrap().hash(), theirs[2].hash());
+    }
+
+    #[test]
+    fn a_mismatched_body_does_not_roll_the_chain_back() {
+        let mut chain = Blockchain::with_params(ChainParams::regtest());
+        let mut branch = chain.clone();
+        let ours = extend(&mut chain, 2);
+        let theirs = extend(&mut branch, 3);
+
+        let mut sync = Sync::new(&chain);
+        sync.add_peer(peer(1), 3);
+        sync.add_headers(peer(1), headers(&theirs)).unwrap();
+        let mut transactions = theirs[0].transactions().to_vec();
+        transactions.push(transactions[0].clone());
+        sync.add_block(Block::new(theirs[0].header().clone(), transactions), Some(peer(2))).unwrap();
+        for block in &theirs[1..] {
+            sync.add_block(block.clone(), Some(peer(1))).unwrap();
+        }
+
+        let progress = sync.connect(&mut chain);
+        assert!(progress.disconnected.is_empty() && progress.connected.is_empty());
+        assert!(matches!(
+            progress.invalid.as_slice(),
+            [(hash, BtcError::InvalidMerkleRoot, Some(from))] if *hash == theirs[0].hash() && *from == peer(2)
+        ));
+        assert_eq!(chain.blocks.last().unwrap().hash(), ours[1].hash());
+
+        // the branch's other blocks wait for the right body
+        sync.add_block(theirs[0].clone(), Some(peer(1))).unwrap();
+        let progress = sync.connect(&mut chain);
+        assert_eq!(progress.disconnected.len(), 2);
+        assert_eq!(progress.connected.len(), 3);
+        assert_eq!(chain.blocks.last().unwrap().hash(), theirs[2].hash());
+    }
+
+    #[test]
+    fn invalid_blocks_are_reported_with_their_peer() {
+        let (mut chain, mut sync, blocks) = behind(2);
+        // a body that doesn't match the header can still come right from elsewhere
+        let mut transactions = blocks[0].transactions().to_vec();
+        transactions.push(transactions[0].clone());
+        let mismatched = Block::new(blocks[0].header().clone(), transactions);
+        sync.add_block(mismatched, Some(peer(2))).unwrap();
+
+        let progress = sync.connect(&mut chain);
+        assert!(progress.connected.is_empty());
+        assert!(matches!(
+            progress.invalid.as_slice(),
+            [(hash, BtcError::InvalidMerkleRoot, Some(from))] if *hash == blocks[0].hash() && *from == peer(2)
+        ));
+        assert!(sync.headers.is_on_best_chain(&blocks[0].hash()));
+
+        sync.add_block(blocks[0].clone(), Some(peer(1))).unwrap();
+        sync.add_block(blocks[1].clone(), Some(peer(1))).unwrap();
+        assert_eq!(sync.connect(&mut chain).connected.len(), 2);
+    }
+}
@@ -1,3 +1,487 @@
-fn main() {
+use std::path::PathBuf;
 
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use lib::address::Address;
+use lib::crypto::{PrivateKey, PublicKey};
+use lib::htlc::{self, Htlc, Preimage};
+use lib::message::{self, MessageSignature};
+use lib::params::{ChainParams, Network};
+use lib::sha256::Hash;
+use lib::types::{Blockchain, LockingCondition, Transaction};
+use lib::util::Saveable;
+
+mod transactions;
+
+#[derive(Parser)]
+#[command(author, version, about = "rsbtc wallet")]
+struct Cli {
+    /// Network whose addresses and chain snapshots are used
+    #[arg(long, global = true, default_value_t = Network::Main)]
+    network: Network,
+    /// Parameter file of a custom network, overrides --network
+    #[arg(long, global = true)]
+    params: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a new private key and save it together with its public key
+    GenerateKey {
+        /// Where to write the private key
+        #[arg(long)]
+        private_key: PathBuf,
+        /// Where to write the public key
+        #[arg(long)]
+        public_key: PathBuf,
+    },
+    /// Sign a message to prove ownership of a key without moving funds
+    SignMessage {
+        /// Private key file
+        #[arg(long)]
+        key: PathBuf,
+        message: String,
+    },
+    /// Verify a signed message and print the public key that signed it
+    VerifyMessage {
+        /// Public key file the signature is expected to belong to
+        #[arg(long)]
+        pubkey: Option<PathBuf>,
+        /// Base64 signature as printed by sign-message
+        signature: String,
+        message: String,
+    },
+    /// Pay from the key's own UTXOs to one key, or to an m-of-n multisig lock
+    Send {
+        /// Chain snapshot to take UTXOs from
+        #[arg(long)]
+        chain: PathBuf,
+        /// Private key file paying for the transaction
+        #[arg(long)]
+        key: PathBuf,
+        /// Recipient addresses or public key files, more than one locks the output to a multisig
+        #[arg(long = "to", required = true)]
+        recipients: Vec<String>,
+        /// Signatures required to spend a multisig output, defaults to all keys
+        #[arg(long)]
+        threshold: Option<u8>,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        /// Where to write the signed transaction
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Build an unsigned transaction spending a multisig UTXO, to be passed around for cosign
+    SpendMultisig {
+        #[arg(long)]
+        chain: PathBuf,
+        /// Hash of the multisig UTXO
+        #[arg(long)]
+        utxo: Hash,
+        /// Recipient address or public key file
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Add the key's signatures to a transaction file in place
+    Cosign {
+        #[arg(long)]
+        chain: PathBuf,
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long)]
+        tx: PathBuf,
+    },
+    /// Anchor data (e.g. a document hash) in the chain with a data carrier output
+    Anchor {
+        #[arg(long)]
+        chain: PathBuf,
+        /// Private key file paying the fee
+        #[arg(long)]
+        key: PathBuf,
+        /// Data to embed, as text
+        #[arg(long, conflicts_with = "hex", required_unless_present = "hex")]
+        text: Option<String>,
+        /// Data to embed, hex encoded
+        #[arg(long, value_parser = parse_hex)]
+        hex: Option<Vec<u8>>,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Print a transaction file's inputs and outputs
+    ShowTx {
+        #[arg(long)]
+        tx: PathBuf,
+    },
+    /// Print the transactions of the block at the height
+    ShowBlock {
+        #[arg(long)]
+        chain: PathBuf,
+        #[arg(long)]
+        height: u64,
+    },
+    /// Generate a random HTLC preimage and print it with its payment hash
+    HtlcSecret,
+    /// Lock funds in an HTLC the recipient can claim with the preimage, refundable after the timeout
+    HtlcCreate {
+        #[arg(long)]
+        chain: PathBuf,
+        /// Private key file paying for the HTLC, its ECDSA key gets the refund
+        #[arg(long)]
+        key: PathBuf,
+        /// Recipient address or public key file
+        #[arg(long)]
+        to: String,
+        /// Hex SHA-256 hash of the preimage
+        #[arg(long, value_parser = parse_hex32)]
+        payment_hash: [u8; 32],
+        /// Block height or unix timestamp from which the refund is possible
+        #[arg(long)]
+        timeout: u64,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Claim an HTLC UTXO by revealing the preimage
+    HtlcClaim {
+        #[arg(long)]
+        chain: PathBuf,
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long)]
+        utxo: Hash,
+        /// Hex preimage
+        #[arg(long, value_parser = parse_hex32)]
+        preimage: Preimage,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Take an HTLC UTXO back after its timeout
+    HtlcRefund {
+        #[arg(long)]
+        chain: PathBuf,
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long)]
+        utxo: Hash,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Print the preimage revealed by a transaction claiming an HTLC with the payment hash
+    HtlcPreimage {
+        /// Claim transaction file
+        #[arg(long)]
+        tx: PathBuf,
+        #[arg(long, value_parser = parse_hex32)]
+        payment_hash: [u8; 32],
+    },
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>> {
+    Ok(hex::decode(value)?)
+}
+
+fn parse_hex32(value: &str) -> Result<[u8; 32]> {
+    parse_hex(value)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("expected 32 hex encoded bytes"))
+}
+
+fn load<T: Saveable>(path: &PathBuf) -> Result<T> {
+    T::load_from_file(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+fn chain_params(cli: &Cli) -> Result<ChainParams> {
+    match &cli.params {
+        Some(path) => ChainParams::load_from_file(path)
+            .with_context(|| format!("failed to read {}", path.display())),
+        None => ChainParams::for_network(cli.network)
+            .context("the custom network needs a --params file"),
+    }
+}
+
+/// Loads a chain snapshot, refusing those of another network
+fn load_chain(path: &PathBuf, params: &ChainParams) -> Result<Blockchain> {
+    let chain: Blockchain = load(path)?;
+    if chain.params().magic != params.magic {
+        bail!(
+            "{} holds a {} chain, not {}",
+            path.display(),
+            chain.params().network,
+            params.network
+        );
+    }
+    chain
+        .verify_genesis(params)
+        .with_context(|| format!("{} has a different genesis block", path.display()))?;
+    Ok(chain)
+}
+
+/// Public key of an address of the network, or read from a key file
+fn load_recipient(recipient: &str, params: &ChainParams) -> Result<PublicKey> {
+    match Address::parse(recipient, params) {
+        Ok(address) => Ok(address.pubkey),
+        Err(_) => load(&PathBuf::from(recipient))
+            .with_context(|| format!("{recipient} is neither a {} address nor a key file", params.network)),
+    }
+}
+
+fn print_transaction(transaction: &Transaction, params: &ChainParams) {
+    println!("transaction {}", transaction.hash());
+    println!("  wtxid {}", transaction.wtxid());
+    println!("  weight {} ({} vbytes)", transaction.weight(), transaction.virtual_size());
+    if transaction.lock_time() != 0 {
+        println!("  lock time {}", transaction.lock_time());
+    }
+
+    for input in transaction.inputs() {
+        println!("  in  {}", input.prev_transaction_output_hash);
+    }
+
+    for output in transaction.outputs() {
+        println!("  out {} {}", output.value, output.lock);
+
+        if let LockingCondition::PublicKey(pubkey) = &output.lock {
+            println!("      address {}", Address::new(pubkey.clone(), params));
+        }
+
+        if let Some(text) = output
+            .data_carrier_payload()
+            .and_then(|data| std::str::from_utf8(data).ok())
+            .filter(|text| !text.chars().any(char::is_control))
+        {
+            println!("      text \"{text}\"");
+        }
+    }
+}
+
+fn save<T: Saveable>(value: &T, path: &PathBuf) -> Result<()> {
+    value
+        .save_to_file(path)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let params = chain_params(&cli)?;
+
+    match cli.command {
+        Command::GenerateKey {
+            private_key,
+            public_key,
+        } => {
+            let key = PrivateKey::new_key();
+            key.save_to_file(&private_key)
+                .with_context(|| format!("failed to write {}", private_key.display()))?;
+            key.public_key()
+                .save_to_file(&public_key)
+                .with_context(|| format!("failed to write {}", public_key.display()))?;
+
+            println!("{}", hex::encode(key.public_key().to_bytes()));
+            println!("address {}", Address::new(key.public_key(), &params));
+        }
+        Command::SignMessage { key, message } => {
+            let key = PrivateKey::load_from_file(&key)
+                .with_context(|| format!("failed to read {}", key.display()))?;
+
+            println!("{}", message::sign_message(&key, &message).to_base64());
+        }
+        Command::VerifyMessage {
+            pubkey,
+            signature,
+            message,
+        } => {
+            let signature = MessageSignature::from_base64(&signature)?;
+            let recovered = signature.recover(&message)?;
+            println!("signed by {}", hex::encode(recovered.to_bytes()));
+
+            if let Some(pubkey) = pubkey {
+                let pubkey = PublicKey::load_from_file(&pubkey)
+                    .with_context(|| format!("failed to read {}", pubkey.display()))?;
+
+                if !message::verify_message(&pubkey, &message, &signature) {
+                    bail!("signature does not belong to {}", hex::encode(pubkey.to_bytes()));
+                }
+                println!("signature is valid");
+            }
+        }
+        Command::Send {
+            chain,
+            key,
+            recipients,
+            threshold,
+            amount,
+            fee,
+            out,
+        } => {
+            let chain = load_chain(&chain, &params)?;
+            let key: PrivateKey = load(&key)?;
+            let pubkeys = recipients
+                .iter()
+                .map(|recipient| load_recipient(recipient, &params))
+                .collect::<Result<Vec<_>>>()?;
+
+            let lock = if pubkeys.len() == 1 && threshold.is_none() {
+                LockingCondition::PublicKey(pubkeys[0].clone())
+            } else {
+                let threshold = threshold.unwrap_or(pubkeys.len() as u8);
+                LockingCondition::multisig(threshold, pubkeys)?
+            };
+
+            let transaction = transactions::build_send(&chain, &key, lock, amount, fee)?;
+            save(&transaction, &out)?;
+            println!("{}", transaction.hash());
+        }
+        Command::SpendMultisig {
+            chain,
+            utxo,
+            to,
+            amount,
+            fee,
+            out,
+        } => {
+            let chain = load_chain(&chain, &params)?;
+            let recipient = load_recipient(&to, &params)?;
+
+            let transaction =
+                transactions::build_multisig_spend(&chain, utxo, recipient, amount, fee)?;
+            save(&transaction, &out)?;
+            println!("{}", transaction.hash());
+        }
+        Command::Cosign { chain, key, tx } => {
+            let chain = load_chain(&chain, &params)?;
+            let key: PrivateKey = load(&key)?;
+            let mut transaction: Transaction = load(&tx)?;
+
+            let signed = transactions::cosign(&chain, &key, &mut transaction)?;
+            if signed == 0 {
+                bail!("the key is not part of any input's locking condition");
+            }
+            save(&transaction, &tx)?;
+
+            for (index, (collected, required)) in transactions::signature_progress(&chain, &transaction)
+                .into_iter()
+                .enumerate()
+            {
+                println!("input {index}: {collected}/{required} signatures");
+            }
+        }
+        Command::Anchor {
+            chain,
+            key,
+            text,
+            hex,
+            fee,
+            out,
+        } => {
+            let chain = load_chain(&chain, &params)?;
+            let key: PrivateKey = load(&key)?;
+            let data = text.map(String::into_bytes).or(hex).unwrap_or_default();
+
+            let transaction = transactions::build_data_carrier(&chain, &key, &data, fee)?;
+            save(&transaction, &out)?;
+            println!("{}", transaction.hash());
+        }
+        Command::ShowTx { tx } => {
+            let transaction: Transaction = load(&tx)?;
+            print_transaction(&transaction, &params);
+        }
+        Command::ShowBlock { chain, height } => {
+            let chain = load_chain(&chain, &params)?;
+            let block = chain
+                .blocks
+                .get(height as usize)
+                .with_context(|| format!("no block at height {height}"))?;
+
+            println!("block {} at {}", block.hash(), block.header().timestamp);
+            for transaction in block.transactions() {
+                print_transaction(transaction, &params);
+            }
+        }
+        Command::HtlcSecret => {
+            let preimage = htlc::new_preimage();
+            println!("preimage:     {}", hex::encode(preimage));
+            println!("payment hash: {}", hex::encode(htlc::payment_hash(&preimage)));
+        }
+        Command::HtlcCreate {
+            chain,
+            key,
+            to,
+            payment_hash,
+            timeout,
+            amount,
+            fee,
+            out,
+        } => {
+            let chain = load_chain(&chain, &params)?;
+            let key: PrivateKey = load(&key)?;
+            let recipient = load_recipient(&to, &params)?;
+
+            let htlc = Htlc::new(payment_hash, recipient, key.public_key(), timeout);
+            let transaction = transactions::build_send(&chain, &key, htlc.lock(), amount, fee)?;
+            save(&transaction, &out)?;
+            println!("transaction: {}", transaction.hash());
+            println!("htlc utxo:   {}", transaction.outputs()[0].hash());
+        }
+        Command::HtlcClaim {
+            chain,
+            key,
+            utxo,
+            preimage,
+            fee,
+            out,
+        } => {
+            let chain = load_chain(&chain, &params)?;
+            let key: PrivateKey = load(&key)?;
+
+            let transaction = transactions::build_htlc_claim(&chain, &key, utxo, &preimage, fee)?;
+            save(&transaction, &out)?;
+            println!("{}", transaction.hash());
+        }
+        Command::HtlcRefund {
+            chain,
+            key,
+            utxo,
+            fee,
+            out,
+        } => {
+            let chain = load_chain(&chain, &params)?;
+            let key: PrivateKey = load(&key)?;
+
+            let transaction = transactions::build_htlc_refund(&chain, &key, utxo, fee)?;
+            save(&transaction, &out)?;
+            println!("{}", transaction.hash());
+            println!("valid in blocks after lock time {}", transaction.lock_time());
+        }
+        Command::HtlcPreimage { tx, payment_hash } => {
+            let transaction: Transaction = load(&tx)?;
+
+            let preimage = transaction
+                .inputs()
+                .iter()
+                .find_map(|input| htlc::extract_preimage(input, &payment_hash))
+                .context("the transaction does not reveal a preimage for the payment hash")?;
+            println!("{}", hex::encode(preimage));
+        }
+    }
+
+    Ok(())
 }
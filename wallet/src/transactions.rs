@@ -0,0 +1,243 @@
+use anyhow::{anyhow, bail, Result};
+use lib::crypto::{PrivateKey, PublicKey};
+use lib::htlc::{Htlc, Preimage};
+use lib::policy::MAX_DATA_CARRIER_SIZE;
+use lib::sha256::Hash;
+use lib::types::{Blockchain, LockingCondition, Transaction, TransactionInput, TransactionOutput};
+
+/// Keys of the private key under both signature schemes
+fn own_keys(key: &PrivateKey) -> [PublicKey; 2] {
+    [key.public_key(), key.schnorr_public_key()]
+}
+
+/// UTXOs (confirmed and not already spent in the mempool) the key can spend on its own
+pub fn spendable_utxos(chain: &Blockchain, key: &PrivateKey) -> Vec<(Hash, TransactionOutput)> {
+    let own_keys = own_keys(key);
+    let spent_in_mempool: Vec<Hash> = chain
+        .mempool
+        .iter()
+        .flat_map(|tx| tx.inputs().iter())
+        .map(|input| input.prev_transaction_output_hash)
+        .collect();
+
+    let mut utxos: Vec<(Hash, TransactionOutput)> = chain
+        .utxos
+        .iter()
+        .filter(|(hash, _)| !spent_in_mempool.contains(hash))
+        .filter(|(_, output)| {
+            matches!(&output.lock, LockingCondition::PublicKey(pubkey) if own_keys.contains(pubkey))
+        })
+        .map(|(hash, output)| (*hash, output.clone()))
+        .collect();
+
+    // largest first, so few inputs are needed
+    utxos.sort_by_key(|(_, output)| std::cmp::Reverse(output.value));
+    utxos
+}
+
+/// Pays `amount` to `lock` from the key's own UTXOs, returning change to its ECDSA key
+pub fn build_send(
+    chain: &Blockchain,
+    key: &PrivateKey,
+    lock: LockingCondition,
+    amount: u64,
+    fee: u64,
+) -> Result<Transaction> {
+    build_payment(chain, key, vec![TransactionOutput::new(amount, lock)], fee)
+}
+
+/// Zero value transaction anchoring `data` in the chain, paid for by the fee
+pub fn build_data_carrier(
+    chain: &Blockchain,
+    key: &PrivateKey,
+    data: &[u8],
+    fee: u64,
+) -> Result<Transaction> {
+    if data.len() > MAX_DATA_CARRIER_SIZE {
+        bail!("data is {} bytes, at most {MAX_DATA_CARRIER_SIZE} are relayed", data.len());
+    }
+
+    build_payment(chain, key, vec![TransactionOutput::data_carrier(data)], fee)
+}
+
+/// Funds the outputs and the fee from the key's own UTXOs, returning change to its ECDSA key
+fn build_payment(
+    chain: &Blockchain,
+    key: &PrivateKey,
+    mut outputs: Vec<TransactionOutput>,
+    fee: u64,
+) -> Result<Transaction> {
+    let amount: u64 = outputs.iter().map(|output| output.value).sum();
+    let needed = amount + fee;
+    let mut selected = Vec::new();
+    let mut total = 0;
+
+    for (hash, output) in spendable_utxos(chain, key) {
+        if total >= needed {
+            break;
+        }
+        total += output.value;
+        selected.push((hash, output));
+    }
+
+    if total < needed {
+        bail!("insufficient funds: have {total}, need {needed}");
+    }
+
+    let inputs = selected
+        .iter()
+        .map(|(hash, _)| TransactionInput::unsigned(*hash))
+        .collect();
+
+    if total > needed {
+        outputs.push(TransactionOutput::new(total - needed, key.public_key().into()));
+    }
+
+    // the signatures commit to the finished transaction
+    let mut transaction = Transaction::new(inputs, outputs);
+    for (index, (_, output)) in selected.iter().enumerate() {
+        let LockingCondition::PublicKey(pubkey) = &output.lock else {
+            unreachable!("spendable_utxos only returns single key outputs")
+        };
+        transaction.sign_input(index, 0, key, pubkey);
+    }
+    Ok(transaction)
+}
+
+/// Unsigned transaction spending a multisig UTXO, change goes back to the same multisig lock
+pub fn build_multisig_spend(
+    chain: &Blockchain,
+    utxo: Hash,
+    recipient: PublicKey,
+    amount: u64,
+    fee: u64,
+) -> Result<Transaction> {
+    let output = chain
+        .utxos
+        .get(&utxo)
+        .ok_or_else(|| anyhow!("unknown UTXO {utxo}"))?;
+
+    if !matches!(output.lock, LockingCondition::Multisig { .. }) {
+        bail!("UTXO {utxo} is not locked to a multisig condition");
+    }
+
+    let needed = amount + fee;
+    if output.value < needed {
+        bail!("UTXO {utxo} holds {}, need {needed}", output.value);
+    }
+
+    let mut outputs = vec![TransactionOutput::new(amount, recipient.into())];
+    if output.value > needed {
+        outputs.push(TransactionOutput::new(output.value - needed, output.lock.clone()));
+    }
+
+    Ok(Transaction::new(vec![TransactionInput::unsigned(utxo)], outputs))
+}
+
+/// Adds the key's signature to every input whose spent output it is part of.
+/// Returns the number of signatures added
+pub fn cosign(chain: &Blockchain, key: &PrivateKey, transaction: &mut Transaction) -> Result<usize> {
+    let mut signed = 0;
+
+    for index in 0..transaction.inputs().len() {
+        let utxo = transaction.inputs()[index].prev_transaction_output_hash;
+        let output = chain
+            .utxos
+            .get(&utxo)
+            .ok_or_else(|| anyhow!("unknown UTXO {utxo}"))?;
+
+        let required = output.lock.required_signatures();
+
+        for pubkey in own_keys(key) {
+            if let Some(key_index) = output.lock.key_index(&pubkey) {
+                let input = &transaction.inputs()[index];
+                let already_signed = input
+                    .signatures
+                    .iter()
+                    .any(|existing| existing.key_index == key_index);
+                if input.signatures.len() >= required && !already_signed {
+                    continue;
+                }
+
+                transaction.sign_input(index, key_index, key, &pubkey);
+                signed += 1;
+            }
+        }
+    }
+
+    Ok(signed)
+}
+
+/// (collected, required) signature counts of every input
+pub fn signature_progress(chain: &Blockchain, transaction: &Transaction) -> Vec<(usize, usize)> {
+    transaction
+        .inputs()
+        .iter()
+        .map(|input| {
+            let required = chain
+                .utxos
+                .get(&input.prev_transaction_output_hash)
+                .map_or(1, |output| output.lock.required_signatures());
+            (input.signatures.len(), required)
+        })
+        .collect()
+}
+
+fn htlc_utxo(chain: &Blockchain, utxo: Hash) -> Result<(Htlc, u64)> {
+    let output = chain
+        .utxos
+        .get(&utxo)
+        .ok_or_else(|| anyhow!("unknown UTXO {utxo}"))?;
+    let htlc = Htlc::from_lock(&output.lock)
+        .ok_or_else(|| anyhow!("UTXO {utxo} is not an HTLC"))?;
+
+    Ok((htlc, output.value))
+}
+
+/// Claims an HTLC UTXO with the preimage, paying it to the key's ECDSA key
+pub fn build_htlc_claim(
+    chain: &Blockchain,
+    key: &PrivateKey,
+    utxo: Hash,
+    preimage: &Preimage,
+    fee: u64,
+) -> Result<Transaction> {
+    let (htlc, value) = htlc_utxo(chain, utxo)?;
+
+    if !own_keys(key).contains(&htlc.recipient) {
+        bail!("the key is not the recipient of HTLC {utxo}");
+    }
+    if lib::htlc::payment_hash(preimage) != htlc.payment_hash {
+        bail!("preimage does not match the payment hash of HTLC {utxo}");
+    }
+    if value < fee {
+        bail!("HTLC {utxo} holds {value}, less than the fee");
+    }
+
+    let mut transaction = Transaction::new(
+        vec![Htlc::claim_input(utxo)],
+        vec![TransactionOutput::new(value - fee, key.public_key().into())],
+    );
+    htlc.sign_claim(&mut transaction, 0, key, preimage);
+    Ok(transaction)
+}
+
+/// Takes an HTLC UTXO back after its timeout, the transaction is minable once the chain passes it
+pub fn build_htlc_refund(chain: &Blockchain, key: &PrivateKey, utxo: Hash, fee: u64) -> Result<Transaction> {
+    let (htlc, value) = htlc_utxo(chain, utxo)?;
+
+    if !own_keys(key).contains(&htlc.refund) {
+        bail!("the key is not the refund key of HTLC {utxo}");
+    }
+    if value < fee {
+        bail!("HTLC {utxo} holds {value}, less than the fee");
+    }
+
+    let mut transaction = Transaction::new(
+        vec![Htlc::refund_input(utxo)],
+        vec![TransactionOutput::new(value - fee, key.public_key().into())],
+    )
+    .with_lock_time(htlc.timeout);
+    htlc.sign_refund(&mut transaction, 0, key);
+    Ok(transaction)
+}